@@ -1,6 +1,10 @@
 pub use memflow::cglue::prelude::v1::{CVec, ReprCString};
 use memflow::prelude::v1::Pod;
 
+/// Version of the structures shared between the guest agent and the host,
+/// the agent and mirror should be built against the same version
+pub const PROTOCOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[repr(u8)]
 #[derive(Clone, Copy, Debug)]
 pub enum CaptureTargetType {
@@ -15,13 +19,17 @@ pub struct CaptureTarget {
     pub name: ReprCString,
 }
 
+/// Capture modes allowed by the host.
+///
+/// The config is read from guest memory where any byte value is possible,
+/// so the flags are stored as `u8` and every non-zero value counts as enabled.
 #[repr(C)]
 #[derive(Clone, Debug)]
 pub struct CaptureConfig {
     /// allowed capture modes
-    pub gdi: bool,
-    pub dxgi: bool,
-    pub obs: bool,
+    gdi: u8,
+    dxgi: u8,
+    obs: u8,
     // a list of all potential capture targets
     //pub targets: CVec<CaptureTarget>,
 
@@ -29,12 +37,37 @@ pub struct CaptureConfig {
     //pub current_target: usize,
 }
 
+unsafe impl Pod for CaptureConfig {}
+
+impl CaptureConfig {
+    pub fn gdi(&self) -> bool {
+        self.gdi != 0
+    }
+    pub fn set_gdi(&mut self, gdi: bool) {
+        self.gdi = gdi as u8;
+    }
+
+    pub fn dxgi(&self) -> bool {
+        self.dxgi != 0
+    }
+    pub fn set_dxgi(&mut self, dxgi: bool) {
+        self.dxgi = dxgi as u8;
+    }
+
+    pub fn obs(&self) -> bool {
+        self.obs != 0
+    }
+    pub fn set_obs(&mut self, obs: bool) {
+        self.obs = obs as u8;
+    }
+}
+
 impl Default for CaptureConfig {
     fn default() -> Self {
         Self {
-            gdi: true,
-            dxgi: true,
-            obs: false,
+            gdi: 1,
+            dxgi: 1,
+            obs: 0,
             //targets: Vec::new().into(),
 
             //current_target: 0,
@@ -75,6 +108,270 @@ impl Default for Cursor {
     }
 }
 
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEventType {
+    None = 0,
+    MouseMove = 1,
+    MouseButton = 2,
+    MouseWheel = 3,
+    KeyDown = 4,
+    KeyUp = 5,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MouseButton {
+    Left = 0,
+    Right = 1,
+    Middle = 2,
+    X1 = 3,
+    X2 = 4,
+}
+
+/// A single input event sent from the host to the guest.
+///
+/// Mouse positions are absolute coordinates within the captured frame.
+/// Keys are sent as (set 1) scancodes so they are independent of the keyboard layout on the host.
+#[repr(C)]
+#[derive(Pod, Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub ty: u32, // InputEventType
+    /// mouse button or key scancode
+    pub code: u32,
+    /// mouse x, horizontal wheel delta
+    pub x: i32,
+    /// mouse y, vertical wheel delta
+    pub y: i32,
+    pub flags: u32,
+}
+
+impl InputEvent {
+    /// The mouse button has been pressed
+    pub const FLAG_PRESSED: u32 = 1;
+    /// The scancode is prefixed with 0xE0
+    pub const FLAG_EXTENDED: u32 = 2;
+
+    pub fn mouse_move(x: i32, y: i32) -> Self {
+        Self {
+            ty: InputEventType::MouseMove as u32,
+            code: 0,
+            x,
+            y,
+            flags: 0,
+        }
+    }
+
+    pub fn mouse_button(button: MouseButton, pressed: bool, x: i32, y: i32) -> Self {
+        Self {
+            ty: InputEventType::MouseButton as u32,
+            code: button as u32,
+            x,
+            y,
+            flags: if pressed { Self::FLAG_PRESSED } else { 0 },
+        }
+    }
+
+    /// Wheel deltas are in multiples of WHEEL_DELTA (120) per notch
+    pub fn mouse_wheel(dx: i32, dy: i32) -> Self {
+        Self {
+            ty: InputEventType::MouseWheel as u32,
+            code: 0,
+            x: dx,
+            y: dy,
+            flags: 0,
+        }
+    }
+
+    pub fn key(scancode: u16, extended: bool, pressed: bool) -> Self {
+        Self {
+            ty: if pressed {
+                InputEventType::KeyDown as u32
+            } else {
+                InputEventType::KeyUp as u32
+            },
+            code: scancode as u32,
+            x: 0,
+            y: 0,
+            flags: if extended { Self::FLAG_EXTENDED } else { 0 },
+        }
+    }
+}
+
+impl Default for InputEvent {
+    fn default() -> Self {
+        Self {
+            ty: InputEventType::None as u32,
+            code: 0,
+            x: 0,
+            y: 0,
+            flags: 0,
+        }
+    }
+}
+
+pub const INPUT_QUEUE_LEN: usize = 64;
+
+/// Bounded ring buffer of input events.
+///
+/// The host only writes the events and the `write_index`, the guest only writes the `read_index`.
+/// Both indices are increasing and wrap around at u32::MAX.
+#[repr(C)]
+#[derive(Pod, Clone, Copy, Debug)]
+pub struct InputQueue {
+    pub write_index: u32,
+    pub read_index: u32,
+    pub events: [InputEvent; INPUT_QUEUE_LEN],
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        Self {
+            write_index: 0,
+            read_index: 0,
+            events: [InputEvent::default(); INPUT_QUEUE_LEN],
+        }
+    }
+
+    /// Returns the number of events that have not been consumed by the guest yet
+    pub fn len(&self) -> usize {
+        self.write_index.wrapping_sub(self.read_index) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for InputQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const CLIPBOARD_CAPACITY: usize = 0x10000;
+pub const CLIPBOARD_MIME_LEN: usize = 32;
+pub const CLIPBOARD_MIME_TEXT: &str = "text/plain;charset=utf-8";
+
+/// Describes the clipboard contents published by either the guest or the host.
+///
+/// The payload is stored in a separate buffer of `CLIPBOARD_CAPACITY` bytes which is allocated by the guest.
+/// The `sequence` is incremented by the publishing side after the payload has been written.
+#[repr(C)]
+#[derive(Pod, Clone, Copy, Debug)]
+pub struct ClipboardArea {
+    pub sequence: u32,
+    pub len: u32,
+    pub mime: [u8; CLIPBOARD_MIME_LEN],
+    pub buffer: u64,
+}
+
+impl ClipboardArea {
+    pub fn new(buffer: u64) -> Self {
+        Self {
+            sequence: 0,
+            len: 0,
+            mime: [0u8; CLIPBOARD_MIME_LEN],
+            buffer,
+        }
+    }
+
+    /// Returns the mime type of the payload
+    pub fn mime(&self) -> &str {
+        let len = self
+            .mime
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(CLIPBOARD_MIME_LEN);
+        std::str::from_utf8(&self.mime[..len]).unwrap_or_default()
+    }
+
+    /// Encodes a mime type, longer types are truncated
+    pub fn encode_mime(mime: &str) -> [u8; CLIPBOARD_MIME_LEN] {
+        let mut result = [0u8; CLIPBOARD_MIME_LEN];
+        let len = mime.len().min(CLIPBOARD_MIME_LEN);
+        result[..len].copy_from_slice(&mime.as_bytes()[..len]);
+        result
+    }
+}
+
+impl Default for ClipboardArea {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioFormat {
+    None = 0,
+    /// 32-bit float samples
+    F32 = 1,
+    /// 16-bit signed integer samples
+    S16 = 2,
+}
+
+impl AudioFormat {
+    pub fn from_u32(format: u32) -> Self {
+        match format {
+            1 => AudioFormat::F32,
+            2 => AudioFormat::S16,
+            _ => AudioFormat::None,
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            AudioFormat::None => 0,
+            AudioFormat::F32 => 4,
+            AudioFormat::S16 => 2,
+        }
+    }
+}
+
+/// Ring buffer of interleaved PCM samples captured on the guest.
+///
+/// The buffer of `capacity` bytes is allocated by the guest.
+/// `write_cursor` is the total amount of bytes written so far, the position in the ring buffer is `write_cursor % capacity`.
+/// `timestamp` is the time (in microseconds of the guest clock) right after the last written sample,
+/// it uses the same clock as the `frame_timestamp` of the global buffer.
+#[repr(C)]
+#[derive(Pod, Clone, Copy, Debug)]
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub format: u32, // AudioFormat
+    pub capacity: u32,
+    pub write_cursor: u64,
+    pub timestamp: u64,
+    pub buffer: u64,
+}
+
+impl AudioBuffer {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 0,
+            channels: 0,
+            format: AudioFormat::None as u32,
+            capacity: 0,
+            write_cursor: 0,
+            timestamp: 0,
+            buffer: 0,
+        }
+    }
+
+    /// Size of a single frame (one sample for each channel) in bytes
+    pub fn frame_size(&self) -> usize {
+        AudioFormat::from_u32(self.format).bytes_per_sample() * self.channels as usize
+    }
+}
+
+impl Default for AudioBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct GlobalBufferGuest {
@@ -88,6 +385,14 @@ pub struct GlobalBufferGuest {
     pub frame_buffer: CVec<u8>,
     pub cursor: Cursor,
     pub screen_index: u32,
+    pub input: InputQueue,
+    /// clipboard contents published by the guest
+    pub clipboard_guest: ClipboardArea,
+    /// clipboard contents published by the host
+    pub clipboard_host: ClipboardArea,
+    /// time the current frame was captured at (in microseconds of the guest clock)
+    pub frame_timestamp: u64,
+    pub audio: AudioBuffer,
 }
 
 #[repr(C)]
@@ -104,6 +409,14 @@ pub struct GlobalBufferHost {
     pub frame_buffer_pad: [u8; 32], // padding due to internal layout of CVec<T>
     pub cursor: Cursor,
     pub screen_index: u32,
+    pub input: InputQueue,
+    /// clipboard contents published by the guest
+    pub clipboard_guest: ClipboardArea,
+    /// clipboard contents published by the host
+    pub clipboard_host: ClipboardArea,
+    /// time the current frame was captured at (in microseconds of the guest clock)
+    pub frame_timestamp: u64,
+    pub audio: AudioBuffer,
 }
 unsafe impl Pod for GlobalBufferHost {}
 const _: [(); std::mem::size_of::<GlobalBufferGuest>()] =
@@ -122,6 +435,11 @@ impl GlobalBufferGuest {
             frame_buffer: vec![0u8; resolution.0 as usize * resolution.1 as usize * 4].into(),
             cursor: Cursor::default(),
             screen_index,
+            input: InputQueue::default(),
+            clipboard_guest: ClipboardArea::default(),
+            clipboard_host: ClipboardArea::default(),
+            frame_timestamp: 0,
+            audio: AudioBuffer::default(),
         }
     }
 }
@@ -140,6 +458,80 @@ impl GlobalBufferHost {
             frame_buffer_pad: [0u8; 32],
             cursor: Cursor::default(),
             screen_index,
+            input: InputQueue::default(),
+            clipboard_guest: ClipboardArea::default(),
+            clipboard_host: ClipboardArea::default(),
+            frame_timestamp: 0,
+            audio: AudioBuffer::default(),
         }
     }
 }
+
+/// Signature at the start of the page holding the [`PhysicalDescriptor`].
+pub const PHYSICAL_MARKER: [u8; 16] = [
+    0xD, 0xE, 0xA, 0xD, 0xB, 0xA, 0xB, 0xE, b'M', b'I', b'R', b'R', b'O', b'R', b'P', b'H',
+];
+pub const PHYSICAL_VERSION: u32 = 2;
+pub const PHYSICAL_PAGE_SIZE: u64 = 0x1000;
+
+/// Number of page-list pages that can be referenced from the descriptor.
+///
+/// Each page-list page contains `PHYSICAL_PAGE_SIZE / 8` page frame numbers of the frame buffer.
+pub const PHYSICAL_PAGE_LIST_LEN: usize = 448;
+
+/// Descriptor for the physical memory transport.
+///
+/// The guest allocates the descriptor, the page lists and the frame buffer in pinned memory
+/// and publishes the page frame numbers of all pages here.
+/// The host locates the descriptor by scanning physical memory for the `PHYSICAL_MARKER` at the beginning of a page.
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct PhysicalDescriptor {
+    pub marker: [u8; 16],
+    pub version: u32,
+    pub page_size: u32,
+    pub width: u64,
+    pub height: u64,
+    pub config: CaptureConfig,
+    pub frame_counter: u32,
+    pub frame_read_counter: u32,
+    pub frame_texmode: u8, // TextureMode,
+    pub cursor: Cursor,
+    pub frame_buffer_len: u64,
+    pub frame_page_count: u64,
+    pub page_list_count: u64,
+    /// Incremented by the guest every time it publishes new page lists
+    pub generation: u64,
+    pub page_list: [u64; PHYSICAL_PAGE_LIST_LEN],
+}
+unsafe impl Pod for PhysicalDescriptor {}
+const _: () = assert!(std::mem::size_of::<PhysicalDescriptor>() <= PHYSICAL_PAGE_SIZE as usize);
+
+impl PhysicalDescriptor {
+    pub fn new(resolution: (u64, u64)) -> Self {
+        Self {
+            marker: PHYSICAL_MARKER,
+            version: PHYSICAL_VERSION,
+            page_size: PHYSICAL_PAGE_SIZE as u32,
+            width: resolution.0,
+            height: resolution.1,
+            config: CaptureConfig::default(),
+            frame_counter: 0,
+            frame_read_counter: 0,
+            frame_texmode: TextureMode::BGRA as u8, // dxgi default
+            cursor: Cursor::default(),
+            frame_buffer_len: 0,
+            frame_page_count: 0,
+            page_list_count: 0,
+            generation: 0,
+            page_list: [0u64; PHYSICAL_PAGE_LIST_LEN],
+        }
+    }
+
+    /// Returns true if the marker and version of this descriptor are valid
+    pub fn is_valid(&self) -> bool {
+        self.marker == PHYSICAL_MARKER
+            && self.version == PHYSICAL_VERSION
+            && self.page_size as u64 == PHYSICAL_PAGE_SIZE
+    }
+}
@@ -0,0 +1,255 @@
+use ::std::{ptr, slice, thread, time::Duration};
+
+use ::log::{error, info};
+
+use ::winapi::{
+    shared::{
+        guiddef::IsEqualGUID,
+        ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        mmreg::{
+            WAVEFORMATEX, WAVEFORMATEXTENSIBLE, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_IEEE_FLOAT,
+            WAVE_FORMAT_PCM,
+        },
+        winerror::FAILED,
+        wtypesbase::CLSCTX_ALL,
+    },
+    um::{
+        audioclient::{IAudioCaptureClient, IAudioClient, AUDCLNT_BUFFERFLAGS_SILENT},
+        audiosessiontypes::{AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_LOOPBACK},
+        combaseapi::{CoCreateInstance, CoInitializeEx, CoTaskMemFree},
+        mmdeviceapi::{
+            eConsole, eRender, CLSID_MMDeviceEnumerator, IMMDevice, IMMDeviceEnumerator,
+        },
+        objbase::COINIT_MULTITHREADED,
+    },
+    Interface,
+};
+
+use ::mirror_dto::{AudioBuffer, AudioFormat};
+
+use crate::util;
+
+// duration of the wasapi buffer in 100ns units
+const CLIENT_BUFFER_DURATION: i64 = 10_000_000;
+
+// length of the ring buffer that is shared with the host
+const RING_BUFFER_SECONDS: usize = 2;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Starts capturing the audio output of the default playback device via wasapi loopback.
+///
+/// The samples are written into a ring buffer which is published in the given `AudioBuffer`.
+pub fn start(audio: &'static mut AudioBuffer) {
+    thread::spawn(move || {
+        if let Err(err) = unsafe { capture_loop(audio) } {
+            error!("audio capture stopped: {}", err);
+        }
+    });
+}
+
+unsafe fn capture_loop(audio: &mut AudioBuffer) -> Result<(), String> {
+    check(
+        CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED),
+        "unable to initialize com",
+    )?;
+
+    let mut enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
+    check(
+        CoCreateInstance(
+            &CLSID_MMDeviceEnumerator,
+            ptr::null_mut(),
+            CLSCTX_ALL,
+            &IMMDeviceEnumerator::uuidof(),
+            &mut enumerator as *mut _ as *mut _,
+        ),
+        "unable to create device enumerator",
+    )?;
+
+    let mut device: *mut IMMDevice = ptr::null_mut();
+    let result = (*enumerator).GetDefaultAudioEndpoint(eRender, eConsole, &mut device);
+    (*enumerator).Release();
+    check(result, "unable to find default playback device")?;
+
+    let mut client: *mut IAudioClient = ptr::null_mut();
+    let result = (*device).Activate(
+        &IAudioClient::uuidof(),
+        CLSCTX_ALL,
+        ptr::null_mut(),
+        &mut client as *mut _ as *mut _,
+    );
+    (*device).Release();
+    check(result, "unable to activate audio client")?;
+
+    let mut mix_format: *mut WAVEFORMATEX = ptr::null_mut();
+    check(
+        (*client).GetMixFormat(&mut mix_format),
+        "unable to query mix format",
+    )?;
+    let format = sample_format(&*mix_format);
+    let sample_rate = (*mix_format).nSamplesPerSec;
+    let channels = (*mix_format).nChannels as u32;
+    let frame_size = (*mix_format).nBlockAlign as usize;
+
+    let result = (*client).Initialize(
+        AUDCLNT_SHAREMODE_SHARED,
+        AUDCLNT_STREAMFLAGS_LOOPBACK,
+        CLIENT_BUFFER_DURATION,
+        0,
+        mix_format,
+        ptr::null(),
+    );
+    CoTaskMemFree(mix_format as _);
+    check(result, "unable to initialize loopback capture")?;
+
+    if format == AudioFormat::None || frame_size != format.bytes_per_sample() * channels as usize {
+        return Err("unsupported mix format".to_string());
+    }
+
+    let mut capture_client: *mut IAudioCaptureClient = ptr::null_mut();
+    check(
+        (*client).GetService(
+            &IAudioCaptureClient::uuidof(),
+            &mut capture_client as *mut _ as *mut _,
+        ),
+        "unable to get capture client",
+    )?;
+    check((*client).Start(), "unable to start audio client")?;
+
+    // publish the ring buffer to the host
+    let mut ring_buffer = vec![0u8; sample_rate as usize * RING_BUFFER_SECONDS * frame_size];
+    ptr::write_volatile(
+        audio,
+        AudioBuffer {
+            sample_rate,
+            channels,
+            format: format as u32,
+            capacity: ring_buffer.len() as u32,
+            write_cursor: 0,
+            timestamp: util::timestamp(),
+            buffer: ring_buffer.as_mut_ptr() as u64,
+        },
+    );
+    info!(
+        "audio capture started: {} Hz, {} channels, {:?}",
+        sample_rate, channels, format
+    );
+
+    let result = stream(
+        audio,
+        capture_client,
+        &mut ring_buffer,
+        frame_size,
+        sample_rate,
+    );
+
+    // the host must not read the ring buffer anymore once it is released
+    ptr::write_volatile(&mut audio.buffer, 0);
+    ptr::write_volatile(&mut audio.format, AudioFormat::None as u32);
+    drop(ring_buffer);
+    result
+}
+
+/// Copies the captured packets into the ring buffer until an error occurs
+unsafe fn stream(
+    audio: &mut AudioBuffer,
+    capture_client: *mut IAudioCaptureClient,
+    ring_buffer: &mut [u8],
+    frame_size: usize,
+    sample_rate: u32,
+) -> Result<(), String> {
+    let mut write_cursor = 0u64;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let mut packet_len = 0u32;
+        check(
+            (*capture_client).GetNextPacketSize(&mut packet_len),
+            "unable to query packet size",
+        )?;
+        while packet_len > 0 {
+            let mut data = ptr::null_mut();
+            let mut frames = 0u32;
+            let mut flags = 0u32;
+            let mut qpc_position = 0u64;
+            check(
+                (*capture_client).GetBuffer(
+                    &mut data,
+                    &mut frames,
+                    &mut flags,
+                    ptr::null_mut(),
+                    &mut qpc_position,
+                ),
+                "unable to read audio packet",
+            )?;
+
+            let len = frames as usize * frame_size;
+            if flags & AUDCLNT_BUFFERFLAGS_SILENT != 0 || data.is_null() {
+                write_ring_buffer(ring_buffer, write_cursor, None, len);
+            } else {
+                write_ring_buffer(
+                    ring_buffer,
+                    write_cursor,
+                    Some(slice::from_raw_parts(data, len)),
+                    len,
+                );
+            }
+            (*capture_client).ReleaseBuffer(frames);
+            write_cursor += len as u64;
+
+            // the qpc position is in 100ns units and refers to the first frame of the packet
+            let timestamp = qpc_position / 10 + frames as u64 * 1_000_000 / sample_rate as u64;
+            ptr::write_volatile(&mut audio.timestamp, timestamp);
+            ptr::write_volatile(&mut audio.write_cursor, write_cursor);
+
+            check(
+                (*capture_client).GetNextPacketSize(&mut packet_len),
+                "unable to query packet size",
+            )?;
+        }
+    }
+}
+
+/// Writes `len` bytes at the given cursor into the ring buffer, `None` writes silence
+fn write_ring_buffer(ring_buffer: &mut [u8], cursor: u64, data: Option<&[u8]>, len: usize) {
+    let capacity = ring_buffer.len();
+    let mut offset = (cursor % capacity as u64) as usize;
+    let mut written = 0;
+    while written < len {
+        let chunk_len = (len - written).min(capacity - offset);
+        let target = &mut ring_buffer[offset..offset + chunk_len];
+        match data {
+            Some(data) => target.copy_from_slice(&data[written..written + chunk_len]),
+            None => target.fill(0),
+        }
+        written += chunk_len;
+        offset = (offset + chunk_len) % capacity;
+    }
+}
+
+/// Maps the wasapi mix format to the sample format of the ring buffer
+unsafe fn sample_format(format: &WAVEFORMATEX) -> AudioFormat {
+    let is_float = match format.wFormatTag {
+        WAVE_FORMAT_IEEE_FLOAT => true,
+        WAVE_FORMAT_PCM => false,
+        WAVE_FORMAT_EXTENSIBLE => {
+            let extensible = &*(format as *const _ as *const WAVEFORMATEXTENSIBLE);
+            IsEqualGUID(&extensible.SubFormat, &KSDATAFORMAT_SUBTYPE_IEEE_FLOAT)
+        }
+        _ => return AudioFormat::None,
+    };
+
+    match (is_float, format.wBitsPerSample) {
+        (true, 32) => AudioFormat::F32,
+        (false, 16) => AudioFormat::S16,
+        _ => AudioFormat::None,
+    }
+}
+
+fn check(result: i32, message: &str) -> Result<(), String> {
+    if FAILED(result) {
+        Err(format!("{} (hresult {:#x})", message, result))
+    } else {
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use ::std::slice;
 
-use ::mirror_dto::{CVec, TextureMode};
+use ::mirror_dto::TextureMode;
 
 #[derive(Clone, PartialEq)]
 pub enum CaptureMode {
@@ -111,7 +111,7 @@ impl<'a> Frame<'a> {
         }
     }
 
-    pub unsafe fn copy_frame(&self, to: &mut CVec<u8>) {
+    pub unsafe fn copy_frame(&self, to: &mut [u8]) {
         match self {
             Frame::DXGI((buffer, _)) => {
                 to.copy_from_slice(slice::from_raw_parts(
@@ -0,0 +1,194 @@
+use ::std::{
+    ptr, slice,
+    time::{Duration, Instant},
+};
+
+use ::log::warn;
+
+use ::winapi::{
+    shared::windef::HWND,
+    um::{
+        winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+        winuser::{
+            CloseClipboard, CreateWindowExA, DestroyWindow, EmptyClipboard, GetClipboardData,
+            GetClipboardSequenceNumber, OpenClipboard, SetClipboardData, CF_UNICODETEXT,
+            HWND_MESSAGE,
+        },
+    },
+};
+
+use ::mirror_dto::{ClipboardArea, CLIPBOARD_CAPACITY, CLIPBOARD_MIME_TEXT};
+
+// interval in which the clipboard of the guest is polled for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Mirrors the windows clipboard into the clipboard areas of the global buffer
+pub struct ClipboardSync {
+    // message-only window that owns the clipboard contents set by the host
+    window: HWND,
+
+    guest_buffer: Vec<u8>,
+    host_buffer: Vec<u8>,
+
+    clipboard_sequence: u32,
+    host_sequence: u32,
+    last_poll: Instant,
+}
+
+impl ClipboardSync {
+    /// Allocates the payload buffers and publishes them in the given clipboard areas
+    pub fn new(guest_area: &mut ClipboardArea, host_area: &mut ClipboardArea) -> Self {
+        let mut guest_buffer = vec![0u8; CLIPBOARD_CAPACITY];
+        let mut host_buffer = vec![0u8; CLIPBOARD_CAPACITY];
+        *guest_area = ClipboardArea::new(guest_buffer.as_mut_ptr() as u64);
+        *host_area = ClipboardArea::new(host_buffer.as_mut_ptr() as u64);
+
+        // a clipboard owner is required for SetClipboardData to succeed
+        let window = unsafe {
+            CreateWindowExA(
+                0,
+                b"STATIC\0".as_ptr() as _,
+                b"mirror-guest clipboard\0".as_ptr() as _,
+                0,
+                0,
+                0,
+                0,
+                0,
+                HWND_MESSAGE,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if window.is_null() {
+            warn!("unable to create clipboard window");
+        }
+
+        Self {
+            window,
+
+            guest_buffer,
+            host_buffer,
+
+            clipboard_sequence: 0,
+            host_sequence: 0,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub unsafe fn update(&mut self, guest_area: &mut ClipboardArea, host_area: &ClipboardArea) {
+        // host -> guest
+        let host_sequence = ptr::read_volatile(&host_area.sequence);
+        if host_sequence != self.host_sequence {
+            self.host_sequence = host_sequence;
+
+            let len = ptr::read_volatile(&host_area.len) as usize;
+            if len > CLIPBOARD_CAPACITY {
+                warn!("clipboard contents from host are too large");
+            } else if host_area.mime() == CLIPBOARD_MIME_TEXT {
+                // the buffer is written by the host
+                let data = (0..len)
+                    .map(|i| ptr::read_volatile(self.host_buffer.as_ptr().add(i)))
+                    .collect::<Vec<_>>();
+                if set_text(self.window, &String::from_utf8_lossy(&data)).is_err() {
+                    warn!("unable to set clipboard contents");
+                }
+            }
+
+            // do not publish our own update back to the host
+            self.clipboard_sequence = GetClipboardSequenceNumber();
+        }
+
+        // guest -> host
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+
+        let clipboard_sequence = GetClipboardSequenceNumber();
+        if clipboard_sequence == self.clipboard_sequence {
+            return;
+        }
+        self.clipboard_sequence = clipboard_sequence;
+
+        if let Some(text) = get_text(self.window) {
+            if text.len() > CLIPBOARD_CAPACITY {
+                warn!("clipboard contents are too large to be sent to the host");
+                return;
+            }
+
+            self.guest_buffer[..text.len()].copy_from_slice(text.as_bytes());
+            ptr::write_volatile(&mut guest_area.len, text.len() as u32);
+            ptr::write_volatile(
+                &mut guest_area.mime,
+                ClipboardArea::encode_mime(CLIPBOARD_MIME_TEXT),
+            );
+
+            // publish the contents after they have been written
+            let sequence = ptr::read_volatile(&guest_area.sequence).wrapping_add(1);
+            ptr::write_volatile(&mut guest_area.sequence, sequence);
+        }
+    }
+}
+
+impl Drop for ClipboardSync {
+    fn drop(&mut self) {
+        if !self.window.is_null() {
+            unsafe { DestroyWindow(self.window) };
+        }
+    }
+}
+
+unsafe fn get_text(window: HWND) -> Option<String> {
+    if OpenClipboard(window) == 0 {
+        return None;
+    }
+
+    let mut result = None;
+    let handle = GetClipboardData(CF_UNICODETEXT);
+    if !handle.is_null() {
+        let data = GlobalLock(handle) as *const u16;
+        if !data.is_null() {
+            let mut len = 0;
+            while *data.add(len) != 0 {
+                len += 1;
+            }
+            result = Some(String::from_utf16_lossy(slice::from_raw_parts(data, len)));
+            GlobalUnlock(handle);
+        }
+    }
+
+    CloseClipboard();
+    result
+}
+
+unsafe fn set_text(window: HWND, text: &str) -> Result<(), ()> {
+    let wide = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+
+    let handle = GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2);
+    if handle.is_null() {
+        return Err(());
+    }
+    let data = GlobalLock(handle) as *mut u16;
+    if data.is_null() {
+        GlobalFree(handle);
+        return Err(());
+    }
+    ptr::copy_nonoverlapping(wide.as_ptr(), data, wide.len());
+    GlobalUnlock(handle);
+
+    if OpenClipboard(window) == 0 {
+        GlobalFree(handle);
+        return Err(());
+    }
+    EmptyClipboard();
+    // the system owns the memory after the data has been set successfully
+    let result = if SetClipboardData(CF_UNICODETEXT, handle).is_null() {
+        GlobalFree(handle);
+        Err(())
+    } else {
+        Ok(())
+    };
+    CloseClipboard();
+    result
+}
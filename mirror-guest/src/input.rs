@@ -0,0 +1,152 @@
+use ::std::{mem, ptr};
+
+use ::log::warn;
+
+use ::winapi::um::winuser::{
+    GetSystemMetrics, SendInput, INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT,
+    KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, MOUSEEVENTF_ABSOLUTE,
+    MOUSEEVENTF_HWHEEL, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN,
+    MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
+    MOUSEEVENTF_WHEEL, MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSEINPUT, SM_CXSCREEN, SM_CYSCREEN,
+    XBUTTON1, XBUTTON2,
+};
+
+use ::mirror_dto::{InputEvent, InputEventType, InputQueue, MouseButton, INPUT_QUEUE_LEN};
+
+/// Consumes all pending input events from the queue and injects them via SendInput
+pub unsafe fn process_queue(queue: &mut InputQueue) {
+    let write_index = ptr::read_volatile(&queue.write_index);
+    let mut read_index = ptr::read_volatile(&queue.read_index);
+
+    if write_index.wrapping_sub(read_index) as usize > INPUT_QUEUE_LEN {
+        warn!("input queue overflow, dropping pending events");
+        ptr::write_volatile(&mut queue.read_index, write_index);
+        return;
+    }
+
+    while read_index != write_index {
+        let event = ptr::read_volatile(&queue.events[read_index as usize % INPUT_QUEUE_LEN]);
+        send_event(&event);
+        read_index = read_index.wrapping_add(1);
+    }
+
+    ptr::write_volatile(&mut queue.read_index, read_index);
+}
+
+unsafe fn send_event(event: &InputEvent) {
+    let mut input: INPUT = mem::zeroed();
+
+    if event.ty == InputEventType::MouseMove as u32 {
+        let (dx, dy) = to_absolute(event.x, event.y);
+        input.type_ = INPUT_MOUSE;
+        *input.u.mi_mut() = mouse_input(dx, dy, 0, MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE);
+    } else if event.ty == InputEventType::MouseButton as u32 {
+        let pressed = event.flags & InputEvent::FLAG_PRESSED != 0;
+        let (flags, data) = match event.code {
+            c if c == MouseButton::Left as u32 => (
+                if pressed {
+                    MOUSEEVENTF_LEFTDOWN
+                } else {
+                    MOUSEEVENTF_LEFTUP
+                },
+                0,
+            ),
+            c if c == MouseButton::Right as u32 => (
+                if pressed {
+                    MOUSEEVENTF_RIGHTDOWN
+                } else {
+                    MOUSEEVENTF_RIGHTUP
+                },
+                0,
+            ),
+            c if c == MouseButton::Middle as u32 => (
+                if pressed {
+                    MOUSEEVENTF_MIDDLEDOWN
+                } else {
+                    MOUSEEVENTF_MIDDLEUP
+                },
+                0,
+            ),
+            c if c == MouseButton::X1 as u32 || c == MouseButton::X2 as u32 => (
+                if pressed {
+                    MOUSEEVENTF_XDOWN
+                } else {
+                    MOUSEEVENTF_XUP
+                },
+                if c == MouseButton::X1 as u32 {
+                    XBUTTON1
+                } else {
+                    XBUTTON2
+                },
+            ),
+            _ => return,
+        };
+
+        // move the cursor to the position of the click first
+        let (dx, dy) = to_absolute(event.x, event.y);
+        input.type_ = INPUT_MOUSE;
+        *input.u.mi_mut() = mouse_input(
+            dx,
+            dy,
+            data as u32,
+            flags | MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE,
+        );
+    } else if event.ty == InputEventType::MouseWheel as u32 {
+        if event.y != 0 {
+            let mut wheel: INPUT = mem::zeroed();
+            wheel.type_ = INPUT_MOUSE;
+            *wheel.u.mi_mut() = mouse_input(0, 0, event.y as u32, MOUSEEVENTF_WHEEL);
+            SendInput(1, &mut wheel, mem::size_of::<INPUT>() as i32);
+        }
+        if event.x == 0 {
+            return;
+        }
+        input.type_ = INPUT_MOUSE;
+        *input.u.mi_mut() = mouse_input(0, 0, event.x as u32, MOUSEEVENTF_HWHEEL);
+    } else if event.ty == InputEventType::KeyDown as u32 || event.ty == InputEventType::KeyUp as u32
+    {
+        let mut flags = KEYEVENTF_SCANCODE;
+        if event.flags & InputEvent::FLAG_EXTENDED != 0 {
+            flags |= KEYEVENTF_EXTENDEDKEY;
+        }
+        if event.ty == InputEventType::KeyUp as u32 {
+            flags |= KEYEVENTF_KEYUP;
+        }
+
+        input.type_ = INPUT_KEYBOARD;
+        *input.u.ki_mut() = KEYBDINPUT {
+            wVk: 0,
+            wScan: event.code as u16,
+            dwFlags: flags,
+            time: 0,
+            dwExtraInfo: 0,
+        };
+    } else {
+        return;
+    }
+
+    if SendInput(1, &mut input, mem::size_of::<INPUT>() as i32) != 1 {
+        warn!("unable to inject input event: {:?}", event);
+    }
+}
+
+fn mouse_input(dx: i32, dy: i32, data: u32, flags: u32) -> MOUSEINPUT {
+    MOUSEINPUT {
+        dx,
+        dy,
+        mouseData: data,
+        dwFlags: flags,
+        time: 0,
+        dwExtraInfo: 0,
+    }
+}
+
+/// Converts screen coordinates of the primary monitor into the normalized range (0..65535) used by SendInput
+unsafe fn to_absolute(x: i32, y: i32) -> (i32, i32) {
+    let width = GetSystemMetrics(SM_CXSCREEN).max(2);
+    let height = GetSystemMetrics(SM_CYSCREEN).max(2);
+    (
+        (x.clamp(0, width - 1) as i64 * 65535 / (width - 1) as i64) as i32,
+        (y.clamp(0, height - 1) as i64 * 65535 / (height - 1) as i64) as i32,
+    )
+}
@@ -5,18 +5,28 @@ use ::std::{
     time::{Duration, Instant},
 };
 
-use ::log::{info, LevelFilter};
+use ::log::{error, info, LevelFilter};
 
 use ::trayicon::{MenuBuilder, TrayIconBuilder};
 use ::winapi::um::winuser;
 
 use ::mirror_dto::GlobalBufferGuest;
 
+mod audio;
+
 mod capture;
 use capture::{Capture, CaptureMode};
 
+mod clipboard;
+use clipboard::ClipboardSync;
+
 mod cursor;
 
+mod input;
+
+mod physical;
+use physical::PhysicalTransport;
+
 mod util;
 
 static mut GLOBAL_BUFFER: Option<GlobalBufferGuest> = None;
@@ -60,9 +70,27 @@ fn main() {
     let mut capture = Capture::new().expect("unable to start capture");
     let mut resolution = capture.resolution();
     info!("resolution: {:?}", resolution);
-    unsafe {
+    let mut clipboard_sync = unsafe {
         GLOBAL_BUFFER = Some(GlobalBufferGuest::new(resolution, 0));
-    }
+        let global_buffer = GLOBAL_BUFFER.as_mut().unwrap();
+
+        // audio is captured in a separate thread and streamed through its own ring buffer
+        audio::start(&mut global_buffer.audio);
+
+        ClipboardSync::new(
+            &mut global_buffer.clipboard_guest,
+            &mut global_buffer.clipboard_host,
+        )
+    };
+
+    // additionally publish frames through pinned physical pages so the host can read them without os introspection
+    let mut physical_transport = match PhysicalTransport::new(resolution) {
+        Ok(physical_transport) => Some(physical_transport),
+        Err(err) => {
+            error!("unable to create physical transport: {}", err);
+            None
+        }
+    };
 
     // main application loop
     let mut last_capture_mode_check = Instant::now();
@@ -81,9 +109,18 @@ fn main() {
         // check if the frame has been read and we need to generate a new one
         unsafe {
             if let Some(global_buffer) = &mut GLOBAL_BUFFER {
+                // inject pending input events from the host
+                input::process_queue(&mut global_buffer.input);
+
+                // exchange clipboard contents with the host
+                clipboard_sync.update(
+                    &mut global_buffer.clipboard_guest,
+                    &global_buffer.clipboard_host,
+                );
+
                 if last_capture_mode_check.elapsed() >= Duration::from_secs(1) {
                     // detect fullscreen window once per second
-                    if global_buffer.config.obs {
+                    if global_buffer.config.obs() {
                         if let Some(window_name) = util::find_fullscreen_window() {
                             if capture.mode() != CaptureMode::OBS(window_name.clone()) {
                                 println!(
@@ -93,13 +130,13 @@ fn main() {
                                 capture.set_mode(CaptureMode::OBS(window_name)).ok();
                             }
                         } else {
-                            if global_buffer.config.dxgi && capture.mode() != CaptureMode::DXGI {
+                            if global_buffer.config.dxgi() && capture.mode() != CaptureMode::DXGI {
                                 println!("fullscreen window closed, trying to switch to dxgi");
                                 capture.set_mode(CaptureMode::DXGI).ok();
                             }
                         }
                     } else {
-                        if global_buffer.config.dxgi && capture.mode() != CaptureMode::DXGI {
+                        if global_buffer.config.dxgi() && capture.mode() != CaptureMode::DXGI {
                             println!("fullscreen window closed, trying to switch to dxgi");
                             capture.set_mode(CaptureMode::DXGI).ok();
                         }
@@ -111,12 +148,29 @@ fn main() {
                     last_capture_mode_check = Instant::now();
                 }
 
+                // the host acknowledges frames either through the global buffer or the physical descriptor
+                let physical_read = match &mut physical_transport {
+                    Some(physical_transport) => {
+                        let descriptor = physical_transport.descriptor();
+                        let frame_read_counter =
+                            std::ptr::read_volatile(&descriptor.frame_read_counter);
+                        if frame_read_counter == frame_counter {
+                            global_buffer.config = descriptor.config.clone();
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    None => false,
+                };
+
                 // generate new frame first then check if we can update it
                 let captured_frame = capture.capture_frame();
+                let captured_timestamp = util::timestamp();
                 let update_frame = {
                     let frame_read_counter =
                         std::ptr::read_volatile(&global_buffer.frame_read_counter);
-                    frame_read_counter == global_buffer.frame_counter
+                    frame_read_counter == global_buffer.frame_counter || physical_read
                 };
                 if captured_frame.is_ok() && update_frame {
                     let frame = captured_frame.unwrap();
@@ -153,7 +207,30 @@ fn main() {
                         std::ptr::write_volatile(&mut global_buffer.cursor, cursor);
                     }
 
+                    if let Some(transport) = &mut physical_transport {
+                        if let Err(err) = transport.resize(frame_buffer_len) {
+                            error!("unable to resize physical transport: {}", err);
+                            physical_transport = None;
+                        } else {
+                            frame.copy_frame(transport.frame_buffer());
+
+                            let descriptor = transport.descriptor();
+                            std::ptr::write_volatile(&mut descriptor.width, resolution.0);
+                            std::ptr::write_volatile(&mut descriptor.height, resolution.1);
+                            std::ptr::write_volatile(
+                                &mut descriptor.frame_texmode,
+                                frame.texture_mode() as u8,
+                            );
+                            std::ptr::write_volatile(&mut descriptor.cursor, global_buffer.cursor);
+                            std::ptr::write_volatile(&mut descriptor.frame_counter, frame_counter);
+                        }
+                    }
+
                     // update frame counter
+                    std::ptr::write_volatile(
+                        &mut global_buffer.frame_timestamp,
+                        captured_timestamp,
+                    );
                     std::ptr::write_volatile(&mut global_buffer.frame_counter, frame_counter);
                 } else {
                     // forcefully update metadata to prevent swap-outs
@@ -167,6 +244,10 @@ fn main() {
 
                     if let Ok(cursor) = cursor::get_state() {
                         std::ptr::write_volatile(&mut global_buffer.cursor, cursor);
+
+                        if let Some(transport) = &mut physical_transport {
+                            std::ptr::write_volatile(&mut transport.descriptor().cursor, cursor);
+                        }
                     }
 
                     std::ptr::write_volatile(&mut global_buffer.frame_counter, frame_counter);
@@ -0,0 +1,249 @@
+use ::std::{ffi::CString, mem::size_of, ptr, slice};
+
+use ::log::info;
+
+use ::winapi::{
+    shared::{basetsd::ULONG_PTR, minwindef::FALSE, winerror::ERROR_NOT_ALL_ASSIGNED},
+    um::{
+        errhandlingapi::GetLastError,
+        handleapi::CloseHandle,
+        memoryapi::{
+            AllocateUserPhysicalPages, FreeUserPhysicalPages, MapUserPhysicalPages, VirtualAlloc,
+            VirtualFree,
+        },
+        processthreadsapi::{GetCurrentProcess, OpenProcessToken},
+        securitybaseapi::AdjustTokenPrivileges,
+        winbase::LookupPrivilegeValueA,
+        winnt::{
+            HANDLE, MEM_PHYSICAL, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE, SE_PRIVILEGE_ENABLED,
+            TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+        },
+    },
+};
+
+use ::mirror_dto::{PhysicalDescriptor, PHYSICAL_PAGE_LIST_LEN, PHYSICAL_PAGE_SIZE};
+
+/// A block of pinned memory allocated via AWE (Address Windowing Extensions).
+///
+/// AWE pages are never paged out and the page frame numbers are returned to user-mode,
+/// which allows publishing them to the host.
+struct PinnedPages {
+    ptr: *mut u8,
+    pfns: Vec<ULONG_PTR>,
+}
+
+impl PinnedPages {
+    pub fn new(len: usize) -> Result<Self, String> {
+        let mut page_count = (len + PHYSICAL_PAGE_SIZE as usize - 1) / PHYSICAL_PAGE_SIZE as usize;
+        let mut pfns = vec![0 as ULONG_PTR; page_count];
+
+        if unsafe {
+            AllocateUserPhysicalPages(GetCurrentProcess(), &mut page_count, pfns.as_mut_ptr())
+        } == FALSE
+        {
+            return Err("unable to allocate physical pages".to_string());
+        }
+        if page_count != pfns.len() {
+            // only a part of the pages could be allocated
+            unsafe {
+                FreeUserPhysicalPages(GetCurrentProcess(), &mut page_count, pfns.as_mut_ptr())
+            };
+            return Err("unable to allocate enough physical pages".to_string());
+        }
+
+        let ptr = unsafe {
+            VirtualAlloc(
+                ptr::null_mut(),
+                page_count * PHYSICAL_PAGE_SIZE as usize,
+                MEM_RESERVE | MEM_PHYSICAL,
+                PAGE_READWRITE,
+            )
+        } as *mut u8;
+        if ptr.is_null() {
+            unsafe {
+                FreeUserPhysicalPages(GetCurrentProcess(), &mut page_count, pfns.as_mut_ptr())
+            };
+            return Err("unable to reserve memory for physical pages".to_string());
+        }
+
+        if unsafe { MapUserPhysicalPages(ptr as _, page_count, pfns.as_mut_ptr()) } == FALSE {
+            unsafe {
+                VirtualFree(ptr as _, 0, MEM_RELEASE);
+                FreeUserPhysicalPages(GetCurrentProcess(), &mut page_count, pfns.as_mut_ptr());
+            }
+            return Err("unable to map physical pages".to_string());
+        }
+
+        // pages are not guaranteed to be zeroed
+        unsafe { ptr::write_bytes(ptr, 0, page_count * PHYSICAL_PAGE_SIZE as usize) };
+
+        Ok(Self { ptr, pfns })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pfns.len() * PHYSICAL_PAGE_SIZE as usize
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len()) }
+    }
+}
+
+impl Drop for PinnedPages {
+    fn drop(&mut self) {
+        let mut page_count = self.pfns.len();
+        unsafe {
+            MapUserPhysicalPages(self.ptr as _, page_count, ptr::null_mut());
+            VirtualFree(self.ptr as _, 0, MEM_RELEASE);
+            FreeUserPhysicalPages(GetCurrentProcess(), &mut page_count, self.pfns.as_mut_ptr());
+        }
+    }
+}
+
+/// Publishes the frame buffer through a `PhysicalDescriptor` so the host can read it
+/// without any os introspection.
+pub struct PhysicalTransport {
+    descriptor: PinnedPages,
+    page_lists: Option<PinnedPages>,
+    frame_buffer: Option<PinnedPages>,
+}
+
+impl PhysicalTransport {
+    pub fn new(resolution: (u64, u64)) -> Result<Self, String> {
+        enable_lock_memory_privilege()?;
+
+        let mut descriptor = PinnedPages::new(PHYSICAL_PAGE_SIZE as usize)?;
+        unsafe {
+            ptr::write_volatile(
+                descriptor.as_mut_slice().as_mut_ptr() as *mut PhysicalDescriptor,
+                PhysicalDescriptor::new(resolution),
+            )
+        };
+
+        let mut transport = Self {
+            descriptor,
+            page_lists: None,
+            frame_buffer: None,
+        };
+        transport.resize(resolution.0 as usize * resolution.1 as usize * 4)?;
+        Ok(transport)
+    }
+
+    pub fn descriptor(&mut self) -> &mut PhysicalDescriptor {
+        unsafe { &mut *(self.descriptor.as_mut_slice().as_mut_ptr() as *mut PhysicalDescriptor) }
+    }
+
+    pub fn frame_buffer(&mut self) -> &mut [u8] {
+        let frame_buffer_len = self.descriptor().frame_buffer_len as usize;
+        match &mut self.frame_buffer {
+            Some(frame_buffer) => &mut frame_buffer.as_mut_slice()[..frame_buffer_len],
+            None => &mut [],
+        }
+    }
+
+    /// Re-allocates the frame buffer and publishes the new page lists
+    pub fn resize(&mut self, frame_buffer_len: usize) -> Result<(), String> {
+        if self.descriptor().frame_buffer_len as usize == frame_buffer_len {
+            return Ok(());
+        }
+
+        // invalidate the current frame buffer before releasing it
+        unsafe {
+            let descriptor = self.descriptor();
+            ptr::write_volatile(&mut descriptor.frame_buffer_len, 0);
+            ptr::write_volatile(&mut descriptor.frame_page_count, 0);
+            ptr::write_volatile(&mut descriptor.page_list_count, 0);
+        }
+        self.frame_buffer = None;
+        self.page_lists = None;
+
+        let frame_buffer = PinnedPages::new(frame_buffer_len)?;
+        let entries_per_page = PHYSICAL_PAGE_SIZE as usize / size_of::<u64>();
+        let page_list_count = (frame_buffer.pfns.len() + entries_per_page - 1) / entries_per_page;
+        if page_list_count > PHYSICAL_PAGE_LIST_LEN {
+            return Err("frame buffer is too large for the physical transport".to_string());
+        }
+
+        let mut page_lists = PinnedPages::new(page_list_count * PHYSICAL_PAGE_SIZE as usize)?;
+        let entries = unsafe {
+            slice::from_raw_parts_mut(
+                page_lists.as_mut_slice().as_mut_ptr() as *mut u64,
+                page_list_count * entries_per_page,
+            )
+        };
+        for (entry, pfn) in entries.iter_mut().zip(frame_buffer.pfns.iter()) {
+            *entry = *pfn as u64;
+        }
+
+        let frame_page_count = frame_buffer.pfns.len() as u64;
+        let page_list_pfns = page_lists.pfns.clone();
+        self.frame_buffer = Some(frame_buffer);
+        self.page_lists = Some(page_lists);
+
+        unsafe {
+            let descriptor = self.descriptor();
+            for (entry, pfn) in descriptor.page_list.iter_mut().zip(page_list_pfns.iter()) {
+                ptr::write_volatile(entry, *pfn as u64);
+            }
+            ptr::write_volatile(&mut descriptor.page_list_count, page_list_count as u64);
+            ptr::write_volatile(&mut descriptor.frame_page_count, frame_page_count);
+            ptr::write_volatile(&mut descriptor.frame_buffer_len, frame_buffer_len as u64);
+            // the host re-reads the page lists even if the new buffer has the same size
+            let generation = ptr::read_volatile(&descriptor.generation);
+            ptr::write_volatile(&mut descriptor.generation, generation.wrapping_add(1));
+        }
+
+        info!(
+            "physical transport published {} frame pages",
+            frame_page_count
+        );
+        Ok(())
+    }
+}
+
+/// AWE allocations require the SeLockMemoryPrivilege to be enabled for the current process
+fn enable_lock_memory_privilege() -> Result<(), String> {
+    let mut token: HANDLE = ptr::null_mut();
+    if unsafe {
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        )
+    } == FALSE
+    {
+        return Err("unable to open process token".to_string());
+    }
+
+    let privilege_name = CString::new("SeLockMemoryPrivilege").unwrap();
+    let mut privileges: TOKEN_PRIVILEGES = unsafe { std::mem::zeroed() };
+    privileges.PrivilegeCount = 1;
+    privileges.Privileges[0].Attributes = SE_PRIVILEGE_ENABLED;
+
+    let result = unsafe {
+        if LookupPrivilegeValueA(
+            ptr::null(),
+            privilege_name.as_ptr(),
+            &mut privileges.Privileges[0].Luid,
+        ) == FALSE
+        {
+            Err("unable to lookup SeLockMemoryPrivilege".to_string())
+        } else if AdjustTokenPrivileges(
+            token,
+            FALSE,
+            &mut privileges,
+            0,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        ) == FALSE
+            || GetLastError() == ERROR_NOT_ALL_ASSIGNED
+        {
+            Err("unable to enable SeLockMemoryPrivilege".to_string())
+        } else {
+            Ok(())
+        }
+    };
+
+    unsafe { CloseHandle(token) };
+    result
+}
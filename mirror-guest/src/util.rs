@@ -8,9 +8,10 @@ use ::log::{error, info};
 use ::winapi::um::{
     libloaderapi::{GetModuleHandleA, GetProcAddress},
     processthreadsapi::{GetCurrentProcess, SetPriorityClass},
+    profileapi::{QueryPerformanceCounter, QueryPerformanceFrequency},
     shellapi::{SHQueryUserNotificationState, QUNS_BUSY, QUNS_RUNNING_D3D_FULL_SCREEN},
     winbase::REALTIME_PRIORITY_CLASS,
-    winnt::HANDLE,
+    winnt::{HANDLE, LARGE_INTEGER},
     winuser::{GetForegroundWindow, GetWindowTextW},
 };
 
@@ -103,3 +104,17 @@ pub fn find_fullscreen_window() -> Option<String> {
     }
     */
 }
+
+/// Returns the current time in microseconds based on the performance counter.
+///
+/// This clock is shared by frame and audio timestamps so the host can synchronize them.
+pub fn timestamp() -> u64 {
+    let mut counter: LARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let mut frequency: LARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let (counter, frequency) = unsafe {
+        QueryPerformanceCounter(&mut counter);
+        QueryPerformanceFrequency(&mut frequency);
+        (*counter.QuadPart() as u128, *frequency.QuadPart() as u128)
+    };
+    (counter * 1_000_000 / frequency.max(1)) as u64
+}
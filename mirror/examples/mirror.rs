@@ -43,26 +43,9 @@ fn main() -> Result<()> {
     )
     .unwrap();
 
-    // parse args
-    let conn_iter = matches
-        .indices_of("connector")
-        .zip(matches.get_many::<String>("connector"))
-        .map(|(a, b)| a.zip(b.map(String::as_str)))
-        .into_iter()
-        .flatten();
-
-    let os_iter = matches
-        .indices_of("os")
-        .zip(matches.get_many::<String>("os"))
-        .map(|(a, b)| a.zip(b.map(String::as_str)))
-        .into_iter()
-        .flatten();
-
-    let chain = OsChain::new(conn_iter, os_iter)?;
-
-    // create memflow inventory + os
+    // create memflow inventory + os from the chain arguments
     let inventory = Inventory::scan();
-    let os = inventory.builder().os_chain(chain).build()?;
+    let os = ChainArgs::from_matches(&matches).build_os(&inventory)?;
 
     // initialize capture
     let mut capture = SequentialCapture::new(os);
@@ -0,0 +1,31 @@
+use ::std::time::Duration;
+
+use ::clap::{crate_authors, crate_version, Arg, Command};
+
+use ::mirror::prelude::v1::*;
+
+fn main() -> std::io::Result<()> {
+    let matches = Command::new("memflow-mirror-shm-reader")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .short('n')
+                .default_value("/memflow-mirror"),
+        )
+        .get_matches();
+
+    // attach to the shared memory of a running mirror instance (started with `--shm`)
+    let mut reader = ShmReader::open(matches.get_one::<String>("name").unwrap())?;
+
+    loop {
+        // wait for the next frame, frames that were published in the meantime are skipped
+        if let Some((frame, frame_counter)) = reader.next_frame(Duration::from_secs(1)) {
+            println!(
+                "frame {} read: size={}x{} cursor=({}, {})",
+                frame_counter, frame.width, frame.height, frame.cursor.x, frame.cursor.y
+            );
+        }
+    }
+}
@@ -1,55 +1,287 @@
 use ::log::warn;
+use ::std::{sync::Arc, time::Duration};
 
-use ::egui_dock::{DockArea, DockState, Style};
+use ::egui_dock::{DockArea, DockState, NodeIndex, Style};
 use ::egui_notify::Toasts;
+use ::epaint::Color32;
+
+mod chain;
+
+mod clipboard;
+
+mod detached;
+use detached::{DetachedState, DetachedTab};
 
 mod frame_history;
 use frame_history::FrameHistory;
 
+mod input;
+
+mod inspector;
+
+mod keybindings;
+use keybindings::KeyBindings;
+
+mod layout;
+use layout::Layout;
+
+mod log_console;
+use log_console::LogConsole;
+pub use log_console::{LogBuffer, LogSink};
+
 mod tab_viewer;
-use tab_viewer::{CaptureTab, TabViewer};
 
-use crate::MirrorConfig;
+mod viewer;
+pub use tab_viewer::{connect_last, connect_profile};
+use tab_viewer::{CaptureTab, Tab, TabViewer};
+
+#[cfg(feature = "preview-server")]
+use crate::PreviewServerConfig;
+#[cfg(all(unix, feature = "shared-memory"))]
+use crate::ShmPublisherConfig;
+#[cfg(feature = "vnc-server")]
+use crate::VncServerConfig;
+use crate::{
+    config::{format_shortcut, Action, Profile},
+    Capture, MirrorConfig, RecorderConfig,
+};
+
+/// Options that are passed to the app on startup
+#[derive(Default)]
+pub struct StartupOptions {
+    /// Capture of the first tab, the last connection is restored if this is not set
+    pub capture: Option<Box<dyn Capture>>,
+    /// Profile the first tab connects with if no capture is set
+    pub profile: Option<Profile>,
+    /// Records the first connection
+    pub recording: Option<RecorderConfig>,
+    /// Serves the frames of the first connection over http
+    #[cfg(feature = "preview-server")]
+    pub preview_server: Option<PreviewServerConfig>,
+    /// Serves the frames of the first connection to vnc clients
+    #[cfg(feature = "vnc-server")]
+    pub vnc_server: Option<VncServerConfig>,
+    /// Publishes the frames of the first connection in shared memory
+    #[cfg(all(unix, feature = "shared-memory"))]
+    pub shm_publisher: Option<ShmPublisherConfig>,
+    /// Records captured by the log sink, shown in the log tab
+    pub log_buffer: Option<Arc<LogBuffer>>,
+    /// Config with the overrides of the command line, it is loaded from disk if this is not set
+    pub config: Option<MirrorConfig>,
+}
 
 pub struct MirrorApp {
-    _toasts: Toasts,
+    toasts: Toasts,
     frame_history: FrameHistory,
-    tree: DockState<CaptureTab>,
+    log_console: LogConsole,
+    tree: DockState<Tab>,
     tree_len: usize,
+    detached: Vec<DetachedTab>,
+    restore_layout: bool,
 
     config: MirrorConfig,
+    keybindings: KeyBindings,
 
     window_stats: bool,
     window_settings: bool,
 }
 
 impl MirrorApp {
-    pub fn new(_: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(_: &eframe::CreationContext<'_>, options: StartupOptions) -> Self {
         // This is also where you can customized the look at feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
-        let config = MirrorConfig::load_or_default();
-        let capture_tab = match CaptureTab::connect(0, &config) {
-            Ok(capture_tab) => capture_tab,
-            Err(_) => {
-                //config.connect_on_startup = false;
-                //config.save().ok();
-                CaptureTab::new(0, &config)
+        let config = options.config.unwrap_or_else(MirrorConfig::load_or_default);
+        // an explicit capture or profile replaces the last session, its layout is kept for the next start
+        let restore_layout = options.capture.is_none() && options.profile.is_none();
+        let (mut tree, tree_len, detached) = match (options.capture, options.profile) {
+            (Some(capture), _) => (
+                DockState::new(vec![Tab::Capture(CaptureTab::with_capture(
+                    0, &config, capture,
+                ))]),
+                1,
+                Vec::new(),
+            ),
+            (None, Some(profile)) => {
+                let mut capture_tab = CaptureTab::new(0, &config);
+                capture_tab.apply_profile(&profile);
+                capture_tab.start_connect(&config);
+                (
+                    DockState::new(vec![Tab::Capture(capture_tab)]),
+                    1,
+                    Vec::new(),
+                )
             }
+            (None, None) => match Layout::load() {
+                // the tabs of the last session are restored and connect in the background
+                Ok(layout) => (
+                    layout
+                        .dock
+                        .map_tabs(|state| Tab::Capture(CaptureTab::from_state(state, &config))),
+                    layout.next_id(),
+                    layout
+                        .detached
+                        .iter()
+                        .map(|state| {
+                            DetachedTab::new(CaptureTab::from_state(state, &config), &config)
+                        })
+                        .collect(),
+                ),
+                Err(_) => {
+                    let mut capture_tab = CaptureTab::new(0, &config);
+                    // the last connection is restored in the background
+                    if config.connect_on_startup && !config.last_chain.is_empty() {
+                        capture_tab.start_connect(&config);
+                    }
+                    (
+                        DockState::new(vec![Tab::Capture(capture_tab)]),
+                        1,
+                        Vec::new(),
+                    )
+                }
+            },
         };
 
+        // the startup options are applied to the first tab
+        if let Some(capture_tab) = tree
+            .iter_all_tabs_mut()
+            .find_map(|(_, tab)| tab.capture_mut())
+        {
+            // recording starts as soon as the first connection is capturing
+            if let Some(recording) = options.recording {
+                capture_tab.start_recording(recording);
+            }
+
+            #[cfg(feature = "preview-server")]
+            if let Some(preview_server) = options.preview_server {
+                capture_tab.start_preview_server(preview_server);
+            }
+
+            #[cfg(feature = "vnc-server")]
+            if let Some(vnc_server) = options.vnc_server {
+                capture_tab.start_vnc_server(vnc_server);
+            }
+
+            #[cfg(all(unix, feature = "shared-memory"))]
+            if let Some(shm_publisher) = options.shm_publisher {
+                capture_tab.start_shm_publisher(shm_publisher);
+            }
+        }
+
+        let mut toasts = Toasts::default().with_anchor(egui_notify::Anchor::BottomRight);
+        if !config.issues.is_empty() {
+            toasts
+                .warning(format!(
+                    "{} problems were found in the config, see the settings for details",
+                    config.issues.len()
+                ))
+                .set_duration(Some(Duration::from_secs(10)));
+        }
+
         Self {
-            _toasts: Toasts::default().with_anchor(egui_notify::Anchor::BottomRight),
+            toasts,
             frame_history: FrameHistory::default(),
-            tree: DockState::new(vec![capture_tab]),
-            tree_len: 1,
+            log_console: LogConsole::new(options.log_buffer.unwrap_or_default()),
+            tree,
+            tree_len,
+            detached,
+            restore_layout,
 
             config,
+            keybindings: KeyBindings::default(),
 
             window_stats: false,
             window_settings: false,
         }
     }
+
+    /// Moves the tab out of the dock into its own window
+    fn detach_tab(&mut self, id: usize, fullscreen: bool) {
+        if let Some(tab) = self.remove_tab(id) {
+            let mut detached = DetachedTab::new(tab, &self.config);
+            if fullscreen {
+                detached.set_fullscreen(true);
+            }
+            self.detached.push(detached);
+        }
+    }
+
+    /// Moves a detached tab back into the dock or closes it, its placement is kept for the next time
+    fn close_detached(&mut self, index: usize, dock: bool) {
+        let detached = self.detached.remove(index);
+        self.config.set_viewport(detached.placement());
+        self.config.save().map_err(|err| warn!("{}", err)).ok();
+        if dock {
+            self.tree
+                .push_to_focused_leaf(Tab::Capture(detached.into_tab()));
+        }
+    }
+
+    fn add_tab(&mut self) {
+        self.tree
+            .push_to_focused_leaf(Tab::Capture(CaptureTab::new(self.tree_len, &self.config)));
+        self.tree_len += 1;
+    }
+
+    fn remove_tab(&mut self, id: usize) -> Option<CaptureTab> {
+        let index = self
+            .tree
+            .find_tab_from(|tab| tab.capture().map(CaptureTab::id) == Some(id))?;
+        match self.tree.remove_tab(index)? {
+            Tab::Capture(tab) => Some(tab),
+            Tab::Log => None,
+        }
+    }
+
+    fn focused_tab(&mut self) -> Option<usize> {
+        self.tree
+            .find_active_focused()
+            .and_then(|(_, tab)| tab.capture().map(CaptureTab::id))
+    }
+
+    /// Opens the log below the other tabs or closes it if it is already open
+    fn toggle_log(&mut self) {
+        if let Some(index) = self.tree.find_tab_from(|tab| matches!(tab, Tab::Log)) {
+            self.tree.remove_tab(index);
+        } else if self.tree.main_surface().is_empty() {
+            self.tree.push_to_first_leaf(Tab::Log);
+        } else {
+            self.tree
+                .main_surface_mut()
+                .split_below(NodeIndex::root(), 0.7, vec![Tab::Log]);
+        }
+    }
+
+    /// Runs the action of a keyboard shortcut, actions of a single tab apply to the focused tab
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::NewTab => self.add_tab(),
+            Action::CloseTab => {
+                if matches!(self.tree.find_active_focused(), Some((_, Tab::Log))) {
+                    self.toggle_log();
+                } else if let Some(id) = self.focused_tab() {
+                    self.remove_tab(id);
+                }
+            }
+            Action::Fullscreen => {
+                if let Some(id) = self.focused_tab() {
+                    self.detach_tab(id, true);
+                }
+            }
+            Action::ToggleStats => self.window_stats = !self.window_stats,
+            Action::ToggleSettings => self.window_settings = !self.window_settings,
+            Action::ToggleLog => self.toggle_log(),
+            Action::SaveFrame
+            | Action::ZoomIn
+            | Action::ZoomOut
+            | Action::ResetZoom
+            | Action::Reconnect => {
+                if let Some((_, Tab::Capture(tab))) = self.tree.find_active_focused() {
+                    tab.run_action(action, &self.config);
+                }
+            }
+        }
+    }
 }
 
 impl eframe::App for MirrorApp {
@@ -57,24 +289,66 @@ impl eframe::App for MirrorApp {
         self.frame_history
             .on_new_frame(ctx.input(|i| i.time), frame.info().cpu_usage);
 
+        // shortcuts are handled before the tabs so their keys are not forwarded to the guest,
+        // a detached tab that is shown inside the main window handles them itself
+        if !self.detached.iter().any(|tab| tab.takes_shortcuts(ctx)) {
+            let captured = self
+                .tree
+                .iter_all_tabs()
+                .filter_map(|(_, tab)| tab.capture())
+                .any(|tab| tab.captures_keyboard(ctx));
+            for action in self.keybindings.update(ctx, &mut self.config, captured) {
+                self.run_action(action);
+            }
+        }
+
         #[cfg(not(target_arch = "wasm32"))] // no File->Quit on web pages!
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    if ui.add(menu_button(&self.config, Action::NewTab)).clicked() {
+                        self.add_tab();
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .add(menu_button(&self.config, Action::CloseTab))
+                        .clicked()
+                    {
+                        self.run_action(Action::CloseTab);
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+
                     if ui.button("Quit").clicked() {
                         ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
                 ui.menu_button("Windows", |ui| {
-                    if ui.button("Stats").clicked() {
+                    if ui
+                        .add(menu_button(&self.config, Action::ToggleStats))
+                        .clicked()
+                    {
                         self.window_stats = !self.window_stats;
                         ui.close_menu();
                     }
 
-                    if ui.button("Settings").clicked() {
+                    if ui
+                        .add(menu_button(&self.config, Action::ToggleSettings))
+                        .clicked()
+                    {
                         self.window_settings = !self.window_settings;
                         ui.close_menu();
                     }
+
+                    if ui
+                        .add(menu_button(&self.config, Action::ToggleLog))
+                        .clicked()
+                    {
+                        self.toggle_log();
+                        ui.close_menu();
+                    }
                 });
             });
         });
@@ -95,17 +369,55 @@ impl eframe::App for MirrorApp {
                     &mut TabViewer {
                         added_nodes: &mut added_nodes,
                         config: &mut self.config,
+                        toasts: &mut self.toasts,
+                        log_console: &mut self.log_console,
                     },
                 );
 
             added_nodes.drain(..).for_each(|(surface, node)| {
                 self.tree.set_focused_node_and_surface((surface, node));
-                self.tree
-                    .push_to_focused_leaf(CaptureTab::new(self.tree_len, &self.config));
+                self.tree.push_to_focused_leaf(Tab::Capture(CaptureTab::new(
+                    self.tree_len,
+                    &self.config,
+                )));
                 self.tree_len += 1;
             });
         });
 
+        // tabs are detached with their button, the fullscreen shortcut detaches the focused tab
+        let detach = self
+            .tree
+            .iter_all_tabs_mut()
+            .filter_map(|(_, tab)| tab.capture_mut())
+            .filter_map(|tab| tab.take_detach_request().then(|| tab.id()))
+            .collect::<Vec<_>>();
+        for id in detach {
+            self.detach_tab(id, false);
+        }
+
+        let mut closed = Vec::new();
+        let mut actions = Vec::new();
+        let mut added_nodes = Vec::new();
+        let mut tab_viewer = TabViewer {
+            added_nodes: &mut added_nodes,
+            config: &mut self.config,
+            toasts: &mut self.toasts,
+            log_console: &mut self.log_console,
+        };
+        for (index, detached) in self.detached.iter_mut().enumerate() {
+            match detached.show(ctx, &mut tab_viewer, &mut actions) {
+                DetachedState::Open => (),
+                DetachedState::Dock => closed.push((index, true)),
+                DetachedState::Close => closed.push((index, false)),
+            }
+        }
+        for (index, dock) in closed.into_iter().rev() {
+            self.close_detached(index, dock);
+        }
+        for action in actions {
+            self.run_action(action);
+        }
+
         // windows
         if self.window_stats {
             let mut window_stats = true;
@@ -124,6 +436,20 @@ impl eframe::App for MirrorApp {
                 .collapsible(false)
                 .open(&mut window_settings)
                 .show(ctx, |ui| {
+                    if !self.config.issues.is_empty() {
+                        ui.colored_label(
+                            Color32::RED,
+                            "Problems found in the config, invalid fields have been reset:",
+                        );
+                        for issue in self.config.issues.iter() {
+                            ui.colored_label(Color32::RED, issue.to_string());
+                        }
+                        if ui.button("Dismiss").clicked() {
+                            self.config.issues.clear();
+                        }
+                        ui.separator();
+                    }
+
                     let mut connect_on_startup = self.config.connect_on_startup;
                     if ui
                         .checkbox(&mut connect_on_startup, "Auto-connect on startup")
@@ -155,10 +481,63 @@ impl eframe::App for MirrorApp {
                         self.config.obs_capture = obs_capture;
                         self.config.save().map_err(|err| warn!("{}", err)).ok();
                     };
+
+                    ui.separator();
+
+                    let mut clipboard_sync = self.config.clipboard_sync;
+                    if ui
+                        .checkbox(&mut clipboard_sync, "Synchronize Clipboard")
+                        .on_hover_text(
+                            "Shares text copied on the host or the guest with the other side",
+                        )
+                        .changed()
+                    {
+                        self.config.clipboard_sync = clipboard_sync;
+                        self.config.save().map_err(|err| warn!("{}", err)).ok();
+                    }
+
+                    ui.separator();
+
+                    egui::CollapsingHeader::new("Keyboard shortcuts").show(ui, |ui| {
+                        self.keybindings.ui(ui, &mut self.config);
+                    });
                 });
             self.window_settings = window_settings;
         }
 
+        self.toasts.show(ctx);
+
         ctx.request_repaint();
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if !self.detached.is_empty() {
+            for detached in self.detached.iter() {
+                self.config.set_viewport(detached.placement());
+            }
+            self.config.save().map_err(|err| warn!("{}", err)).ok();
+        }
+
+        if !self.restore_layout {
+            return;
+        }
+
+        let layout = Layout {
+            // the log tab is not restored
+            dock: self
+                .tree
+                .filter_map_tabs(|tab| tab.capture().map(CaptureTab::state)),
+            detached: self
+                .detached
+                .iter()
+                .map(|detached| detached.tab().state())
+                .collect(),
+        };
+        layout.save().map_err(|err| warn!("{}", err)).ok();
+    }
+}
+
+/// Returns a menu entry of the action with its shortcut
+fn menu_button(config: &MirrorConfig, action: Action) -> egui::Button<'static> {
+    egui::Button::new(action.label()).shortcut_text(format_shortcut(config.keybinding(action)))
 }
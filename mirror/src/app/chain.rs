@@ -0,0 +1,201 @@
+use ::std::collections::HashMap;
+
+use ::egui_dock::egui;
+use ::epaint::Color32;
+
+use ::memflow::prelude::v1::*;
+
+use crate::config::{ChainStage, ChainStageKind};
+use crate::ChainArgs;
+
+/// Edits a connector / os chain, e.g. `kvm -> win32` or `qemu -> win32 -> (nested) win32`
+#[derive(Default)]
+pub struct ChainEditor {
+    // help texts are only loaded once per plugin as this requires loading the library
+    help: HashMap<(ChainStageKind, String), String>,
+}
+
+impl ChainEditor {
+    /// Shows the editor, the last stage of the chain should be of the `target` kind
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        id: usize,
+        inventory: &Inventory,
+        chain: &mut Vec<ChainStage>,
+        target: ChainStageKind,
+    ) {
+        let connectors = inventory.available_connectors();
+        let os_plugins = inventory.available_os();
+
+        let mut move_up = None;
+        let mut remove = None;
+        egui::Grid::new(("chain", id))
+            .num_columns(4)
+            .show(ui, |ui| {
+                let len = chain.len();
+                for (index, stage) in chain.iter_mut().enumerate() {
+                    let (label, plugins) = match stage.kind {
+                        ChainStageKind::Connector => ("Connector", &connectors),
+                        ChainStageKind::Os => ("OS", &os_plugins),
+                    };
+                    ui.label(format!("{}. {}", index + 1, label));
+
+                    egui::ComboBox::from_id_source(("chain_stage", id, index))
+                        .selected_text(if stage.name.is_empty() {
+                            "None"
+                        } else {
+                            stage.name.as_str()
+                        })
+                        .show_ui(ui, |ui| {
+                            for plugin in plugins.iter() {
+                                ui.selectable_value(&mut stage.name, plugin.clone(), plugin);
+                            }
+                        });
+
+                    let args_valid = validate_args(stage).is_ok();
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut stage.args)
+                            .hint_text("arguments")
+                            .text_color_opt((!args_valid).then_some(Color32::RED)),
+                    );
+                    if !stage.name.is_empty() {
+                        let help = self.help(inventory, stage);
+                        response.on_hover_text(help);
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(index > 0, egui::Button::new("⬆")).clicked() {
+                            move_up = Some(index);
+                        }
+                        if ui
+                            .add_enabled(index + 1 < len, egui::Button::new("⬇"))
+                            .clicked()
+                        {
+                            move_up = Some(index + 1);
+                        }
+                        if ui.button("🗙").clicked() {
+                            remove = Some(index);
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+
+        if let Some(index) = move_up {
+            chain.swap(index - 1, index);
+        }
+        if let Some(index) = remove {
+            chain.remove(index);
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Add connector").clicked() {
+                chain.push(ChainStage::connector(""));
+            }
+            if ui.button("Add OS").clicked() {
+                chain.push(ChainStage::os(""));
+            }
+        });
+
+        match validate(chain, target) {
+            Ok(()) => {
+                ui.weak(chain_args(chain));
+            }
+            Err(err) => {
+                ui.colored_label(Color32::RED, err);
+            }
+        }
+    }
+
+    fn help(&mut self, inventory: &Inventory, stage: &ChainStage) -> &str {
+        self.help
+            .entry((stage.kind, stage.name.clone()))
+            .or_insert_with(|| {
+                let help = match stage.kind {
+                    ChainStageKind::Connector => inventory.connector_help(&stage.name),
+                    ChainStageKind::Os => inventory.os_help(&stage.name),
+                };
+                help.unwrap_or_else(|_| "No help available".to_string())
+            })
+    }
+}
+
+/// Returns the chain in the notation of the command line arguments
+pub fn chain_args(chain: &[ChainStage]) -> String {
+    chain
+        .iter()
+        .map(|stage| match stage.kind {
+            ChainStageKind::Connector => format!("-c {}", stage.to_arg()),
+            ChainStageKind::Os => format!("-o {}", stage.to_arg()),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns a short description of the chain, e.g. for the tab title
+pub fn chain_label(chain: &[ChainStage]) -> String {
+    chain
+        .iter()
+        .map(|stage| stage.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+fn validate_args(stage: &ChainStage) -> std::result::Result<(), String> {
+    let parsed = match stage.kind {
+        ChainStageKind::Connector => str::parse::<ConnectorArgs>(&stage.args).map(|_| ()),
+        ChainStageKind::Os => str::parse::<OsArgs>(&stage.args).map(|_| ()),
+    };
+    parsed.map_err(|err| format!("invalid arguments for {}: {}", stage.name, err))
+}
+
+/// Checks that all plugins are selected, all arguments can be parsed and the chain ends with the `target` kind
+pub fn validate(chain: &[ChainStage], target: ChainStageKind) -> std::result::Result<(), String> {
+    let last = chain
+        .last()
+        .ok_or_else(|| "The chain is empty".to_string())?;
+    if let Some(index) = chain.iter().position(|stage| stage.name.is_empty()) {
+        return Err(format!("No plugin selected in stage {}", index + 1));
+    }
+    chain.iter().try_for_each(validate_args)?;
+
+    if last.kind != target {
+        return Err(match target {
+            ChainStageKind::Connector => "The chain has to end with a connector",
+            ChainStageKind::Os => "The chain has to end with an OS",
+        }
+        .to_string());
+    }
+    Ok(())
+}
+
+fn to_chain_args(chain: &[ChainStage]) -> ChainArgs {
+    let mut args = ChainArgs::default();
+    for stage in chain.iter() {
+        match stage.kind {
+            ChainStageKind::Connector => args.push_connector(&stage.to_arg()),
+            ChainStageKind::Os => args.push_os(&stage.to_arg()),
+        }
+    }
+    args
+}
+
+/// Builds the os at the end of the chain
+pub fn build_os(inventory: &Inventory, chain: &[ChainStage]) -> Result<OsInstanceArcBox<'static>> {
+    validate(chain, ChainStageKind::Os)
+        .map_err(|_| Error(ErrorOrigin::Other, ErrorKind::Configuration))?;
+
+    to_chain_args(chain).build_os(inventory)
+}
+
+/// Builds the connector at the end of the chain
+pub fn build_connector(
+    inventory: &Inventory,
+    chain: &[ChainStage],
+) -> Result<ConnectorInstanceArcBox<'static>> {
+    validate(chain, ChainStageKind::Connector)
+        .map_err(|_| Error(ErrorOrigin::Other, ErrorKind::Configuration))?;
+
+    to_chain_args(chain).build_connector(inventory)
+}
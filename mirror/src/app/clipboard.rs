@@ -0,0 +1,97 @@
+use ::std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use ::log::warn;
+
+use crate::capture::{Capture, ClipboardData};
+
+// interval in which the system clipboard of the host is polled for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Synchronizes text between the system clipboard of the host and the guest.
+///
+/// The system clipboard is kept open so contents copied by mirror stay available.
+pub struct ClipboardSync {
+    clipboard: Option<arboard::Clipboard>,
+    initialized: bool,
+
+    guest_sequence: Option<u32>,
+    last_text: Option<String>,
+    last_poll: Instant,
+}
+
+impl Default for ClipboardSync {
+    fn default() -> Self {
+        Self {
+            clipboard: None,
+            initialized: false,
+
+            guest_sequence: None,
+            last_text: None,
+            last_poll: Instant::now(),
+        }
+    }
+}
+
+impl ClipboardSync {
+    pub fn update(&mut self, capture: &mut Box<dyn Capture>) {
+        let clipboard = match Self::open(&mut self.clipboard, &mut self.initialized) {
+            Some(clipboard) => clipboard,
+            None => return,
+        };
+
+        // guest -> host
+        if let Some(data) = capture.clipboard() {
+            if self.guest_sequence != Some(data.sequence) {
+                self.guest_sequence = Some(data.sequence);
+                if let Some(text) = data.as_text() {
+                    if self.last_text.as_deref() != Some(text) && clipboard.set_text(text).is_ok() {
+                        self.last_text = Some(text.to_string());
+                    }
+                }
+            }
+        }
+
+        // host -> guest
+        if self.last_poll.elapsed() >= POLL_INTERVAL {
+            self.last_poll = Instant::now();
+            if let Ok(text) = clipboard.get_text() {
+                if self.last_text.as_ref() != Some(&text) {
+                    capture.set_clipboard(ClipboardData::text(&text));
+                    self.last_text = Some(text);
+                }
+            }
+        }
+    }
+
+    /// Copies a rgba image into the system clipboard of the host
+    pub fn copy_image(&mut self, width: u32, height: u32, rgba: Vec<u8>) -> bool {
+        match Self::open(&mut self.clipboard, &mut self.initialized) {
+            Some(clipboard) => clipboard
+                .set_image(arboard::ImageData {
+                    width: width as usize,
+                    height: height as usize,
+                    bytes: Cow::Owned(rgba),
+                })
+                .map_err(|err| warn!("unable to copy image into the clipboard: {}", err))
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    fn open<'a>(
+        clipboard: &'a mut Option<arboard::Clipboard>,
+        initialized: &mut bool,
+    ) -> Option<&'a mut arboard::Clipboard> {
+        if !*initialized {
+            // the system clipboard is only opened once it is used
+            *initialized = true;
+            *clipboard = arboard::Clipboard::new()
+                .map_err(|err| warn!("unable to open system clipboard: {}", err))
+                .ok();
+        }
+        clipboard.as_mut()
+    }
+}
@@ -0,0 +1,306 @@
+use ::egui_dock::egui::{
+    self, LayerId, Order, Vec2, ViewportBuilder, ViewportClass, ViewportCommand, ViewportId,
+    WindowLevel,
+};
+
+use super::keybindings::KeyBindings;
+use super::tab_viewer::{CaptureTab, TabViewer};
+use crate::config::{Action, MirrorConfig, ViewportPlacement};
+
+// inner size of the picture in picture window
+const MINI_SIZE: [f32; 2] = [320.0, 180.0];
+
+/// What happens with a detached tab after its window was shown
+#[derive(Clone, Copy, PartialEq)]
+pub enum DetachedState {
+    Open,
+    /// The tab is moved back into the dock
+    Dock,
+    /// The tab is closed with its connection
+    Close,
+}
+
+/// A tab that is shown in its own native window instead of the dock
+pub struct DetachedTab {
+    tab: CaptureTab,
+    placement: ViewportPlacement,
+    // the window is created with this placement, later changes are sent as viewport commands
+    initial: ViewportPlacement,
+    // size that was requested from the window, the placement is not updated until it was applied
+    pending_size: Option<Vec2>,
+    // the tab was shown as a window inside the main window the last time
+    embedded: bool,
+}
+
+impl DetachedTab {
+    /// Detaches the tab, the window is opened where it was placed the last time
+    pub fn new(tab: CaptureTab, config: &MirrorConfig) -> Self {
+        let placement = config
+            .viewport(tab.id())
+            .copied()
+            .unwrap_or_else(|| ViewportPlacement::new(tab.id()));
+
+        let mut detached = Self {
+            tab,
+            placement,
+            initial: placement,
+            pending_size: None,
+            embedded: false,
+        };
+        detached.update_tab();
+        detached
+    }
+
+    pub fn tab(&self) -> &CaptureTab {
+        &self.tab
+    }
+
+    pub fn placement(&self) -> ViewportPlacement {
+        self.placement
+    }
+
+    /// Returns the tab so it can be docked again
+    pub fn into_tab(mut self) -> CaptureTab {
+        self.tab.set_detached(false, false);
+        self.tab
+    }
+
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        self.placement.fullscreen = fullscreen;
+        self.update_tab();
+    }
+
+    /// Shows the tab in its own window
+    ///
+    /// Shortcuts of actions that do not concern the tab are added to `actions`.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        viewer: &mut TabViewer,
+        actions: &mut Vec<Action>,
+    ) -> DetachedState {
+        let title = viewer.capture_title(&self.tab).text().to_string();
+        let viewport_id = self.viewport_id();
+        let builder = self.builder(&title);
+
+        ctx.show_viewport_immediate(viewport_id, builder, |ctx, class| {
+            self.embedded = class == ViewportClass::Embedded;
+            if self.embedded {
+                // the backend does not support multiple windows
+                let (mut dock, close) = if self.takes_shortcuts(ctx) {
+                    self.shortcuts(ctx, viewer, actions)
+                } else {
+                    (false, false)
+                };
+                let mut open = true;
+                egui::Window::new(title)
+                    .id(self.window_id())
+                    .default_size(self.placement.size)
+                    .open(&mut open)
+                    .show(ctx, |ui| viewer.capture_ui(ui, &mut self.tab));
+                dock |= !open;
+                return state(dock, close);
+            }
+
+            self.ui(ctx, viewer, actions)
+        })
+    }
+
+    /// Returns true if the shortcuts of the main window belong to this tab
+    ///
+    /// This is only the case if the tab is shown as a window inside the main window, either the
+    /// pointer is over it or the tab captures the keyboard.
+    pub fn takes_shortcuts(&self, ctx: &egui::Context) -> bool {
+        if !self.embedded {
+            return false;
+        }
+        let layer_id = LayerId::new(Order::Middle, self.window_id());
+        let hovered = ctx
+            .pointer_hover_pos()
+            .map_or(false, |pos| ctx.layer_id_at(pos) == Some(layer_id));
+        hovered || self.tab.captures_keyboard(ctx)
+    }
+
+    fn viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of(("capture_tab", self.tab.id()))
+    }
+
+    // the window that is shown instead of the viewport if the backend has only one window
+    fn window_id(&self) -> egui::Id {
+        egui::Id::new(self.viewport_id())
+    }
+
+    /// Runs the pressed shortcuts, returns if the tab is docked or closed
+    fn shortcuts(
+        &mut self,
+        ctx: &egui::Context,
+        viewer: &mut TabViewer,
+        actions: &mut Vec<Action>,
+    ) -> (bool, bool) {
+        let mut dock = false;
+        let mut close = false;
+        let captured = self.tab.captures_keyboard(ctx);
+        for action in KeyBindings::pressed(ctx, viewer.config, captured) {
+            match action {
+                // toggles between docked and fullscreen, a window inside the main window is docked
+                Action::Fullscreen if self.placement.fullscreen || self.embedded => {
+                    // the window is opened normally when the tab is detached with the button again
+                    self.set_fullscreen(false);
+                    dock = true;
+                }
+                Action::Fullscreen => {
+                    self.set_mini(ctx, false);
+                    self.set_fullscreen(true);
+                }
+                Action::CloseTab => close = true,
+                Action::SaveFrame
+                | Action::ZoomIn
+                | Action::ZoomOut
+                | Action::ResetZoom
+                | Action::Reconnect => self.tab.run_action(action, viewer.config),
+                _ => actions.push(action),
+            }
+        }
+        (dock, close)
+    }
+
+    fn ui(
+        &mut self,
+        ctx: &egui::Context,
+        viewer: &mut TabViewer,
+        actions: &mut Vec<Action>,
+    ) -> DetachedState {
+        let (mut dock, close) = self.shortcuts(ctx, viewer, actions);
+
+        let frame_only = self.placement.fullscreen || self.placement.mini;
+        if !frame_only {
+            egui::TopBottomPanel::top("viewport_controls").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    dock |= ui
+                        .button("Dock")
+                        .on_hover_text("Moves the tab back into the main window")
+                        .clicked();
+                    if ui
+                        .button("Fullscreen")
+                        .on_hover_text(
+                            "Borderless fullscreen on the current monitor, \
+                            the fullscreen shortcut (F11) docks the tab again",
+                        )
+                        .clicked()
+                    {
+                        self.set_fullscreen(true);
+                    }
+                    if ui
+                        .button("Mini")
+                        .on_hover_text(
+                            "Small window on top of other windows, \
+                            drag to move it and double click to restore it",
+                        )
+                        .clicked()
+                    {
+                        self.set_mini(ui.ctx(), true);
+                    }
+                    ui.checkbox(&mut self.placement.always_on_top, "Always on top");
+                });
+            });
+        }
+
+        let frame = if frame_only {
+            egui::Frame::none()
+        } else {
+            egui::Frame::central_panel(&ctx.style())
+        };
+        egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
+            viewer.capture_ui(ui, &mut self.tab);
+
+            // the borderless mini window is moved by dragging the frame
+            if self.placement.mini {
+                let response = ui.interact(
+                    ui.max_rect(),
+                    ui.id().with("mini"),
+                    egui::Sense::click_and_drag(),
+                );
+                if response.drag_started() {
+                    ui.ctx().send_viewport_cmd(ViewportCommand::StartDrag);
+                }
+                if response.double_clicked() {
+                    self.set_mini(ui.ctx(), false);
+                }
+            }
+        });
+
+        ctx.input(|i| {
+            let viewport = i.viewport();
+            dock |= viewport.close_requested();
+
+            // only the placement of the normal window is kept
+            let windowed = viewport.fullscreen == Some(false) && !self.placement.mini;
+            if let Some(inner_rect) = viewport.inner_rect.filter(|_| windowed) {
+                match self.pending_size {
+                    Some(size) if (inner_rect.size() - size).length() > 1.0 => {}
+                    _ => {
+                        self.pending_size = None;
+                        self.placement.size = inner_rect.size().into();
+                        if let Some(outer_rect) = viewport.outer_rect {
+                            self.placement.position = Some(outer_rect.min.into());
+                        }
+                    }
+                }
+            }
+        });
+
+        state(dock, close)
+    }
+
+    /// Shrinks the window to a small borderless window on top of other windows or restores it
+    fn set_mini(&mut self, ctx: &egui::Context, mini: bool) {
+        if self.placement.mini == mini {
+            return;
+        }
+        self.placement.mini = mini;
+        self.update_tab();
+
+        let size = Vec2::from(if mini { MINI_SIZE } else { self.placement.size });
+        ctx.send_viewport_cmd(ViewportCommand::InnerSize(size));
+        self.pending_size = Some(size);
+    }
+
+    fn update_tab(&mut self) {
+        let frame_only = self.placement.fullscreen || self.placement.mini;
+        self.tab.set_detached(true, frame_only);
+    }
+
+    fn builder(&self, title: &str) -> ViewportBuilder {
+        let window_level = if self.placement.always_on_top || self.placement.mini {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        };
+        let size = if self.initial.mini {
+            MINI_SIZE
+        } else {
+            self.initial.size
+        };
+
+        let mut builder = ViewportBuilder::default()
+            .with_title(title)
+            .with_inner_size(size)
+            .with_fullscreen(self.placement.fullscreen)
+            .with_decorations(!self.placement.mini)
+            .with_window_level(window_level);
+        if let Some(position) = self.initial.position {
+            builder = builder.with_position(position);
+        }
+        builder
+    }
+}
+
+fn state(dock: bool, close: bool) -> DetachedState {
+    if close {
+        DetachedState::Close
+    } else if dock {
+        DetachedState::Dock
+    } else {
+        DetachedState::Open
+    }
+}
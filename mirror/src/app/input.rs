@@ -0,0 +1,329 @@
+use ::egui::{Event, Key, Modifiers, PointerButton, Pos2, Rect};
+
+use ::mirror_dto::{InputEvent, MouseButton};
+
+// egui reports one scroll line as 50 points while windows uses 120 units per wheel notch
+const POINTS_PER_SCROLL_LINE: f32 = 50.0;
+const WHEEL_DELTA: f32 = 120.0;
+
+const SCANCODE_LCTRL: u16 = 0x1D;
+const SCANCODE_LSHIFT: u16 = 0x2A;
+const SCANCODE_LALT: u16 = 0x38;
+
+/// Translates egui input events into input events for the guest
+#[derive(Default)]
+pub struct InputMapper {
+    modifiers: Modifiers,
+    pointer_inside: bool,
+}
+
+impl InputMapper {
+    /// Maps all events of the current frame.
+    ///
+    /// `rect` is the screen rect the frame is displayed at and `frame_size` the resolution of the frame.
+    /// Pointer events are only forwarded inside of `clip`, the part of `rect` that is visible.
+    /// Keyboard events are only forwarded if `keyboard` is set.
+    pub fn map_events(
+        &mut self,
+        events: &[Event],
+        modifiers: Modifiers,
+        rect: Rect,
+        clip: Rect,
+        frame_size: [usize; 2],
+        keyboard: bool,
+    ) -> Vec<InputEvent> {
+        let visible = rect.intersect(clip);
+        let to_frame = |pos: Pos2| -> Option<(i32, i32)> {
+            if !visible.contains(pos) {
+                return None;
+            }
+            let x = (pos.x - rect.left()) / rect.width() * frame_size[0] as f32;
+            let y = (pos.y - rect.top()) / rect.height() * frame_size[1] as f32;
+            Some((x as i32, y as i32))
+        };
+
+        let mut result = Vec::new();
+
+        if keyboard {
+            self.map_modifiers(modifiers, &mut result);
+        } else {
+            self.release_modifiers(&mut result);
+        }
+
+        for event in events.iter() {
+            match event {
+                Event::PointerMoved(pos) => {
+                    let frame_pos = to_frame(*pos);
+                    self.pointer_inside = frame_pos.is_some();
+                    if let Some((x, y)) = frame_pos {
+                        result.push(InputEvent::mouse_move(x, y));
+                    }
+                }
+                Event::PointerGone => {
+                    self.pointer_inside = false;
+                }
+                Event::PointerButton {
+                    pos,
+                    button,
+                    pressed,
+                    ..
+                } => {
+                    // releases are always forwarded so buttons do not get stuck on the guest
+                    let (x, y) = match to_frame(*pos) {
+                        Some(pos) => pos,
+                        None if !*pressed => to_frame(visible.clamp(*pos)).unwrap_or_default(),
+                        None => continue,
+                    };
+                    result.push(InputEvent::mouse_button(
+                        mouse_button(*button),
+                        *pressed,
+                        x,
+                        y,
+                    ));
+                }
+                Event::Scroll(delta) => {
+                    if self.pointer_inside {
+                        result.push(InputEvent::mouse_wheel(
+                            (-delta.x / POINTS_PER_SCROLL_LINE * WHEEL_DELTA) as i32,
+                            (delta.y / POINTS_PER_SCROLL_LINE * WHEEL_DELTA) as i32,
+                        ));
+                    }
+                }
+                Event::Key {
+                    key,
+                    physical_key,
+                    pressed,
+                    ..
+                } if keyboard => {
+                    // prefer the physical key so the layout of the guest is used
+                    if let Some((scancode, extended)) = scancode(physical_key.unwrap_or(*key)) {
+                        result.push(InputEvent::key(scancode, extended, *pressed));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        result
+    }
+
+    /// Releases all modifiers that are still held down on the guest
+    pub fn release_modifiers(&mut self, result: &mut Vec<InputEvent>) {
+        self.map_modifiers(Modifiers::NONE, result);
+    }
+
+    /// egui does not emit key events for modifiers so state changes are forwarded instead
+    fn map_modifiers(&mut self, modifiers: Modifiers, result: &mut Vec<InputEvent>) {
+        for (prev, next, scancode) in [
+            (self.modifiers.ctrl, modifiers.ctrl, SCANCODE_LCTRL),
+            (self.modifiers.shift, modifiers.shift, SCANCODE_LSHIFT),
+            (self.modifiers.alt, modifiers.alt, SCANCODE_LALT),
+        ] {
+            if prev != next {
+                result.push(InputEvent::key(scancode, false, next));
+            }
+        }
+        self.modifiers = modifiers;
+    }
+}
+
+fn mouse_button(button: PointerButton) -> MouseButton {
+    match button {
+        PointerButton::Primary => MouseButton::Left,
+        PointerButton::Secondary => MouseButton::Right,
+        PointerButton::Middle => MouseButton::Middle,
+        PointerButton::Extra1 => MouseButton::X1,
+        PointerButton::Extra2 => MouseButton::X2,
+    }
+}
+
+/// Maps an egui key to its (set 1) scancode and whether it is an extended key
+fn scancode(key: Key) -> Option<(u16, bool)> {
+    let scancode = match key {
+        Key::Escape => 0x01,
+        Key::Num1 => 0x02,
+        Key::Num2 => 0x03,
+        Key::Num3 => 0x04,
+        Key::Num4 => 0x05,
+        Key::Num5 => 0x06,
+        Key::Num6 => 0x07,
+        Key::Num7 => 0x08,
+        Key::Num8 => 0x09,
+        Key::Num9 => 0x0A,
+        Key::Num0 => 0x0B,
+        Key::Minus => 0x0C,
+        Key::Equals | Key::Plus => 0x0D,
+        Key::Backspace => 0x0E,
+        Key::Tab => 0x0F,
+        Key::Q => 0x10,
+        Key::W => 0x11,
+        Key::E => 0x12,
+        Key::R => 0x13,
+        Key::T => 0x14,
+        Key::Y => 0x15,
+        Key::U => 0x16,
+        Key::I => 0x17,
+        Key::O => 0x18,
+        Key::P => 0x19,
+        Key::OpenBracket => 0x1A,
+        Key::CloseBracket => 0x1B,
+        Key::Enter => 0x1C,
+        Key::A => 0x1E,
+        Key::S => 0x1F,
+        Key::D => 0x20,
+        Key::F => 0x21,
+        Key::G => 0x22,
+        Key::H => 0x23,
+        Key::J => 0x24,
+        Key::K => 0x25,
+        Key::L => 0x26,
+        Key::Semicolon | Key::Colon => 0x27,
+        Key::Backtick => 0x29,
+        Key::Backslash | Key::Pipe => 0x2B,
+        Key::Z => 0x2C,
+        Key::X => 0x2D,
+        Key::C => 0x2E,
+        Key::V => 0x2F,
+        Key::B => 0x30,
+        Key::N => 0x31,
+        Key::M => 0x32,
+        Key::Comma => 0x33,
+        Key::Period => 0x34,
+        Key::Slash | Key::Questionmark => 0x35,
+        Key::Space => 0x39,
+        Key::F1 => 0x3B,
+        Key::F2 => 0x3C,
+        Key::F3 => 0x3D,
+        Key::F4 => 0x3E,
+        Key::F5 => 0x3F,
+        Key::F6 => 0x40,
+        Key::F7 => 0x41,
+        Key::F8 => 0x42,
+        Key::F9 => 0x43,
+        Key::F10 => 0x44,
+        Key::F11 => 0x57,
+        Key::F12 => 0x58,
+        Key::F13 => 0x64,
+        Key::F14 => 0x65,
+        Key::F15 => 0x66,
+        Key::F16 => 0x67,
+        Key::F17 => 0x68,
+        Key::F18 => 0x69,
+        Key::F19 => 0x6A,
+        Key::F20 => 0x6B,
+
+        // extended keys
+        Key::Home => return Some((0x47, true)),
+        Key::ArrowUp => return Some((0x48, true)),
+        Key::PageUp => return Some((0x49, true)),
+        Key::ArrowLeft => return Some((0x4B, true)),
+        Key::ArrowRight => return Some((0x4D, true)),
+        Key::End => return Some((0x4F, true)),
+        Key::ArrowDown => return Some((0x50, true)),
+        Key::PageDown => return Some((0x51, true)),
+        Key::Insert => return Some((0x52, true)),
+        Key::Delete => return Some((0x53, true)),
+
+        Key::Copy | Key::Cut | Key::Paste => return None,
+    };
+    Some((scancode, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::egui::{pos2, vec2};
+
+    const FRAME_SIZE: [usize; 2] = [400, 200];
+
+    fn map(mapper: &mut InputMapper, events: &[Event], keyboard: bool) -> Vec<InputEvent> {
+        let rect = Rect::from_min_size(pos2(0.0, 0.0), vec2(200.0, 100.0));
+        mapper.map_events(events, Modifiers::NONE, rect, rect, FRAME_SIZE, keyboard)
+    }
+
+    fn key(key: Key, pressed: bool) -> Event {
+        Event::Key {
+            key,
+            physical_key: None,
+            pressed,
+            repeat: false,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    fn button(pos: Pos2, pressed: bool) -> Event {
+        Event::PointerButton {
+            pos,
+            button: PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn pointer_is_scaled_to_the_frame() {
+        let mut mapper = InputMapper::default();
+        let events = map(
+            &mut mapper,
+            &[
+                Event::PointerMoved(pos2(100.0, 50.0)),
+                button(pos2(100.0, 50.0), true),
+                Event::Scroll(vec2(0.0, POINTS_PER_SCROLL_LINE)),
+            ],
+            false,
+        );
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::mouse_move(200, 100),
+                InputEvent::mouse_button(MouseButton::Left, true, 200, 100),
+                InputEvent::mouse_wheel(0, WHEEL_DELTA as i32),
+            ]
+        );
+    }
+
+    #[test]
+    fn pointer_outside_of_the_frame() {
+        let mut mapper = InputMapper::default();
+        let events = map(
+            &mut mapper,
+            &[
+                Event::PointerMoved(pos2(300.0, 50.0)),
+                button(pos2(300.0, 50.0), true),
+                Event::Scroll(vec2(0.0, POINTS_PER_SCROLL_LINE)),
+                // releases are clamped to the frame so buttons do not get stuck
+                button(pos2(300.0, 50.0), false),
+            ],
+            false,
+        );
+        assert_eq!(
+            events,
+            vec![InputEvent::mouse_button(MouseButton::Left, false, 400, 100)]
+        );
+    }
+
+    #[test]
+    fn keys_require_keyboard_focus() {
+        let mut mapper = InputMapper::default();
+        let events = [key(Key::A, true), key(Key::ArrowUp, false)];
+        assert!(map(&mut mapper, &events, false).is_empty());
+        assert_eq!(
+            map(&mut mapper, &events, true),
+            vec![
+                InputEvent::key(0x1E, false, true),
+                InputEvent::key(0x48, true, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn modifiers_are_released() {
+        let mut mapper = InputMapper::default();
+        let rect = Rect::from_min_size(pos2(0.0, 0.0), vec2(200.0, 100.0));
+        let events = mapper.map_events(&[], Modifiers::CTRL, rect, rect, FRAME_SIZE, true);
+        assert_eq!(events, vec![InputEvent::key(SCANCODE_LCTRL, false, true)]);
+
+        let events = map(&mut mapper, &[], false);
+        assert_eq!(events, vec![InputEvent::key(SCANCODE_LCTRL, false, false)]);
+    }
+}
@@ -0,0 +1,331 @@
+use ::egui_dock::egui::{self, Align2, Color32, ColorImage, FontId, Pos2, Rect, Stroke, Vec2};
+
+// size of a magnified pixel in points
+const CELL_SIZE: f32 = 10.0;
+// distance of the magnifier from the pointer
+const MAGNIFIER_OFFSET: Vec2 = Vec2::new(24.0, 24.0);
+
+#[derive(Clone, Copy, PartialEq)]
+enum InspectorMode {
+    Off,
+    Magnifier,
+    Measure,
+}
+
+/// Shows the values of the pixels around the pointer and measures distances, all coordinates are in frame space
+pub struct Inspector {
+    mode: InspectorMode,
+    /// Amount of pixels shown around the center pixel in each direction
+    radius: usize,
+    measure_start: Option<[usize; 2]>,
+    measure_end: Option<[usize; 2]>,
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        Self {
+            mode: InspectorMode::Off,
+            radius: 6,
+            measure_start: None,
+            measure_end: None,
+        }
+    }
+}
+
+impl Inspector {
+    pub fn is_active(&self) -> bool {
+        self.mode != InspectorMode::Off
+    }
+
+    pub fn ui_controls(&mut self, ui: &mut egui::Ui) {
+        let mut magnifier = self.mode == InspectorMode::Magnifier;
+        if ui
+            .toggle_value(&mut magnifier, "🔍 Inspect")
+            .on_hover_text("Shows the pixels around the pointer, click to copy the colour")
+            .changed()
+        {
+            self.mode = if magnifier {
+                InspectorMode::Magnifier
+            } else {
+                InspectorMode::Off
+            };
+        }
+
+        let mut measure = self.mode == InspectorMode::Measure;
+        if ui
+            .toggle_value(&mut measure, "📏 Measure")
+            .on_hover_text("Click on two pixels to measure the distance between them")
+            .changed()
+        {
+            self.mode = if measure {
+                InspectorMode::Measure
+            } else {
+                InspectorMode::Off
+            };
+            self.measure_start = None;
+            self.measure_end = None;
+        }
+
+        if self.mode == InspectorMode::Magnifier {
+            ui.add(
+                egui::DragValue::new(&mut self.radius)
+                    .clamp_range(1..=16)
+                    .prefix("radius: "),
+            );
+        }
+    }
+
+    /// Draws the overlay on top of the frame, returns the colour that was copied into the clipboard
+    pub fn ui_overlay(
+        &mut self,
+        ui: &egui::Ui,
+        response: &egui::Response,
+        painter: &egui::Painter,
+        frame_rect: Rect,
+        image: &ColorImage,
+    ) -> Option<String> {
+        let pointer = response
+            .hover_pos()
+            .and_then(|pos| to_frame(frame_rect, image.size, pos));
+
+        match self.mode {
+            InspectorMode::Off => None,
+            InspectorMode::Magnifier => {
+                let pos = response.hover_pos()?;
+                let pixel = pointer?;
+                self.magnifier(ui, painter, frame_rect, image, pos, pixel);
+
+                let hex = hex(image[(pixel[0], pixel[1])]);
+                if response.clicked() {
+                    ui.ctx().copy_text(hex.clone());
+                    Some(hex)
+                } else {
+                    None
+                }
+            }
+            InspectorMode::Measure => {
+                if response.clicked() {
+                    if let Some(pixel) = pointer {
+                        if self.measure_start.is_none() || self.measure_end.is_some() {
+                            self.measure_start = Some(pixel);
+                            self.measure_end = None;
+                        } else {
+                            self.measure_end = Some(pixel);
+                        }
+                    }
+                }
+
+                if let Some(start) = self.measure_start {
+                    // the distance to the pointer is shown until the second point is selected
+                    if let Some(end) = self.measure_end.or(pointer) {
+                        measure(painter, frame_rect, image.size, start, end);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn magnifier(
+        &self,
+        ui: &egui::Ui,
+        painter: &egui::Painter,
+        frame_rect: Rect,
+        image: &ColorImage,
+        pos: Pos2,
+        pixel: [usize; 2],
+    ) {
+        // outline of the pixel under the pointer
+        painter.rect_stroke(
+            pixel_rect(frame_rect, image.size, pixel),
+            0.0,
+            Stroke::new(1.0, Color32::WHITE),
+        );
+
+        let radius = self.radius as isize;
+        let cells = (2 * radius + 1) as f32;
+        let size = Vec2::splat(cells * CELL_SIZE);
+
+        // keep the magnifier inside of the visible area
+        let clip = painter.clip_rect();
+        let mut min = pos + MAGNIFIER_OFFSET;
+        if min.x + size.x > clip.right() {
+            min.x = pos.x - MAGNIFIER_OFFSET.x - size.x;
+        }
+        if min.y + size.y > clip.bottom() {
+            min.y = pos.y - MAGNIFIER_OFFSET.y - size.y;
+        }
+        let grid = Rect::from_min_size(min, size);
+
+        painter.rect_filled(grid.expand(2.0), 2.0, ui.visuals().extreme_bg_color);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let x = pixel[0] as isize + dx;
+                let y = pixel[1] as isize + dy;
+                if x < 0 || y < 0 || x >= image.size[0] as isize || y >= image.size[1] as isize {
+                    continue;
+                }
+                let [r, g, b, _] = image[(x as usize, y as usize)].to_array();
+                let cell = Rect::from_min_size(
+                    grid.min + Vec2::new((dx + radius) as f32, (dy + radius) as f32) * CELL_SIZE,
+                    Vec2::splat(CELL_SIZE),
+                );
+                painter.rect_filled(cell, 0.0, Color32::from_rgb(r, g, b));
+            }
+        }
+
+        // highlight the center pixel in a colour that is visible on light and dark pixels
+        let center = Rect::from_min_size(
+            grid.min + Vec2::splat(radius as f32 * CELL_SIZE),
+            Vec2::splat(CELL_SIZE),
+        );
+        painter.rect_stroke(center, 0.0, Stroke::new(2.0, Color32::BLACK));
+        painter.rect_stroke(center.shrink(1.0), 0.0, Stroke::new(1.0, Color32::WHITE));
+
+        let color = image[(pixel[0], pixel[1])];
+        let [r, g, b, a] = color.to_array();
+        label(
+            painter,
+            grid.left_bottom() + Vec2::new(0.0, 4.0),
+            Align2::LEFT_TOP,
+            format!(
+                "{}, {}\nRGBA({}, {}, {}, {})\n{}",
+                pixel[0],
+                pixel[1],
+                r,
+                g,
+                b,
+                a,
+                hex(color)
+            ),
+        );
+    }
+}
+
+/// Converts a screen position into the coordinates of the pixel in the frame
+fn to_frame(frame_rect: Rect, frame_size: [usize; 2], pos: Pos2) -> Option<[usize; 2]> {
+    if !frame_rect.contains(pos) || frame_size[0] == 0 || frame_size[1] == 0 {
+        return None;
+    }
+    let x = (pos.x - frame_rect.left()) / frame_rect.width() * frame_size[0] as f32;
+    let y = (pos.y - frame_rect.top()) / frame_rect.height() * frame_size[1] as f32;
+    Some([
+        (x as usize).min(frame_size[0] - 1),
+        (y as usize).min(frame_size[1] - 1),
+    ])
+}
+
+/// Returns the screen rect of a pixel of the frame
+fn pixel_rect(frame_rect: Rect, frame_size: [usize; 2], pixel: [usize; 2]) -> Rect {
+    let scale = frame_rect.size() / Vec2::new(frame_size[0] as f32, frame_size[1] as f32);
+    Rect::from_min_size(
+        frame_rect.min + Vec2::new(pixel[0] as f32, pixel[1] as f32) * scale,
+        scale,
+    )
+}
+
+fn measure(
+    painter: &egui::Painter,
+    frame_rect: Rect,
+    frame_size: [usize; 2],
+    start: [usize; 2],
+    end: [usize; 2],
+) {
+    let from = pixel_rect(frame_rect, frame_size, start).center();
+    let to = pixel_rect(frame_rect, frame_size, end).center();
+    painter.line_segment([from, to], Stroke::new(3.0, Color32::BLACK));
+    painter.line_segment([from, to], Stroke::new(1.0, Color32::YELLOW));
+    painter.circle_filled(from, 3.0, Color32::YELLOW);
+    painter.circle_filled(to, 3.0, Color32::YELLOW);
+
+    let dx = end[0] as f32 - start[0] as f32;
+    let dy = end[1] as f32 - start[1] as f32;
+    label(
+        painter,
+        to + Vec2::new(8.0, 8.0),
+        Align2::LEFT_TOP,
+        format!(
+            "({}, {}) -> ({}, {})\ndx: {} dy: {} distance: {:.1}",
+            start[0],
+            start[1],
+            end[0],
+            end[1],
+            dx,
+            dy,
+            dx.hypot(dy)
+        ),
+    );
+}
+
+fn label(painter: &egui::Painter, pos: Pos2, anchor: Align2, text: String) {
+    let galley = painter.layout_no_wrap(text, FontId::monospace(12.0), Color32::WHITE);
+    let rect = anchor.anchor_rect(Rect::from_min_size(pos, galley.size()));
+    painter.rect_filled(rect.expand(4.0), 2.0, Color32::from_black_alpha(200));
+    painter.galley(rect.min, galley, Color32::WHITE);
+}
+
+fn hex(color: Color32) -> String {
+    let [r, g, b, a] = color.to_array();
+    format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::egui_dock::egui::{pos2, vec2};
+
+    #[test]
+    fn scaled_frame() {
+        // every pixel of the frame is drawn as 2x2 points
+        let frame_rect = Rect::from_min_size(pos2(10.0, 20.0), vec2(400.0, 200.0));
+        let size = [200, 100];
+        assert_eq!(to_frame(frame_rect, size, pos2(10.0, 20.0)), Some([0, 0]));
+        assert_eq!(to_frame(frame_rect, size, pos2(11.9, 21.9)), Some([0, 0]));
+        assert_eq!(to_frame(frame_rect, size, pos2(12.0, 22.0)), Some([1, 1]));
+        assert_eq!(to_frame(frame_rect, size, pos2(9.0, 20.0)), None);
+        assert_eq!(to_frame(frame_rect, size, pos2(10.0, 221.0)), None);
+
+        assert_eq!(
+            pixel_rect(frame_rect, size, [3, 4]),
+            Rect::from_min_size(pos2(16.0, 28.0), vec2(2.0, 2.0))
+        );
+        for pixel in [[0, 0], [57, 33], [199, 99]].iter() {
+            let center = pixel_rect(frame_rect, size, *pixel).center();
+            assert_eq!(to_frame(frame_rect, size, center), Some(*pixel));
+        }
+    }
+
+    #[test]
+    fn zoomed_frame() {
+        // zoomed in, the frame extends beyond the top left corner of the tab
+        let frame_rect = Rect::from_min_size(pos2(-300.0, -100.0), vec2(1600.0, 800.0));
+        let size = [200, 100];
+        assert_eq!(to_frame(frame_rect, size, pos2(0.0, 0.0)), Some([37, 12]));
+        assert_eq!(
+            pixel_rect(frame_rect, size, [37, 12]),
+            Rect::from_min_size(pos2(-4.0, -4.0), vec2(8.0, 8.0))
+        );
+    }
+
+    #[test]
+    fn right_and_bottom_edge() {
+        let frame_rect = Rect::from_min_size(pos2(10.0, 20.0), vec2(400.0, 200.0));
+        assert_eq!(
+            to_frame(frame_rect, [200, 100], frame_rect.max),
+            Some([199, 99])
+        );
+        assert_eq!(
+            to_frame(frame_rect, [200, 100], frame_rect.right_top()),
+            Some([199, 0])
+        );
+    }
+
+    #[test]
+    fn empty_frame() {
+        let frame_rect = Rect::from_min_size(pos2(10.0, 20.0), vec2(400.0, 200.0));
+        let center = frame_rect.center();
+        assert_eq!(to_frame(frame_rect, [0, 100], center), None);
+        assert_eq!(to_frame(frame_rect, [200, 0], center), None);
+        assert_eq!(to_frame(frame_rect, [0, 0], center), None);
+    }
+}
@@ -0,0 +1,201 @@
+use ::std::cmp::Reverse;
+
+use ::egui_dock::egui::{self, KeyboardShortcut, Modifiers};
+use ::epaint::Color32;
+use ::log::warn;
+
+use crate::config::{format_shortcut, Action, MirrorConfig};
+
+/// Triggers the actions bound in the config and lets the user rebind them
+#[derive(Default)]
+pub struct KeyBindings {
+    /// Action that is bound to the next key press
+    recording: Option<Action>,
+    error: Option<String>,
+}
+
+impl KeyBindings {
+    /// Records a new shortcut or returns the actions whose shortcut was pressed
+    pub fn update(
+        &mut self,
+        ctx: &egui::Context,
+        config: &mut MirrorConfig,
+        captured: bool,
+    ) -> Vec<Action> {
+        match self.recording {
+            Some(action) => {
+                self.record(ctx, config, action);
+                Vec::new()
+            }
+            None => Self::pressed(ctx, config, captured),
+        }
+    }
+
+    /// Returns the actions whose shortcut was pressed
+    ///
+    /// The keys are consumed so they are not forwarded to the guest. If the keyboard is `captured`
+    /// by a tab only the fullscreen shortcut is handled, so a fullscreen window can still be left.
+    pub fn pressed(ctx: &egui::Context, config: &MirrorConfig, captured: bool) -> Vec<Action> {
+        let mut bindings = Action::ALL
+            .iter()
+            .filter(|action| !captured || **action == Action::Fullscreen)
+            .filter_map(|action| Some((*action, config.keybinding(*action)?)))
+            .collect::<Vec<_>>();
+        // extra modifiers are ignored when matching, `Ctrl+Shift+S` has to be checked before `Ctrl+S`
+        bindings.sort_by_key(|(_, shortcut)| Reverse(modifier_count(shortcut.modifiers)));
+
+        ctx.input_mut(|i| {
+            bindings
+                .into_iter()
+                .filter(|(_, shortcut)| i.consume_shortcut(shortcut))
+                .map(|(action, _)| action)
+                .collect()
+        })
+    }
+
+    fn record(&mut self, ctx: &egui::Context, config: &mut MirrorConfig, action: Action) {
+        // the key press is taken out of the input so no widget reacts to it
+        let pressed = ctx.input_mut(|i| {
+            let mut pressed = None;
+            i.events.retain(|event| match event {
+                egui::Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } if pressed.is_none() => {
+                    pressed = Some(KeyboardShortcut::new(normalize(*modifiers), *key));
+                    false
+                }
+                _ => true,
+            });
+            pressed
+        });
+
+        let shortcut = match pressed {
+            Some(shortcut) => shortcut,
+            None => return,
+        };
+        self.recording = None;
+        if shortcut == KeyboardShortcut::new(Modifiers::NONE, egui::Key::Escape) {
+            return;
+        }
+
+        match config.keybinding_conflict(action, shortcut) {
+            Some(other) => {
+                self.error = Some(format!(
+                    "{} is already used by {}",
+                    format_shortcut(Some(shortcut)),
+                    other.label()
+                ));
+            }
+            None => {
+                config.set_keybinding(action, Some(shortcut));
+                config.save().map_err(|err| warn!("{}", err)).ok();
+            }
+        }
+    }
+
+    /// Shows the shortcuts of all actions so they can be changed
+    pub fn ui(&mut self, ui: &mut egui::Ui, config: &mut MirrorConfig) {
+        let mut changed = false;
+        egui::Grid::new("keybindings")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                for action in Action::ALL.iter().copied() {
+                    let shortcut = config.keybinding(action);
+                    ui.label(action.label());
+
+                    let text = if self.recording == Some(action) {
+                        "Press a key...".to_string()
+                    } else if shortcut.is_none() {
+                        "-".to_string()
+                    } else {
+                        format_shortcut(shortcut)
+                    };
+                    if ui
+                        .button(text)
+                        .on_hover_text("Click and press the new shortcut, Escape cancels")
+                        .clicked()
+                    {
+                        self.recording = Some(action);
+                        self.error = None;
+                    }
+
+                    if ui
+                        .add_enabled(shortcut.is_some(), egui::Button::new("Clear"))
+                        .clicked()
+                    {
+                        config.set_keybinding(action, None);
+                        changed = true;
+                    }
+
+                    let default = action.default_shortcut();
+                    if ui
+                        .add_enabled(shortcut != default, egui::Button::new("Default"))
+                        .clicked()
+                    {
+                        let conflict =
+                            default.and_then(|default| config.keybinding_conflict(action, default));
+                        match conflict {
+                            Some(other) => {
+                                self.error = Some(format!(
+                                    "{} is already used by {}",
+                                    format_shortcut(default),
+                                    other.label()
+                                ));
+                            }
+                            None => {
+                                config.set_keybinding(action, default);
+                                changed = true;
+                            }
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+
+        if ui.button("Reset all").clicked() {
+            for action in Action::ALL.iter().copied() {
+                config.set_keybinding(action, action.default_shortcut());
+            }
+            self.error = None;
+            changed = true;
+        }
+
+        if changed {
+            config.save().map_err(|err| warn!("{}", err)).ok();
+        }
+    }
+}
+
+/// Keeps only the modifiers that can be written in the config, ctrl is treated like cmd on mac
+fn normalize(modifiers: Modifiers) -> Modifiers {
+    let mut normalized = Modifiers::NONE;
+    if modifiers.command || modifiers.ctrl {
+        normalized = normalized | Modifiers::COMMAND;
+    }
+    if modifiers.alt {
+        normalized = normalized | Modifiers::ALT;
+    }
+    if modifiers.shift {
+        normalized = normalized | Modifiers::SHIFT;
+    }
+    normalized
+}
+
+fn modifier_count(modifiers: Modifiers) -> usize {
+    [
+        modifiers.command || modifiers.ctrl,
+        modifiers.alt,
+        modifiers.shift,
+    ]
+    .iter()
+    .filter(|pressed| **pressed)
+    .count()
+}
@@ -0,0 +1,106 @@
+use ::std::{fs, io, path::PathBuf};
+
+use ::egui_dock::DockState;
+use ::serde::{Deserialize, Serialize};
+
+use crate::config::{MirrorConfig, Profile};
+
+/// Persisted state of a single tab
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TabState {
+    pub id: usize,
+    /// Connection and options of the tab, the name is empty if no profile is selected
+    #[serde(flatten)]
+    pub profile: Profile,
+    #[serde(default)]
+    pub connect_on_startup: bool,
+    #[serde(default)]
+    pub replay_path: String,
+    /// Framebuffer of the agentless framebuffer mode
+    #[serde(default)]
+    pub framebuffer: Option<FramebufferState>,
+}
+
+/// Location and layout of the framebuffer of a tab, see `FramebufferConfig`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FramebufferState {
+    /// Base address as it was entered in the ui
+    pub base: String,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    /// Label of the `PixelFormat`
+    pub format: String,
+}
+
+/// The dock layout and the tabs of the ui, it is stored next to the config
+///
+/// The layout is kept in its own json file as the nested dock state does not map well onto toml.
+#[derive(Serialize, Deserialize)]
+pub struct Layout {
+    pub dock: DockState<TabState>,
+    /// Tabs that are shown in their own window, their placement is stored in the config
+    #[serde(default)]
+    pub detached: Vec<TabState>,
+}
+
+impl Layout {
+    pub fn load() -> io::Result<Self> {
+        let contents = fs::read(Self::layout_path())?;
+        serde_json::from_slice(&contents).map_err(io::Error::other)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::layout_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    /// Returns the id that should be used for the next tab
+    pub fn next_id(&self) -> usize {
+        self.dock
+            .iter_all_tabs()
+            .map(|(_, tab)| tab)
+            .chain(self.detached.iter())
+            .map(|tab| tab.id + 1)
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn layout_path() -> PathBuf {
+        MirrorConfig::config_dir().join("layout.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tab_state() {
+        // layouts of older versions do not contain the framebuffer
+        let state: TabState =
+            serde_json::from_str(r#"{ "id": 1, "replay_path": "a.mirror" }"#).unwrap();
+        assert_eq!((state.id, state.replay_path.as_str()), (1, "a.mirror"));
+        assert!(state.framebuffer.is_none());
+
+        let framebuffer = FramebufferState {
+            base: "0xfd000000".to_string(),
+            width: 1280,
+            height: 720,
+            stride: 5120,
+            format: "BGRX8888".to_string(),
+        };
+        let state = TabState {
+            framebuffer: Some(framebuffer.clone()),
+            ..state
+        };
+        let state: TabState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(state.framebuffer, Some(framebuffer));
+    }
+}
@@ -0,0 +1,437 @@
+use ::std::{
+    cell::Cell,
+    collections::{BTreeSet, VecDeque},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ::log::{Level, LevelFilter, Log, Metadata, Record};
+use ::parking_lot::Mutex;
+
+use ::egui_dock::egui;
+use ::egui_notify::Toasts;
+use ::epaint::Color32;
+
+/// Maximum amount of records kept in the buffer, older records are discarded
+const DEFAULT_CAPACITY: usize = 10_000;
+
+thread_local! {
+    // the tab that is currently handled on this thread
+    static CURRENT_TAB: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// Attributes all records logged on the current thread to the given tab until the guard is dropped
+pub fn tab_scope(tab: usize) -> TabScope {
+    TabScope {
+        previous: CURRENT_TAB.with(|current| current.replace(Some(tab))),
+    }
+}
+
+pub struct TabScope {
+    previous: Option<usize>,
+}
+
+impl Drop for TabScope {
+    fn drop(&mut self) {
+        CURRENT_TAB.with(|current| current.set(self.previous));
+    }
+}
+
+#[derive(Clone)]
+pub struct LogEntry {
+    /// Time since the buffer was created
+    pub time: f64,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /// Tab that was active when the record was logged
+    pub tab: Option<usize>,
+}
+
+impl LogEntry {
+    fn line(&self) -> String {
+        let tab = match self.tab {
+            Some(tab) => format!(" #{}", tab + 1),
+            None => String::new(),
+        };
+        format!(
+            "{:>10.3} {:<5} [{}{}] {}",
+            self.time, self.level, self.target, tab, self.message
+        )
+    }
+}
+
+/// Bounded ring buffer of log records
+pub struct LogBuffer {
+    start: Instant,
+    capacity: usize,
+    inner: Mutex<LogBufferInner>,
+}
+
+struct LogBufferInner {
+    entries: VecDeque<LogEntry>,
+    // total amount of records that have been pushed
+    total: u64,
+    // records that were dropped because the buffer was full, cleared records are not counted
+    discarded: u64,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            capacity: capacity.max(1),
+            inner: Mutex::new(LogBufferInner {
+                entries: VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY)),
+                total: 0,
+                discarded: 0,
+            }),
+        }
+    }
+
+    fn push(&self, entry: LogEntry) {
+        let mut inner = self.inner.lock();
+        if inner.entries.len() >= self.capacity {
+            inner.entries.pop_front();
+            inner.discarded += 1;
+        }
+        inner.entries.push_back(entry);
+        inner.total += 1;
+    }
+
+    /// Returns the amount of records that have been discarded because the buffer was full
+    pub fn discarded(&self) -> u64 {
+        self.inner.lock().discarded
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().entries.clear();
+    }
+
+    /// Appends the records that were pushed after the first `seen` records to `entries`
+    /// and returns the amount of records that have been pushed so far.
+    ///
+    /// Older records are removed from `entries` so it does not outgrow the buffer.
+    pub fn read_since(&self, seen: u64, entries: &mut VecDeque<LogEntry>) -> u64 {
+        let inner = self.inner.lock();
+        let new = (inner.total - seen).min(inner.entries.len() as u64) as usize;
+        entries.extend(inner.entries.range(inner.entries.len() - new..).cloned());
+        while entries.len() > self.capacity {
+            entries.pop_front();
+        }
+        inner.total
+    }
+}
+
+/// Logger that writes all records into a `LogBuffer`, it is combined with the terminal logger
+pub struct LogSink {
+    level: LevelFilter,
+    buffer: Arc<LogBuffer>,
+}
+
+impl LogSink {
+    pub fn new(level: LevelFilter, buffer: Arc<LogBuffer>) -> Box<Self> {
+        Box::new(Self { level, buffer })
+    }
+}
+
+impl Log for LogSink {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.buffer.push(LogEntry {
+            time: self.buffer.start.elapsed().as_secs_f64(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            tab: CURRENT_TAB.with(|current| current.get()),
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+impl simplelog::SharedLogger for LogSink {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TabFilter {
+    All,
+    App,
+    Tab(usize),
+}
+
+struct LogFilter {
+    level: LevelFilter,
+    tab: TabFilter,
+    module: String,
+    search: String,
+}
+
+impl LogFilter {
+    /// `search` is the lowercase search text
+    fn matches(&self, entry: &LogEntry, search: &str) -> bool {
+        if entry.level > self.level {
+            return false;
+        }
+        match self.tab {
+            TabFilter::All => (),
+            TabFilter::App if entry.tab.is_none() => (),
+            TabFilter::Tab(tab) if entry.tab == Some(tab) => (),
+            _ => return false,
+        }
+        if !self.module.is_empty() && !entry.target.contains(self.module.as_str()) {
+            return false;
+        }
+        search.is_empty() || entry.message.to_lowercase().contains(search)
+    }
+}
+
+/// Ui state of the log tab
+pub struct LogConsole {
+    buffer: Arc<LogBuffer>,
+    filter: LogFilter,
+    // copy of the records in the buffer, only new records are copied every frame
+    entries: VecDeque<LogEntry>,
+    seen: u64,
+    // records are not updated while the console is paused
+    paused: bool,
+}
+
+impl LogConsole {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        Self {
+            buffer,
+            filter: LogFilter {
+                level: LevelFilter::Info,
+                tab: TabFilter::All,
+                module: String::new(),
+                search: String::new(),
+            },
+            entries: VecDeque::new(),
+            seen: 0,
+            paused: false,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, toasts: &mut Toasts) {
+        if !self.paused {
+            self.seen = self.buffer.read_since(self.seen, &mut self.entries);
+        }
+
+        let LogConsole {
+            buffer,
+            filter,
+            entries,
+            paused,
+            ..
+        } = self;
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("log_level")
+                .selected_text(filter.level.to_string())
+                .show_ui(ui, |ui| {
+                    for level in LevelFilter::iter().skip(1) {
+                        ui.selectable_value(&mut filter.level, level, level.to_string());
+                    }
+                });
+
+            let tabs = entries
+                .iter()
+                .filter_map(|entry| entry.tab)
+                .collect::<BTreeSet<_>>();
+            egui::ComboBox::from_id_source("log_tab")
+                .selected_text(tab_filter_label(filter.tab))
+                .show_ui(ui, |ui| {
+                    let filters = [TabFilter::All, TabFilter::App]
+                        .iter()
+                        .copied()
+                        .chain(tabs.into_iter().map(TabFilter::Tab));
+                    for tab in filters {
+                        ui.selectable_value(&mut filter.tab, tab, tab_filter_label(tab));
+                    }
+                });
+
+            ui.add(
+                egui::TextEdit::singleline(&mut filter.module)
+                    .hint_text("module")
+                    .desired_width(120.0),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut filter.search)
+                    .hint_text("search")
+                    .desired_width(160.0),
+            );
+        });
+
+        let search = filter.search.to_lowercase();
+        let filtered = entries
+            .iter()
+            .filter(|entry| filter.matches(entry, &search))
+            .collect::<Vec<_>>();
+
+        let mut clear = false;
+        ui.horizontal(|ui| {
+            ui.toggle_value(paused, "⏸ Pause");
+
+            if ui.button("Clear").clicked() {
+                buffer.clear();
+                clear = true;
+            }
+
+            if ui
+                .button("Save")
+                .on_hover_text("Saves the filtered records into a text file")
+                .clicked()
+            {
+                let path = default_log_dir().join(format!(
+                    "log-{}.txt",
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis()
+                ));
+                match save(&path, &filtered) {
+                    Ok(()) => {
+                        toasts.info(format!("Saved log to {}", path.display()));
+                    }
+                    Err(err) => {
+                        toasts.error(format!("Unable to save log: {}", err));
+                    }
+                }
+            }
+
+            ui.weak(format!(
+                "{} / {} records ({} discarded)",
+                filtered.len(),
+                entries.len(),
+                buffer.discarded()
+            ));
+        });
+
+        ui.separator();
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .stick_to_bottom(!*paused)
+            .show_rows(ui, row_height, filtered.len(), |ui, rows| {
+                for entry in &filtered[rows] {
+                    let color = match entry.level {
+                        Level::Error => Color32::RED,
+                        Level::Warn => Color32::YELLOW,
+                        Level::Info => ui.visuals().text_color(),
+                        Level::Debug | Level::Trace => ui.visuals().weak_text_color(),
+                    };
+                    ui.add(
+                        egui::Label::new(
+                            egui::RichText::new(entry.line()).monospace().color(color),
+                        )
+                        .wrap(false),
+                    );
+                }
+            });
+
+        if clear {
+            entries.clear();
+        }
+    }
+}
+
+fn tab_filter_label(filter: TabFilter) -> String {
+    match filter {
+        TabFilter::All => "All tabs".to_string(),
+        TabFilter::App => "Application".to_string(),
+        TabFilter::Tab(tab) => format!("Connection #{}", tab + 1),
+    }
+}
+
+fn save(path: &Path, entries: &[&LogEntry]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    for entry in entries {
+        writeln!(file, "{}", entry.line())?;
+    }
+    file.flush()
+}
+
+fn default_log_dir() -> PathBuf {
+    dirs::document_dir()
+        .map(|dir| dir.join("mirror"))
+        .unwrap_or_else(|| "./logs".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(buffer: &LogBuffer, message: &str) {
+        buffer.push(LogEntry {
+            time: 0.0,
+            level: Level::Info,
+            target: "mirror".to_string(),
+            message: message.to_string(),
+            tab: None,
+        });
+    }
+
+    fn messages(entries: &VecDeque<LogEntry>) -> Vec<&str> {
+        entries.iter().map(|entry| entry.message.as_str()).collect()
+    }
+
+    #[test]
+    fn only_new_records_are_read() {
+        let buffer = LogBuffer::new(3);
+        let mut entries = VecDeque::new();
+        push(&buffer, "a");
+        push(&buffer, "b");
+
+        let seen = buffer.read_since(0, &mut entries);
+        assert_eq!((seen, messages(&entries)), (2, vec!["a", "b"]));
+        let seen = buffer.read_since(seen, &mut entries);
+        assert_eq!((seen, messages(&entries)), (2, vec!["a", "b"]));
+
+        // records that were discarded before they were read are skipped
+        for message in ["c", "d", "e", "f"] {
+            push(&buffer, message);
+        }
+        let seen = buffer.read_since(seen, &mut entries);
+        assert_eq!((seen, messages(&entries)), (6, vec!["d", "e", "f"]));
+        assert_eq!(buffer.discarded(), 3);
+
+        buffer.clear();
+        entries.clear();
+        push(&buffer, "g");
+        let seen = buffer.read_since(seen, &mut entries);
+        assert_eq!((seen, messages(&entries)), (7, vec!["g"]));
+        // cleared records were not discarded
+        assert_eq!(buffer.discarded(), 3);
+    }
+}
@@ -1,67 +1,201 @@
-use ::std::io::Cursor;
+use ::std::{
+    fs,
+    io::{self, Cursor},
+    path::PathBuf,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ::log::{info, warn};
 
 use ::egui_dock::egui::{self, pos2};
 use ::egui_dock::NodeIndex;
 use ::egui_dock::SurfaceIndex;
+use ::egui_notify::Toasts;
 use ::epaint::{Color32, Rect, TextureHandle};
 
 use ::memflow::prelude::v1::*;
 
+use super::chain::{build_connector, build_os, chain_label, validate, ChainEditor};
+use super::clipboard::ClipboardSync;
+use super::input::InputMapper;
+use super::inspector::Inspector;
+use super::layout::{FramebufferState, TabState};
+use super::log_console::{self, LogConsole};
+use super::viewer::Viewer;
 use crate::{
-    capture::{Capture, ThreadedCapture},
-    MirrorConfig, SequentialCapture,
+    capture::{
+        detect_framebuffers, Capture, FileCapture, FramebufferCandidate, FramebufferCapture,
+        FramebufferConfig, PhysicalCapture, PixelFormat, Playback, ReplayConfig, ThreadedCapture,
+    },
+    config::{
+        Action, CaptureOptions, ChainStage, ChainStageKind, ConnectionMode, DisplayOptions,
+        Profile, ScaleMode, TextureFilter,
+    },
+    export::{ClipExportConfig, ClipExporter, ClipFormat, ClipSource},
+    recorder::{RecordFormat, Recorder, RecorderConfig},
+    screenshot::{GuestInfo, ScreenshotOptions},
+    MirrorConfig, SequentialCapture, DEFAULT_PROCESS_NAME,
 };
+#[cfg(feature = "preview-server")]
+use crate::{PreviewServer, PreviewServerConfig};
+#[cfg(all(unix, feature = "shared-memory"))]
+use crate::{ShmPublisher, ShmPublisherConfig};
+#[cfg(feature = "vnc-server")]
+use crate::{VncServer, VncServerConfig};
+
+/// A tab of the dock
+pub enum Tab {
+    Capture(CaptureTab),
+    /// Shows the records of the log sink, there is at most one log tab
+    Log,
+}
+
+impl Tab {
+    pub fn capture(&self) -> Option<&CaptureTab> {
+        match self {
+            Tab::Capture(tab) => Some(tab),
+            Tab::Log => None,
+        }
+    }
+
+    pub fn capture_mut(&mut self) -> Option<&mut CaptureTab> {
+        match self {
+            Tab::Capture(tab) => Some(tab),
+            Tab::Log => None,
+        }
+    }
+}
 
 pub struct TabViewer<'a> {
     pub(crate) added_nodes: &'a mut Vec<(SurfaceIndex, NodeIndex)>,
     pub(crate) config: &'a mut MirrorConfig,
+    pub(crate) toasts: &'a mut Toasts,
+    pub(crate) log_console: &'a mut LogConsole,
 }
 
-impl egui_dock::TabViewer for TabViewer<'_> {
-    type Tab = CaptureTab;
+impl TabViewer<'_> {
+    /// Shows a capture tab, this is also used for detached tabs
+    pub fn capture_ui(&mut self, ui: &mut egui::Ui, tab: &mut CaptureTab) {
+        let _log_scope = log_console::tab_scope(tab.id);
+        tab.poll_connect(self.config, self.toasts);
 
-    fn ui(&mut self, ui: &mut egui::Ui, tab: &mut Self::Tab) {
         match &mut tab.capture {
             Some(_) => {
-                tab.ui_capturing(ui, self.config);
+                tab.ui_capturing(ui, self.config, self.toasts);
             }
             None => {
-                tab.ui_connection(ui, self.config);
+                tab.ui_connection(ui, self.config, self.toasts);
             }
         }
     }
 
-    fn title(&mut self, tab: &mut Self::Tab) -> egui::WidgetText {
+    pub fn capture_title(&self, tab: &CaptureTab) -> egui::WidgetText {
         match &tab.capture {
-            Some(_) => format!(
-                "Connection #{} ({})",
-                tab.id + 1,
-                tab.selected_connector.as_ref().unwrap()
-            )
-            .into(),
+            Some(_) if tab.connection_mode == ConnectionMode::File => {
+                format!("Replay #{} ({})", tab.id + 1, tab.replay_path).into()
+            }
+            Some(_) => format!("Connection #{} ({})", tab.id + 1, chain_label(&tab.chain)).into(),
             None => format!("Connection #{}", tab.id + 1).into(),
         }
     }
+}
+
+impl egui_dock::TabViewer for TabViewer<'_> {
+    type Tab = Tab;
+
+    fn ui(&mut self, ui: &mut egui::Ui, tab: &mut Self::Tab) {
+        match tab {
+            Tab::Capture(tab) => self.capture_ui(ui, tab),
+            Tab::Log => self.log_console.ui(ui, self.toasts),
+        }
+    }
+
+    fn title(&mut self, tab: &mut Self::Tab) -> egui::WidgetText {
+        match tab {
+            Tab::Capture(tab) => self.capture_title(tab),
+            Tab::Log => "Log".into(),
+        }
+    }
 
     fn on_add(&mut self, surface: SurfaceIndex, node: NodeIndex) {
         self.added_nodes.push((surface, node));
     }
 }
 
+/// A connection attempt that is running in the background
+struct PendingConnection {
+    thread_handle: JoinHandle<Result<Box<dyn Capture + Send>>>,
+    label: String,
+    started: Instant,
+}
+
 pub struct CaptureTab {
     id: usize,
 
     // memflow select ui
     inventory: Inventory,
-    selected_connector: Option<String>,
+    chain: Vec<ChainStage>,
+    chain_editor: ChainEditor,
+    connection_mode: ConnectionMode,
     connect_on_startup: bool,
+    connecting: Option<PendingConnection>,
+    connect_error: Option<String>,
+
+    // profiles
+    profile_name: String,
+    profile_path: String,
+    process_name: String,
+    capture_options: Option<CaptureOptions>,
+    display: DisplayOptions,
+
+    // agentless framebuffer
+    framebuffer_base: String,
+    framebuffer_config: FramebufferConfig,
+    framebuffer_candidates: Vec<FramebufferCandidate>,
+    framebuffer_detect: Option<JoinHandle<Result<Vec<FramebufferCandidate>>>>,
+
+    // replay
+    replay_path: String,
 
     // capturing
     capture: Option<Box<dyn Capture>>,
+    input_mapper: InputMapper,
+    // the widget that receives the keyboard input for the guest while it has the focus
+    input_id: Option<egui::Id>,
+    clipboard_sync: ClipboardSync,
+
+    // recording
+    recorder: Option<Recorder>,
+    recorder_config: RecorderConfig,
+    record_on_capture: bool,
+    replay_config: ReplayConfig,
+    replay_save_duration: u64,
+    replay_save: Option<JoinHandle<io::Result<u64>>>,
+    clip_export_config: ClipExportConfig,
+    screenshot_options: ScreenshotOptions,
+    screenshot_requested: bool,
+    #[cfg(feature = "preview-server")]
+    preview_server: Option<PreviewServer>,
+    #[cfg(feature = "vnc-server")]
+    vnc_server: Option<VncServer>,
+    #[cfg(all(unix, feature = "shared-memory"))]
+    shm_publisher: Option<ShmPublisher>,
 
     frame_counter: u32,
     frame_texture: Option<TextureHandle>,
+    frame_filter: TextureFilter,
+    // the last uploaded frame, it is read by the inspector
+    frame_image: Option<Arc<egui::ColorImage>>,
     cursor: Option<TextureHandle>,
+    viewer: Viewer,
+    inspector: Inspector,
+
+    // window
+    detached: bool,
+    frame_only: bool,
+    detach_requested: bool,
 }
 
 impl CaptureTab {
@@ -70,86 +204,394 @@ impl CaptureTab {
             id,
 
             inventory: Inventory::scan(),
-            selected_connector: None,
+            chain: default_chain(config),
+            chain_editor: ChainEditor::default(),
+            connection_mode: ConnectionMode::Os,
             connect_on_startup: config.connect_on_startup,
+            connecting: None,
+            connect_error: None,
+
+            profile_name: String::new(),
+            profile_path: String::new(),
+            process_name: DEFAULT_PROCESS_NAME.to_string(),
+            capture_options: None,
+            display: DisplayOptions::default(),
+
+            framebuffer_base: "0x0".to_string(),
+            framebuffer_config: default_framebuffer_config(),
+            framebuffer_candidates: Vec::new(),
+            framebuffer_detect: None,
+
+            replay_path: String::new(),
 
             capture: None,
+            input_mapper: InputMapper::default(),
+            input_id: None,
+            clipboard_sync: ClipboardSync::default(),
+
+            recorder: None,
+            recorder_config: default_recorder_config(),
+            record_on_capture: false,
+            replay_config: ReplayConfig::default(),
+            replay_save_duration: 30,
+            replay_save: None,
+            clip_export_config: ClipExportConfig::default(),
+            screenshot_options: ScreenshotOptions::default(),
+            screenshot_requested: false,
+            #[cfg(feature = "preview-server")]
+            preview_server: None,
+            #[cfg(feature = "vnc-server")]
+            vnc_server: None,
+            #[cfg(all(unix, feature = "shared-memory"))]
+            shm_publisher: None,
 
             frame_counter: 0,
             frame_texture: None,
+            frame_filter: TextureFilter::default(),
+            frame_image: None,
             cursor: None,
+            viewer: Viewer::default(),
+            inspector: Inspector::default(),
+
+            detached: false,
+            frame_only: false,
+            detach_requested: false,
         }
     }
 
-    pub fn connect(id: usize, config: &MirrorConfig) -> Result<Self> {
-        if !config.connect_on_startup || config.last_connector.is_none() || config.last_os.is_none()
-        {
-            return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration));
+    pub fn with_capture(id: usize, config: &MirrorConfig, mut capture: Box<dyn Capture>) -> Self {
+        Self::update_capture_flags(&mut capture, config.capture_options());
+
+        let mut tab = Self::new(id, config);
+        tab.capture = Some(capture);
+        tab
+    }
+
+    /// Restores a tab of the last session, the connection is started if it was enabled for the tab
+    pub fn from_state(state: &TabState, config: &MirrorConfig) -> Self {
+        let mut tab = Self::new(state.id, config);
+        tab.apply_profile(&state.profile);
+        tab.connect_on_startup = state.connect_on_startup;
+        tab.replay_path = state.replay_path.clone();
+        if let Some(framebuffer) = &state.framebuffer {
+            tab.framebuffer_base = framebuffer.base.clone();
+            tab.framebuffer_config = FramebufferConfig {
+                base: parse_address(&framebuffer.base).unwrap_or_else(Address::null),
+                width: framebuffer.width,
+                height: framebuffer.height,
+                stride: framebuffer.stride,
+                format: PixelFormat::ALL
+                    .iter()
+                    .copied()
+                    .find(|format| format.label() == framebuffer.format)
+                    .unwrap_or(tab.framebuffer_config.format),
+            };
         }
 
-        let inventory = Inventory::scan();
+        // recordings are opened again, other tabs connect in the background
+        if config.connect_on_startup && tab.connect_on_startup {
+            tab.reconnect(config);
+        }
+        tab
+    }
 
-        let last_connector = config.last_connector.as_ref().unwrap();
-        let connector_args = config
-            .last_connector_args
-            .as_ref()
-            .and_then(|s| str::parse(s).ok());
-        let connector =
-            inventory.create_connector(last_connector, None, connector_args.as_ref())?;
+    /// Returns the state of the tab that is restored on the next startup
+    pub fn state(&self) -> TabState {
+        TabState {
+            id: self.id,
+            profile: self.to_profile(&self.profile_name),
+            connect_on_startup: self.connect_on_startup,
+            replay_path: self.replay_path.clone(),
+            framebuffer: Some(FramebufferState {
+                base: self.framebuffer_base.clone(),
+                width: self.framebuffer_config.width,
+                height: self.framebuffer_config.height,
+                stride: self.framebuffer_config.stride,
+                format: self.framebuffer_config.format.label().to_string(),
+            }),
+        }
+    }
 
-        let last_os = config.last_os.as_ref().unwrap();
-        let os_args = config
-            .last_os_args
-            .as_ref()
-            .and_then(|s| str::parse(s).ok());
-        let os = inventory.create_os(last_os, Some(connector), os_args.as_ref())?;
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Marks the tab as shown in its own window, only the frame is drawn if `frame_only` is set
+    pub fn set_detached(&mut self, detached: bool, frame_only: bool) {
+        self.detached = detached;
+        self.frame_only = frame_only;
+    }
+
+    /// Returns true if the keyboard input is sent to the guest
+    pub fn captures_keyboard(&self, ctx: &egui::Context) -> bool {
+        self.input_id
+            .map_or(false, |id| ctx.memory(|memory| memory.has_focus(id)))
+    }
+
+    /// Returns true once after the detach button was clicked
+    pub fn take_detach_request(&mut self) -> bool {
+        std::mem::take(&mut self.detach_requested)
+    }
+
+    /// Runs an action of a keyboard shortcut that applies to a single tab
+    pub fn run_action(&mut self, action: Action, config: &MirrorConfig) {
+        match action {
+            Action::SaveFrame if self.capture.is_some() => self.screenshot_requested = true,
+            Action::ZoomIn => self.viewer.zoom_in(),
+            Action::ZoomOut => self.viewer.zoom_out(),
+            Action::ResetZoom => self.viewer.reset(),
+            Action::Reconnect => self.reconnect(config),
+            _ => (),
+        }
+    }
 
-        // create capture instance
-        let mut capture: Box<dyn Capture> = if config.multithreading {
-            Box::new(ThreadedCapture::new(os))
+    /// Drops the connection and connects again, recordings and servers keep running
+    fn reconnect(&mut self, config: &MirrorConfig) {
+        if self.connecting.is_some() {
+            return;
+        }
+
+        self.capture = None;
+        if self.connection_mode == ConnectionMode::File {
+            // recordings are opened again from the start
+            match FileCapture::open(&self.replay_path) {
+                Ok(capture) => self.set_capture(Box::new(capture)),
+                Err(err) => warn!("unable to open recording {}: {}", self.replay_path, err),
+            }
         } else {
-            Box::new(SequentialCapture::new(os))
+            self.start_connect(config);
+        }
+    }
+
+    /// Takes over the connection and the options of the profile
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        self.profile_name = profile.name.clone();
+        self.connection_mode = profile.mode;
+        if !profile.chain.is_empty() {
+            self.chain = profile.chain.clone();
+        }
+        self.process_name = profile.process_name.clone();
+        self.capture_options = profile.capture;
+        self.display = profile.display;
+    }
+
+    /// Returns the connection and the options of the tab as a profile
+    fn to_profile(&self, name: &str) -> Profile {
+        Profile {
+            name: name.to_string(),
+            mode: self.connection_mode,
+            chain: self.chain.clone(),
+            process_name: self.process_name.clone(),
+            capture: self.capture_options,
+            display: self.display,
+        }
+    }
+
+    /// Returns the capture options of the profile or of the settings if the profile does not override them
+    fn capture_options(&self, config: &MirrorConfig) -> CaptureOptions {
+        self.capture_options
+            .unwrap_or_else(|| config.capture_options())
+    }
+
+    /// Connects in the background using the current chain and mode, the result is picked up by the ui
+    pub fn start_connect(&mut self, config: &MirrorConfig) {
+        let target = match self.connection_mode {
+            ConnectionMode::Os => ChainStageKind::Os,
+            _ => ChainStageKind::Connector,
         };
-        Self::update_capture_flags(&mut capture, config);
+        if let Err(err) = validate(&self.chain, target) {
+            self.connect_error = Some(err);
+            return;
+        }
 
-        Ok(Self {
-            id,
+        let chain = self.chain.clone();
+        let connection_mode = self.connection_mode;
+        let framebuffer_config = self.framebuffer_config.clone();
+        let capture_options = self.capture_options(config);
+        let process_name = self.process_name.clone();
+        let id = self.id;
+        let thread_handle = thread::spawn(move || -> Result<Box<dyn Capture + Send>> {
+            let _log_scope = log_console::tab_scope(id);
 
-            inventory,
-            selected_connector: Some(last_connector.clone()),
-            connect_on_startup: true,
+            // loading the plugins can take a while so the inventory is also created in the background
+            let inventory = Inventory::scan();
+            let capture: Box<dyn Capture + Send> = match connection_mode {
+                ConnectionMode::Os => os_capture(
+                    build_os(&inventory, &chain)?,
+                    capture_options,
+                    &process_name,
+                ),
+                ConnectionMode::Physical => {
+                    Box::new(PhysicalCapture::new(build_connector(&inventory, &chain)?))
+                }
+                ConnectionMode::Framebuffer => Box::new(FramebufferCapture::new(
+                    build_connector(&inventory, &chain)?,
+                    framebuffer_config,
+                )),
+                ConnectionMode::File => {
+                    return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration))
+                }
+            };
+            Ok(capture)
+        });
 
-            capture: Some(capture),
+        self.connect_error = None;
+        self.connecting = Some(PendingConnection {
+            thread_handle,
+            label: chain_label(&self.chain),
+            started: Instant::now(),
+        });
+    }
 
-            frame_counter: 0,
-            frame_texture: None,
-            cursor: None,
-        })
+    /// Takes the capture of a finished connection attempt
+    fn poll_connect(&mut self, config: &mut MirrorConfig, toasts: &mut Toasts) {
+        match &self.connecting {
+            Some(connecting) if connecting.thread_handle.is_finished() => (),
+            _ => return,
+        }
+        let connecting = self.connecting.take().unwrap();
+
+        let result = match connecting.thread_handle.join() {
+            Ok(result) => result.map_err(|err| err.to_string()),
+            Err(_) => Err("the connection attempt panicked".to_string()),
+        };
+        match result {
+            Ok(capture) => {
+                let mut capture: Box<dyn Capture> = capture;
+                Self::update_capture_flags(&mut capture, self.capture_options(config));
+                self.set_capture(capture);
+                toasts.success(format!("Connected to {}", connecting.label));
+
+                // update configuration, new tabs start with the chain of the last connection
+                config.last_chain = self.chain.clone();
+                config.save().map_err(|err| warn!("{}", err)).ok();
+            }
+            Err(err) => {
+                let err = format!("Unable to connect to {}: {}", connecting.label, err);
+                warn!("{}", err);
+                toasts
+                    .error(err.clone())
+                    .set_duration(Some(Duration::from_secs(10)));
+                self.connect_error = Some(err);
+            }
+        }
+    }
+
+    /// Replaces the capture, running recordings and servers keep receiving the frames of the new capture
+    fn set_capture(&mut self, mut capture: Box<dyn Capture>) {
+        if let Some(recorder) = &self.recorder {
+            capture.add_frame_sink(recorder.frame_sink());
+        }
+        #[cfg(feature = "preview-server")]
+        if let Some(preview_server) = &self.preview_server {
+            capture.add_frame_sink(preview_server.frame_sink());
+        }
+        #[cfg(feature = "vnc-server")]
+        if let Some(vnc_server) = &self.vnc_server {
+            capture.add_frame_sink(vnc_server.frame_sink());
+        }
+        #[cfg(all(unix, feature = "shared-memory"))]
+        if let Some(shm_publisher) = &self.shm_publisher {
+            capture.add_frame_sink(shm_publisher.frame_sink());
+        }
+        self.capture = Some(capture);
+    }
+
+    /// Starts recording with the given configuration once the tab is capturing
+    pub fn start_recording(&mut self, config: RecorderConfig) {
+        self.recorder_config = config;
+        self.record_on_capture = true;
     }
 }
 
 impl CaptureTab {
-    fn ui_connection(&mut self, ui: &mut egui::Ui, config: &mut MirrorConfig) {
+    #[cfg(feature = "preview-server")]
+    pub fn start_preview_server(&mut self, config: PreviewServerConfig) {
+        match PreviewServer::start(config) {
+            Ok(preview_server) => {
+                if let Some(capture) = &mut self.capture {
+                    capture.add_frame_sink(preview_server.frame_sink());
+                }
+                self.preview_server = Some(preview_server);
+            }
+            Err(err) => warn!("unable to start preview server: {}", err),
+        }
+    }
+
+    #[cfg(feature = "vnc-server")]
+    pub fn start_vnc_server(&mut self, config: VncServerConfig) {
+        match VncServer::start(config) {
+            Ok(vnc_server) => {
+                if let Some(capture) = &mut self.capture {
+                    capture.add_frame_sink(vnc_server.frame_sink());
+                }
+                self.vnc_server = Some(vnc_server);
+            }
+            Err(err) => warn!("unable to start vnc server: {}", err),
+        }
+    }
+
+    #[cfg(all(unix, feature = "shared-memory"))]
+    pub fn start_shm_publisher(&mut self, config: ShmPublisherConfig) {
+        match ShmPublisher::create(config) {
+            Ok(shm_publisher) => {
+                if let Some(capture) = &mut self.capture {
+                    capture.add_frame_sink(shm_publisher.frame_sink());
+                }
+                self.shm_publisher = Some(shm_publisher);
+            }
+            Err(err) => warn!("unable to create shared memory: {}", err),
+        }
+    }
+
+    fn ui_connection(&mut self, ui: &mut egui::Ui, config: &mut MirrorConfig, toasts: &mut Toasts) {
         ui.label("Connection:".to_string());
 
-        let connectors = self.inventory.available_connectors();
-        let mut selected = self
-            .selected_connector
-            .clone()
-            .unwrap_or_else(|| "None".to_string());
-        let prev_selected = selected.clone();
+        if let Some(connecting) = &self.connecting {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!(
+                    "Connecting to {} ({}s)",
+                    connecting.label,
+                    connecting.started.elapsed().as_secs()
+                ));
+            });
+            return;
+        }
+
+        self.ui_profiles(ui, config, toasts);
+        ui.separator();
 
-        egui::ComboBox::from_label("Connector")
-            .selected_text(selected.clone())
+        egui::ComboBox::from_label("Mode")
+            .selected_text(self.connection_mode.label())
             .show_ui(ui, |ui| {
-                ui.selectable_value(&mut selected, "None".to_string(), "None");
-                for option in connectors.iter() {
-                    ui.selectable_value(&mut selected, option.clone(), option);
+                for mode in ConnectionMode::ALL {
+                    ui.selectable_value(&mut self.connection_mode, mode, mode.label());
                 }
             });
 
-        if selected != prev_selected {
-            self.selected_connector = Some(selected);
+        // replays do not require a connector
+        if self.connection_mode == ConnectionMode::File {
+            self.ui_replay(ui, toasts);
+            return;
+        }
+
+        // the guest agent is reached through an os, the other modes read the physical memory of a connector
+        let target = match self.connection_mode {
+            ConnectionMode::Os => ChainStageKind::Os,
+            _ => ChainStageKind::Connector,
+        };
+        ui.separator();
+        self.chain_editor
+            .ui(ui, self.id, &self.inventory, &mut self.chain, target);
+        ui.separator();
+
+        if self.connection_mode == ConnectionMode::Framebuffer {
+            self.ui_framebuffer(ui);
+        }
+
+        if self.connection_mode == ConnectionMode::Os {
+            self.ui_capture_options(ui, config);
         }
 
         ui.add(egui::Checkbox::new(
@@ -157,134 +599,929 @@ impl CaptureTab {
             "Connect on next startup",
         ));
 
-        // TODO: chaining + os selector
-        // TODO: connector+os args
-        // TODO: logging window
-        // TODO: store last connection + autoconnect
         if ui
             .add_enabled(
-                self.selected_connector.is_some(),
+                validate(&self.chain, target).is_ok(),
                 egui::Button::new("Connect"),
             )
             .clicked()
         {
-            let connector = self
-                .inventory
-                .create_connector(self.selected_connector.as_ref().unwrap(), None, None)
-                .unwrap(); // TODO:
-            let os = self
-                .inventory
-                .create_os("win32", Some(connector), None)
-                .unwrap(); // TODO:
-
-            // create capture instance
-            let mut capture: Box<dyn Capture> = if config.multithreading {
-                Box::new(ThreadedCapture::new(os))
+            self.start_connect(config);
+        }
+
+        let mut retry = false;
+        if let Some(err) = &self.connect_error {
+            ui.horizontal(|ui| {
+                ui.colored_label(Color32::RED, err);
+                retry = ui.button("Retry").clicked();
+            });
+        }
+        if retry {
+            self.start_connect(config);
+        }
+    }
+
+    fn ui_profiles(&mut self, ui: &mut egui::Ui, config: &mut MirrorConfig, toasts: &mut Toasts) {
+        let mut selected = None;
+        egui::ComboBox::from_label("Profile")
+            .selected_text(if self.profile_name.is_empty() {
+                "None"
+            } else {
+                self.profile_name.as_str()
+            })
+            .show_ui(ui, |ui| {
+                for profile in config.profiles.iter() {
+                    if ui
+                        .selectable_label(profile.name == self.profile_name, &profile.name)
+                        .clicked()
+                    {
+                        selected = Some(profile.clone());
+                    }
+                }
+            });
+        if let Some(profile) = selected {
+            self.apply_profile(&profile);
+        }
+
+        let exists = config.profile(&self.profile_name).is_some();
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.profile_name)
+                    .hint_text("profile name")
+                    .desired_width(160.0),
+            );
+            if ui
+                .add_enabled(!self.profile_name.is_empty(), egui::Button::new("Save"))
+                .on_hover_text("Stores the connection and the options of this tab")
+                .clicked()
+            {
+                config.set_profile(self.to_profile(&self.profile_name));
+                config.save().map_err(|err| warn!("{}", err)).ok();
+            }
+            if ui
+                .add_enabled(exists, egui::Button::new("Delete"))
+                .clicked()
+            {
+                config.remove_profile(&self.profile_name);
+                config.save().map_err(|err| warn!("{}", err)).ok();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let default_path = MirrorConfig::profile_path(if self.profile_name.is_empty() {
+                "profile"
+            } else {
+                &self.profile_name
+            });
+            ui.add(
+                egui::TextEdit::singleline(&mut self.profile_path)
+                    .hint_text(default_path.to_string_lossy().to_string())
+                    .desired_width(240.0),
+            );
+            let path = if self.profile_path.is_empty() {
+                default_path
             } else {
-                Box::new(SequentialCapture::new(os))
+                PathBuf::from(&self.profile_path)
             };
-            Self::update_capture_flags(&mut capture, config);
-            self.capture = Some(capture);
 
-            // update configuration
-            config.connect_on_startup = self.connect_on_startup;
-            config.last_connector = Some(self.selected_connector.as_ref().unwrap().clone());
-            config.last_connector_args = None;
-            config.last_os = Some("win32".to_string());
-            config.last_os_args = None;
-            config.save().ok();
+            if ui
+                .button("Import")
+                .on_hover_text(
+                    "Adds the profile of the file and selects it, \
+                    existing profiles with the same name are kept",
+                )
+                .clicked()
+            {
+                match Profile::import(&path) {
+                    Ok(mut profile) => {
+                        let name = config.unique_profile_name(&profile.name);
+                        if name != profile.name {
+                            toasts.info(format!(
+                                "Imported profile {} as {}, a profile with its name already exists",
+                                profile.name, name
+                            ));
+                            profile.name = name;
+                        } else {
+                            toasts.info(format!("Imported profile {}", profile.name));
+                        }
+                        self.apply_profile(&profile);
+                        config.set_profile(profile);
+                        config.save().map_err(|err| warn!("{}", err)).ok();
+                    }
+                    Err(err) => {
+                        let err = format!("Unable to import {}: {}", path.display(), err);
+                        warn!("{}", err);
+                        toasts.error(err);
+                    }
+                }
+            }
+            if ui
+                .add_enabled(!self.profile_name.is_empty(), egui::Button::new("Export"))
+                .clicked()
+            {
+                match self.to_profile(&self.profile_name).export(&path) {
+                    Ok(()) => {
+                        toasts.info(format!("Exported profile to {}", path.display()));
+                    }
+                    Err(err) => {
+                        let err = format!("Unable to export {}: {}", path.display(), err);
+                        warn!("{}", err);
+                        toasts.error(err);
+                    }
+                }
+            }
+        });
+    }
+
+    fn ui_capture_options(&mut self, ui: &mut egui::Ui, config: &MirrorConfig) {
+        ui.horizontal(|ui| {
+            ui.label("Process");
+            ui.text_edit_singleline(&mut self.process_name)
+                .on_hover_text("Name of the guest agent process");
+        });
+
+        let mut override_options = self.capture_options.is_some();
+        if ui
+            .checkbox(&mut override_options, "Override capture settings")
+            .changed()
+        {
+            self.capture_options = Some(config.capture_options()).filter(|_| override_options);
+        }
+        if let Some(options) = &mut self.capture_options {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut options.multithreading, "Multithreaded Capture");
+                ui.checkbox(&mut options.obs_capture, "OBS Capture");
+            });
         }
     }
 
-    fn ui_capturing(&mut self, ui: &mut egui::Ui, config: &MirrorConfig) {
+    fn ui_replay(&mut self, ui: &mut egui::Ui, toasts: &mut Toasts) {
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.replay_path)
+                .on_hover_text("A recording in the mirror replay format");
+        });
+
+        if ui
+            .add_enabled(!self.replay_path.is_empty(), egui::Button::new("Open"))
+            .clicked()
+        {
+            match FileCapture::open(&self.replay_path) {
+                Ok(capture) => self.set_capture(Box::new(capture)),
+                Err(err) => {
+                    let err = format!("Unable to open recording {}: {}", self.replay_path, err);
+                    warn!("{}", err);
+                    toasts.error(err);
+                }
+            }
+        }
+    }
+
+    fn ui_framebuffer(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("framebuffer")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Base address");
+                if ui
+                    .text_edit_singleline(&mut self.framebuffer_base)
+                    .changed()
+                {
+                    if let Some(base) = parse_address(&self.framebuffer_base) {
+                        self.framebuffer_config.base = base;
+                    }
+                }
+                ui.end_row();
+
+                ui.label("Resolution");
+                ui.horizontal(|ui| {
+                    // the stride follows the width, it only has to be changed for padded scanlines
+                    if ui
+                        .add(egui::DragValue::new(&mut self.framebuffer_config.width))
+                        .changed()
+                    {
+                        self.framebuffer_config.reset_stride();
+                    }
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut self.framebuffer_config.height));
+                });
+                ui.end_row();
+
+                ui.label("Stride (bytes)");
+                ui.add(egui::DragValue::new(&mut self.framebuffer_config.stride));
+                ui.end_row();
+
+                ui.label("Pixel format");
+                let mut format_changed = false;
+                egui::ComboBox::from_id_source("framebuffer_format")
+                    .selected_text(self.framebuffer_config.format.label())
+                    .show_ui(ui, |ui| {
+                        for format in PixelFormat::ALL {
+                            format_changed |= ui
+                                .selectable_value(
+                                    &mut self.framebuffer_config.format,
+                                    format,
+                                    format.label(),
+                                )
+                                .changed();
+                        }
+                    });
+                if format_changed {
+                    self.framebuffer_config.reset_stride();
+                }
+                ui.end_row();
+            });
+
+        // the whole physical memory is scanned so the detection runs in the background
+        if self
+            .framebuffer_detect
+            .as_ref()
+            .map(|thread_handle| thread_handle.is_finished())
+            .unwrap_or(false)
+        {
+            match self.framebuffer_detect.take().unwrap().join() {
+                Ok(Ok(candidates)) => self.framebuffer_candidates = candidates,
+                Ok(Err(err)) => warn!("unable to create connector: {}", err),
+                Err(_) => warn!("framebuffer detection panicked"),
+            }
+        }
+
+        if self.framebuffer_detect.is_some() {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Scanning physical memory");
+            });
+        } else if ui
+            .add_enabled(
+                validate(&self.chain, ChainStageKind::Connector).is_ok(),
+                egui::Button::new("Detect framebuffer"),
+            )
+            .on_hover_text("Scans the physical memory for plausible framebuffer regions")
+            .clicked()
+        {
+            let chain = self.chain.clone();
+            let id = self.id;
+            self.framebuffer_detect = Some(thread::spawn(move || {
+                let _log_scope = log_console::tab_scope(id);
+                let inventory = Inventory::scan();
+                let mut connector = build_connector(&inventory, &chain)?;
+                Ok(detect_framebuffers(&mut connector))
+            }));
+        }
+
+        let mut selected_candidate = None;
+        for candidate in self.framebuffer_candidates.iter() {
+            let config = &candidate.config;
+            if ui
+                .selectable_label(
+                    self.framebuffer_config == *config,
+                    format!(
+                        "{:x}: {}x{} {} (score {:.1})",
+                        config.base,
+                        config.width,
+                        config.height,
+                        config.format.label(),
+                        candidate.score
+                    ),
+                )
+                .clicked()
+            {
+                selected_candidate = Some(config.clone());
+            }
+        }
+        if let Some(config) = selected_candidate {
+            self.framebuffer_base = format!("{:x}", config.base);
+            self.framebuffer_config = config;
+        }
+    }
+
+    fn ui_capturing(&mut self, ui: &mut egui::Ui, config: &MirrorConfig, toasts: &mut Toasts) {
         ui.vertical_centered(|ui| {
             self.update_capture_config(config);
+            self.update_recording();
+            if !self.frame_only {
+                self.ui_recording(ui);
+                self.ui_instant_replay(ui);
+            }
 
             let capture = self.capture.as_mut().unwrap();
 
             // update internal state, then read frame_counter and image_data
             capture.update();
 
+            if config.clipboard_sync {
+                self.clipboard_sync.update(capture);
+            }
+
             let frame_counter = capture.frame_counter();
 
-            // only update frame_texture on demand
-            if frame_counter != self.frame_counter {
+            // only update frame_texture on demand, a different filter requires uploading the frame again
+            let filter_changed =
+                self.frame_texture.is_some() && self.frame_filter != self.display.filter;
+            if frame_counter != self.frame_counter || filter_changed {
                 let frame = capture.image_data();
+                if let egui::ImageData::Color(image) = &frame {
+                    self.frame_image = Some(image.clone());
+                }
                 self.frame_counter = frame_counter;
+                self.frame_filter = self.display.filter;
 
+                let options = match self.frame_filter {
+                    TextureFilter::Linear => egui::TextureOptions::LINEAR,
+                    TextureFilter::Nearest => egui::TextureOptions::NEAREST,
+                };
                 if let Some(frame_texture) = &mut self.frame_texture {
-                    frame_texture.set(frame, egui::TextureOptions::LINEAR);
+                    frame_texture.set(frame, options);
                 } else {
-                    self.frame_texture = Some(ui.ctx().load_texture(
-                        "frame",
-                        frame,
-                        egui::TextureOptions::LINEAR,
+                    self.frame_texture = Some(ui.ctx().load_texture("frame", frame, options));
+                }
+            }
+
+            #[cfg(feature = "preview-server")]
+            if let Some(preview_server) = &self.preview_server {
+                if !self.frame_only {
+                    ui.label(format!(
+                        "Serving on http://{} ({} clients)",
+                        preview_server.address(),
+                        preview_server.clients()
+                    ));
+                }
+            }
+
+            #[cfg(feature = "vnc-server")]
+            if let Some(vnc_server) = &self.vnc_server {
+                if !self.frame_only {
+                    ui.label(format!(
+                        "Serving vnc on {} ({} clients)",
+                        vnc_server.address(),
+                        vnc_server.clients()
+                    ));
+                }
+            }
+
+            #[cfg(all(unix, feature = "shared-memory"))]
+            if let Some(shm_publisher) = &self.shm_publisher {
+                if !self.frame_only {
+                    ui.label(format!(
+                        "Publishing in shared memory {} ({} frames)",
+                        shm_publisher.name(),
+                        shm_publisher.frames_published()
                     ));
                 }
             }
 
-            // render frame_texture
+            if !self.frame_only {
+                if let Some(playback) = capture.playback() {
+                    Self::ui_playback(ui, self.id, playback);
+                }
+            }
+
+            let screenshot_options = &mut self.screenshot_options;
+            let frame_size = self
+                .frame_texture
+                .as_ref()
+                .map(|frame_texture| frame_texture.size())
+                .unwrap_or_default();
+            // a screenshot can also be requested with the shortcut while the controls are hidden
+            let mut take_screenshot = std::mem::take(&mut self.screenshot_requested);
+            if !self.frame_only {
+                ui.horizontal(|ui| {
+                    take_screenshot |= ui
+                        .button("Screenshot")
+                        .on_hover_text("Saves the current frame and copies it into the clipboard")
+                        .clicked();
+                    ui.checkbox(&mut screenshot_options.cursor, "Cursor");
+
+                    let mut crop = screenshot_options.crop.is_some();
+                    if ui.checkbox(&mut crop, "Crop").changed() {
+                        screenshot_options.crop =
+                            Some([0, 0, frame_size[0] as u32, frame_size[1] as u32])
+                                .filter(|_| crop);
+                    }
+                    if let Some(crop) = &mut screenshot_options.crop {
+                        for (value, label) in crop.iter_mut().zip(["x", "y", "w", "h"]) {
+                            ui.add(egui::DragValue::new(value).prefix(format!("{}: ", label)));
+                        }
+                    }
+                });
+            }
+
+            if take_screenshot {
+                let mut screenshot = capture.screenshot();
+                if !self.chain.is_empty() {
+                    screenshot = screenshot.with_guest(GuestInfo {
+                        chain: chain_label(&self.chain),
+                        os: self
+                            .chain
+                            .iter()
+                            .rfind(|stage| stage.kind == ChainStageKind::Os)
+                            .map(|stage| stage.name.clone()),
+                        // only captures of an os read the frames from the guest agent process
+                        process: capture.os().map(|_| self.process_name.clone()),
+                    });
+                }
+
+                let image = screenshot.image(&self.screenshot_options);
+                self.clipboard_sync
+                    .copy_image(image.width(), image.height(), image.into_raw());
+
+                // encoding the png takes a while so it is saved in the background
+                let path = default_screenshot_dir().join(format!(
+                    "screenshot-{}.png",
+                    screenshot
+                        .captured_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis()
+                ));
+                let options = self.screenshot_options.clone();
+                thread::spawn(move || match screenshot.save(&path, &options) {
+                    Ok(_) => info!("saved screenshot to {:?}", path),
+                    Err(err) => warn!("unable to save screenshot: {}", err),
+                });
+            }
+
+            let id = self.id;
+            let display = &mut self.display;
+            let viewer = &mut self.viewer;
+            let inspector = &mut self.inspector;
+            let detached = self.detached;
+            let detach_requested = &mut self.detach_requested;
+            if !self.frame_only {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source(("scale_mode", id))
+                        .selected_text(display.scale_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in ScaleMode::ALL {
+                                ui.selectable_value(&mut display.scale_mode, mode, mode.label());
+                            }
+                        });
+                    egui::ComboBox::from_id_source(("texture_filter", id))
+                        .selected_text(display.filter.label())
+                        .show_ui(ui, |ui| {
+                            for filter in TextureFilter::ALL {
+                                ui.selectable_value(&mut display.filter, filter, filter.label());
+                            }
+                        });
+                    if ui
+                        .add_enabled(
+                            !viewer.is_reset(),
+                            egui::Button::new(format!("{:.0}%", viewer.zoom() * 100.0)),
+                        )
+                        .on_hover_text("Resets zoom and pan (double click on the frame)")
+                        .clicked()
+                    {
+                        viewer.reset();
+                    }
+                    inspector.ui_controls(ui);
+
+                    ui.checkbox(&mut display.show_cursor, "Show cursor");
+                    if capture.supports_input() {
+                        ui.checkbox(&mut display.capture_input, "Capture input")
+                            .on_hover_text(
+                                "Forwards mouse and keyboard input to the guest.\n\
+                            Click on the frame to capture the keyboard.\n\
+                            Zoom (mouse wheel) and pan (drag) are available \
+                            while input is not captured.",
+                            );
+                    }
+
+                    if !detached
+                        && ui
+                            .button("⏏ Detach")
+                            .on_hover_text("Shows the tab in its own window")
+                            .clicked()
+                    {
+                        *detach_requested = true;
+                    }
+                });
+            }
+
+            // render frame_texture, the frame can be larger than the tab when zoomed in
+            let interactive = !(self.display.capture_input && capture.supports_input());
+            let (available, response) = ui.allocate_exact_size(
+                ui.available_size(),
+                if interactive {
+                    egui::Sense::click_and_drag()
+                } else {
+                    egui::Sense::hover()
+                },
+            );
+            let mut frame_rect = None;
             if let Some(frame_texture) = &self.frame_texture {
                 let texture_size = frame_texture.size();
-                let aspect_ratio = texture_size[0] as f32 / texture_size[1] as f32;
-                let desired_height = ui.available_height();
-                let desired_width = desired_height * aspect_ratio;
+                let rect = self.viewer.frame_rect(
+                    available,
+                    texture_size,
+                    self.display.scale_mode,
+                    ui.ctx().pixels_per_point(),
+                );
 
-                let render_position = ui
-                    .add(egui::Image::new(egui::load::SizedTexture::new(
-                        frame_texture.id(),
-                        [desired_width, desired_height],
-                    )))
-                    .rect;
+                let painter = ui.painter_at(available);
+                painter.image(
+                    frame_texture.id(),
+                    rect,
+                    Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+                    Color32::WHITE,
+                );
 
                 // render cursor on top of frame
                 let cursor_data = capture.cursor_data();
-                if cursor_data.is_visible != 0 {
+                if self.display.show_cursor && cursor_data.is_visible != 0 {
                     let cursor = self.cursor_texture(ui);
 
                     let (x, y, w, h) = {
-                        let scale_x = desired_width / texture_size[0] as f32;
-                        let scale_y = desired_height / texture_size[1] as f32;
+                        let scale_x = rect.width() / texture_size[0] as f32;
+                        let scale_y = rect.height() / texture_size[1] as f32;
                         (
-                            render_position.left() + cursor_data.x as f32 * scale_x,
-                            render_position.top() + cursor_data.y as f32 * scale_y,
+                            rect.left() + cursor_data.x as f32 * scale_x,
+                            rect.top() + cursor_data.y as f32 * scale_y,
                             cursor.size()[0] as f32 * scale_x,
                             cursor.size()[1] as f32 * scale_y,
                         )
                     };
-                    ui.painter().image(
+                    painter.image(
                         cursor.id(),
                         Rect::from_min_max(pos2(x, y), pos2(x + w, y + h)),
                         Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
                         Color32::WHITE,
                     );
                 }
+
+                // the inspector reads the pixels of the frame, so it is only available while input is not captured
+                if interactive && self.inspector.is_active() {
+                    if response.hovered() {
+                        ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+                    }
+                    if let Some(image) = &self.frame_image {
+                        if let Some(color) = self
+                            .inspector
+                            .ui_overlay(ui, &response, &painter, rect, image)
+                        {
+                            toasts.info(format!("Copied {} into the clipboard", color));
+                        }
+                    }
+                }
+
+                frame_rect = Some((rect, texture_size));
+            }
+
+            if let Some((rect, frame_size)) = frame_rect {
+                if interactive {
+                    self.viewer.handle_input(ui, &response, rect);
+                }
+                self.handle_input(ui, rect, available, frame_size);
             }
         });
     }
 
+    fn ui_playback(ui: &mut egui::Ui, id: usize, playback: &mut dyn Playback) {
+        ui.horizontal(|ui| {
+            if playback.is_playing() {
+                if ui.button("Pause").clicked() {
+                    playback.pause();
+                }
+            } else if ui.button("Play").clicked() {
+                playback.play();
+            }
+
+            let mut position = playback.position().as_secs_f32();
+            let duration = playback.duration().as_secs_f32();
+            if ui
+                .add(egui::Slider::new(&mut position, 0.0..=duration).suffix(" s"))
+                .changed()
+            {
+                playback.seek(Duration::from_secs_f32(position));
+            }
+
+            let mut speed = playback.speed();
+            let mut speed_changed = false;
+            egui::ComboBox::from_id_source(("playback_speed", id))
+                .selected_text(format!("{}x", speed))
+                .show_ui(ui, |ui| {
+                    for option in [0.25, 0.5, 1.0, 2.0, 4.0] {
+                        speed_changed |= ui
+                            .selectable_value(&mut speed, option, format!("{}x", option))
+                            .changed();
+                    }
+                });
+            if speed_changed {
+                playback.set_speed(speed);
+            }
+        });
+    }
+
+    /// Starts a requested recording and stops a recording that failed, the controls might be hidden
+    fn update_recording(&mut self) {
+        if self.record_on_capture {
+            self.record_on_capture = false;
+            match Recorder::start(self.recorder_config.clone()) {
+                // the capture passes its frames to the recorder, even while the tab is hidden
+                Ok(recorder) => {
+                    if let Some(capture) = &mut self.capture {
+                        capture.add_frame_sink(recorder.frame_sink());
+                    }
+                    self.recorder = Some(recorder);
+                }
+                Err(err) => warn!("unable to start recording: {}", err),
+            }
+        }
+
+        if self
+            .recorder
+            .as_ref()
+            .map(|recorder| !recorder.is_running())
+            .unwrap_or(false)
+        {
+            self.stop_recording();
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(err) = recorder.stop() {
+                warn!("unable to finish recording: {}", err);
+            }
+        }
+    }
+
+    fn ui_recording(&mut self, ui: &mut egui::Ui) {
+        let mut stop_recording = false;
+        ui.horizontal(|ui| match &self.recorder {
+            Some(recorder) => {
+                stop_recording = ui.button("Stop recording").clicked();
+                ui.label(format!(
+                    "{} frames ({:.1} MB) written, {} dropped",
+                    recorder.frames_written(),
+                    recorder.bytes_written() as f64 / (1024.0 * 1024.0),
+                    recorder.frames_dropped()
+                ));
+            }
+            None => {
+                egui::ComboBox::from_id_source(("record_format", self.id))
+                    .selected_text(self.recorder_config.format.label())
+                    .show_ui(ui, |ui| {
+                        for format in RecordFormat::ALL {
+                            ui.selectable_value(
+                                &mut self.recorder_config.format,
+                                format,
+                                format.label(),
+                            );
+                        }
+                    });
+
+                // a value of 0 disables splitting
+                let mut split_size =
+                    self.recorder_config.max_segment_size.unwrap_or(0) / (1024 * 1024);
+                ui.label("Split after");
+                if ui
+                    .add(egui::DragValue::new(&mut split_size).suffix(" MB"))
+                    .changed()
+                {
+                    self.recorder_config.max_segment_size =
+                        Some(split_size * 1024 * 1024).filter(|&size| size > 0);
+                }
+                let mut split_duration = self
+                    .recorder_config
+                    .max_segment_duration
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or(0);
+                ui.label("or");
+                if ui
+                    .add(egui::DragValue::new(&mut split_duration).suffix(" s"))
+                    .changed()
+                {
+                    self.recorder_config.max_segment_duration =
+                        Some(Duration::from_secs(split_duration)).filter(|d| !d.is_zero());
+                }
+
+                if ui
+                    .button("Record")
+                    .on_hover_text(format!("Records into {:?}", self.recorder_config.path))
+                    .clicked()
+                {
+                    self.record_on_capture = true;
+                }
+            }
+        });
+
+        if stop_recording {
+            self.stop_recording();
+        }
+    }
+
+    fn ui_instant_replay(&mut self, ui: &mut egui::Ui) {
+        let Self {
+            id,
+            capture,
+            recorder_config,
+            replay_config,
+            replay_save_duration,
+            replay_save,
+            clip_export_config,
+            ..
+        } = self;
+        let capture = capture.as_mut().unwrap();
+        if !capture.supports_replay() {
+            return;
+        }
+
+        // report the result of the last save once it is done
+        if replay_save
+            .as_ref()
+            .map(|thread_handle| thread_handle.is_finished())
+            .unwrap_or(false)
+        {
+            match replay_save.take().unwrap().join() {
+                Ok(Ok(frames)) => info!("saved {} frames of the instant replay", frames),
+                Ok(Err(err)) => warn!("unable to save instant replay: {}", err),
+                Err(_) => warn!("instant replay writer panicked"),
+            }
+        }
+
+        ui.horizontal(|ui| {
+            let mut enabled = capture.replay_config().is_some();
+            if ui
+                .checkbox(&mut enabled, "Instant replay")
+                .on_hover_text(
+                    "Keeps the most recent frames in memory \
+                    so they can be saved after something happened.",
+                )
+                .changed()
+            {
+                capture.set_replay_config(Some(replay_config.clone()).filter(|_| enabled));
+            }
+
+            let mut changed = false;
+            let mut duration = replay_config.duration.as_secs();
+            ui.label("Keep");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut duration)
+                        .clamp_range(1..=600)
+                        .suffix(" s"),
+                )
+                .changed()
+            {
+                replay_config.duration = Duration::from_secs(duration);
+                changed = true;
+            }
+            egui::ComboBox::from_id_source(("replay_downscale", *id))
+                .selected_text(format!("1/{} scale", replay_config.downscale))
+                .show_ui(ui, |ui| {
+                    for downscale in [1, 2, 4] {
+                        changed |= ui
+                            .selectable_value(
+                                &mut replay_config.downscale,
+                                downscale,
+                                format!("1/{} scale", downscale),
+                            )
+                            .changed();
+                    }
+                });
+            changed |= ui
+                .checkbox(&mut replay_config.compress, "Compress")
+                .changed();
+            if changed && enabled {
+                capture.set_replay_config(Some(replay_config.clone()));
+            }
+
+            if enabled {
+                ui.label("Save last");
+                ui.add(
+                    egui::DragValue::new(replay_save_duration)
+                        .clamp_range(1..=600)
+                        .suffix(" s"),
+                );
+                if ui
+                    .add_enabled(replay_save.is_none(), egui::Button::new("Save"))
+                    .on_hover_text(format!(
+                        "Saves the clip as {} into {:?}",
+                        recorder_config.format.label(),
+                        recorder_config.path
+                    ))
+                    .clicked()
+                {
+                    if let Some(clip) =
+                        capture.replay_clip(Duration::from_secs(*replay_save_duration))
+                    {
+                        let config = recorder_config.clone();
+                        *replay_save =
+                            Some(thread::spawn(move || Recorder::save_clip(config, &clip)));
+                    }
+                }
+
+                egui::ComboBox::from_id_source(("clip_format", *id))
+                    .selected_text(clip_export_config.format.label())
+                    .show_ui(ui, |ui| {
+                        for format in ClipFormat::ALL {
+                            ui.selectable_value(
+                                &mut clip_export_config.format,
+                                format,
+                                format.label(),
+                            );
+                        }
+                    });
+                if ui
+                    .add_enabled(replay_save.is_none(), egui::Button::new("Export clip"))
+                    .on_hover_text(format!(
+                        "Exports a shareable clip at {} fps and {}% scale into {:?}",
+                        clip_export_config.max_frame_rate,
+                        (clip_export_config.scale * 100.0) as u32,
+                        recorder_config.path
+                    ))
+                    .clicked()
+                {
+                    if let Some(clip) =
+                        capture.replay_clip(Duration::from_secs(*replay_save_duration))
+                    {
+                        let dir = recorder_config.path.clone();
+                        let exporter = ClipExporter::new(clip_export_config.clone());
+                        *replay_save = Some(thread::spawn(move || {
+                            fs::create_dir_all(&dir)?;
+                            let path = dir.join(format!(
+                                "clip-{}.{}",
+                                unix_time(),
+                                exporter.config().format.extension()
+                            ));
+                            exporter.export(&ClipSource::Replay(clip), path)
+                        }));
+                    }
+                }
+            }
+        });
+    }
+
+    fn handle_input(&mut self, ui: &mut egui::Ui, rect: Rect, clip: Rect, frame_size: [usize; 2]) {
+        let capture = self.capture.as_mut().unwrap();
+
+        if !self.display.capture_input || !capture.supports_input() {
+            // make sure no modifiers are stuck on the guest after disabling input capture
+            let mut events = Vec::new();
+            self.input_mapper.release_modifiers(&mut events);
+            events
+                .into_iter()
+                .for_each(|event| capture.send_input(event));
+            self.input_id = None;
+            return;
+        }
+
+        let response = ui.interact(
+            rect.intersect(clip),
+            ui.id().with("input"),
+            egui::Sense::click(),
+        );
+        if response.clicked() {
+            response.request_focus();
+        }
+        self.input_id = Some(response.id);
+
+        let keyboard = response.has_focus();
+        if keyboard {
+            // keep tab and arrow keys from moving the focus away from the frame
+            ui.memory_mut(|memory| {
+                memory.set_focus_lock_filter(
+                    response.id,
+                    egui::EventFilter {
+                        tab: true,
+                        horizontal_arrows: true,
+                        vertical_arrows: true,
+                        escape: false,
+                    },
+                )
+            });
+        }
+
+        let input_mapper = &mut self.input_mapper;
+        let events = ui.input(|i| {
+            input_mapper.map_events(&i.events, i.modifiers, rect, clip, frame_size, keyboard)
+        });
+        events
+            .into_iter()
+            .for_each(|event| capture.send_input(event));
+    }
+
     fn update_capture_config(&mut self, config: &MirrorConfig) {
+        let options = self.capture_options(config);
+
         // update multithreading:
         if let Some(capture) = &mut self.capture {
-            if capture.multithreading() != config.multithreading {
-                // re-create capture
-                let os = capture.os();
-                if config.multithreading {
-                    self.capture = Some(Box::new(ThreadedCapture::new(os)));
-                } else {
-                    self.capture = Some(Box::new(SequentialCapture::new(os)));
+            if capture.multithreading() != options.multithreading {
+                // re-create capture (only os based captures support both modes)
+                if let Some(os) = capture.os() {
+                    let capture: Box<dyn Capture> = os_capture(os, options, &self.process_name);
+                    self.set_capture(capture);
                 }
             }
         }
 
         // update flags
         if let Some(capture) = &mut self.capture {
-            Self::update_capture_flags(capture, config);
+            Self::update_capture_flags(capture, options);
         }
     }
 
-    fn update_capture_flags(capture: &mut Box<dyn Capture>, config: &MirrorConfig) {
-        capture.set_obs_capture(config.obs_capture);
+    fn update_capture_flags(capture: &mut Box<dyn Capture>, options: CaptureOptions) {
+        capture.set_obs_capture(options.obs_capture);
     }
 
     fn cursor_texture<'a>(&mut self, ui: &'a mut egui::Ui) -> &egui::TextureHandle {
@@ -304,3 +1541,90 @@ impl CaptureTab {
         })
     }
 }
+
+/// Opens a capture for the connection that was used last
+pub fn connect_last(inventory: &Inventory, config: &MirrorConfig) -> Result<Box<dyn Capture>> {
+    let os = build_os(inventory, &config.last_chain)?;
+    let capture: Box<dyn Capture> = os_capture(os, config.capture_options(), DEFAULT_PROCESS_NAME);
+    Ok(capture)
+}
+
+/// Opens a capture for the connection of the profile
+pub fn connect_profile(
+    inventory: &Inventory,
+    config: &MirrorConfig,
+    profile: &Profile,
+) -> Result<Box<dyn Capture>> {
+    let options = profile.capture.unwrap_or_else(|| config.capture_options());
+    let capture: Box<dyn Capture> = match profile.mode {
+        ConnectionMode::Os => os_capture(
+            build_os(inventory, &profile.chain)?,
+            options,
+            &profile.process_name,
+        ),
+        ConnectionMode::Physical => Box::new(PhysicalCapture::new(build_connector(
+            inventory,
+            &profile.chain,
+        )?)),
+        // the framebuffer has to be located in the ui and replays are not stored in profiles
+        ConnectionMode::Framebuffer | ConnectionMode::File => {
+            return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration))
+        }
+    };
+    Ok(capture)
+}
+
+/// Creates a capture of the guest agent with the given options
+fn os_capture(
+    os: OsInstanceArcBox<'static>,
+    options: CaptureOptions,
+    process_name: &str,
+) -> Box<dyn Capture + Send> {
+    let mut capture: Box<dyn Capture + Send> = if options.multithreading {
+        Box::new(ThreadedCapture::with_process_name(os, process_name))
+    } else {
+        Box::new(SequentialCapture::with_process_name(os, process_name))
+    };
+    capture.set_obs_capture(options.obs_capture);
+    capture
+}
+
+/// Returns the chain of the last connection or a connector followed by the windows os
+fn default_chain(config: &MirrorConfig) -> Vec<ChainStage> {
+    if config.last_chain.is_empty() {
+        vec![ChainStage::connector(""), ChainStage::os("win32")]
+    } else {
+        config.last_chain.clone()
+    }
+}
+
+fn default_framebuffer_config() -> FramebufferConfig {
+    FramebufferConfig::new(Address::null(), 1024, 768, PixelFormat::Bgrx8888)
+}
+
+/// Parses a hexadecimal address with an optional `0x` prefix
+fn parse_address(text: &str) -> Option<Address> {
+    u64::from_str_radix(text.trim().trim_start_matches("0x"), 16)
+        .ok()
+        .map(Address::from)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn default_screenshot_dir() -> PathBuf {
+    dirs::picture_dir()
+        .map(|dir| dir.join("mirror"))
+        .unwrap_or_else(|| "./screenshots".into())
+}
+
+fn default_recorder_config() -> RecorderConfig {
+    let path = dirs::video_dir()
+        .map(|dir| dir.join("mirror"))
+        .unwrap_or_else(|| "./recordings".into());
+    RecorderConfig::new(path, RecordFormat::Y4m)
+}
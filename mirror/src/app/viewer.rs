@@ -0,0 +1,193 @@
+use ::egui_dock::egui::{self, Rect, Vec2};
+
+use crate::config::ScaleMode;
+
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 64.0;
+
+// scroll distance in points that doubles / halves the zoom
+const POINTS_PER_ZOOM_STEP: f32 = 200.0;
+
+// zoom factor of the zoom shortcuts
+const ZOOM_STEP: f32 = 1.25;
+
+/// Zoom and pan of the frame in a tab, the frame is placed according to the scale mode first
+pub struct Viewer {
+    zoom: f32,
+    pan: Vec2,
+}
+
+impl Default for Viewer {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            pan: Vec2::ZERO,
+        }
+    }
+}
+
+impl Viewer {
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn is_reset(&self) -> bool {
+        self.zoom == 1.0 && self.pan == Vec2::ZERO
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Zooms by the given factor, the point `anchor` (relative to the center of the frame) stays in place
+    pub fn zoom_by(&mut self, factor: f32, anchor: Vec2) {
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let factor = zoom / self.zoom;
+        self.pan -= anchor * (factor - 1.0);
+        self.zoom = zoom;
+    }
+
+    /// Zooms in around the center of the tab
+    pub fn zoom_in(&mut self) {
+        self.zoom_by(ZOOM_STEP, -self.pan);
+    }
+
+    /// Zooms out around the center of the tab
+    pub fn zoom_out(&mut self) {
+        self.zoom_by(1.0 / ZOOM_STEP, -self.pan);
+    }
+
+    /// Returns the screen rect of the frame, it can exceed the `available` rect when zoomed in
+    pub fn frame_rect(
+        &self,
+        available: Rect,
+        frame_size: [usize; 2],
+        mode: ScaleMode,
+        pixels_per_point: f32,
+    ) -> Rect {
+        let frame_size = Vec2::new(frame_size[0] as f32, frame_size[1] as f32);
+        let scale = available.size() / frame_size;
+        let size = match mode {
+            ScaleMode::Fit => frame_size * scale.min_elem(),
+            ScaleMode::Fill => frame_size * scale.max_elem(),
+            ScaleMode::Stretch => available.size(),
+            ScaleMode::Native => frame_size / pixels_per_point,
+            ScaleMode::Integer => {
+                let factor = (scale.min_elem() * pixels_per_point).floor().max(1.0);
+                frame_size * factor / pixels_per_point
+            }
+        };
+        Rect::from_center_size(available.center() + self.pan, size * self.zoom)
+    }
+
+    /// Zooms with the mouse wheel and pans by dragging the frame
+    pub fn handle_input(&mut self, ui: &egui::Ui, response: &egui::Response, frame_rect: Rect) {
+        if response.dragged_by(egui::PointerButton::Primary) {
+            self.pan += response.drag_delta();
+        }
+        if response.double_clicked() {
+            self.reset();
+        }
+
+        if let Some(pointer) = response.hover_pos() {
+            let (scroll, zoom) = ui.input(|i| (i.raw_scroll_delta.y, i.zoom_delta()));
+            let factor = (scroll / POINTS_PER_ZOOM_STEP).exp2() * zoom;
+            if factor != 1.0 {
+                self.zoom_by(factor, pointer - frame_rect.center());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::egui_dock::egui::{pos2, vec2, Pos2};
+
+    fn available() -> Rect {
+        Rect::from_min_size(pos2(10.0, 20.0), vec2(500.0, 300.0))
+    }
+
+    fn assert_pos(actual: Pos2, expected: Pos2) {
+        assert!(
+            (actual - expected).length() < 1e-3,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn scale_modes() {
+        let viewer = Viewer::default();
+        let size = |mode, pixels_per_point| {
+            let rect = viewer.frame_rect(available(), [200, 100], mode, pixels_per_point);
+            assert_pos(rect.center(), available().center());
+            rect.size()
+        };
+
+        assert_eq!(size(ScaleMode::Fit, 1.0), vec2(500.0, 250.0));
+        assert_eq!(size(ScaleMode::Fill, 1.0), vec2(600.0, 300.0));
+        assert_eq!(size(ScaleMode::Stretch, 1.0), vec2(500.0, 300.0));
+        assert_eq!(size(ScaleMode::Native, 2.0), vec2(100.0, 50.0));
+        assert_eq!(size(ScaleMode::Integer, 1.0), vec2(400.0, 200.0));
+        // the integer factor applies to physical pixels
+        assert_eq!(size(ScaleMode::Integer, 2.0), vec2(500.0, 250.0));
+
+        // frames larger than the tab are not scaled below their native size
+        let rect = viewer.frame_rect(available(), [1000, 1000], ScaleMode::Integer, 1.0);
+        assert_eq!(rect.size(), vec2(1000.0, 1000.0));
+    }
+
+    #[test]
+    fn zoom_keeps_the_anchor_in_place() {
+        let mut viewer = Viewer::default();
+        let before = viewer.frame_rect(available(), [200, 100], ScaleMode::Fit, 1.0);
+        let anchor = vec2(100.0, -50.0);
+        let point = before.center() + anchor;
+        let uv = (point - before.min) / before.size();
+
+        viewer.zoom_by(4.0, anchor);
+        assert_eq!(viewer.zoom(), 4.0);
+        let after = viewer.frame_rect(available(), [200, 100], ScaleMode::Fit, 1.0);
+        assert_eq!(after.size(), before.size() * 4.0);
+        assert_pos(after.min + uv * after.size(), point);
+        assert!(!viewer.is_reset());
+
+        viewer.reset();
+        assert!(viewer.is_reset());
+    }
+
+    #[test]
+    fn zoom_is_clamped() {
+        let mut viewer = Viewer::default();
+        let before = viewer.frame_rect(available(), [200, 100], ScaleMode::Fit, 1.0);
+        let anchor = vec2(-30.0, 40.0);
+        let point = before.center() + anchor;
+        let uv = (point - before.min) / before.size();
+
+        viewer.zoom_by(1000.0, anchor);
+        assert_eq!(viewer.zoom(), MAX_ZOOM);
+        let after = viewer.frame_rect(available(), [200, 100], ScaleMode::Fit, 1.0);
+        assert_pos(after.min + uv * after.size(), point);
+
+        viewer.zoom_by(1e-6, Vec2::ZERO);
+        assert_eq!(viewer.zoom(), MIN_ZOOM);
+    }
+
+    #[test]
+    fn zoom_shortcuts() {
+        let mut viewer = Viewer::default();
+        viewer.zoom_by(2.0, vec2(50.0, 0.0));
+        let center = viewer
+            .frame_rect(available(), [200, 100], ScaleMode::Fit, 1.0)
+            .center();
+
+        // the shortcuts zoom around the center of the tab, zooming in and out again restores the frame
+        viewer.zoom_in();
+        viewer.zoom_out();
+        assert!((viewer.zoom() - 2.0).abs() < 1e-5);
+        let rect = viewer.frame_rect(available(), [200, 100], ScaleMode::Fit, 1.0);
+        assert_pos(rect.center(), center);
+    }
+}
@@ -1,28 +1,59 @@
 use ::frame_counter::FrameCounter;
 use ::log::{info, warn};
-use ::mirror_dto::{CaptureConfig, Cursor, GlobalBufferHost};
-use ::parking_lot::RwLock;
+use ::mirror_dto::{
+    AudioBuffer, AudioFormat, CaptureConfig, ClipboardArea, Cursor, GlobalBufferHost, InputEvent,
+    InputEventType, InputQueue, CLIPBOARD_CAPACITY, CLIPBOARD_MIME_TEXT, INPUT_QUEUE_LEN,
+};
+use ::parking_lot::{Mutex, RwLock};
 use ::pelite::pattern;
 use ::pelite::pattern::Atom;
 use ::std::{
+    collections::VecDeque,
     convert::TryInto,
+    mem::{offset_of, size_of},
     sync::atomic::{AtomicBool, Ordering},
     sync::Arc,
     thread,
     thread::JoinHandle,
+    time::Duration,
 };
 
 use ::memflow::prelude::v1::*;
 
+use crate::screenshot::Screenshot;
+
+mod physical;
+pub use physical::PhysicalCapture;
+
+mod file;
+pub use file::{FileCapture, FrameFileWriter, Playback};
+
+mod replay;
+pub use replay::{ReplayBuffer, ReplayClip, ReplayConfig};
+
+mod framebuffer;
+pub use framebuffer::{
+    detect_framebuffers, FramebufferCandidate, FramebufferCapture, FramebufferConfig, PixelFormat,
+};
+
+/// Name of the guest agent process the os based captures attach to
+pub const DEFAULT_PROCESS_NAME: &str = "mirror-guest.exe";
+
 const DEFAULT_FRAME_WIDTH: u64 = 1920;
 const DEFAULT_FRAME_HEIGHT: u64 = 1080;
 
+// maximum amount of input events that are buffered on the host while the guest is not consuming them
+const MAX_PENDING_INPUT_EVENTS: usize = 256;
+
+// maximum amount of audio chunks that are buffered on the host while they are not being consumed
+const MAX_PENDING_AUDIO_CHUNKS: usize = 512;
+
 pub trait Capture {
     // Is this a multithreaded reader?
     fn multithreading(&self) -> bool;
 
-    // Consumes self and returns the underlying os object
-    fn os(&self) -> OsInstanceArcBox<'static>;
+    // Returns the underlying os object if this capture is attached through an os
+    fn os(&self) -> Option<OsInstanceArcBox<'static>>;
 
     fn obs_capture(&self) -> bool;
     fn set_obs_capture(&mut self, obs: bool);
@@ -31,30 +62,223 @@ pub trait Capture {
 
     fn frame_counter(&self) -> u32;
 
+    // Adds a sink that receives every new frame on the thread that captures it,
+    // frames reach the sink even while they are not drawn
+    fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>);
+
     // Returns a new egui::ImageData from the captured data
     fn image_data(&self) -> egui::ImageData;
 
     // Returns a copy of the current cursor state
     fn cursor_data(&self) -> Cursor;
+
+    // Returns a copy of the current frame in rgba format
+    fn frame(&self) -> Frame {
+        let (size, data) = match self.image_data() {
+            // the image data owns a copy of the frame which is taken over if it is not shared
+            egui::ImageData::Color(image) => match Arc::try_unwrap(image) {
+                Ok(image) => (image.size, buffer_from_pixels(image.pixels)),
+                Err(image) => (image.size, image.as_raw().to_vec()),
+            },
+            egui::ImageData::Font(_) => ([0, 0], Vec::new()),
+        };
+
+        Frame {
+            width: size[0] as u32,
+            height: size[1] as u32,
+            timestamp: self.frame_timestamp(),
+            cursor: self.cursor_data(),
+            data,
+        }
+    }
+
+    // Returns a copy of the current frame together with the frame counter
+    fn screenshot(&self) -> Screenshot {
+        Screenshot::new(self.frame(), self.frame_counter())
+    }
+
+    // Returns true if this capture is able to forward input events to the guest
+    fn supports_input(&self) -> bool {
+        false
+    }
+
+    // Queues an input event that will be forwarded to the guest on the next update
+    fn send_input(&mut self, _event: InputEvent) {}
+
+    // Returns the latest clipboard contents published by the guest
+    fn clipboard(&self) -> Option<ClipboardData> {
+        None
+    }
+
+    // Publishes new clipboard contents to the guest on the next update
+    fn set_clipboard(&mut self, _clipboard: ClipboardData) {}
+
+    // Returns the time the current frame was captured at (in microseconds of the guest clock)
+    fn frame_timestamp(&self) -> Option<u64> {
+        None
+    }
+
+    // Returns all audio chunks that have been captured since the last call,
+    // audio is only streamed from the guest once this has been called
+    fn read_audio(&mut self) -> Vec<AudioChunk> {
+        Vec::new()
+    }
+
+    // Returns the playback controls if this capture replays a recording
+    fn playback(&mut self) -> Option<&mut dyn Playback> {
+        None
+    }
+
+    // Returns true if this capture is able to keep an instant replay buffer of the recent frames
+    fn supports_replay(&self) -> bool {
+        false
+    }
+
+    // Returns the configuration of the instant replay buffer if it is enabled
+    fn replay_config(&self) -> Option<ReplayConfig> {
+        None
+    }
+
+    // Enables, reconfigures or disables (None) the instant replay buffer
+    fn set_replay_config(&mut self, _config: Option<ReplayConfig>) {}
+
+    // Returns the frames of the given duration before the latest frame in the instant replay buffer
+    fn replay_clip(&self, _last: Duration) -> Option<ReplayClip> {
+        None
+    }
+}
+
+/// A single captured frame
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// Time the frame was captured at in microseconds of the guest clock, if known
+    pub timestamp: Option<u64>,
+    pub cursor: Cursor,
+    /// Pixels in rgba format
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn is_empty(&self) -> bool {
+        self.width == 0
+            || self.height == 0
+            || self.data.len() < self.width as usize * self.height as usize * 4
+    }
+}
+
+/// Receives the frames of a `Capture`, see `Capture::add_frame_sink`
+pub trait FrameSink: Send {
+    /// Called for every new frame, the sink is removed once it returns false
+    fn push_frame(&mut self, frame: &Frame, frame_counter: u32) -> bool;
+}
+
+/// The sinks of a capture
+#[derive(Default)]
+pub(crate) struct FrameSinks(Vec<Box<dyn FrameSink>>);
+
+impl FrameSinks {
+    pub fn add(&mut self, sink: Box<dyn FrameSink>) {
+        self.0.push(sink);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Passes a new frame to all sinks
+    pub fn push(&mut self, frame: &Frame, frame_counter: u32) {
+        self.0
+            .retain_mut(|sink| sink.push_frame(frame, frame_counter));
+    }
+
+    /// Passes a new frame to all sinks, the frame is only copied if there are any sinks
+    pub fn push_frame<F: FnOnce() -> Frame>(&mut self, frame_counter: u32, frame: F) {
+        if !self.is_empty() {
+            self.push(&frame(), frame_counter);
+        }
+    }
+}
+
+/// A chunk of interleaved audio samples captured on the guest
+#[derive(Clone, Debug)]
+pub struct AudioChunk {
+    /// Time of the first sample in microseconds of the guest clock, comparable with `Capture::frame_timestamp`
+    pub timestamp: u64,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Interleaved samples in the range of -1.0 to 1.0
+    pub samples: Vec<f32>,
+}
+
+impl AudioChunk {
+    /// Returns the number of frames (one sample for each channel) in this chunk
+    pub fn frames(&self) -> usize {
+        self.samples.len() / (self.channels as usize).max(1)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// Clipboard contents exchanged with the guest
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipboardData {
+    /// Sequence number assigned by the guest, it is ignored when sending clipboard contents to the guest
+    pub sequence: u32,
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+impl ClipboardData {
+    pub fn text(text: &str) -> Self {
+        Self {
+            sequence: 0,
+            mime: CLIPBOARD_MIME_TEXT.to_string(),
+            data: text.as_bytes().to_vec(),
+        }
+    }
+
+    /// Returns the contents as text if they contain utf-8 text
+    pub fn as_text(&self) -> Option<&str> {
+        if self.mime == CLIPBOARD_MIME_TEXT {
+            std::str::from_utf8(&self.data).ok()
+        } else {
+            None
+        }
+    }
 }
 
 pub struct SequentialCapture {
     os: OsInstanceArcBox<'static>,
 
+    process_name: String,
     process: Option<CaptureProcess>,
     capture_config: CaptureConfig,
     capture_data: CaptureData,
+    host_data: HostData,
+    frame_sinks: FrameSinks,
     update_counter: FrameCounter,
 }
 
 impl SequentialCapture {
     pub fn new(os: OsInstanceArcBox<'static>) -> Self {
+        Self::with_process_name(os, DEFAULT_PROCESS_NAME)
+    }
+
+    /// Creates a capture that attaches to a guest agent with a different process name
+    pub fn with_process_name(os: OsInstanceArcBox<'static>, process_name: &str) -> Self {
         Self {
             os,
 
+            process_name: process_name.to_string(),
             process: None,
             capture_config: CaptureConfig::default(),
             capture_data: CaptureData::default(),
+            host_data: HostData::default(),
+            frame_sinks: FrameSinks::default(),
             update_counter: FrameCounter::new(0f64),
         }
     }
@@ -65,33 +289,41 @@ impl Capture for SequentialCapture {
         false
     }
 
-    // Consumes self and returns the underlying os object
-    fn os(&self) -> OsInstanceArcBox<'static> {
-        self.os.clone()
+    fn os(&self) -> Option<OsInstanceArcBox<'static>> {
+        Some(self.os.clone())
     }
 
     fn obs_capture(&self) -> bool {
-        self.capture_config.obs
+        self.capture_config.obs()
     }
     fn set_obs_capture(&mut self, obs: bool) {
-        self.capture_config.obs = obs;
+        self.capture_config.set_obs(obs);
     }
 
     fn update(&mut self) {
         if let Some(process) = &mut self.process {
             if process.is_alive() {
                 if process
-                    .update_into(&self.capture_config, &mut self.capture_data)
+                    .update_into(
+                        &self.capture_config,
+                        &mut self.capture_data,
+                        &mut self.host_data,
+                    )
                     .is_ok()
                 {
                     self.update_counter.tick();
+                    let capture_data = &self.capture_data;
+                    self.frame_sinks
+                        .push_frame(capture_data.global_buffer.frame_counter, || {
+                            capture_data.frame()
+                        });
                 }
             } else {
                 self.process = None;
             }
         } else {
             // try to open the process
-            if let Ok(capture_process) = CaptureProcess::new(self.os.clone(), "mirror-guest.exe") {
+            if let Ok(capture_process) = CaptureProcess::new(self.os.clone(), &self.process_name) {
                 self.process = Some(capture_process);
             }
         }
@@ -101,31 +333,46 @@ impl Capture for SequentialCapture {
         self.capture_data.global_buffer.frame_counter
     }
 
-    fn image_data(&self) -> egui::ImageData {
-        let (frame_width, frame_height, frame_buffer) = {
-            (
-                self.capture_data.global_buffer.width,
-                self.capture_data.global_buffer.height,
-                self.capture_data.frame_buffer.clone(),
-            )
-        };
-
-        let size = [frame_width as usize, frame_height as usize];
-        let mut data = std::mem::ManuallyDrop::new(frame_buffer);
-        let pixels: Vec<egui::Color32> = unsafe {
-            Vec::from_raw_parts(
-                data.as_mut_ptr() as *mut _,
-                data.len() / std::mem::size_of::<egui::Color32>(),
-                data.len() / std::mem::size_of::<egui::Color32>(),
-            )
-        };
+    fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.add(sink);
+    }
 
-        egui::ImageData::Color(Arc::new(egui::ColorImage { size, pixels }))
+    fn image_data(&self) -> egui::ImageData {
+        image_data_from_buffer(
+            self.capture_data.global_buffer.width,
+            self.capture_data.global_buffer.height,
+            self.capture_data.frame_buffer.clone(),
+        )
     }
 
     fn cursor_data(&self) -> Cursor {
         self.capture_data.global_buffer.cursor
     }
+
+    fn supports_input(&self) -> bool {
+        true
+    }
+
+    fn send_input(&mut self, event: InputEvent) {
+        push_input_event(&mut self.host_data.input_events, event);
+    }
+
+    fn clipboard(&self) -> Option<ClipboardData> {
+        self.capture_data.clipboard.clone()
+    }
+
+    fn set_clipboard(&mut self, clipboard: ClipboardData) {
+        self.host_data.clipboard = Some(clipboard);
+    }
+
+    fn frame_timestamp(&self) -> Option<u64> {
+        Some(self.capture_data.global_buffer.frame_timestamp).filter(|&ts| ts != 0)
+    }
+
+    fn read_audio(&mut self) -> Vec<AudioChunk> {
+        self.host_data.audio_requested = true;
+        self.capture_data.audio.drain(..).collect()
+    }
 }
 
 pub struct ThreadedCapture {
@@ -137,14 +384,33 @@ pub struct ThreadedCapture {
     // synced with main thread
     capture_config: Arc<RwLock<CaptureConfig>>,
     capture_data: Arc<RwLock<CaptureData>>,
+    host_data: Arc<Mutex<HostData>>,
+    frame_sinks: Arc<Mutex<FrameSinks>>,
+    replay: Arc<Mutex<Option<ReplayBuffer>>>,
+    replay_config: Option<ReplayConfig>,
 }
 
 impl ThreadedCapture {
     pub fn new(os: OsInstanceArcBox<'static>) -> Self {
+        Self::with_process_name(os, DEFAULT_PROCESS_NAME)
+    }
+
+    /// Creates a capture that attaches to a guest agent with a different process name
+    pub fn with_process_name(os: OsInstanceArcBox<'static>, process_name: &str) -> Self {
         let capture_config = Arc::new(RwLock::new(CaptureConfig::default()));
         let capture_data = Arc::new(RwLock::new(CaptureData::default()));
-        let mut inner =
-            ThreadedCaptureInner::new(os.clone(), capture_config.clone(), capture_data.clone());
+        let host_data = Arc::new(Mutex::new(HostData::default()));
+        let frame_sinks = Arc::new(Mutex::new(FrameSinks::default()));
+        let replay = Arc::new(Mutex::new(None));
+        let mut inner = ThreadedCaptureInner::new(
+            os.clone(),
+            process_name,
+            capture_config.clone(),
+            capture_data.clone(),
+            host_data.clone(),
+            frame_sinks.clone(),
+            replay.clone(),
+        );
 
         let mut reader = Self {
             os,
@@ -154,6 +420,10 @@ impl ThreadedCapture {
 
             capture_config,
             capture_data,
+            host_data,
+            frame_sinks,
+            replay,
+            replay_config: None,
         };
 
         let alive = reader.thread_alive.clone();
@@ -177,15 +447,15 @@ impl Capture for ThreadedCapture {
         true
     }
 
-    fn os(&self) -> OsInstanceArcBox<'static> {
-        self.os.clone()
+    fn os(&self) -> Option<OsInstanceArcBox<'static>> {
+        Some(self.os.clone())
     }
 
     fn obs_capture(&self) -> bool {
-        self.capture_config.read().obs
+        self.capture_config.read().obs()
     }
     fn set_obs_capture(&mut self, obs: bool) {
-        self.capture_config.write().obs = obs;
+        self.capture_config.write().set_obs(obs);
     }
 
     fn update(&mut self) {}
@@ -194,6 +464,10 @@ impl Capture for ThreadedCapture {
         self.capture_data.read().global_buffer.frame_counter
     }
 
+    fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.lock().add(sink);
+    }
+
     fn image_data(&self) -> egui::ImageData {
         let (frame_width, frame_height, frame_buffer) = {
             let capture_data = self.capture_data.read();
@@ -204,22 +478,60 @@ impl Capture for ThreadedCapture {
             )
         };
 
-        let size = [frame_width as usize, frame_height as usize];
-        let mut data = std::mem::ManuallyDrop::new(frame_buffer);
-        let pixels: Vec<egui::Color32> = unsafe {
-            Vec::from_raw_parts(
-                data.as_mut_ptr() as *mut _,
-                data.len() / std::mem::size_of::<egui::Color32>(),
-                data.len() / std::mem::size_of::<egui::Color32>(),
-            )
-        };
-
-        egui::ImageData::Color(Arc::new(egui::ColorImage { size, pixels }))
+        image_data_from_buffer(frame_width, frame_height, frame_buffer)
     }
 
     fn cursor_data(&self) -> Cursor {
         self.capture_data.read().global_buffer.cursor
     }
+
+    fn supports_input(&self) -> bool {
+        true
+    }
+
+    fn send_input(&mut self, event: InputEvent) {
+        push_input_event(&mut self.host_data.lock().input_events, event);
+    }
+
+    fn clipboard(&self) -> Option<ClipboardData> {
+        self.capture_data.read().clipboard.clone()
+    }
+
+    fn set_clipboard(&mut self, clipboard: ClipboardData) {
+        self.host_data.lock().clipboard = Some(clipboard);
+    }
+
+    fn frame_timestamp(&self) -> Option<u64> {
+        Some(self.capture_data.read().global_buffer.frame_timestamp).filter(|&ts| ts != 0)
+    }
+
+    fn read_audio(&mut self) -> Vec<AudioChunk> {
+        self.host_data.lock().audio_requested = true;
+        self.capture_data.write().audio.drain(..).collect()
+    }
+
+    fn supports_replay(&self) -> bool {
+        true
+    }
+
+    fn replay_config(&self) -> Option<ReplayConfig> {
+        // a copy is kept so the ui does not have to lock the buffer
+        self.replay_config.clone()
+    }
+
+    fn set_replay_config(&mut self, config: Option<ReplayConfig>) {
+        self.replay_config = config.clone();
+        let mut replay = self.replay.lock();
+        match (replay.as_mut(), config) {
+            (Some(replay), Some(config)) => replay.set_config(config),
+            (None, Some(config)) => *replay = Some(ReplayBuffer::new(config)),
+            (_, None) => *replay = None,
+        }
+    }
+
+    fn replay_clip(&self, last: Duration) -> Option<ReplayClip> {
+        self.replay.lock().as_ref().map(|replay| replay.clip(last))
+    }
 }
 
 impl Drop for ThreadedCapture {
@@ -241,23 +553,35 @@ impl Drop for ThreadedCapture {
 
 struct ThreadedCaptureInner {
     os: OsInstanceArcBox<'static>,
+    process_name: String,
     process: Option<CaptureProcess>,
     capture_config: Arc<RwLock<CaptureConfig>>,
     capture_data: Arc<RwLock<CaptureData>>,
+    host_data: Arc<Mutex<HostData>>,
+    frame_sinks: Arc<Mutex<FrameSinks>>,
+    replay: Arc<Mutex<Option<ReplayBuffer>>>,
     update_counter: FrameCounter,
 }
 
 impl ThreadedCaptureInner {
     pub fn new(
         os: OsInstanceArcBox<'static>,
+        process_name: &str,
         capture_config: Arc<RwLock<CaptureConfig>>,
         capture_data: Arc<RwLock<CaptureData>>,
+        host_data: Arc<Mutex<HostData>>,
+        frame_sinks: Arc<Mutex<FrameSinks>>,
+        replay: Arc<Mutex<Option<ReplayBuffer>>>,
     ) -> Self {
         Self {
             os,
+            process_name: process_name.to_string(),
             process: None,
             capture_config,
             capture_data,
+            host_data,
+            frame_sinks,
+            replay,
             update_counter: FrameCounter::new(0f64),
         }
     }
@@ -266,28 +590,151 @@ impl ThreadedCaptureInner {
         if let Some(process) = &mut self.process {
             if process.is_alive() {
                 if process
-                    .update_into(&self.capture_config.read(), &mut self.capture_data.write())
+                    .update_into(
+                        &self.capture_config.read(),
+                        &mut self.capture_data.write(),
+                        &mut self.host_data.lock(),
+                    )
                     .is_ok()
                 {
                     self.update_counter.tick();
+                    self.update_outputs();
                 }
             } else {
                 self.process = None;
             }
         } else {
             // try to open the process
-            if let Ok(capture_process) = CaptureProcess::new(self.os.clone(), "mirror-guest.exe") {
+            if let Ok(capture_process) = CaptureProcess::new(self.os.clone(), &self.process_name) {
                 self.process = Some(capture_process);
             } else {
                 std::thread::sleep(std::time::Duration::from_millis(100));
             }
         }
     }
+
+    /// Passes the latest frame to the sinks and to the instant replay buffer if it is enabled
+    fn update_outputs(&mut self) {
+        let replay_config = self
+            .replay
+            .lock()
+            .as_ref()
+            .map(|replay| replay.config().clone());
+        let mut frame_sinks = self.frame_sinks.lock();
+        if frame_sinks.is_empty() && replay_config.is_none() {
+            return;
+        }
+
+        // the frame is copied so the capture data is not locked while it is processed
+        let (frame_counter, frame) = {
+            let capture_data = self.capture_data.read();
+            (
+                capture_data.global_buffer.frame_counter,
+                capture_data.frame(),
+            )
+        };
+        frame_sinks.push(&frame, frame_counter);
+        drop(frame_sinks);
+
+        // the frame is encoded before the buffer is locked, the ui locks it to show its stats
+        if let Some(frame) = replay_config.and_then(|config| config.encode(&frame)) {
+            if let Some(replay) = self.replay.lock().as_mut() {
+                replay.push_encoded(frame);
+            }
+        }
+    }
+}
+
+/// Queues an input event for the guest
+///
+/// Consecutive mouse moves are merged as only the latest position matters.
+/// Once the queue is full mouse moves and wheel events are dropped first,
+/// releases of keys and buttons are never dropped so nothing gets stuck on the guest.
+fn push_input_event(input_events: &mut VecDeque<InputEvent>, event: InputEvent) {
+    let is_move = |event: &InputEvent| event.ty == InputEventType::MouseMove as u32;
+    if is_move(&event) {
+        if let Some(last) = input_events.back_mut().filter(|last| is_move(last)) {
+            *last = event;
+            return;
+        }
+    }
+
+    if input_events.len() >= MAX_PENDING_INPUT_EVENTS {
+        let droppable = input_events
+            .iter()
+            .position(|event| is_move(event) || event.ty == InputEventType::MouseWheel as u32);
+        match droppable {
+            Some(index) => {
+                input_events.remove(index);
+            }
+            // the queue only grows beyond its limit for releases
+            None if !is_release(&event) => return,
+            None => (),
+        }
+    }
+    input_events.push_back(event);
+}
+
+fn is_release(event: &InputEvent) -> bool {
+    event.ty == InputEventType::KeyUp as u32
+        || (event.ty == InputEventType::MouseButton as u32
+            && event.flags & InputEvent::FLAG_PRESSED == 0)
+}
+
+/// Converts the pixels of an egui::ColorImage back into a raw rgba frame buffer without copying them
+fn buffer_from_pixels(pixels: Vec<egui::Color32>) -> Vec<u8> {
+    let mut pixels = std::mem::ManuallyDrop::new(pixels);
+    unsafe {
+        Vec::from_raw_parts(
+            pixels.as_mut_ptr() as *mut u8,
+            pixels.len() * std::mem::size_of::<egui::Color32>(),
+            pixels.capacity() * std::mem::size_of::<egui::Color32>(),
+        )
+    }
+}
+
+/// Converts a raw rgba frame buffer into egui::ImageData without copying it
+fn image_data_from_buffer(
+    frame_width: u64,
+    frame_height: u64,
+    frame_buffer: Vec<u8>,
+) -> egui::ImageData {
+    let size = [frame_width as usize, frame_height as usize];
+    let mut data = std::mem::ManuallyDrop::new(frame_buffer);
+    let pixels: Vec<egui::Color32> = unsafe {
+        Vec::from_raw_parts(
+            data.as_mut_ptr() as *mut _,
+            data.len() / std::mem::size_of::<egui::Color32>(),
+            data.capacity() / std::mem::size_of::<egui::Color32>(),
+        )
+    };
+
+    egui::ImageData::Color(Arc::new(egui::ColorImage { size, pixels }))
 }
 
 struct CaptureData {
     global_buffer: GlobalBufferHost,
     frame_buffer: Vec<u8>,
+    clipboard: Option<ClipboardData>,
+    audio: VecDeque<AudioChunk>,
+}
+
+impl CaptureData {
+    fn frame(&self) -> Frame {
+        let width = self.global_buffer.width as u32;
+        let height = self.global_buffer.height as u32;
+        Frame {
+            width,
+            height,
+            timestamp: Some(self.global_buffer.frame_timestamp).filter(|&ts| ts != 0),
+            cursor: self.global_buffer.cursor,
+            data: self
+                .frame_buffer
+                .get(..width as usize * height as usize * 4)
+                .map(|data| data.to_vec())
+                .unwrap_or_default(),
+        }
+    }
 }
 
 impl Default for CaptureData {
@@ -299,10 +746,21 @@ impl Default for CaptureData {
                 0u8;
                 DEFAULT_FRAME_WIDTH as usize * DEFAULT_FRAME_HEIGHT as usize * 4
             ],
+            clipboard: None,
+            audio: VecDeque::new(),
         }
     }
 }
 
+/// Data that is sent from the host to the guest
+#[derive(Default)]
+struct HostData {
+    input_events: VecDeque<InputEvent>,
+    clipboard: Option<ClipboardData>,
+    // no audio chunks are queued until they are read for the first time
+    audio_requested: bool,
+}
+
 struct CaptureProcess {
     process: IntoProcessInstanceArcBox<'static>,
     marker_addr: Address,
@@ -311,6 +769,8 @@ struct CaptureProcess {
     frame_width: u32,
     frame_height: u32,
     frame_counter: u32,
+    clipboard_sequence: u32,
+    audio_cursor: Option<u64>,
 }
 
 impl CaptureProcess {
@@ -375,6 +835,8 @@ impl CaptureProcess {
                 frame_width: 0,
                 frame_height: 0,
                 frame_counter: 0,
+                clipboard_sequence: 0,
+                audio_cursor: None,
             });
         }
 
@@ -417,10 +879,29 @@ impl CaptureProcess {
         &mut self,
         capture_config: &CaptureConfig,
         capture_data: &mut CaptureData,
+        host_data: &mut HostData,
     ) -> Result<()> {
         // check if a new buffer is necessary
         self.process
             .read_into(self.marker_addr, &mut capture_data.global_buffer)?;
+
+        // input and clipboard are synchronized independently of new frames
+        if !host_data.input_events.is_empty() {
+            self.write_input(
+                &capture_data.global_buffer.input,
+                &mut host_data.input_events,
+            );
+        }
+        self.read_clipboard(capture_data);
+        if let Some(clipboard) = host_data.clipboard.take() {
+            self.write_clipboard(&capture_data.global_buffer.clipboard_host, &clipboard);
+        }
+
+        // audio is streamed alongside the frames
+        if host_data.audio_requested {
+            self.read_audio(capture_data);
+        }
+
         let frame_width = capture_data.global_buffer.width as u32;
         let frame_height = capture_data.global_buffer.height as u32;
         let frame_counter = capture_data.global_buffer.frame_counter;
@@ -450,14 +931,292 @@ impl CaptureProcess {
             .ok();
 
         // update configuration on guest
+        // only fields owned by the host are written back so guest updates (e.g. the input read_index) are not overwritten
         capture_data.global_buffer.config = capture_config.clone();
         capture_data.global_buffer.frame_read_counter = capture_data.global_buffer.frame_counter;
         self.process
-            .write(self.marker_addr, &capture_data.global_buffer)
+            .write(
+                self.marker_addr + offset_of!(GlobalBufferHost, config) as umem,
+                &capture_data.global_buffer.config,
+            )
+            .ok();
+        self.process
+            .write(
+                self.marker_addr + offset_of!(GlobalBufferHost, frame_read_counter) as umem,
+                &capture_data.global_buffer.frame_read_counter,
+            )
             .ok();
 
         self.frame_counter = frame_counter;
 
         Ok(())
     }
+
+    /// Writes pending input events into the free slots of the input queue on the guest
+    fn write_input(&mut self, input: &InputQueue, input_events: &mut VecDeque<InputEvent>) {
+        if input.len() > INPUT_QUEUE_LEN {
+            warn!("input queue on guest is corrupted");
+            return;
+        }
+
+        let input_addr = self.marker_addr + offset_of!(GlobalBufferHost, input) as umem;
+        let events_addr = input_addr + offset_of!(InputQueue, events) as umem;
+
+        let free = INPUT_QUEUE_LEN - input.len();
+        let mut write_index = input.write_index;
+        for event in input_events.drain(..free.min(input_events.len())) {
+            let slot = write_index as usize % INPUT_QUEUE_LEN;
+            self.process
+                .write(
+                    events_addr + (slot * size_of::<InputEvent>()) as umem,
+                    &event,
+                )
+                .ok();
+            write_index = write_index.wrapping_add(1);
+        }
+
+        // publish the events after they have been written
+        self.process
+            .write(
+                input_addr + offset_of!(InputQueue, write_index) as umem,
+                &write_index,
+            )
+            .ok();
+    }
+
+    /// Reads the clipboard contents from the guest if it published new contents
+    fn read_clipboard(&mut self, capture_data: &mut CaptureData) {
+        let area = capture_data.global_buffer.clipboard_guest;
+        if area.sequence == self.clipboard_sequence || area.buffer == 0 {
+            return;
+        }
+        if area.len as usize > CLIPBOARD_CAPACITY {
+            warn!("clipboard area on guest is corrupted");
+            return;
+        }
+
+        let mut data = vec![0u8; area.len as usize];
+        if self
+            .process
+            .read_raw_into(Address::from(area.buffer), &mut data)
+            .data_part()
+            .is_err()
+        {
+            return;
+        }
+
+        // discard the contents if the guest changed them while they were read
+        let sequence_addr = self.marker_addr
+            + offset_of!(GlobalBufferHost, clipboard_guest) as umem
+            + offset_of!(ClipboardArea, sequence) as umem;
+        match self.process.read::<u32>(sequence_addr).data_part() {
+            Ok(sequence) if sequence == area.sequence => (),
+            _ => return,
+        }
+
+        capture_data.clipboard = Some(ClipboardData {
+            sequence: area.sequence,
+            mime: area.mime().to_string(),
+            data,
+        });
+        self.clipboard_sequence = area.sequence;
+    }
+
+    /// Writes new clipboard contents into the buffer provided by the guest
+    fn write_clipboard(&mut self, area: &ClipboardArea, clipboard: &ClipboardData) {
+        if area.buffer == 0 {
+            warn!("guest does not provide a clipboard buffer");
+            return;
+        }
+        if clipboard.data.len() > CLIPBOARD_CAPACITY {
+            warn!(
+                "clipboard contents exceed the maximum size of {} bytes",
+                CLIPBOARD_CAPACITY
+            );
+            return;
+        }
+
+        let area_addr = self.marker_addr + offset_of!(GlobalBufferHost, clipboard_host) as umem;
+        self.process
+            .write_raw(Address::from(area.buffer), &clipboard.data)
+            .ok();
+        self.process
+            .write(
+                area_addr + offset_of!(ClipboardArea, len) as umem,
+                &(clipboard.data.len() as u32),
+            )
+            .ok();
+        self.process
+            .write(
+                area_addr + offset_of!(ClipboardArea, mime) as umem,
+                &ClipboardArea::encode_mime(&clipboard.mime),
+            )
+            .ok();
+
+        // publish the contents after they have been written
+        self.process
+            .write(
+                area_addr + offset_of!(ClipboardArea, sequence) as umem,
+                &area.sequence.wrapping_add(1),
+            )
+            .ok();
+    }
+
+    /// Reads all audio samples that have been written by the guest since the last update
+    fn read_audio(&mut self, capture_data: &mut CaptureData) {
+        let audio = capture_data.global_buffer.audio;
+        let format = AudioFormat::from_u32(audio.format);
+        let frame_size = audio.frame_size();
+        if format == AudioFormat::None
+            || frame_size == 0
+            || audio.sample_rate == 0
+            || audio.buffer == 0
+            || audio.capacity as usize % frame_size != 0
+        {
+            return;
+        }
+        let capacity = audio.capacity as u64;
+
+        let mut start = match self.audio_cursor {
+            Some(audio_cursor) if audio_cursor <= audio.write_cursor => audio_cursor,
+            _ => {
+                // start streaming at the current position of the guest
+                self.audio_cursor = Some(audio.write_cursor);
+                return;
+            }
+        };
+        if audio.write_cursor - start > capacity {
+            warn!("audio ring buffer overrun, dropping samples");
+            start = audio.write_cursor - capacity;
+        }
+
+        let len = (audio.write_cursor - start) as usize;
+        if len == 0 {
+            return;
+        }
+        self.audio_cursor = Some(audio.write_cursor);
+
+        // the requested range might wrap around the end of the ring buffer
+        let mut data = vec![0u8; len];
+        let offset = (start % capacity) as usize;
+        let first_len = len.min(capacity as usize - offset);
+        let buffer_addr = Address::from(audio.buffer);
+        if self
+            .process
+            .read_raw_into(buffer_addr + offset as umem, &mut data[..first_len])
+            .data_part()
+            .is_err()
+            || (first_len < len
+                && self
+                    .process
+                    .read_raw_into(buffer_addr, &mut data[first_len..])
+                    .data_part()
+                    .is_err())
+        {
+            return;
+        }
+
+        // discard the samples if the guest overwrote them while they were read
+        let write_cursor_addr = self.marker_addr
+            + offset_of!(GlobalBufferHost, audio) as umem
+            + offset_of!(AudioBuffer, write_cursor) as umem;
+        match self.process.read::<u64>(write_cursor_addr).data_part() {
+            Ok(write_cursor) if write_cursor.wrapping_sub(start) <= capacity => (),
+            _ => {
+                warn!("audio ring buffer overrun, dropping samples");
+                return;
+            }
+        }
+
+        let samples = match format {
+            AudioFormat::F32 => data
+                .chunks_exact(4)
+                .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                .collect::<Vec<_>>(),
+            AudioFormat::S16 => data
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+                .collect::<Vec<_>>(),
+            AudioFormat::None => return,
+        };
+
+        // the timestamp of the guest refers to the end of the written samples
+        let frames = (len / frame_size) as u64;
+        let timestamp = audio
+            .timestamp
+            .saturating_sub(frames * 1_000_000 / audio.sample_rate as u64);
+
+        if capture_data.audio.len() >= MAX_PENDING_AUDIO_CHUNKS {
+            capture_data.audio.pop_front();
+        }
+        capture_data.audio.push_back(AudioChunk {
+            timestamp,
+            sample_rate: audio.sample_rate,
+            channels: audio.channels,
+            samples,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::mirror_dto::MouseButton;
+
+    #[test]
+    fn merge_mouse_moves() {
+        let mut input_events = VecDeque::new();
+        push_input_event(&mut input_events, InputEvent::mouse_move(1, 1));
+        push_input_event(&mut input_events, InputEvent::mouse_move(2, 2));
+        push_input_event(
+            &mut input_events,
+            InputEvent::mouse_button(MouseButton::Left, true, 2, 2),
+        );
+        push_input_event(&mut input_events, InputEvent::mouse_move(3, 3));
+        push_input_event(&mut input_events, InputEvent::mouse_move(4, 4));
+
+        assert_eq!(
+            input_events.into_iter().collect::<Vec<_>>(),
+            vec![
+                InputEvent::mouse_move(2, 2),
+                InputEvent::mouse_button(MouseButton::Left, true, 2, 2),
+                InputEvent::mouse_move(4, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn full_queue_drops_moves() {
+        let mut input_events = VecDeque::new();
+        push_input_event(&mut input_events, InputEvent::mouse_move(1, 1));
+        for i in 0..MAX_PENDING_INPUT_EVENTS - 1 {
+            push_input_event(&mut input_events, InputEvent::key(i as u16, false, true));
+        }
+        push_input_event(&mut input_events, InputEvent::key(0, false, false));
+
+        assert_eq!(input_events.len(), MAX_PENDING_INPUT_EVENTS);
+        assert_eq!(input_events[0], InputEvent::key(0, false, true));
+        assert_eq!(input_events.back(), Some(&InputEvent::key(0, false, false)));
+    }
+
+    #[test]
+    fn full_queue_keeps_releases() {
+        let mut input_events = VecDeque::new();
+        for i in 0..MAX_PENDING_INPUT_EVENTS {
+            push_input_event(&mut input_events, InputEvent::key(i as u16, false, true));
+        }
+
+        // new presses and moves are dropped, releases are always queued
+        push_input_event(&mut input_events, InputEvent::key(1, false, true));
+        push_input_event(&mut input_events, InputEvent::mouse_move(1, 1));
+        assert_eq!(input_events.len(), MAX_PENDING_INPUT_EVENTS);
+
+        push_input_event(&mut input_events, InputEvent::key(1, false, false));
+        push_input_event(
+            &mut input_events,
+            InputEvent::mouse_button(MouseButton::Left, false, 0, 0),
+        );
+        assert_eq!(input_events.len(), MAX_PENDING_INPUT_EVENTS + 2);
+        assert_eq!(input_events[0], InputEvent::key(0, false, true));
+    }
 }
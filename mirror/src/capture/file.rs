@@ -0,0 +1,523 @@
+use ::log::warn;
+use ::mirror_dto::Cursor;
+use ::std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ::memflow::prelude::v1::*;
+
+use super::{image_data_from_buffer, Capture, Frame, FrameSink, FrameSinks};
+
+/// Signature at the start of a frame file
+const FILE_MAGIC: [u8; 8] = *b"MIRRCAP\0";
+const FILE_VERSION: u32 = 1;
+
+// size of the header in front of every frame
+const FRAME_HEADER_LEN: u64 = 40;
+
+pub(super) const ENCODING_RAW: u32 = 0;
+pub(super) const ENCODING_QOI: u32 = 1;
+
+/// Writes frames into a file that can be replayed with `FileCapture`.
+///
+/// The file consists of a small header followed by one record per frame.
+/// Each record stores the timestamp, the resolution, the cursor state and the qoi encoded frame.
+pub struct FrameFileWriter {
+    file: BufWriter<File>,
+    bytes_written: u64,
+}
+
+impl FrameFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&FILE_MAGIC)?;
+        file.write_all(&FILE_VERSION.to_le_bytes())?;
+        Ok(Self {
+            file,
+            bytes_written: (FILE_MAGIC.len() + 4) as u64,
+        })
+    }
+
+    /// Returns the size of the file including the header
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Appends a frame and returns the amount of bytes written
+    pub fn write_frame(&mut self, timestamp: Duration, frame: &Frame) -> io::Result<u64> {
+        let data = encode_frame(frame, ENCODING_QOI)?;
+
+        let mut header = Vec::with_capacity(FRAME_HEADER_LEN as usize);
+        header.extend_from_slice(&(timestamp.as_micros() as u64).to_le_bytes());
+        header.extend_from_slice(&frame.width.to_le_bytes());
+        header.extend_from_slice(&frame.height.to_le_bytes());
+        header.extend_from_slice(&frame.cursor.is_visible.to_le_bytes());
+        header.extend_from_slice(&frame.cursor.cursor_id.to_le_bytes());
+        header.extend_from_slice(&frame.cursor.x.to_le_bytes());
+        header.extend_from_slice(&frame.cursor.y.to_le_bytes());
+        header.extend_from_slice(&ENCODING_QOI.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+        self.file.write_all(&header)?;
+        self.file.write_all(&data)?;
+        let len = header.len() as u64 + data.len() as u64;
+        self.bytes_written += len;
+        Ok(len)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Playback controls of a capture that replays a recording
+pub trait Playback {
+    fn is_playing(&self) -> bool;
+    fn play(&mut self);
+    fn pause(&mut self);
+
+    fn position(&self) -> Duration;
+    fn duration(&self) -> Duration;
+    fn seek(&mut self, position: Duration);
+
+    fn speed(&self) -> f32;
+    fn set_speed(&mut self, speed: f32);
+}
+
+#[derive(Clone, Debug)]
+struct FrameEntry {
+    offset: u64,
+    timestamp: Duration,
+    width: u32,
+    height: u32,
+    cursor: Cursor,
+    encoding: u32,
+    len: u32,
+}
+
+/// Replays a file written by `FrameFileWriter` (or the `Mirror` format of the `Recorder`).
+///
+/// This allows working on the ui or analyzing captures without a running vm.
+pub struct FileCapture {
+    reader: BufReader<File>,
+    entries: Vec<FrameEntry>,
+
+    playing: bool,
+    speed: f32,
+    position: Duration,
+    last_update: Instant,
+
+    current: Option<usize>,
+    frame_buffer: Vec<u8>,
+    frame_counter: u32,
+    frame_sinks: FrameSinks,
+}
+
+impl FileCapture {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if header[..8] != FILE_MAGIC
+            || u32::from_le_bytes([header[8], header[9], header[10], header[11]]) != FILE_VERSION
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a mirror frame file",
+            ));
+        }
+
+        // index all frames, a truncated frame at the end of the file is ignored
+        let file_len = reader.get_ref().metadata()?.len();
+        let mut entries = Vec::new();
+        let mut offset = header.len() as u64;
+        while offset + FRAME_HEADER_LEN <= file_len {
+            let mut header = [0u8; FRAME_HEADER_LEN as usize];
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut header)?;
+
+            let u32_at = |i: usize| {
+                u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]])
+            };
+            let entry = FrameEntry {
+                offset: offset + FRAME_HEADER_LEN,
+                timestamp: Duration::from_micros(u64::from_le_bytes(
+                    header[..8].try_into().unwrap(),
+                )),
+                width: u32_at(8),
+                height: u32_at(12),
+                cursor: Cursor {
+                    is_visible: u32_at(16) as i32,
+                    cursor_id: u32_at(20),
+                    x: u32_at(24) as i32,
+                    y: u32_at(28) as i32,
+                },
+                encoding: u32_at(32),
+                len: u32_at(36),
+            };
+            if entry.offset + entry.len as u64 > file_len {
+                break;
+            }
+            offset = entry.offset + entry.len as u64;
+            entries.push(entry);
+        }
+
+        let mut capture = Self {
+            reader,
+            entries,
+
+            playing: true,
+            speed: 1.0,
+            position: Duration::ZERO,
+            last_update: Instant::now(),
+
+            current: None,
+            frame_buffer: Vec::new(),
+            frame_counter: 0,
+            frame_sinks: FrameSinks::default(),
+        };
+        capture.seek(Duration::ZERO);
+        Ok(capture)
+    }
+
+    /// Returns the number of frames in the file
+    pub fn frame_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the index of the frame that is currently shown
+    pub fn current_frame(&self) -> Option<usize> {
+        self.current
+    }
+
+    fn start_time(&self) -> Duration {
+        self.entries
+            .first()
+            .map(|entry| entry.timestamp)
+            .unwrap_or_default()
+    }
+
+    /// Loads the frame that should be visible at the current position
+    fn update_frame(&mut self) {
+        let timestamp = self.start_time() + self.position;
+        let index = self
+            .entries
+            .partition_point(|entry| entry.timestamp <= timestamp)
+            .saturating_sub(1);
+        if index >= self.entries.len() || self.current == Some(index) {
+            return;
+        }
+
+        match self.read_frame(index) {
+            Ok(frame_buffer) => {
+                self.frame_buffer = frame_buffer;
+                self.current = Some(index);
+                self.frame_counter = self.frame_counter.wrapping_add(1);
+            }
+            Err(err) => {
+                warn!("unable to read frame {}: {}", index, err);
+                self.playing = false;
+            }
+        }
+    }
+
+    fn read_frame(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let entry = &self.entries[index];
+        let mut data = vec![0u8; entry.len as usize];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut data)?;
+
+        decode_frame(entry.encoding, data, entry.width, entry.height)
+    }
+}
+
+/// Encodes the pixels of a frame with the given encoding
+pub(super) fn encode_frame(frame: &Frame, encoding: u32) -> io::Result<Vec<u8>> {
+    let pixels = &frame.data[..frame.width as usize * frame.height as usize * 4];
+    match encoding {
+        ENCODING_RAW => Ok(pixels.to_vec()),
+        ENCODING_QOI => {
+            let mut data = io::Cursor::new(Vec::new());
+            image::write_buffer_with_format(
+                &mut data,
+                pixels,
+                frame.width,
+                frame.height,
+                image::ColorType::Rgba8,
+                image::ImageFormat::Qoi,
+            )
+            .map_err(io::Error::other)?;
+            Ok(data.into_inner())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unknown frame encoding",
+        )),
+    }
+}
+
+/// Decodes frame data written by `encode_frame` back into rgba pixels
+pub(super) fn decode_frame(
+    encoding: u32,
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+) -> io::Result<Vec<u8>> {
+    let frame_buffer = match encoding {
+        ENCODING_RAW => data,
+        ENCODING_QOI => image::load_from_memory_with_format(&data, image::ImageFormat::Qoi)
+            .map_err(io::Error::other)?
+            .to_rgba8()
+            .into_raw(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown frame encoding",
+            ))
+        }
+    };
+
+    if frame_buffer.len() != width as usize * height as usize * 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame size does not match its resolution",
+        ));
+    }
+    Ok(frame_buffer)
+}
+
+impl Playback for FileCapture {
+    fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    fn play(&mut self) {
+        if self.position >= self.duration() {
+            self.seek(Duration::ZERO);
+        }
+        self.playing = true;
+        self.last_update = Instant::now();
+    }
+
+    fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    fn position(&self) -> Duration {
+        self.position
+    }
+
+    fn duration(&self) -> Duration {
+        self.entries
+            .last()
+            .map(|entry| entry.timestamp)
+            .unwrap_or_default()
+            .saturating_sub(self.start_time())
+    }
+
+    fn seek(&mut self, position: Duration) {
+        self.position = position.min(self.duration());
+        self.last_update = Instant::now();
+        self.update_frame();
+    }
+
+    fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(0.0);
+    }
+}
+
+impl Capture for FileCapture {
+    fn multithreading(&self) -> bool {
+        false
+    }
+
+    fn os(&self) -> Option<OsInstanceArcBox<'static>> {
+        None
+    }
+
+    // recordings can not change their capture mode
+    fn obs_capture(&self) -> bool {
+        false
+    }
+    fn set_obs_capture(&mut self, _obs: bool) {}
+
+    fn update(&mut self) {
+        let elapsed = self.last_update.elapsed();
+        self.last_update = Instant::now();
+
+        if self.playing {
+            self.position += elapsed.mul_f32(self.speed);
+            if self.position >= self.duration() {
+                self.position = self.duration();
+                self.playing = false;
+            }
+
+            let frame_counter = self.frame_counter;
+            self.update_frame();
+            if self.frame_counter != frame_counter {
+                let mut frame_sinks = std::mem::take(&mut self.frame_sinks);
+                frame_sinks.push_frame(self.frame_counter, || self.frame());
+                self.frame_sinks = frame_sinks;
+            }
+        }
+    }
+
+    fn frame_counter(&self) -> u32 {
+        self.frame_counter
+    }
+
+    fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.add(sink);
+    }
+
+    fn image_data(&self) -> egui::ImageData {
+        let (width, height) = self
+            .current
+            .map(|index| (self.entries[index].width, self.entries[index].height))
+            .unwrap_or_default();
+        image_data_from_buffer(width as u64, height as u64, self.frame_buffer.clone())
+    }
+
+    fn cursor_data(&self) -> Cursor {
+        self.current
+            .map(|index| self.entries[index].cursor)
+            .unwrap_or_default()
+    }
+
+    fn frame_timestamp(&self) -> Option<u64> {
+        self.current
+            .map(|index| self.entries[index].timestamp.as_micros() as u64)
+    }
+
+    fn playback(&mut self) -> Option<&mut dyn Playback> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::{RecordFormat, Recorder, RecorderConfig};
+    use ::std::{fs, path::PathBuf, thread};
+
+    const FRAMES: u8 = 4;
+    // microseconds between the recorded frames
+    const FRAME_INTERVAL: u64 = 16_000;
+
+    fn frame(index: u8) -> Frame {
+        Frame {
+            width: 2,
+            height: 2,
+            timestamp: Some(1_000_000 + index as u64 * FRAME_INTERVAL),
+            cursor: Cursor {
+                is_visible: 1,
+                cursor_id: 0,
+                x: index as i32,
+                y: 0,
+            },
+            data: vec![index; 2 * 2 * 4],
+        }
+    }
+
+    /// Records `FRAMES` frames with the `Recorder` and returns the written file
+    fn record(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("mirror-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        let mut recorder =
+            Recorder::start(RecorderConfig::new(&dir, RecordFormat::Mirror)).unwrap();
+        for index in 0..FRAMES {
+            // the queue of the recorder is shorter than the recording
+            while !recorder.push_frame(frame(index)) {
+                thread::yield_now();
+            }
+        }
+        recorder.stop().unwrap();
+
+        let path = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().map(|ext| ext == "mirror").unwrap_or(false))
+            .unwrap();
+        (dir, path)
+    }
+
+    fn assert_frame(capture: &FileCapture, index: u8) {
+        assert_eq!(capture.current_frame(), Some(index as usize));
+        let frame = capture.frame();
+        assert_eq!((frame.width, frame.height), (2, 2));
+        assert_eq!(frame.data, vec![index; 2 * 2 * 4]);
+        assert_eq!(frame.cursor.x, index as i32);
+        // the recorder stores the timestamps relative to the first frame
+        assert_eq!(frame.timestamp, Some(index as u64 * FRAME_INTERVAL));
+    }
+
+    #[test]
+    fn frame_order() {
+        let (dir, path) = record("frame_order");
+        let mut capture = FileCapture::open(&path).unwrap();
+        assert_eq!(capture.frame_count(), FRAMES as usize);
+        assert_eq!(
+            capture.duration(),
+            Duration::from_micros((FRAMES - 1) as u64 * FRAME_INTERVAL)
+        );
+
+        for index in 0..FRAMES {
+            capture.seek(Duration::from_micros(index as u64 * FRAME_INTERVAL));
+            assert_frame(&capture, index);
+        }
+        // positions between two frames show the earlier one
+        capture.seek(Duration::from_micros(FRAME_INTERVAL + FRAME_INTERVAL / 2));
+        assert_frame(&capture, 1);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn end_of_file() {
+        let (dir, path) = record("end_of_file");
+
+        // a truncated frame at the end of the file is ignored
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0u8; FRAME_HEADER_LEN as usize / 2])
+            .unwrap();
+        drop(file);
+
+        let mut capture = FileCapture::open(&path).unwrap();
+        assert_eq!(capture.frame_count(), FRAMES as usize);
+
+        // playback stops at the last frame
+        capture.set_speed(1_000_000.0);
+        thread::sleep(Duration::from_millis(1));
+        capture.update();
+        assert!(!capture.is_playing());
+        assert_eq!(capture.position(), capture.duration());
+        assert_frame(&capture, FRAMES - 1);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn play_again_from_the_start() {
+        let (dir, path) = record("play_again");
+        let mut capture = FileCapture::open(&path).unwrap();
+        capture.seek(capture.duration());
+        capture.update();
+        assert_frame(&capture, FRAMES - 1);
+
+        // playing a finished recording starts it over
+        capture.pause();
+        capture.play();
+        assert!(capture.is_playing());
+        assert_eq!(capture.position(), Duration::ZERO);
+        assert_frame(&capture, 0);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,513 @@
+use ::mirror_dto::Cursor;
+
+use ::memflow::prelude::v1::*;
+
+use super::{image_data_from_buffer, Capture, FrameSink, FrameSinks};
+
+/// Size of the chunks that are analyzed while detecting framebuffers
+const DETECT_CHUNK_SIZE: usize = 0x100000;
+
+/// Maximum distance between the start of a plausible region and the chunk containing visible content
+const DETECT_MAX_LOOKBEHIND: usize = 32;
+
+/// Framebuffers are page aligned
+const DETECT_PAGE_SIZE: usize = 0x1000;
+
+/// Widths (in pixels) that are tested when estimating the stride of a framebuffer
+const COMMON_RESOLUTIONS: &[(u32, u32)] = &[
+    (640, 480),
+    (800, 600),
+    (1024, 768),
+    (1280, 720),
+    (1280, 800),
+    (1280, 1024),
+    (1366, 768),
+    (1440, 900),
+    (1600, 900),
+    (1600, 1200),
+    (1680, 1050),
+    (1920, 1080),
+    (1920, 1200),
+    (2048, 1536),
+    (2560, 1440),
+    (2560, 1600),
+    (3840, 2160),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Bgrx8888,
+    Rgbx8888,
+    Bgr888,
+    Rgb565,
+}
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 4] = [
+        PixelFormat::Bgrx8888,
+        PixelFormat::Rgbx8888,
+        PixelFormat::Bgr888,
+        PixelFormat::Rgb565,
+    ];
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Bgrx8888 | PixelFormat::Rgbx8888 => 4,
+            PixelFormat::Bgr888 => 3,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PixelFormat::Bgrx8888 => "BGRX8888",
+            PixelFormat::Rgbx8888 => "RGBX8888",
+            PixelFormat::Bgr888 => "BGR888",
+            PixelFormat::Rgb565 => "RGB565",
+        }
+    }
+
+    /// Converts a single pixel into rgba
+    fn to_rgba(self, pixel: &[u8]) -> [u8; 4] {
+        match self {
+            PixelFormat::Bgrx8888 | PixelFormat::Bgr888 => [pixel[2], pixel[1], pixel[0], 255],
+            PixelFormat::Rgbx8888 => [pixel[0], pixel[1], pixel[2], 255],
+            PixelFormat::Rgb565 => {
+                let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let r = ((value >> 11) & 0x1F) as u8;
+                let g = ((value >> 5) & 0x3F) as u8;
+                let b = (value & 0x1F) as u8;
+                [
+                    (r << 3) | (r >> 2),
+                    (g << 2) | (g >> 4),
+                    (b << 3) | (b >> 2),
+                    255,
+                ]
+            }
+        }
+    }
+}
+
+/// Location and layout of a linear framebuffer in physical memory
+#[derive(Clone, Debug, PartialEq)]
+pub struct FramebufferConfig {
+    pub base: Address,
+    pub width: u32,
+    pub height: u32,
+    /// Length of a single scanline in bytes
+    pub stride: u32,
+    pub format: PixelFormat,
+}
+
+impl FramebufferConfig {
+    pub fn new(base: Address, width: u32, height: u32, format: PixelFormat) -> Self {
+        let mut config = Self {
+            base,
+            width,
+            height,
+            stride: 0,
+            format,
+        };
+        config.reset_stride();
+        config
+    }
+
+    /// Sets the stride to the length of a scanline without any padding
+    pub fn reset_stride(&mut self) {
+        self.stride = self.width * self.format.bytes_per_pixel() as u32;
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self.width <= 15360
+            && self.height <= 8640
+            && self.stride as usize >= self.width as usize * self.format.bytes_per_pixel()
+    }
+}
+
+/// A plausible framebuffer region found by `detect_framebuffers`
+#[derive(Clone, Debug)]
+pub struct FramebufferCandidate {
+    pub config: FramebufferConfig,
+    /// Mean difference between vertically adjacent pixels, lower is better
+    pub score: f32,
+}
+
+/// Agentless capture that reads a linear framebuffer (e.g. the UEFI GOP framebuffer) straight from physical memory.
+///
+/// This works without any guest agent and therefore also shows boot screens, bluescreens and installers.
+pub struct FramebufferCapture<T> {
+    mem: T,
+    config: FramebufferConfig,
+
+    raw_buffer: Vec<u8>,
+    frame_buffer: Vec<u8>,
+    frame_counter: u32,
+    frame_sinks: FrameSinks,
+}
+
+impl<T: PhysicalMemory> FramebufferCapture<T> {
+    pub fn new(mem: T, config: FramebufferConfig) -> Self {
+        Self {
+            mem,
+            config,
+
+            raw_buffer: Vec::new(),
+            frame_buffer: Vec::new(),
+            frame_counter: 0,
+            frame_sinks: FrameSinks::default(),
+        }
+    }
+
+    pub fn config(&self) -> &FramebufferConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: FramebufferConfig) {
+        self.config = config;
+        self.raw_buffer.clear();
+    }
+
+    fn read_frame(&mut self) -> Result<bool> {
+        if !self.config.is_valid() {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::InvalidArgument));
+        }
+
+        let raw_len = self.config.stride as usize * self.config.height as usize;
+        let mut raw_buffer = vec![0u8; raw_len];
+        self.mem
+            .phys_view()
+            .read_raw_into(self.config.base, &mut raw_buffer)
+            .data_part()?;
+
+        // only convert the frame when its contents changed
+        if raw_buffer == self.raw_buffer {
+            return Ok(false);
+        }
+
+        let width = self.config.width as usize;
+        let bytes_per_pixel = self.config.format.bytes_per_pixel();
+        self.frame_buffer
+            .resize(width * self.config.height as usize * 4, 0u8);
+        for (line, out) in raw_buffer
+            .chunks_exact(self.config.stride as usize)
+            .zip(self.frame_buffer.chunks_exact_mut(width * 4))
+        {
+            for (pixel, out) in line[..width * bytes_per_pixel]
+                .chunks_exact(bytes_per_pixel)
+                .zip(out.chunks_exact_mut(4))
+            {
+                out.copy_from_slice(&self.config.format.to_rgba(pixel));
+            }
+        }
+
+        self.raw_buffer = raw_buffer;
+        Ok(true)
+    }
+}
+
+impl<T: PhysicalMemory> Capture for FramebufferCapture<T> {
+    fn multithreading(&self) -> bool {
+        false
+    }
+
+    fn os(&self) -> Option<OsInstanceArcBox<'static>> {
+        None
+    }
+
+    // the firmware framebuffer does not support any capture modes
+    fn obs_capture(&self) -> bool {
+        false
+    }
+    fn set_obs_capture(&mut self, _obs: bool) {}
+
+    fn update(&mut self) {
+        if let Ok(true) = self.read_frame() {
+            self.frame_counter = self.frame_counter.wrapping_add(1);
+            let mut frame_sinks = std::mem::take(&mut self.frame_sinks);
+            frame_sinks.push_frame(self.frame_counter, || self.frame());
+            self.frame_sinks = frame_sinks;
+        }
+    }
+
+    fn frame_counter(&self) -> u32 {
+        self.frame_counter
+    }
+
+    fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.add(sink);
+    }
+
+    fn image_data(&self) -> egui::ImageData {
+        image_data_from_buffer(
+            self.config.width as u64,
+            self.config.height as u64,
+            self.frame_buffer.clone(),
+        )
+    }
+
+    fn cursor_data(&self) -> Cursor {
+        Cursor::default()
+    }
+}
+
+/// Scans the physical memory for regions that look like a linear framebuffer.
+///
+/// A region is considered plausible for a 32-bit format if the unused fourth byte of every pixel
+/// is constant and it contains some visible content. The stride is estimated by finding the common
+/// screen width with the smallest difference between vertically adjacent pixels.
+/// Formats without padding bytes are only reported if that width stands out clearly.
+/// The byte order of the 32-bit formats can not be told apart, they are reported as `Bgrx8888`.
+/// Zeroed memory is never used as the start of a region and all bases are page aligned.
+/// The returned candidates are sorted by their score.
+pub fn detect_framebuffers<T: PhysicalMemory>(mem: &mut T) -> Vec<FramebufferCandidate> {
+    let max_address = mem.metadata().max_address;
+    let mut chunk = vec![0u8; DETECT_CHUNK_SIZE];
+
+    let mut candidates: Vec<FramebufferCandidate> = Vec::new();
+    let mut region_start: Option<Address> = None;
+    let mut region_has_candidate = false;
+    // format of the packed candidate found in the previous chunk
+    let mut packed_format: Option<PixelFormat> = None;
+
+    let mut chunk_addr = Address::null();
+    while chunk_addr < max_address {
+        if mem
+            .phys_view()
+            .read_raw_into(chunk_addr, &mut chunk)
+            .data_part()
+            .is_err()
+        {
+            region_start = None;
+            packed_format = None;
+            chunk_addr += DETECT_CHUNK_SIZE as umem;
+            continue;
+        }
+
+        if !has_constant_padding(&chunk) {
+            // the region might start within this chunk
+            region_start = padded_start(&chunk).map(|offset| chunk_addr + offset as umem);
+            region_has_candidate = false;
+
+            let packed = [PixelFormat::Bgr888, PixelFormat::Rgb565]
+                .iter()
+                .copied()
+                .find_map(|format| Some((format, estimate_packed_width(&chunk, format)?)));
+            match packed {
+                Some((format, (width, score))) if packed_format != Some(format) => {
+                    candidates.push(FramebufferCandidate {
+                        config: FramebufferConfig::new(
+                            chunk_addr,
+                            width,
+                            common_height(width),
+                            format,
+                        ),
+                        score,
+                    });
+                    packed_format = Some(format);
+                }
+                Some(_) => (),
+                None => packed_format = None,
+            }
+
+            chunk_addr += DETECT_CHUNK_SIZE as umem;
+            continue;
+        }
+        packed_format = None;
+
+        // black parts of the screen are part of the region but can not be used to estimate the stride,
+        // zeroed memory can not be told apart from a black screen so it does not start a region
+        let region = match region_start {
+            Some(region_start) => region_start,
+            None => match padded_start(&chunk) {
+                Some(offset) => {
+                    let start = chunk_addr + offset as umem;
+                    region_start = Some(start);
+                    region_has_candidate = false;
+                    start
+                }
+                None => {
+                    chunk_addr += DETECT_CHUNK_SIZE as umem;
+                    continue;
+                }
+            },
+        };
+        if region_has_candidate || !has_content(&chunk) {
+            chunk_addr += DETECT_CHUNK_SIZE as umem;
+            continue;
+        }
+
+        if let Some((width, score)) = estimate_width(&chunk, PixelFormat::Bgrx8888) {
+            let lookbehind = DETECT_MAX_LOOKBEHIND as umem * DETECT_CHUNK_SIZE as umem;
+            let base = if chunk_addr.to_umem() - region.to_umem() > lookbehind {
+                chunk_addr.to_umem() - lookbehind
+            } else {
+                region.to_umem()
+            };
+            let base = Address::from(base & !(DETECT_PAGE_SIZE as umem - 1));
+
+            candidates.push(FramebufferCandidate {
+                config: FramebufferConfig::new(
+                    base,
+                    width,
+                    common_height(width),
+                    PixelFormat::Bgrx8888,
+                ),
+                score,
+            });
+            region_has_candidate = true;
+        }
+
+        chunk_addr += DETECT_CHUNK_SIZE as umem;
+    }
+
+    candidates.sort_by(|a, b| a.score.total_cmp(&b.score));
+    candidates
+}
+
+/// Checks if the fourth byte of (almost) all 32-bit pixels is either 0x00 or 0xFF
+fn has_constant_padding(chunk: &[u8]) -> bool {
+    let pixels = chunk.len() / 4;
+    let zero = chunk.chunks_exact(4).filter(|p| p[3] == 0x00).count();
+    let full = chunk.chunks_exact(4).filter(|p| p[3] == 0xFF).count();
+    zero.max(full) * 100 >= pixels * 99
+}
+
+/// Returns the offset of the first page of the trailing pages with constant padding,
+/// leading pages that are zeroed are skipped
+fn padded_start(chunk: &[u8]) -> Option<usize> {
+    let pages = chunk.chunks_exact(DETECT_PAGE_SIZE).collect::<Vec<_>>();
+    let padded = pages
+        .iter()
+        .rev()
+        .take_while(|page| has_constant_padding(page))
+        .count();
+    pages[pages.len() - padded..]
+        .iter()
+        .position(|page| page.iter().any(|b| *b != 0))
+        .map(|page| (pages.len() - padded + page) * DETECT_PAGE_SIZE)
+}
+
+/// Checks if at least one percent of the pixels differ from the first pixel
+fn has_content(chunk: &[u8]) -> bool {
+    let pixels = chunk.len() / 4;
+    let first = &chunk[..3];
+    let different = chunk.chunks_exact(4).filter(|p| &p[..3] != first).count();
+    different * 100 >= pixels
+}
+
+/// Returns the height of the common resolution with the given width
+fn common_height(width: u32) -> u32 {
+    COMMON_RESOLUTIONS
+        .iter()
+        .find(|(w, _)| *w == width)
+        .map(|(_, h)| *h)
+        .unwrap_or(width * 3 / 4)
+}
+
+/// Returns the common width whose stride results in the smallest mean difference between vertically adjacent pixels
+fn estimate_width(chunk: &[u8], format: PixelFormat) -> Option<(u32, f32)> {
+    width_scores(chunk, format)
+        .into_iter()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Like `estimate_width` but the best width has to stand out clearly, random data results in similar scores for all widths
+fn estimate_packed_width(chunk: &[u8], format: PixelFormat) -> Option<(u32, f32)> {
+    let mut scores = width_scores(chunk, format);
+    if scores.len() < 3 {
+        return None;
+    }
+    scores.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let median = scores[scores.len() / 2].1;
+    Some(scores[0]).filter(|(_, score)| *score * 4.0 < median)
+}
+
+/// Returns the mean difference between vertically adjacent pixels for every common width
+fn width_scores(chunk: &[u8], format: PixelFormat) -> Vec<(u32, f32)> {
+    let bytes_per_pixel = format.bytes_per_pixel();
+    // padding bytes are not compared
+    let compared = bytes_per_pixel.min(3);
+
+    let mut widths = COMMON_RESOLUTIONS
+        .iter()
+        .map(|(w, _)| *w)
+        .collect::<Vec<_>>();
+    widths.dedup();
+
+    widths
+        .into_iter()
+        .filter_map(|width| {
+            let stride = width as usize * bytes_per_pixel;
+            if stride * 2 > chunk.len() {
+                return None;
+            }
+
+            let mut diff = 0u64;
+            let mut samples = 0u64;
+            for line in (0..chunk.len() / stride - 1).step_by(4) {
+                for x in (0..stride - compared).step_by(16 * bytes_per_pixel) {
+                    let a = &chunk[line * stride + x..line * stride + x + compared];
+                    let b = &chunk[(line + 1) * stride + x..(line + 1) * stride + x + compared];
+                    diff += a
+                        .iter()
+                        .zip(b.iter())
+                        .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as u64)
+                        .sum::<u64>();
+                    samples += 1;
+                }
+            }
+
+            if samples > 0 {
+                Some((width, diff as f32 / samples as f32))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::memflow::dummy::DummyMemory;
+
+    #[test]
+    fn detect_framebuffer() {
+        let mut mem = DummyMemory::new(size::mb(16));
+
+        // the framebuffer starts within a chunk and is surrounded by zeroed memory
+        let base = Address::from(0x40_3000);
+        let (width, height) = (1024usize, 768usize);
+        let pixels = (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                [x as u8, y as u8, (x + y) as u8, 0]
+            })
+            .collect::<Vec<_>>();
+        mem.phys_view().write_raw(base, &pixels).unwrap();
+
+        let candidates = detect_framebuffers(&mut mem);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(
+            candidates[0].config,
+            FramebufferConfig::new(base, 1024, 768, PixelFormat::Bgrx8888)
+        );
+    }
+
+    #[test]
+    fn zeroed_memory() {
+        let mut mem = DummyMemory::new(size::mb(16));
+        assert!(detect_framebuffers(&mut mem).is_empty());
+    }
+
+    #[test]
+    fn reset_stride() {
+        let mut config = FramebufferConfig::new(Address::null(), 1024, 768, PixelFormat::Bgrx8888);
+        config.width = 1280;
+        config.format = PixelFormat::Bgr888;
+        config.reset_stride();
+        assert_eq!(config.stride, 1280 * 3);
+    }
+}
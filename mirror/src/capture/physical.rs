@@ -0,0 +1,466 @@
+use ::frame_counter::FrameCounter;
+use ::log::{info, warn};
+use ::mirror_dto::{
+    CaptureConfig, Cursor, PhysicalDescriptor, PHYSICAL_MARKER, PHYSICAL_PAGE_LIST_LEN,
+    PHYSICAL_PAGE_SIZE,
+};
+use ::std::{
+    mem::offset_of,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ::memflow::prelude::v1::*;
+
+use super::{image_data_from_buffer, Capture, FrameSink, FrameSinks};
+
+/// Size of the chunks that are read while scanning for the descriptor
+const SCAN_CHUNK_SIZE: usize = 0x200000;
+
+/// Delay after a scan in case no descriptor could be found
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Capture that reads frames only through the `PhysicalMemory` interface of a connector.
+///
+/// The guest agent publishes its pinned frame buffer pages through a `PhysicalDescriptor`
+/// which is located by scanning physical memory. This does neither require an os plugin
+/// nor process enumeration on the host.
+/// The scan runs on a background thread on a clone of the connector so updates do not block.
+pub struct PhysicalCapture<T> {
+    mem: T,
+
+    transport: Option<PhysicalTransport>,
+    scan: Option<JoinHandle<Result<Address>>>,
+    last_scan: Option<Instant>,
+
+    capture_config: CaptureConfig,
+    descriptor: PhysicalDescriptor,
+    frame_buffer: Vec<u8>,
+    frame_sinks: FrameSinks,
+    update_counter: FrameCounter,
+}
+
+impl<T: PhysicalMemory + Clone + 'static> PhysicalCapture<T> {
+    pub fn new(mem: T) -> Self {
+        Self {
+            mem,
+
+            transport: None,
+            scan: None,
+            last_scan: None,
+
+            capture_config: CaptureConfig::default(),
+            descriptor: PhysicalDescriptor::new((0, 0)),
+            frame_buffer: Vec::new(),
+            frame_sinks: FrameSinks::default(),
+            update_counter: FrameCounter::new(0f64),
+        }
+    }
+}
+
+impl<T: PhysicalMemory + Clone + 'static> Capture for PhysicalCapture<T> {
+    fn multithreading(&self) -> bool {
+        false
+    }
+
+    fn os(&self) -> Option<OsInstanceArcBox<'static>> {
+        None
+    }
+
+    fn obs_capture(&self) -> bool {
+        self.capture_config.obs()
+    }
+    fn set_obs_capture(&mut self, obs: bool) {
+        self.capture_config.set_obs(obs);
+    }
+
+    fn update(&mut self) {
+        if let Some(transport) = &mut self.transport {
+            match transport.update_into(
+                &mut self.mem,
+                &self.capture_config,
+                &mut self.descriptor,
+                &mut self.frame_buffer,
+            ) {
+                Ok(_) => {
+                    self.update_counter.tick();
+                    let mut frame_sinks = std::mem::take(&mut self.frame_sinks);
+                    frame_sinks.push_frame(self.frame_counter(), || self.frame());
+                    self.frame_sinks = frame_sinks;
+                }
+                Err(Error(_, ErrorKind::AlreadyExists)) => (),
+                Err(err) => {
+                    warn!("lost physical descriptor: {}", err);
+                    self.transport = None;
+                }
+            }
+            return;
+        }
+
+        match self.scan.as_ref().map(|scan| scan.is_finished()) {
+            Some(true) => {
+                // scanning the entire physical memory is expensive so we do not retry right away
+                self.last_scan = Some(Instant::now());
+                match self.scan.take().map(JoinHandle::join) {
+                    Some(Ok(Ok(descriptor_addr))) => {
+                        self.transport = Some(PhysicalTransport::new(descriptor_addr));
+                    }
+                    Some(Ok(Err(_))) => (),
+                    _ => warn!("physical descriptor scan failed"),
+                }
+            }
+            Some(false) => (),
+            None => {
+                if self
+                    .last_scan
+                    .map(|last_scan| last_scan.elapsed() >= SCAN_INTERVAL)
+                    .unwrap_or(true)
+                {
+                    let mut mem = self.mem.clone();
+                    self.scan = Some(thread::spawn(move || {
+                        PhysicalTransport::find_descriptor(&mut mem)
+                    }));
+                }
+            }
+        }
+    }
+
+    fn frame_counter(&self) -> u32 {
+        self.descriptor.frame_counter
+    }
+
+    fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.add(sink);
+    }
+
+    fn image_data(&self) -> egui::ImageData {
+        image_data_from_buffer(
+            self.descriptor.width,
+            self.descriptor.height,
+            self.frame_buffer.clone(),
+        )
+    }
+
+    fn cursor_data(&self) -> Cursor {
+        self.descriptor.cursor
+    }
+}
+
+struct PhysicalTransport {
+    descriptor_addr: Address,
+
+    // page frame numbers of the frame buffer merged into contiguous runs of (address, length)
+    frame_runs: Vec<(Address, usize)>,
+    frame_buffer_len: u64,
+    generation: u64,
+    frame_counter: u32,
+}
+
+impl PhysicalTransport {
+    pub fn new(descriptor_addr: Address) -> Self {
+        info!("physical descriptor found at {:x}", descriptor_addr);
+
+        Self {
+            descriptor_addr,
+
+            frame_runs: Vec::new(),
+            frame_buffer_len: 0,
+            generation: 0,
+            frame_counter: 0,
+        }
+    }
+
+    /// Scans the physical memory for a page starting with the `PHYSICAL_MARKER`
+    ///
+    /// Chunks and pages that cannot be read are skipped.
+    fn find_descriptor<T: PhysicalMemory>(mem: &mut T) -> Result<Address> {
+        let max_address = mem.metadata().max_address;
+        let mut chunk = vec![0u8; SCAN_CHUNK_SIZE];
+
+        let mut chunk_addr = Address::null();
+        while chunk_addr < max_address {
+            // unmapped regions will be zero-filled and simply be skipped
+            if mem
+                .phys_view()
+                .read_raw_into(chunk_addr, &mut chunk)
+                .data_part()
+                .is_err()
+            {
+                chunk_addr += SCAN_CHUNK_SIZE as umem;
+                continue;
+            }
+
+            for (page_idx, page) in chunk.chunks_exact(PHYSICAL_PAGE_SIZE as usize).enumerate() {
+                if page[..PHYSICAL_MARKER.len()] != PHYSICAL_MARKER {
+                    continue;
+                }
+
+                let descriptor_addr = chunk_addr + page_idx as umem * PHYSICAL_PAGE_SIZE as umem;
+                match mem.phys_view().read::<PhysicalDescriptor>(descriptor_addr) {
+                    Ok(descriptor) if descriptor.is_valid() => return Ok(descriptor_addr),
+                    _ => continue,
+                }
+            }
+
+            chunk_addr += SCAN_CHUNK_SIZE as umem;
+        }
+
+        Err(Error(ErrorOrigin::Connector, ErrorKind::NotFound))
+    }
+
+    /// Reads all page lists referenced by the descriptor and merges the frame buffer pages into contiguous runs
+    fn update_page_lists<T: PhysicalMemory>(
+        &mut self,
+        mem: &mut T,
+        descriptor: &PhysicalDescriptor,
+    ) -> Result<()> {
+        let entries_per_page = (PHYSICAL_PAGE_SIZE / 8) as usize;
+        let page_list_count = descriptor.page_list_count as usize;
+        let frame_page_count = descriptor.frame_page_count as usize;
+        if page_list_count > PHYSICAL_PAGE_LIST_LEN
+            || frame_page_count > page_list_count * entries_per_page
+        {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::OutOfBounds));
+        }
+
+        let mut pfns = vec![0u64; page_list_count * entries_per_page];
+        for (pfn, list) in descriptor.page_list[..page_list_count]
+            .iter()
+            .zip(pfns.chunks_exact_mut(entries_per_page))
+        {
+            mem.phys_view()
+                .read_into(Address::from(pfn * PHYSICAL_PAGE_SIZE), list)?;
+        }
+
+        self.frame_runs.clear();
+        for pfn in pfns[..frame_page_count].iter() {
+            let page_addr = Address::from(pfn * PHYSICAL_PAGE_SIZE);
+            match self.frame_runs.last_mut() {
+                Some((run_addr, run_len)) if *run_addr + *run_len as umem == page_addr => {
+                    *run_len += PHYSICAL_PAGE_SIZE as usize;
+                }
+                _ => self
+                    .frame_runs
+                    .push((page_addr, PHYSICAL_PAGE_SIZE as usize)),
+            }
+        }
+        self.frame_buffer_len = descriptor.frame_buffer_len;
+        self.generation = descriptor.generation;
+
+        info!(
+            "frame buffer mapped by {} pages in {} runs",
+            frame_page_count,
+            self.frame_runs.len()
+        );
+        Ok(())
+    }
+
+    pub fn update_into<T: PhysicalMemory>(
+        &mut self,
+        mem: &mut T,
+        capture_config: &CaptureConfig,
+        descriptor: &mut PhysicalDescriptor,
+        frame_buffer: &mut Vec<u8>,
+    ) -> Result<()> {
+        mem.phys_view()
+            .read_into(self.descriptor_addr, descriptor)?;
+        if !descriptor.is_valid() {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::NotFound));
+        }
+
+        if descriptor.frame_counter == self.frame_counter || descriptor.frame_buffer_len == 0 {
+            // no new update yet or the guest is re-allocating the frame buffer
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::AlreadyExists));
+        }
+
+        // check if the guest re-allocated the frame buffer, the new pages might have the same size
+        if descriptor.frame_buffer_len != self.frame_buffer_len
+            || descriptor.generation != self.generation
+            || self.frame_runs.is_empty()
+        {
+            self.update_page_lists(mem, descriptor)?;
+        }
+
+        // limit to 16k resolution, the descriptor is read from guest memory and can not be trusted
+        if descriptor.width > 15360 || descriptor.height > 8640 {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::OutOfBounds));
+        }
+        let frame_len = descriptor
+            .width
+            .checked_mul(descriptor.height)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .filter(|frame_len| *frame_len as u64 <= descriptor.frame_buffer_len)
+            .ok_or(Error(ErrorOrigin::Connector, ErrorKind::OutOfBounds))?
+            as usize;
+        if frame_buffer.len() != frame_len {
+            info!(
+                "changing resolution: to {}x{}",
+                descriptor.width, descriptor.height
+            );
+            frame_buffer.resize(frame_len, 0u8);
+        }
+
+        // update frame_buffer on host
+        let mut offset = 0;
+        for (run_addr, run_len) in self.frame_runs.iter() {
+            if offset >= frame_len {
+                break;
+            }
+            let len = (*run_len).min(frame_len - offset);
+            mem.phys_view()
+                .read_raw_into(*run_addr, &mut frame_buffer[offset..offset + len])
+                .data_part()
+                .ok();
+            offset += len;
+        }
+
+        // update configuration on guest
+        let mut view = mem.phys_view();
+        view.write(
+            self.descriptor_addr + offset_of!(PhysicalDescriptor, config) as umem,
+            capture_config,
+        )
+        .ok();
+        view.write(
+            self.descriptor_addr + offset_of!(PhysicalDescriptor, frame_read_counter) as umem,
+            &descriptor.frame_counter,
+        )
+        .ok();
+
+        self.frame_counter = descriptor.frame_counter;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Frame;
+    use ::memflow::dummy::DummyMemory;
+    use ::mirror_dto::PHYSICAL_VERSION;
+
+    const DESCRIPTOR_ADDR: u64 = 0x30_0000;
+    const PAGE_LIST_ADDR: u64 = 0x10_0000;
+    const WIDTH: u64 = 32;
+    const HEIGHT: u64 = 64;
+
+    /// Publishes a frame in two pages that are not contiguous in physical memory
+    fn publish_frame(mem: &mut DummyMemory, pages: [u64; 2], seed: u8, generation: u64) -> Vec<u8> {
+        let pixels = (0..WIDTH * HEIGHT * 4)
+            .map(|i| (i % 251) as u8 ^ seed)
+            .collect::<Vec<_>>();
+        for (page, data) in pages
+            .iter()
+            .zip(pixels.chunks_exact(PHYSICAL_PAGE_SIZE as usize))
+        {
+            mem.phys_view()
+                .write_raw(Address::from(page * PHYSICAL_PAGE_SIZE), data)
+                .unwrap();
+        }
+        let page_list = pages
+            .iter()
+            .flat_map(|page| page.to_le_bytes())
+            .collect::<Vec<_>>();
+        mem.phys_view()
+            .write_raw(Address::from(PAGE_LIST_ADDR), &page_list)
+            .unwrap();
+
+        let mut descriptor = PhysicalDescriptor::new((WIDTH, HEIGHT));
+        descriptor.frame_counter = generation as u32;
+        descriptor.frame_buffer_len = pixels.len() as u64;
+        descriptor.frame_page_count = 2;
+        descriptor.page_list_count = 1;
+        descriptor.generation = generation;
+        descriptor.page_list[0] = PAGE_LIST_ADDR / PHYSICAL_PAGE_SIZE;
+        mem.phys_view()
+            .write(Address::from(DESCRIPTOR_ADDR), &descriptor)
+            .unwrap();
+
+        pixels
+    }
+
+    fn wait_for_frame(capture: &mut PhysicalCapture<DummyMemory>, frame_counter: u32) -> Frame {
+        let start = Instant::now();
+        while capture.frame_counter() != frame_counter {
+            assert!(start.elapsed() < Duration::from_secs(10), "no frame");
+            capture.update();
+            thread::sleep(Duration::from_millis(10));
+        }
+        capture.frame()
+    }
+
+    #[test]
+    fn find_descriptor() {
+        let mut mem = DummyMemory::new(size::mb(8));
+
+        // a page with the marker but an unknown version is skipped
+        let mut outdated = PhysicalDescriptor::new((WIDTH, HEIGHT));
+        outdated.version = PHYSICAL_VERSION + 1;
+        mem.phys_view()
+            .write(Address::from(0x20_0000), &outdated)
+            .unwrap();
+        publish_frame(&mut mem, [0x500, 0x200], 0, 1);
+
+        assert_eq!(
+            PhysicalTransport::find_descriptor(&mut mem).unwrap(),
+            Address::from(DESCRIPTOR_ADDR)
+        );
+    }
+
+    #[test]
+    fn find_descriptor_not_found() {
+        let mut mem = DummyMemory::new(size::mb(8));
+        assert!(PhysicalTransport::find_descriptor(&mut mem).is_err());
+    }
+
+    #[test]
+    fn frame() {
+        let mut mem = DummyMemory::new(size::mb(8));
+        let pixels = publish_frame(&mut mem, [0x500, 0x200], 0, 1);
+
+        let mut capture = PhysicalCapture::new(mem.clone());
+        let frame = wait_for_frame(&mut capture, 1);
+        assert_eq!((frame.width, frame.height), (WIDTH as u32, HEIGHT as u32));
+        assert_eq!(frame.data, pixels);
+
+        // the guest re-allocated a frame buffer of the same size in different pages
+        let pixels = publish_frame(&mut mem, [0x600, 0x700], 0x55, 2);
+        let frame = wait_for_frame(&mut capture, 2);
+        assert_eq!(frame.data, pixels);
+    }
+
+    #[test]
+    fn invalid_resolution() {
+        let mut mem = DummyMemory::new(size::mb(8));
+        publish_frame(&mut mem, [0x500, 0x200], 0, 1);
+
+        let mut descriptor = PhysicalDescriptor::new((WIDTH, HEIGHT));
+        let mut frame_buffer = Vec::new();
+        let mut transport = PhysicalTransport::new(Address::from(DESCRIPTOR_ADDR));
+        for resolution in [(u64::MAX, u64::MAX), (15360, 8640), (WIDTH, HEIGHT * 2)].iter() {
+            let mut hostile = PhysicalDescriptor::new(*resolution);
+            hostile.frame_counter = 2;
+            hostile.frame_buffer_len = WIDTH * HEIGHT * 4;
+            hostile.frame_page_count = 2;
+            hostile.page_list_count = 1;
+            hostile.generation = 1;
+            hostile.page_list[0] = PAGE_LIST_ADDR / PHYSICAL_PAGE_SIZE;
+            mem.phys_view()
+                .write(Address::from(DESCRIPTOR_ADDR), &hostile)
+                .unwrap();
+
+            let result = transport.update_into(
+                &mut mem,
+                &CaptureConfig::default(),
+                &mut descriptor,
+                &mut frame_buffer,
+            );
+            assert_eq!(
+                result,
+                Err(Error(ErrorOrigin::Connector, ErrorKind::OutOfBounds))
+            );
+            assert!(frame_buffer.is_empty());
+        }
+    }
+}
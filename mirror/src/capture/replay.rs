@@ -0,0 +1,465 @@
+use ::log::warn;
+use ::mirror_dto::Cursor;
+use ::std::{
+    collections::VecDeque,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{
+    file::{decode_frame, encode_frame, ENCODING_QOI, ENCODING_RAW},
+    Frame,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayConfig {
+    /// Amount of time that is kept in the buffer
+    pub duration: Duration,
+    /// Upper bound for the memory used by the buffered frames in bytes
+    pub max_memory: usize,
+    /// Frames are downscaled by this factor before they are buffered (1 = full resolution)
+    pub downscale: u32,
+    /// Compresses the buffered frames with qoi, this trades cpu time for memory
+    pub compress: bool,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(30),
+            max_memory: 1024 * 1024 * 1024,
+            downscale: 1,
+            compress: true,
+        }
+    }
+}
+
+impl ReplayConfig {
+    /// Downscales and encodes a frame for a buffer with this configuration.
+    ///
+    /// This is the expensive part of `ReplayBuffer::push`, it does not need access to the buffer.
+    pub(crate) fn encode(&self, frame: &Frame) -> Option<EncodedFrame> {
+        if frame.is_empty() {
+            return None;
+        }
+
+        let scaled;
+        let frame = if self.downscale > 1 {
+            scaled = downscale(frame, self.downscale);
+            &scaled
+        } else {
+            frame
+        };
+
+        let encoding = if self.compress {
+            ENCODING_QOI
+        } else {
+            ENCODING_RAW
+        };
+        let data: Arc<[u8]> = match encode_frame(frame, encoding) {
+            Ok(data) => data.into(),
+            Err(err) => {
+                warn!("unable to encode replay frame: {}", err);
+                return None;
+            }
+        };
+
+        Some(EncodedFrame {
+            timestamp: frame.timestamp,
+            entry: ReplayEntry {
+                timestamp: Duration::ZERO,
+                width: frame.width,
+                height: frame.height,
+                cursor: frame.cursor,
+                encoding,
+                data,
+            },
+        })
+    }
+}
+
+/// A frame that was encoded with `ReplayConfig::encode` and can be added to a `ReplayBuffer`
+pub(crate) struct EncodedFrame {
+    /// Time the frame was captured at in microseconds of the guest clock, if known
+    timestamp: Option<u64>,
+    entry: ReplayEntry,
+}
+
+#[derive(Clone)]
+struct ReplayEntry {
+    timestamp: Duration,
+    width: u32,
+    height: u32,
+    cursor: Cursor,
+    encoding: u32,
+    data: Arc<[u8]>,
+}
+
+/// Rolling buffer of the most recent frames of a capture.
+///
+/// Old frames are evicted once they are older than the configured duration
+/// or once the buffer exceeds its memory limit.
+pub struct ReplayBuffer {
+    config: ReplayConfig,
+    entries: VecDeque<ReplayEntry>,
+    memory_usage: usize,
+
+    start: Instant,
+    first_timestamp: Option<u64>,
+}
+
+impl ReplayBuffer {
+    pub fn new(config: ReplayConfig) -> Self {
+        Self {
+            config,
+            entries: VecDeque::new(),
+            memory_usage: 0,
+
+            start: Instant::now(),
+            first_timestamp: None,
+        }
+    }
+
+    pub fn config(&self) -> &ReplayConfig {
+        &self.config
+    }
+
+    /// Updates the configuration, frames that do not fit the new limits are evicted
+    pub fn set_config(&mut self, config: ReplayConfig) {
+        self.config = config;
+        self.evict();
+    }
+
+    /// Returns the number of buffered frames
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the amount of memory used by the buffered frames in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    /// Returns the time span covered by the buffered frames
+    pub fn duration(&self) -> Duration {
+        match (self.entries.front(), self.entries.back()) {
+            (Some(first), Some(last)) => last.timestamp.saturating_sub(first.timestamp),
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.memory_usage = 0;
+    }
+
+    /// Adds a frame to the buffer
+    pub fn push(&mut self, frame: &Frame) {
+        if let Some(frame) = self.config.encode(frame) {
+            self.push_encoded(frame);
+        }
+    }
+
+    /// Adds a frame that was already encoded, frames that no longer fit into the buffer are evicted
+    pub(crate) fn push_encoded(&mut self, frame: EncodedFrame) {
+        let mut entry = frame.entry;
+
+        // prefer the timestamps of the guest as they are not affected by the polling of the host
+        entry.timestamp = match frame.timestamp {
+            Some(timestamp) => {
+                let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+                Duration::from_micros(timestamp.saturating_sub(first_timestamp))
+            }
+            None => self.start.elapsed(),
+        };
+        // the two clocks can disagree, the entries stay in order for the eviction and the clips
+        if let Some(last) = self.entries.back() {
+            entry.timestamp = entry.timestamp.max(last.timestamp);
+        }
+
+        self.memory_usage += entry.data.len();
+        self.entries.push_back(entry);
+        self.evict();
+    }
+
+    /// Returns a snapshot of the frames captured within the given duration before the latest frame.
+    ///
+    /// The snapshot shares the buffered data and does not block further captures.
+    pub fn clip(&self, last: Duration) -> ReplayClip {
+        let end = self
+            .entries
+            .back()
+            .map(|entry| entry.timestamp)
+            .unwrap_or_default();
+        let start = end.saturating_sub(last);
+        ReplayClip {
+            entries: self
+                .entries
+                .iter()
+                .filter(|entry| entry.timestamp >= start)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn evict(&mut self) {
+        let end = self
+            .entries
+            .back()
+            .map(|entry| entry.timestamp)
+            .unwrap_or_default();
+        while let Some(entry) = self.entries.front() {
+            if end.saturating_sub(entry.timestamp) <= self.config.duration
+                && self.memory_usage <= self.config.max_memory
+            {
+                break;
+            }
+            self.memory_usage -= entry.data.len();
+            self.entries.pop_front();
+        }
+    }
+}
+
+/// A sequence of frames taken out of a `ReplayBuffer`
+#[derive(Clone, Default)]
+pub struct ReplayClip {
+    entries: Vec<ReplayEntry>,
+}
+
+impl ReplayClip {
+    /// Returns the number of frames in the clip
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn duration(&self) -> Duration {
+        match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => last.timestamp.saturating_sub(first.timestamp),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Returns the time of every frame relative to the start of the clip
+    pub fn timestamps(&self) -> Vec<Duration> {
+        let start = self.start();
+        self.entries
+            .iter()
+            .map(|entry| entry.timestamp.saturating_sub(start))
+            .collect()
+    }
+
+    /// Decodes a single frame of the clip
+    pub fn frame(&self, index: usize) -> io::Result<Frame> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "frame is not in the clip"))?;
+        Ok(Frame {
+            width: entry.width,
+            height: entry.height,
+            timestamp: None,
+            cursor: entry.cursor,
+            data: decode_frame(
+                entry.encoding,
+                entry.data.to_vec(),
+                entry.width,
+                entry.height,
+            )?,
+        })
+    }
+
+    /// Decodes the frames one by one together with their time relative to the start of the clip
+    pub fn frames(&self) -> impl Iterator<Item = (Duration, Frame)> + '_ {
+        let start = self.start();
+        (0..self.entries.len()).filter_map(move |index| match self.frame(index) {
+            Ok(frame) => Some((self.entries[index].timestamp.saturating_sub(start), frame)),
+            Err(err) => {
+                warn!("unable to decode replay frame: {}", err);
+                None
+            }
+        })
+    }
+
+    fn start(&self) -> Duration {
+        self.entries
+            .first()
+            .map(|entry| entry.timestamp)
+            .unwrap_or_default()
+    }
+}
+
+/// Downscales a frame by an integer factor by averaging each block of pixels
+fn downscale(frame: &Frame, factor: u32) -> Frame {
+    let width = (frame.width / factor).max(1);
+    let height = (frame.height / factor).max(1);
+    let stride = frame.width as usize * 4;
+
+    let mut data = vec![0u8; width as usize * height as usize * 4];
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for sy in (y * factor as usize..(y + 1) * factor as usize)
+                .take_while(|&sy| sy < frame.height as usize)
+            {
+                for sx in (x * factor as usize..(x + 1) * factor as usize)
+                    .take_while(|&sx| sx < frame.width as usize)
+                {
+                    let offset = sy * stride + sx * 4;
+                    for (channel, value) in sum.iter_mut().zip(&frame.data[offset..offset + 4]) {
+                        *channel += *value as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            let offset = (y * width as usize + x) * 4;
+            for (target, channel) in data[offset..offset + 4].iter_mut().zip(sum) {
+                *target = (channel / count.max(1)) as u8;
+            }
+        }
+    }
+
+    // the cursor position is kept in the coordinate space of the downscaled frame
+    let mut cursor = frame.cursor;
+    cursor.x /= factor as i32;
+    cursor.y /= factor as i32;
+
+    Frame {
+        width,
+        height,
+        timestamp: frame.timestamp,
+        cursor,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // raw frames of 2x2 pixels
+    const FRAME_SIZE: usize = 2 * 2 * 4;
+
+    fn frame(timestamp_ms: u64) -> Frame {
+        Frame {
+            width: 2,
+            height: 2,
+            timestamp: Some(timestamp_ms * 1000),
+            cursor: Cursor::default(),
+            data: vec![timestamp_ms as u8; FRAME_SIZE],
+        }
+    }
+
+    fn config(duration_ms: u64, max_memory: usize) -> ReplayConfig {
+        ReplayConfig {
+            duration: Duration::from_millis(duration_ms),
+            max_memory,
+            downscale: 1,
+            compress: false,
+        }
+    }
+
+    #[test]
+    fn evict_old_frames() {
+        let mut replay = ReplayBuffer::new(config(100, usize::MAX));
+        for timestamp in (0..=300).step_by(50) {
+            replay.push(&frame(timestamp));
+        }
+        assert_eq!(replay.len(), 3);
+        assert_eq!(replay.duration(), Duration::from_millis(100));
+        assert_eq!(replay.memory_usage(), 3 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn evict_on_memory_limit() {
+        let mut replay = ReplayBuffer::new(config(1000, 2 * FRAME_SIZE + 1));
+        for timestamp in 0..4 {
+            replay.push(&frame(timestamp));
+        }
+        assert_eq!(replay.len(), 2);
+        assert_eq!(replay.memory_usage(), 2 * FRAME_SIZE);
+
+        // the oldest frames are evicted
+        let clip = replay.clip(Duration::from_secs(1));
+        assert_eq!(clip.frame(0).unwrap().data, vec![2; FRAME_SIZE]);
+        assert_eq!(clip.frame(1).unwrap().data, vec![3; FRAME_SIZE]);
+    }
+
+    #[test]
+    fn evict_on_new_config() {
+        let mut replay = ReplayBuffer::new(config(1000, usize::MAX));
+        for timestamp in (0..=300).step_by(100) {
+            replay.push(&frame(timestamp));
+        }
+        assert_eq!(replay.len(), 4);
+
+        replay.set_config(config(100, usize::MAX));
+        assert_eq!(replay.len(), 2);
+        assert_eq!(replay.memory_usage(), 2 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn clip() {
+        let mut replay = ReplayBuffer::new(config(1000, usize::MAX));
+        for timestamp in (0..=300).step_by(100) {
+            replay.push(&frame(timestamp));
+        }
+
+        let clip = replay.clip(Duration::from_millis(150));
+        assert_eq!(
+            clip.timestamps(),
+            vec![Duration::ZERO, Duration::from_millis(100)]
+        );
+        assert_eq!(clip.frame(0).unwrap().data, vec![200; FRAME_SIZE]);
+    }
+
+    #[test]
+    fn timestamps_stay_in_order() {
+        let mut replay = ReplayBuffer::new(config(10_000, usize::MAX));
+        replay.push(&frame(0));
+        replay.push(&frame(5000));
+
+        // a frame without a guest timestamp uses the host clock, which is behind here
+        let mut host_frame = frame(0);
+        host_frame.timestamp = None;
+        replay.push(&host_frame);
+
+        let clip = replay.clip(Duration::from_secs(10));
+        assert_eq!(
+            clip.timestamps(),
+            vec![
+                Duration::ZERO,
+                Duration::from_secs(5),
+                Duration::from_secs(5)
+            ]
+        );
+        assert_eq!(clip.frames().count(), 3);
+    }
+
+    #[test]
+    fn encoded_frames() {
+        let mut config = config(1000, usize::MAX);
+        config.downscale = 2;
+        config.compress = true;
+        let mut replay = ReplayBuffer::new(config.clone());
+
+        let mut frame = frame(0);
+        frame.cursor.x = 1;
+        replay.push_encoded(config.encode(&frame).unwrap());
+
+        let frame = replay.clip(Duration::ZERO).frame(0).unwrap();
+        assert_eq!((frame.width, frame.height), (1, 1));
+        assert_eq!(frame.data, vec![0; 4]);
+        assert_eq!(frame.cursor.x, 0);
+    }
+}
@@ -0,0 +1,124 @@
+use ::log::error;
+
+use ::memflow::prelude::v1::*;
+
+/// Arguments of a connector / os chain together with the position of every stage, see `OsChain::new`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChainArgs {
+    pub connectors: Vec<(usize, String)>,
+    pub os: Vec<(usize, String)>,
+}
+
+impl ChainArgs {
+    /// Collects the `connector` and `os` arguments in the order they were given on the command line
+    #[cfg(feature = "mirror-bin")]
+    pub fn from_matches(matches: &::clap::ArgMatches) -> Self {
+        Self {
+            connectors: indexed_args(matches, "connector"),
+            os: indexed_args(matches, "os"),
+        }
+    }
+
+    pub fn push_connector(&mut self, arg: &str) {
+        let index = self.len();
+        self.connectors.push((index, arg.to_string()));
+    }
+
+    pub fn push_os(&mut self, arg: &str) {
+        let index = self.len();
+        self.os.push((index, arg.to_string()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.connectors.len() + self.os.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Builds the os at the end of the chain
+    pub fn build_os(&self, inventory: &Inventory) -> Result<OsInstanceArcBox<'static>> {
+        if self.last_is_connector() != Some(false) {
+            error!("the chain has to end with an os");
+            return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration));
+        }
+
+        let chain = OsChain::new(self.connector_args(), self.os_args())?;
+        inventory.builder().os_chain(chain).build()
+    }
+
+    /// Builds the connector at the end of the chain
+    pub fn build_connector(
+        &self,
+        inventory: &Inventory,
+    ) -> Result<ConnectorInstanceArcBox<'static>> {
+        if self.last_is_connector() != Some(true) {
+            error!("the chain has to end with a connector");
+            return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration));
+        }
+
+        let chain = ConnectorChain::new(self.connector_args(), self.os_args())?;
+        inventory.builder().connector_chain(chain).build()
+    }
+
+    fn last_is_connector(&self) -> Option<bool> {
+        let connector = self.connectors.iter().map(|(index, _)| *index).max();
+        let os = self.os.iter().map(|(index, _)| *index).max();
+        match (connector, os) {
+            (None, None) => None,
+            (connector, os) => Some(connector > os),
+        }
+    }
+
+    fn connector_args(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.connectors
+            .iter()
+            .map(|(index, arg)| (*index, arg.as_str()))
+    }
+
+    fn os_args(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.os.iter().map(|(index, arg)| (*index, arg.as_str()))
+    }
+}
+
+#[cfg(feature = "mirror-bin")]
+fn indexed_args(matches: &::clap::ArgMatches, id: &str) -> Vec<(usize, String)> {
+    matches
+        .indices_of(id)
+        .zip(matches.get_many::<String>(id))
+        .map(|(indices, args)| indices.zip(args.cloned()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_order() {
+        let mut args = ChainArgs::default();
+        assert!(args.is_empty());
+        assert_eq!(args.last_is_connector(), None);
+
+        args.push_connector("kvm");
+        assert_eq!(args.last_is_connector(), Some(true));
+        args.push_os("win32");
+        args.push_os("win32:dtb=0x1aa000");
+        assert_eq!(args.last_is_connector(), Some(false));
+
+        assert_eq!(args.connector_args().collect::<Vec<_>>(), [(0, "kvm")]);
+        assert_eq!(
+            args.os_args().collect::<Vec<_>>(),
+            [(1, "win32"), (2, "win32:dtb=0x1aa000")]
+        );
+    }
+
+    #[test]
+    fn connector_without_os() {
+        let mut args = ChainArgs::default();
+        args.push_connector("kvm");
+        let inventory = Inventory::empty();
+        assert!(args.build_os(&inventory).is_err());
+    }
+}
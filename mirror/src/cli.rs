@@ -0,0 +1,441 @@
+use ::clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use ::log::{error, info};
+use ::std::{
+    io,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ::memflow::prelude::v1::*;
+use ::mirror_dto::PROTOCOL_VERSION;
+
+use crate::{
+    app::{connect_last, connect_profile},
+    capture::Capture,
+    config::{MirrorConfig, Profile},
+    ChainArgs, PipeFormat, PipeOutput, PipeOutputConfig, PipeTarget, RecordFormat, Recorder,
+    RecorderConfig, ResolutionChange, ScreenshotOptions, SequentialCapture, ThreadedCapture,
+};
+
+// interval in which headless commands poll the capture
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Arguments that describe the connector / os chain, they are shared by all commands
+pub fn chain_args() -> [Arg; 3] {
+    [
+        Arg::new("connector")
+            .long("connector")
+            .short('c')
+            .action(ArgAction::Append)
+            .global(true)
+            .help("Connector of the os chain, the last connection of the ui is used if omitted"),
+        Arg::new("os")
+            .long("os")
+            .short('o')
+            .action(ArgAction::Append)
+            .global(true)
+            .help("Os of the os chain (e.g. win32)"),
+        Arg::new("profile")
+            .long("profile")
+            .short('p')
+            .global(true)
+            .conflicts_with_all(["connector", "os"])
+            .help("Connects with a profile that was saved in the ui"),
+    ]
+}
+
+/// Commands that run without the ui
+pub fn commands() -> [Command; 5] {
+    [
+        Command::new("info")
+            .about("Connects to the guest and prints information about the capture")
+            .arg(timeout_arg()),
+        Command::new("screenshot")
+            .about("Saves a single frame as png")
+            .arg(Arg::new("path").value_name("PATH"))
+            .arg(
+                Arg::new("no-cursor")
+                    .long("no-cursor")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("crop")
+                    .long("crop")
+                    .value_name("X,Y,WIDTH,HEIGHT")
+                    .value_parser(value_parser!(u32))
+                    .value_delimiter(',')
+                    .num_args(4),
+            )
+            .arg(timeout_arg()),
+        Command::new("record")
+            .about("Records frames into a directory")
+            .arg(Arg::new("path").value_name("DIR").required(true))
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(["y4m", "raw", "png", "qoi", "mirror"])
+                    .default_value("y4m"),
+            )
+            .arg(
+                Arg::new("split-size")
+                    .long("split-size")
+                    .value_name("MB")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                Arg::new("split-duration")
+                    .long("split-duration")
+                    .value_name("SECONDS")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(duration_arg().help("Stops the recording after the given duration")),
+        Command::new("stream")
+            .about("Writes raw frames at a constant frame rate to stdout or a named pipe")
+            .arg(
+                Arg::new("path")
+                    .value_name("PATH")
+                    .default_value("-")
+                    .help("Named pipe or file, - writes to stdout"),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(["rgba", "bgra", "i420"])
+                    .default_value("rgba"),
+            )
+            .arg(
+                Arg::new("fps")
+                    .long("fps")
+                    .value_parser(value_parser!(u32).range(1..))
+                    .default_value("30"),
+            )
+            .arg(
+                Arg::new("resize")
+                    .long("resize")
+                    .value_parser(["scale", "restart"])
+                    .default_value("scale")
+                    .help("Scales frames to the initial resolution or restarts the stream when the resolution changes"),
+            ),
+        Command::new("bench")
+            .about("Measures the capture performance")
+            .arg(duration_arg().default_value("10")),
+    ]
+}
+
+fn timeout_arg() -> Arg {
+    Arg::new("timeout")
+        .long("timeout")
+        .value_name("SECONDS")
+        .value_parser(value_parser!(u64))
+        .default_value("10")
+        .help("Time to wait for the first frame")
+}
+
+fn duration_arg() -> Arg {
+    Arg::new("duration")
+        .long("duration")
+        .value_name("SECONDS")
+        .value_parser(value_parser!(u64))
+}
+
+/// Runs a command that does not require the ui
+pub fn run(name: &str, matches: &ArgMatches, config: &MirrorConfig) -> Result<()> {
+    let mut capture = connect(matches, config)?;
+
+    match name {
+        "info" => info(capture.as_mut(), matches),
+        "screenshot" => screenshot(capture.as_mut(), matches),
+        "record" => record(capture.as_mut(), matches),
+        "stream" => stream(capture.as_mut(), matches),
+        "bench" => bench(capture.as_mut(), matches),
+        _ => Err(Error(ErrorOrigin::Other, ErrorKind::Configuration)),
+    }
+}
+
+/// Returns a capture for the os chain of the arguments or for the last connection of the ui
+pub fn connect(matches: &ArgMatches, config: &MirrorConfig) -> Result<Box<dyn Capture>> {
+    let inventory = Inventory::scan();
+    if let Some(name) = matches.get_one::<String>("profile") {
+        let profile = profile(config, name)?;
+        return connect_profile(&inventory, config, profile).map_err(|err| {
+            error!("unable to connect with profile {}: {}", name, err);
+            err
+        });
+    }
+    if !matches.contains_id("os") {
+        if matches.contains_id("connector") {
+            error!("a connector chain has to end with an os, specify --os");
+            return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration));
+        }
+        return connect_last(&inventory, config).map_err(|err| {
+            error!(
+                "unable to open the last connection, connect once via the ui or specify --os: {}",
+                err
+            );
+            err
+        });
+    }
+
+    let os = ChainArgs::from_matches(matches).build_os(&inventory)?;
+
+    let mut capture: Box<dyn Capture> = if config.multithreading {
+        Box::new(ThreadedCapture::new(os))
+    } else {
+        Box::new(SequentialCapture::new(os))
+    };
+    capture.set_obs_capture(config.obs_capture);
+    Ok(capture)
+}
+
+/// Returns the profile with the given name
+pub fn profile<'a>(config: &'a MirrorConfig, name: &str) -> Result<&'a Profile> {
+    config.profile(name).ok_or_else(|| {
+        error!("profile {} not found", name);
+        Error(ErrorOrigin::Other, ErrorKind::NotFound)
+    })
+}
+
+/// Updates the capture until the first frame is available
+fn wait_for_frame(capture: &mut dyn Capture, matches: &ArgMatches) -> Result<()> {
+    let timeout = Duration::from_secs(*matches.get_one::<u64>("timeout").unwrap());
+    let start = Instant::now();
+    loop {
+        capture.update();
+        if capture.frame_counter() != 0 && !capture.frame().is_empty() {
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            error!("no frame received within {} seconds", timeout.as_secs());
+            return Err(Error(ErrorOrigin::Other, ErrorKind::NotFound));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn write_error(err: io::Error) -> Error {
+    error!("{}", err);
+    Error(ErrorOrigin::Other, ErrorKind::UnableToWriteFile)
+}
+
+fn info(capture: &mut dyn Capture, matches: &ArgMatches) -> Result<()> {
+    if let Some(os) = capture.os() {
+        let info = os.info();
+        println!(
+            "guest: {:?} (base {:x}, size {:x})",
+            info.arch, info.base, info.size
+        );
+    }
+    println!("protocol: guest agent (mirror-dto {})", PROTOCOL_VERSION);
+    println!(
+        "capture: {}, obs capture {}",
+        if capture.multithreading() {
+            "threaded"
+        } else {
+            "sequential"
+        },
+        if capture.obs_capture() {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+
+    wait_for_frame(capture, matches)?;
+    let frame = capture.frame();
+    println!("resolution: {}x{}", frame.width, frame.height);
+    println!("frame: {}", capture.frame_counter());
+    println!(
+        "cursor: {}, {} ({})",
+        frame.cursor.x,
+        frame.cursor.y,
+        if frame.cursor.is_visible != 0 {
+            "visible"
+        } else {
+            "hidden"
+        }
+    );
+    println!(
+        "input: {}",
+        if capture.supports_input() {
+            "supported"
+        } else {
+            "not supported"
+        }
+    );
+    Ok(())
+}
+
+fn screenshot(capture: &mut dyn Capture, matches: &ArgMatches) -> Result<()> {
+    wait_for_frame(capture, matches)?;
+
+    let path = matches
+        .get_one::<String>("path")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            PathBuf::from(format!("screenshot-{}.png", timestamp))
+        });
+    let options = ScreenshotOptions {
+        cursor: !matches.get_flag("no-cursor"),
+        crop: matches.get_many::<u32>("crop").map(|crop| {
+            let crop = crop.copied().collect::<Vec<_>>();
+            [crop[0], crop[1], crop[2], crop[3]]
+        }),
+    };
+
+    capture
+        .screenshot()
+        .save(&path, &options)
+        .map_err(write_error)?;
+    println!("saved {}", path.display());
+    Ok(())
+}
+
+fn record(capture: &mut dyn Capture, matches: &ArgMatches) -> Result<()> {
+    let format = matches
+        .get_one::<String>("format")
+        .and_then(|format| RecordFormat::from_name(format))
+        .unwrap_or(RecordFormat::Y4m);
+    let mut config = RecorderConfig::new(matches.get_one::<String>("path").unwrap(), format);
+    config.max_segment_size = matches
+        .get_one::<u64>("split-size")
+        .map(|size| size * 1024 * 1024);
+    config.max_segment_duration = matches
+        .get_one::<u64>("split-duration")
+        .map(|secs| Duration::from_secs(*secs));
+    let duration = matches
+        .get_one::<u64>("duration")
+        .map(|secs| Duration::from_secs(*secs));
+
+    let recorder = Recorder::start(config).map_err(write_error)?;
+    capture.add_frame_sink(recorder.frame_sink());
+    let start = Instant::now();
+    while recorder.is_running() && duration.map(|d| start.elapsed() < d).unwrap_or(true) {
+        capture.update();
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let (written, dropped, bytes) = (
+        recorder.frames_written(),
+        recorder.frames_dropped(),
+        recorder.bytes_written(),
+    );
+    recorder.stop().map_err(write_error)?;
+    println!(
+        "recorded {} frames ({} dropped, {} MB)",
+        written,
+        dropped,
+        bytes / 1024 / 1024
+    );
+    Ok(())
+}
+
+fn stream(capture: &mut dyn Capture, matches: &ArgMatches) -> Result<()> {
+    let target = match matches.get_one::<String>("path").map(String::as_str) {
+        Some("-") | None => PipeTarget::Stdout,
+        Some(path) => PipeTarget::Path(PathBuf::from(path)),
+    };
+    let config = PipeOutputConfig {
+        format: matches
+            .get_one::<String>("format")
+            .and_then(|format| PipeFormat::from_name(format))
+            .unwrap_or(PipeFormat::Rgba),
+        frame_rate: *matches.get_one::<u32>("fps").unwrap(),
+        resolution_change: matches
+            .get_one::<String>("resize")
+            .and_then(|mode| ResolutionChange::from_name(mode))
+            .unwrap_or(ResolutionChange::Scale),
+    };
+
+    // there is no marker in the raw frames, only a pipe or file can show the consumer a restart
+    if config.resolution_change == ResolutionChange::Restart && target == PipeTarget::Stdout {
+        error!("--resize restart needs a named pipe or file, stdout can not be opened again");
+        return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration));
+    }
+
+    let mut output = PipeOutput::new(target, config);
+    let mut restarted = false;
+    loop {
+        capture.update();
+        match output.update(capture) {
+            Ok(true) => {
+                // stdout carries the video, the parameters are always printed on stderr
+                let args = output.ffmpeg_args().unwrap_or_default();
+                if restarted {
+                    eprintln!("resolution changed, stream restarted: {}", args);
+                } else {
+                    eprintln!("ffmpeg input: {}", args);
+                }
+                restarted = true;
+            }
+            Ok(false) if output.is_ended() => break,
+            Ok(false) => (),
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                info!("pipe closed by the consumer");
+                break;
+            }
+            Err(err) => return Err(write_error(err)),
+        }
+
+        thread::sleep(output.time_to_next_frame());
+    }
+
+    info!(
+        "stream finished: {} frames written, {} duplicated, {} dropped",
+        output.frames_written(),
+        output.frames_duplicated(),
+        output.frames_dropped()
+    );
+    Ok(())
+}
+
+fn bench(capture: &mut dyn Capture, matches: &ArgMatches) -> Result<()> {
+    let duration = Duration::from_secs(*matches.get_one::<u64>("duration").unwrap());
+
+    let mut updates = 0u64;
+    let mut frames = 0u64;
+    let mut update_time = Duration::ZERO;
+    let mut max_update_time = Duration::ZERO;
+    let mut frame_counter = capture.frame_counter();
+
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let update_start = Instant::now();
+        capture.update();
+        let elapsed = update_start.elapsed();
+        update_time += elapsed;
+        max_update_time = max_update_time.max(elapsed);
+        updates += 1;
+
+        if capture.frame_counter() != frame_counter {
+            frame_counter = capture.frame_counter();
+            frames += 1;
+        }
+
+        // threaded captures return immediately, the sleep avoids measuring a busy loop
+        if capture.multithreading() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let frame = capture.frame();
+    let frame_size = frame.width as f64 * frame.height as f64 * 4.0;
+    println!("resolution: {}x{}", frame.width, frame.height);
+    println!("frames: {} ({:.1} fps)", frames, frames as f64 / elapsed);
+    println!(
+        "updates: {} (avg {:.3} ms, max {:.3} ms)",
+        updates,
+        update_time.as_secs_f64() * 1000.0 / updates.max(1) as f64,
+        max_update_time.as_secs_f64() * 1000.0
+    );
+    println!(
+        "throughput: {:.1} MB/s",
+        frames as f64 * frame_size / elapsed / 1024.0 / 1024.0
+    );
+    Ok(())
+}
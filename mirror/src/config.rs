@@ -1,68 +1,971 @@
-use ::std::{fs, path::PathBuf};
+use ::std::{
+    collections::BTreeMap,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
+use ::egui_dock::egui::{Key, KeyboardShortcut, Modifiers};
+use ::log::info;
 use ::serde::{Deserialize, Serialize};
 
+use crate::capture::DEFAULT_PROCESS_NAME;
+
 fn default_as_false() -> bool {
     false
 }
 
 fn default_as_true() -> bool {
-    false
+    true
+}
+
+/// Version of the config format, configs of older versions are migrated when they are loaded
+pub const CONFIG_VERSION: u32 = 1;
+
+// prefix of the environment variables that override config fields (e.g. `MIRROR_MULTITHREADING=false`)
+const ENV_PREFIX: &str = "MIRROR_";
+
+// location of the config file that was passed on the command line
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainStageKind {
+    Connector,
+    Os,
+}
+
+/// A single plugin of a memflow connector / os chain
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChainStage {
+    pub kind: ChainStageKind,
+    pub name: String,
+    #[serde(default)]
+    pub args: String,
+}
+
+impl ChainStage {
+    pub fn connector(name: &str) -> Self {
+        Self {
+            kind: ChainStageKind::Connector,
+            name: name.to_string(),
+            args: String::new(),
+        }
+    }
+
+    pub fn os(name: &str) -> Self {
+        Self {
+            kind: ChainStageKind::Os,
+            name: name.to_string(),
+            args: String::new(),
+        }
+    }
+
+    /// Returns the stage in the `name:args` notation of the memflow chains
+    pub fn to_arg(&self) -> String {
+        if self.args.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}:{}", self.name, self.args)
+        }
+    }
+}
+
+/// How a tab connects to the guest
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionMode {
+    #[default]
+    Os,
+    Physical,
+    Framebuffer,
+    File,
+}
+
+impl ConnectionMode {
+    pub const ALL: [ConnectionMode; 4] = [
+        ConnectionMode::Os,
+        ConnectionMode::Physical,
+        ConnectionMode::Framebuffer,
+        ConnectionMode::File,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectionMode::Os => "Guest agent (os)",
+            ConnectionMode::Physical => "Guest agent (physical memory)",
+            ConnectionMode::Framebuffer => "Firmware framebuffer (agentless)",
+            ConnectionMode::File => "Recording (replay)",
+        }
+    }
+}
+
+/// Options of the os based captures
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureOptions {
+    pub multithreading: bool,
+    pub obs_capture: bool,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            multithreading: true,
+            obs_capture: true,
+        }
+    }
+}
+
+/// How the frame is scaled into the tab
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleMode {
+    /// Shows the whole frame and keeps the aspect ratio
+    #[default]
+    Fit,
+    /// Covers the whole tab and keeps the aspect ratio, the frame is cropped
+    Fill,
+    /// Covers the whole tab without keeping the aspect ratio
+    Stretch,
+    /// Shows one frame pixel per screen pixel
+    Native,
+    /// Uses the largest integer multiple of the frame size that fits into the tab
+    Integer,
+}
+
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 5] = [
+        ScaleMode::Fit,
+        ScaleMode::Fill,
+        ScaleMode::Stretch,
+        ScaleMode::Native,
+        ScaleMode::Integer,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScaleMode::Fit => "Fit",
+            ScaleMode::Fill => "Fill",
+            ScaleMode::Stretch => "Stretch",
+            ScaleMode::Native => "1:1",
+            ScaleMode::Integer => "Integer scale",
+        }
+    }
+}
+
+/// Filtering of the frame texture when it is scaled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    #[default]
+    Linear,
+    Nearest,
+}
+
+impl TextureFilter {
+    pub const ALL: [TextureFilter; 2] = [TextureFilter::Linear, TextureFilter::Nearest];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TextureFilter::Linear => "Linear",
+            TextureFilter::Nearest => "Nearest",
+        }
+    }
+}
+
+/// Options of how the frames of a tab are shown
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayOptions {
+    /// Draws the guest cursor on top of the frame
+    pub show_cursor: bool,
+    /// Forwards mouse and keyboard input to the guest
+    pub capture_input: bool,
+    pub scale_mode: ScaleMode,
+    pub filter: TextureFilter,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            show_cursor: true,
+            capture_input: false,
+            scale_mode: ScaleMode::default(),
+            filter: TextureFilter::default(),
+        }
+    }
+}
+
+/// Inner size of a detached tab that was not placed before
+pub const DEFAULT_VIEWPORT_SIZE: [f32; 2] = [960.0, 540.0];
+
+/// Placement of a tab that is detached into its own window, positions and sizes are in points
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewportPlacement {
+    /// Id of the tab in the layout
+    pub tab: usize,
+    /// Outer position of the window, it is placed by the system if not set
+    pub position: Option<[f32; 2]>,
+    /// Inner size of the window when it is neither fullscreen nor in mini mode
+    pub size: [f32; 2],
+    /// Borderless fullscreen on the monitor the window is on
+    pub fullscreen: bool,
+    pub always_on_top: bool,
+    /// Picture in picture, only the frame is shown in a small borderless window on top of other windows
+    pub mini: bool,
+}
+
+impl ViewportPlacement {
+    pub fn new(tab: usize) -> Self {
+        Self {
+            tab,
+            position: None,
+            size: DEFAULT_VIEWPORT_SIZE,
+            fullscreen: false,
+            always_on_top: false,
+            mini: false,
+        }
+    }
+}
+
+impl Default for ViewportPlacement {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// A named connection that can be selected in the ui or via `--profile`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mode: ConnectionMode,
+    #[serde(default)]
+    pub chain: Vec<ChainStage>,
+    /// Name of the guest agent process
+    #[serde(default = "default_process_name")]
+    pub process_name: String,
+    /// Overrides the capture options of the settings
+    #[serde(default)]
+    pub capture: Option<CaptureOptions>,
+    #[serde(default)]
+    pub display: DisplayOptions,
+}
+
+fn default_process_name() -> String {
+    DEFAULT_PROCESS_NAME.to_string()
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            mode: ConnectionMode::default(),
+            chain: Vec::new(),
+            process_name: default_process_name(),
+            capture: None,
+            display: DisplayOptions::default(),
+        }
+    }
+
+    /// Reads a profile that was exported with `Profile::export`, unnamed profiles are named after the file
+    pub fn import<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        let mut profile: Self = toml::from_str(&contents).map_err(io::Error::other)?;
+        if profile.name.is_empty() {
+            profile.name = path
+                .as_ref()
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+        }
+        Ok(profile)
+    }
+
+    /// Writes the profile into a standalone toml file
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(path, contents)
+    }
+}
+
+/// An action of the ui that can be triggered with a keyboard shortcut
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    NewTab,
+    CloseTab,
+    /// Switches the focused tab between docked and fullscreen
+    Fullscreen,
+    SaveFrame,
+    ToggleStats,
+    ToggleSettings,
+    ToggleLog,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    Reconnect,
+}
+
+impl Action {
+    pub const ALL: [Action; 11] = [
+        Action::NewTab,
+        Action::CloseTab,
+        Action::Fullscreen,
+        Action::SaveFrame,
+        Action::ToggleStats,
+        Action::ToggleSettings,
+        Action::ToggleLog,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ResetZoom,
+        Action::Reconnect,
+    ];
+
+    /// Returns the name of the action in the `keybindings` table of the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::NewTab => "new_tab",
+            Action::CloseTab => "close_tab",
+            Action::Fullscreen => "fullscreen",
+            Action::SaveFrame => "save_frame",
+            Action::ToggleStats => "toggle_stats",
+            Action::ToggleSettings => "toggle_settings",
+            Action::ToggleLog => "toggle_log",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+            Action::ResetZoom => "reset_zoom",
+            Action::Reconnect => "reconnect",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::NewTab => "New tab",
+            Action::CloseTab => "Close tab",
+            Action::Fullscreen => "Fullscreen",
+            Action::SaveFrame => "Save frame as PNG",
+            Action::ToggleStats => "Stats",
+            Action::ToggleSettings => "Settings",
+            Action::ToggleLog => "Log",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::ResetZoom => "Reset zoom",
+            Action::Reconnect => "Reconnect",
+        }
+    }
+
+    pub fn default_shortcut(&self) -> Option<KeyboardShortcut> {
+        let (modifiers, key) = match self {
+            Action::NewTab => (Modifiers::COMMAND, Key::T),
+            Action::CloseTab => (Modifiers::COMMAND, Key::W),
+            Action::Fullscreen => (Modifiers::NONE, Key::F11),
+            Action::SaveFrame => (Modifiers::NONE, Key::F12),
+            Action::ToggleStats => (Modifiers::COMMAND | Modifiers::SHIFT, Key::S),
+            Action::ToggleSettings => (Modifiers::COMMAND, Key::Comma),
+            Action::ToggleLog => (Modifiers::COMMAND | Modifiers::SHIFT, Key::L),
+            Action::ZoomIn => (Modifiers::COMMAND, Key::Plus),
+            Action::ZoomOut => (Modifiers::COMMAND, Key::Minus),
+            Action::ResetZoom => (Modifiers::COMMAND, Key::Num0),
+            Action::Reconnect => (Modifiers::COMMAND, Key::R),
+        };
+        Some(KeyboardShortcut::new(modifiers, key))
+    }
+}
+
+/// Parses a shortcut like `Ctrl+Shift+T`, an empty string leaves the action unbound
+pub fn parse_shortcut(text: &str) -> Result<Option<KeyboardShortcut>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    // `Ctrl++` binds the plus key
+    let (names, key) = match text.strip_suffix("++") {
+        Some(names) => (names, "+"),
+        None => text.rsplit_once('+').unwrap_or(("", text)),
+    };
+    let key = Key::from_name(key.trim()).ok_or_else(|| format!("unknown key {}", key))?;
+
+    let mut modifiers = Modifiers::NONE;
+    for name in names.split('+').filter(|name| !name.trim().is_empty()) {
+        modifiers = modifiers
+            | match name.trim().to_lowercase().as_str() {
+                // ctrl is cmd on mac
+                "ctrl" | "cmd" | "command" => Modifiers::COMMAND,
+                "alt" | "option" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                _ => return Err(format!("unknown modifier {}", name)),
+            };
+    }
+    Ok(Some(KeyboardShortcut::new(modifiers, key)))
+}
+
+/// Formats a shortcut so it can be parsed with `parse_shortcut` again
+pub fn format_shortcut(shortcut: Option<KeyboardShortcut>) -> String {
+    let shortcut = match shortcut {
+        Some(shortcut) => shortcut,
+        None => return String::new(),
+    };
+
+    let mut text = String::new();
+    if shortcut.modifiers.command || shortcut.modifiers.ctrl {
+        text.push_str("Ctrl+");
+    }
+    if shortcut.modifiers.alt {
+        text.push_str("Alt+");
+    }
+    if shortcut.modifiers.shift {
+        text.push_str("Shift+");
+    }
+    text.push_str(shortcut.logical_key.name());
+    text
+}
+
+fn default_keybindings() -> BTreeMap<String, String> {
+    Action::ALL
+        .iter()
+        .map(|action| {
+            (
+                action.name().to_string(),
+                format_shortcut(action.default_shortcut()),
+            )
+        })
+        .collect()
+}
+
+/// A problem that was found while loading the config, the affected field was reset to its default
+#[derive(Clone, Debug)]
+pub struct ConfigIssue {
+    /// Path of the field, empty if the issue concerns the whole file
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(field: &str, message: impl fmt::Display) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.field, self.message)
+        }
+    }
+}
+
+/// A field that was overridden from the environment or the command line, it is not persisted
+#[derive(Clone, Debug)]
+struct ConfigOverride {
+    field: String,
+    original: Option<toml::Value>,
+    value: toml::Value,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MirrorConfig {
+    pub version: u32,
+
     #[serde(default = "default_as_true")]
     pub multithreading: bool,
 
     #[serde(default = "default_as_true")]
     pub obs_capture: bool,
 
+    #[serde(default = "default_as_false")]
+    pub clipboard_sync: bool,
+
     #[serde(default = "default_as_false")]
     pub connect_on_startup: bool,
-    pub last_connector: Option<String>,
-    pub last_connector_args: Option<String>,
-    pub last_os: Option<String>,
-    pub last_os_args: Option<String>,
+
+    /// Connector / os chain of the last connection
+    pub last_chain: Vec<ChainStage>,
+
+    pub profiles: Vec<Profile>,
+
+    /// Placement of the detached tabs
+    pub viewports: Vec<ViewportPlacement>,
+
+    /// Keyboard shortcuts by action, missing actions use their default shortcut
+    #[serde(default = "default_keybindings")]
+    pub keybindings: BTreeMap<String, String>,
+
+    /// Problems that were found while loading the config
+    #[serde(skip)]
+    pub issues: Vec<ConfigIssue>,
+
+    #[serde(skip)]
+    overrides: Vec<ConfigOverride>,
 }
 
 impl Default for MirrorConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
+
             multithreading: true,
 
             obs_capture: true,
 
+            clipboard_sync: false,
+
             connect_on_startup: false,
-            last_connector: None,
-            last_connector_args: None,
-            last_os: None,
-            last_os_args: None,
+
+            last_chain: Vec::new(),
+
+            profiles: Vec::new(),
+
+            viewports: Vec::new(),
+
+            keybindings: default_keybindings(),
+
+            issues: Vec::new(),
+            overrides: Vec::new(),
         }
     }
 }
 
 impl MirrorConfig {
+    /// Returns the capture options of the settings
+    pub fn capture_options(&self) -> CaptureOptions {
+        CaptureOptions {
+            multithreading: self.multithreading,
+            obs_capture: self.obs_capture,
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// Adds the profile or replaces the profile with the same name
+    pub fn set_profile(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    pub fn remove_profile(&mut self, name: &str) {
+        self.profiles.retain(|profile| profile.name != name);
+    }
+
+    /// Returns the name with a number appended if a profile with the name already exists
+    pub fn unique_profile_name(&self, name: &str) -> String {
+        if self.profile(name).is_none() {
+            return name.to_string();
+        }
+        (2..)
+            .map(|index| format!("{} ({})", name, index))
+            .find(|name| self.profile(name).is_none())
+            .unwrap()
+    }
+
+    /// Returns the placement the tab had when it was detached the last time
+    pub fn viewport(&self, tab: usize) -> Option<&ViewportPlacement> {
+        self.viewports.iter().find(|viewport| viewport.tab == tab)
+    }
+
+    /// Adds the placement or replaces the placement of the same tab
+    pub fn set_viewport(&mut self, placement: ViewportPlacement) {
+        match self.viewports.iter_mut().find(|v| v.tab == placement.tab) {
+            Some(existing) => *existing = placement,
+            None => self.viewports.push(placement),
+        }
+    }
+
+    /// Returns the shortcut of the action, actions that are not in the config use their default
+    pub fn keybinding(&self, action: Action) -> Option<KeyboardShortcut> {
+        match self.keybindings.get(action.name()) {
+            Some(shortcut) => parse_shortcut(shortcut).ok().flatten(),
+            None => action.default_shortcut(),
+        }
+    }
+
+    pub fn set_keybinding(&mut self, action: Action, shortcut: Option<KeyboardShortcut>) {
+        self.keybindings
+            .insert(action.name().to_string(), format_shortcut(shortcut));
+    }
+
+    /// Returns the action that already uses the shortcut
+    pub fn keybinding_conflict(
+        &self,
+        action: Action,
+        shortcut: KeyboardShortcut,
+    ) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .filter(|other| *other != action)
+            .find(|other| self.keybinding(*other) == Some(shortcut))
+    }
+
+    /// Returns the default location of exported profiles
+    ///
+    /// Characters that could leave the profile directory (e.g. `/` or `..`) are replaced.
+    pub fn profile_path(name: &str) -> PathBuf {
+        let file_name = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '(' | ')') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        let file_name = match file_name.trim() {
+            "" => "profile",
+            file_name => file_name,
+        };
+        Self::config_dir()
+            .join("profiles")
+            .join(format!("{}.toml", file_name))
+    }
+
+    /// Loads the config and applies the overrides of the environment
+    ///
+    /// Problems with the config file are reported in `issues` instead of failing,
+    /// the file is only created if it does not exist yet.
     pub fn load_or_default() -> Self {
-        match Self::load() {
-            Ok(s) => s,
-            Err(_) => {
-                let s = Self::default();
-                s.save().ok();
-                s
+        let mut config = match Self::load() {
+            Ok(config) => config,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let config = Self::default();
+                config.save().ok();
+                config
             }
-        }
+            Err(err) => {
+                let mut config = Self::default();
+                config.issues.push(ConfigIssue::new(
+                    "",
+                    format!("unable to load config file: {}", err),
+                ));
+                config
+            }
+        };
+        config.apply_env_overrides();
+        config
     }
 
-    pub fn load() -> Result<Self, &'static str> {
+    /// Loads the config, migrates configs of older versions and resets invalid fields to their defaults
+    pub fn load() -> io::Result<Self> {
         let path = Self::config_path();
-        if fs::metadata(&path).is_err() {
-            return Err("config file not found");
+        let contents = fs::read_to_string(&path)?;
+
+        let table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(err) => {
+                // the file is replaced on the next save so it is kept as backup
+                let backup = Self::backup("invalid")?;
+                let mut config = Self::default();
+                config.issues.push(ConfigIssue::new(
+                    "",
+                    format!(
+                        "unable to parse config file (saved as {}): {}",
+                        backup.display(),
+                        err
+                    ),
+                ));
+                return Ok(config);
+            }
+        };
+
+        let (mut config, version) = Self::from_table(table)?;
+        if version < CONFIG_VERSION {
+            let backup = Self::backup(&format!("v{}", version))?;
+            info!(
+                "migrated config from version {} to {}, the old config was saved as {:?}",
+                version, CONFIG_VERSION, backup
+            );
+            config.save().map_err(io::Error::other)?;
+        } else if !config.issues.is_empty() {
+            // the config is saved on every change, so the original file is kept before it is lost
+            let backup = Self::backup(if version > CONFIG_VERSION {
+                "newer"
+            } else {
+                "invalid"
+            })?;
+            config.issues.push(ConfigIssue::new(
+                "",
+                format!("the original config was saved as {}", backup.display()),
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Migrates a parsed config and resets invalid fields, returns the version of the file
+    ///
+    /// Problems are recorded in `issues`, the file itself is not touched.
+    fn from_table(mut table: toml::Table) -> io::Result<(Self, u32)> {
+        let mut issues = Vec::new();
+        let version = table
+            .get("version")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(0) as u32;
+        if version > CONFIG_VERSION {
+            issues.push(ConfigIssue::new(
+                "version",
+                format!(
+                    "the config was written by a newer version ({}), unknown fields are lost",
+                    version
+                ),
+            ));
+        } else if version < CONFIG_VERSION {
+            migrate(&mut table, version);
+        }
+
+        // fields are checked one by one so a single invalid field does not discard the whole config
+        let fields = table.keys().cloned().collect::<Vec<_>>();
+        for field in fields {
+            if check_field(&field, table[&field].clone()).is_ok() {
+                continue;
+            }
+
+            // arrays and tables keep their valid elements, a broken profile does not discard the others
+            match table.get_mut(&field) {
+                Some(toml::Value::Array(elements)) => {
+                    let mut index = 0;
+                    elements.retain(|element| {
+                        let result = check_field(&field, toml::Value::Array(vec![element.clone()]));
+                        if let Err(err) = &result {
+                            issues.push(ConfigIssue::new(
+                                &format!("{}[{}]", field, index),
+                                err.message(),
+                            ));
+                        }
+                        index += 1;
+                        result.is_ok()
+                    });
+                }
+                Some(toml::Value::Table(entries)) => {
+                    let keys = entries.keys().cloned().collect::<Vec<_>>();
+                    for key in keys {
+                        let mut single = toml::Table::new();
+                        single.insert(key.clone(), entries[&key].clone());
+                        if let Err(err) = check_field(&field, toml::Value::Table(single)) {
+                            issues.push(ConfigIssue::new(
+                                &format!("{}.{}", field, key),
+                                err.message(),
+                            ));
+                            entries.remove(&key);
+                        }
+                    }
+                }
+                _ => (),
+            }
+
+            if let Err(err) = check_field(&field, table[&field].clone()) {
+                issues.push(ConfigIssue::new(&field, err.message()));
+                table.remove(&field);
+            }
         }
 
-        let contents =
-            fs::read_to_string(&path).map_err(|_| "unable to read config file contents")?;
-        toml::from_str(&contents).map_err(|_| "unable to parse config file contents as toml")
+        let mut config = toml::Value::Table(table)
+            .try_into::<Self>()
+            .map_err(io::Error::other)?;
+        config.issues = issues;
+        config.validate();
+        Ok((config, version))
+    }
+
+    /// Resets fields with invalid values and records them as issues
+    fn validate(&mut self) {
+        let issues = &mut self.issues;
+
+        if self.last_chain.iter().any(|stage| stage.name.is_empty()) {
+            issues.push(ConfigIssue::new(
+                "last_chain",
+                "a stage of the chain has no plugin",
+            ));
+            self.last_chain.clear();
+        }
+
+        let mut names = Vec::new();
+        let mut index = 0;
+        self.profiles.retain_mut(|profile| {
+            let field = format!("profiles[{}]", index);
+            index += 1;
+
+            if profile.name.is_empty() {
+                issues.push(ConfigIssue::new(&field, "the profile has no name"));
+                return false;
+            }
+            if names.contains(&profile.name) {
+                issues.push(ConfigIssue::new(
+                    &field,
+                    format!("a profile named {} already exists", profile.name),
+                ));
+                return false;
+            }
+            names.push(profile.name.clone());
+
+            if profile.process_name.is_empty() {
+                issues.push(ConfigIssue::new(
+                    &format!("{}.process_name", field),
+                    "the process name is empty",
+                ));
+                profile.process_name = default_process_name();
+            }
+            true
+        });
+
+        let mut tabs = Vec::new();
+        let mut index = 0;
+        self.viewports.retain_mut(|viewport| {
+            let field = format!("viewports[{}]", index);
+            index += 1;
+
+            if tabs.contains(&viewport.tab) {
+                issues.push(ConfigIssue::new(
+                    &field,
+                    format!("tab {} was already placed", viewport.tab),
+                ));
+                return false;
+            }
+            tabs.push(viewport.tab);
+
+            if viewport
+                .size
+                .iter()
+                .any(|size| !size.is_finite() || *size < 1.0)
+            {
+                issues.push(ConfigIssue::new(
+                    &format!("{}.size", field),
+                    "the size has to be positive",
+                ));
+                viewport.size = DEFAULT_VIEWPORT_SIZE;
+            }
+            if let Some(position) = viewport.position {
+                if position.iter().any(|coordinate| !coordinate.is_finite()) {
+                    issues.push(ConfigIssue::new(
+                        &format!("{}.position", field),
+                        "the position is invalid",
+                    ));
+                    viewport.position = None;
+                }
+            }
+            true
+        });
+
+        self.keybindings.retain(|name, shortcut| {
+            let field = format!("keybindings.{}", name);
+            if Action::from_name(name).is_none() {
+                issues.push(ConfigIssue::new(&field, "unknown action"));
+                return false;
+            }
+            match parse_shortcut(shortcut) {
+                Ok(_) => true,
+                Err(err) => {
+                    issues.push(ConfigIssue::new(&field, err));
+                    false
+                }
+            }
+        });
+
+        // actions without an entry use their default, the configured shortcuts are kept first
+        let (configured, defaults): (Vec<Action>, Vec<Action>) = Action::ALL
+            .iter()
+            .copied()
+            .partition(|action| self.keybindings.contains_key(action.name()));
+        let mut shortcuts = Vec::new();
+        for action in configured.into_iter().chain(defaults) {
+            let shortcut = match self.keybinding(action) {
+                Some(shortcut) => shortcut,
+                None => continue,
+            };
+            if shortcuts.contains(&shortcut) {
+                self.issues.push(ConfigIssue::new(
+                    &format!("keybindings.{}", action.name()),
+                    format!(
+                        "{} is already used by another action",
+                        format_shortcut(Some(shortcut))
+                    ),
+                ));
+                self.set_keybinding(action, None);
+            } else {
+                shortcuts.push(shortcut);
+            }
+        }
+    }
+
+    /// Overrides fields with the `MIRROR_<FIELD>` environment variables
+    fn apply_env_overrides(&mut self) {
+        let fields = match self.to_table() {
+            Ok(table) => table.keys().cloned().collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        for field in fields.iter().filter(|field| *field != "version") {
+            let name = format!("{}{}", ENV_PREFIX, field.to_uppercase());
+            if let Ok(value) = env::var(&name) {
+                if let Err(err) = self.set(field, &value) {
+                    self.issues.push(ConfigIssue::new(
+                        field,
+                        format!("invalid value in {}: {}", name, err),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Overrides a field with a `field=value` pair, e.g. from the command line
+    pub fn apply_override(&mut self, setting: &str) -> Result<(), String> {
+        let (field, value) = setting
+            .split_once('=')
+            .ok_or_else(|| "expected field=value".to_string())?;
+        self.set(field.trim(), value.trim())
+    }
+
+    /// Overrides a single field for this session, the value is parsed as toml or used as string
+    fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let mut table = self.to_table()?;
+        if field == "version" || !table.contains_key(field) {
+            return Err(format!("unknown field {}", field));
+        }
+
+        let value = format!("value = {}", value)
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string()));
+        let original = table.insert(field.to_string(), value.clone());
+
+        let mut config = toml::Value::Table(table)
+            .try_into::<Self>()
+            .map_err(|err| err.message().to_string())?;
+        config.issues = std::mem::take(&mut self.issues);
+        config.overrides = std::mem::take(&mut self.overrides);
+        // the value from the file is restored when saving, overriding a field twice keeps the first original
+        if !config.overrides.iter().any(|o| o.field == field) {
+            config.overrides.push(ConfigOverride {
+                field: field.to_string(),
+                original,
+                value,
+            });
+        }
+        *self = config;
+        Ok(())
     }
 
     pub fn save(&self) -> Result<(), &'static str> {
@@ -71,13 +974,284 @@ impl MirrorConfig {
             fs::create_dir_all(p).map_err(|_| "unable to create path to store config file")?;
         }
 
-        let contents = toml::to_string(self).map_err(|_| "unable to serialize config to toml")?;
+        let mut table = self
+            .to_table()
+            .map_err(|_| "unable to serialize config to toml")?;
+        // overrides are not persisted unless the field was changed in the meantime
+        for o in self.overrides.iter() {
+            if table.get(&o.field) == Some(&o.value) {
+                match &o.original {
+                    Some(original) => table.insert(o.field.clone(), original.clone()),
+                    None => table.remove(&o.field),
+                };
+            }
+        }
+
+        let contents = toml::to_string(&table).map_err(|_| "unable to serialize config to toml")?;
         fs::write(&path, contents.as_bytes()).map_err(|_| "unable to write config file")
     }
 
+    fn to_table(&self) -> Result<toml::Table, String> {
+        toml::Value::try_from(self)
+            .map_err(|err| err.to_string())?
+            .try_into::<toml::Table>()
+            .map_err(|err| err.to_string())
+    }
+
+    /// Copies the config file next to itself before it is replaced
+    fn backup(suffix: &str) -> io::Result<PathBuf> {
+        let path = Self::config_path();
+        let backup = path.with_extension(format!("{}.toml.bak", suffix));
+        fs::copy(&path, &backup)?;
+        Ok(backup)
+    }
+
+    /// Uses the given config file instead of the default location, has to be called before loading the config
+    pub fn set_path(path: PathBuf) {
+        CONFIG_PATH.set(path).ok();
+    }
+
+    /// Returns the directory that contains the config and other persisted state of the ui
+    pub fn config_dir() -> PathBuf {
+        Self::config_path()
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| ".".into())
+    }
+
     fn config_path() -> PathBuf {
+        if let Some(path) = CONFIG_PATH.get() {
+            return path.clone();
+        }
+        if let Ok(path) = env::var(format!("{}CONFIG", ENV_PREFIX)) {
+            return path.into();
+        }
         dirs::config_dir()
             .map(|dir| dir.join("mirror/config.toml"))
             .unwrap_or_else(|| "./config.toml".into())
     }
 }
+
+/// Checks that the value of a single field can be read into the config
+fn check_field(field: &str, value: toml::Value) -> Result<(), toml::de::Error> {
+    let mut single = toml::Table::new();
+    single.insert(field.to_string(), value);
+    toml::Value::Table(single)
+        .try_into::<MirrorConfig>()
+        .map(|_| ())
+}
+
+/// Migrates the fields of an older config to the current version
+fn migrate(table: &mut toml::Table, version: u32) {
+    if version < 1 {
+        // version 0 only stored a single connector and os
+        let mut take = |field: &str| match table.remove(field) {
+            Some(toml::Value::String(value)) => Some(value),
+            _ => None,
+        };
+        let connector = take("last_connector");
+        let connector_args = take("last_connector_args");
+        let os = take("last_os");
+        let os_args = take("last_os_args");
+
+        if !table.contains_key("last_chain") {
+            let mut chain = Vec::new();
+            if let Some(name) = connector {
+                chain.push(ChainStage {
+                    kind: ChainStageKind::Connector,
+                    name,
+                    args: connector_args.unwrap_or_default(),
+                });
+            }
+            if let Some(name) = os {
+                chain.push(ChainStage {
+                    kind: ChainStageKind::Os,
+                    name,
+                    args: os_args.unwrap_or_default(),
+                });
+            }
+            if let Ok(toml::Value::Array(chain)) = toml::Value::try_from(chain) {
+                table.insert("last_chain".to_string(), toml::Value::Array(chain));
+            }
+        }
+    }
+
+    table.insert(
+        "version".to_string(),
+        toml::Value::Integer(CONFIG_VERSION as i64),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(contents: &str) -> (MirrorConfig, u32) {
+        MirrorConfig::from_table(contents.parse().unwrap()).unwrap()
+    }
+
+    fn issue_fields(config: &MirrorConfig) -> Vec<&str> {
+        config
+            .issues
+            .iter()
+            .map(|issue| issue.field.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn migrate_version_0() {
+        let (config, version) = from_toml(
+            r#"
+            last_connector = "kvm"
+            last_os = "win32"
+            last_os_args = "dtb=0x1aa000"
+            multithreading = false
+            "#,
+        );
+        assert_eq!(version, 0);
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(!config.multithreading);
+        assert!(config.issues.is_empty());
+
+        let stages = config
+            .last_chain
+            .iter()
+            .map(|stage| (stage.kind, stage.name.as_str(), stage.args.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stages,
+            [
+                (ChainStageKind::Connector, "kvm", ""),
+                (ChainStageKind::Os, "win32", "dtb=0x1aa000")
+            ]
+        );
+    }
+
+    #[test]
+    fn migrate_keeps_an_existing_chain() {
+        let (config, _) = from_toml(
+            r#"
+            last_connector = "qemu"
+            [[last_chain]]
+            kind = "connector"
+            name = "kvm"
+            "#,
+        );
+        assert_eq!(config.last_chain.len(), 1);
+        assert_eq!(config.last_chain[0].name, "kvm");
+    }
+
+    #[test]
+    fn keep_valid_elements() {
+        let (config, _) = from_toml(&format!(
+            r#"
+            version = {}
+            [[profiles]]
+            name = "a"
+            [[profiles]]
+            name = "b"
+            process_name = 5
+            [[profiles]]
+            name = "c"
+            [[viewports]]
+            tab = 0
+            [[viewports]]
+            tab = "one"
+            [keybindings]
+            new_tab = {:?}
+            toggle_log = 5
+            "#,
+            CONFIG_VERSION,
+            format_shortcut(Action::NewTab.default_shortcut())
+        ));
+
+        let profiles = config
+            .profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(profiles, ["a", "c"]);
+        assert_eq!(config.viewports.len(), 1);
+        assert!(config.keybindings.contains_key("new_tab"));
+        assert!(!config.keybindings.contains_key("toggle_log"));
+        assert_eq!(
+            issue_fields(&config),
+            ["keybindings.toggle_log", "profiles[1]", "viewports[1]"]
+        );
+    }
+
+    #[test]
+    fn reset_invalid_fields() {
+        let (config, _) = from_toml(&format!(
+            "version = {}\nmultithreading = \"yes\"\nobs_capture = false",
+            CONFIG_VERSION
+        ));
+        assert!(config.multithreading);
+        assert!(!config.obs_capture);
+        assert_eq!(issue_fields(&config), ["multithreading"]);
+    }
+
+    #[test]
+    fn duplicate_keybindings() {
+        let (config, _) = from_toml(&format!(
+            r#"
+            version = {}
+            [keybindings]
+            toggle_log = "Ctrl+T"
+            zoom_in = "F1"
+            zoom_out = "F1"
+            "#,
+            CONFIG_VERSION
+        ));
+
+        // the default of new_tab is taken by toggle_log
+        assert_eq!(config.keybinding(Action::NewTab), None);
+        assert_eq!(
+            config.keybinding(Action::ToggleLog),
+            Action::NewTab.default_shortcut()
+        );
+        assert!(config.keybinding(Action::ZoomIn).is_some());
+        assert_eq!(config.keybinding(Action::ZoomOut), None);
+        assert_eq!(
+            issue_fields(&config),
+            ["keybindings.zoom_out", "keybindings.new_tab"]
+        );
+    }
+
+    #[test]
+    fn newer_version() {
+        let (config, version) = from_toml(&format!(
+            "version = {}\nobs_capture = false\nunknown = 1",
+            CONFIG_VERSION + 1
+        ));
+        assert_eq!(version, CONFIG_VERSION + 1);
+        assert!(!config.obs_capture);
+        assert_eq!(issue_fields(&config), ["version"]);
+    }
+
+    #[test]
+    fn profile_path() {
+        let dir = MirrorConfig::config_dir().join("profiles");
+        assert_eq!(
+            MirrorConfig::profile_path("kvm (2)"),
+            dir.join("kvm (2).toml")
+        );
+        assert_eq!(
+            MirrorConfig::profile_path("../../x"),
+            dir.join("______x.toml")
+        );
+        assert_eq!(MirrorConfig::profile_path("a/b\\c"), dir.join("a_b_c.toml"));
+        assert_eq!(MirrorConfig::profile_path(" "), dir.join("profile.toml"));
+    }
+
+    #[test]
+    fn unique_profile_name() {
+        let mut config = MirrorConfig::default();
+        assert_eq!(config.unique_profile_name("kvm"), "kvm");
+
+        config.set_profile(Profile::new("kvm"));
+        assert_eq!(config.unique_profile_name("kvm"), "kvm (2)");
+        config.set_profile(Profile::new("kvm (2)"));
+        assert_eq!(config.unique_profile_name("kvm"), "kvm (3)");
+    }
+}
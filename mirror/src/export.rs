@@ -0,0 +1,414 @@
+use ::std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ::mirror_dto::Cursor;
+
+use crate::capture::{Frame, ReplayClip};
+use crate::screenshot::{draw_cursor, frame_to_image};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipFormat {
+    /// Animated gif with a 256 color palette per frame
+    Gif,
+    /// Animated png, lossless but larger than gif
+    Apng,
+}
+
+impl ClipFormat {
+    pub const ALL: [ClipFormat; 2] = [ClipFormat::Gif, ClipFormat::Apng];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "gif" => Some(ClipFormat::Gif),
+            "apng" | "png" => Some(ClipFormat::Apng),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "GIF",
+            ClipFormat::Apng => "APNG",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "gif",
+            ClipFormat::Apng => "png",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClipExportConfig {
+    pub format: ClipFormat,
+    /// Start of the exported range relative to the first frame of the source
+    pub start: Duration,
+    /// End of the exported range, the clip runs until the last frame if not set
+    pub end: Option<Duration>,
+    /// Frames are skipped so the clip does not exceed this frame rate
+    pub max_frame_rate: u32,
+    /// Factor the frames are scaled by (1.0 = original resolution)
+    pub scale: f32,
+    /// Draws the cursor on top of the frames if the source contains the cursor state
+    pub cursor: bool,
+    /// Speed of the gif palette quantisation in the range of 1 (best quality) to 30 (fastest)
+    pub quantize_speed: i32,
+}
+
+impl Default for ClipExportConfig {
+    fn default() -> Self {
+        Self {
+            format: ClipFormat::Gif,
+            start: Duration::ZERO,
+            end: None,
+            max_frame_rate: 15,
+            scale: 0.5,
+            cursor: true,
+            quantize_speed: 10,
+        }
+    }
+}
+
+/// Frames that can be exported into a clip
+pub enum ClipSource {
+    /// Frames taken out of an instant replay buffer
+    Replay(ReplayClip),
+    /// Directory of a png or qoi sequence written by the `Recorder`
+    Sequence(PathBuf),
+}
+
+impl ClipSource {
+    /// Returns the timestamps of all frames and the files of a sequence
+    fn index(&self) -> io::Result<Vec<(Duration, Option<PathBuf>)>> {
+        match self {
+            ClipSource::Replay(clip) => Ok(clip
+                .timestamps()
+                .into_iter()
+                .map(|timestamp| (timestamp, None))
+                .collect()),
+            ClipSource::Sequence(dir) => {
+                // frame,timestamp_us,width,height,file
+                let index = fs::read_to_string(dir.join("index.csv"))?;
+                index
+                    .lines()
+                    .skip(1)
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| {
+                        let columns = line.split(',').collect::<Vec<_>>();
+                        match (
+                            columns.get(1).and_then(|ts| ts.parse().ok()),
+                            columns.get(4),
+                        ) {
+                            (Some(timestamp), Some(file)) => Ok((
+                                Duration::from_micros(timestamp),
+                                Some(dir.join(file.trim())),
+                            )),
+                            _ => Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("invalid index entry: {}", line),
+                            )),
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn load(&self, index: usize, file: Option<&Path>) -> io::Result<Frame> {
+        match (self, file) {
+            (ClipSource::Sequence(_), Some(file)) => {
+                let image = image::open(file).map_err(io::Error::other)?.to_rgba8();
+                Ok(Frame {
+                    width: image.width(),
+                    height: image.height(),
+                    timestamp: None,
+                    // sequences do not store the cursor
+                    cursor: Cursor::new(),
+                    data: image.into_raw(),
+                })
+            }
+            (ClipSource::Replay(clip), _) => clip.frame(index),
+            (ClipSource::Sequence(_), None) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "frame file is missing",
+            )),
+        }
+    }
+}
+
+/// Encodes a range of frames into an animated gif or png.
+///
+/// All frames are converted to the resolution of the first exported frame.
+pub struct ClipExporter {
+    config: ClipExportConfig,
+}
+
+impl ClipExporter {
+    pub fn new(config: ClipExportConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &ClipExportConfig {
+        &self.config
+    }
+
+    /// Writes the clip to the given path and returns the number of frames in the clip
+    pub fn export<P: AsRef<Path>>(&self, source: &ClipSource, path: P) -> io::Result<u64> {
+        let frames = self.select_frames(&source.index()?);
+        if frames.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no frames in the selected range",
+            ));
+        }
+
+        let mut output = None;
+        let mut size = None;
+        for (index, file, delay) in frames.iter() {
+            let image = self.prepare_frame(&source.load(*index, file.as_deref())?, size);
+
+            // the resolution of the clip is determined by the first frame
+            if output.is_none() {
+                size = Some(image.dimensions());
+                output = Some(ClipOutput::create(
+                    path.as_ref(),
+                    &self.config,
+                    image.width(),
+                    image.height(),
+                    frames.len() as u32,
+                )?);
+            }
+            output
+                .as_mut()
+                .unwrap()
+                .write_frame(image, *delay, &self.config)?;
+        }
+
+        if let Some(output) = output {
+            output.finish()?;
+        }
+        Ok(frames.len() as u64)
+    }
+
+    /// Returns the frames within the configured range together with the time they are shown for
+    fn select_frames(
+        &self,
+        index: &[(Duration, Option<PathBuf>)],
+    ) -> Vec<(usize, Option<PathBuf>, Duration)> {
+        let min_interval = Duration::from_secs(1) / self.config.max_frame_rate.max(1);
+        let end = self.config.end.unwrap_or(Duration::MAX);
+
+        let mut selected: Vec<(usize, Option<PathBuf>, Duration)> = Vec::new();
+        let mut last_timestamp: Option<Duration> = None;
+        for (i, (timestamp, file)) in index.iter().enumerate() {
+            if *timestamp < self.config.start || *timestamp > end {
+                continue;
+            }
+
+            // skip frames that would exceed the frame rate
+            if let Some(last) = last_timestamp {
+                if timestamp.saturating_sub(last) < min_interval {
+                    continue;
+                }
+                if let Some(previous) = selected.last_mut() {
+                    previous.2 = *timestamp - last;
+                }
+            }
+
+            selected.push((i, file.clone(), min_interval));
+            last_timestamp = Some(*timestamp);
+        }
+        selected
+    }
+
+    /// Draws the cursor and scales the frame to the output size
+    fn prepare_frame(&self, frame: &Frame, size: Option<(u32, u32)>) -> image::RgbaImage {
+        let mut image = frame_to_image(frame);
+        if self.config.cursor {
+            draw_cursor(&mut image, &frame.cursor);
+        }
+
+        let scale = self.config.scale.clamp(0.01, 1.0);
+        let (width, height) = size.unwrap_or((
+            ((image.width() as f32 * scale) as u32).max(1),
+            ((image.height() as f32 * scale) as u32).max(1),
+        ));
+        if (width, height) != image.dimensions() {
+            image = image::imageops::resize(
+                &image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        image
+    }
+}
+
+enum ClipOutput {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+impl ClipOutput {
+    fn create(
+        path: &Path,
+        config: &ClipExportConfig,
+        width: u32,
+        height: u32,
+        frames: u32,
+    ) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        match config.format {
+            ClipFormat::Gif => {
+                if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "resolution is too large for gif",
+                    ));
+                }
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])
+                    .map_err(io::Error::other)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+                Ok(ClipOutput::Gif(encoder))
+            }
+            ClipFormat::Apng => {
+                let mut encoder = png::Encoder::new(file, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frames, 0)?;
+                Ok(ClipOutput::Apng(encoder.write_header()?))
+            }
+        }
+    }
+
+    fn write_frame(
+        &mut self,
+        image: image::RgbaImage,
+        delay: Duration,
+        config: &ClipExportConfig,
+    ) -> io::Result<()> {
+        match self {
+            ClipOutput::Gif(encoder) => {
+                let (width, height) = (image.width() as u16, image.height() as u16);
+                let mut pixels = image.into_raw();
+                let mut frame = gif::Frame::from_rgba_speed(
+                    width,
+                    height,
+                    &mut pixels,
+                    config.quantize_speed.clamp(1, 30),
+                );
+                // gif delays are stored in 1/100th of a second
+                frame.delay = (delay.as_millis() / 10).clamp(1, u16::MAX as u128) as u16;
+                encoder.write_frame(&frame).map_err(io::Error::other)
+            }
+            ClipOutput::Apng(writer) => {
+                writer
+                    .set_frame_delay(delay.as_millis().clamp(1, u16::MAX as u128) as u16, 1000)?;
+                writer.write_image_data(&image)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ClipOutput::Gif(encoder) => {
+                encoder.into_inner()?;
+                Ok(())
+            }
+            ClipOutput::Apng(writer) => Ok(writer.finish()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exporter(start_ms: u64, end_ms: Option<u64>, max_frame_rate: u32) -> ClipExporter {
+        ClipExporter::new(ClipExportConfig {
+            start: Duration::from_millis(start_ms),
+            end: end_ms.map(Duration::from_millis),
+            max_frame_rate,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the index of a replay clip with a frame every `interval_ms`
+    fn index(frames: u64, interval_ms: u64) -> Vec<(Duration, Option<PathBuf>)> {
+        (0..frames)
+            .map(|i| (Duration::from_millis(i * interval_ms), None))
+            .collect()
+    }
+
+    fn selected(frames: Vec<(usize, Option<PathBuf>, Duration)>) -> Vec<(usize, u64)> {
+        frames
+            .into_iter()
+            .map(|(index, _, delay)| (index, delay.as_millis() as u64))
+            .collect()
+    }
+
+    #[test]
+    fn select_all_frames() {
+        let frames = exporter(0, None, 10).select_frames(&index(4, 100));
+        // the last frame is shown for the interval of the frame rate
+        assert_eq!(
+            selected(frames),
+            vec![(0, 100), (1, 100), (2, 100), (3, 100)]
+        );
+    }
+
+    #[test]
+    fn select_range() {
+        let frames = exporter(100, Some(250), 10).select_frames(&index(6, 50));
+        assert_eq!(selected(frames), vec![(2, 100), (4, 100)]);
+    }
+
+    #[test]
+    fn limit_frame_rate() {
+        // frames are skipped so the delay of the remaining frames covers the skipped ones
+        let frames = exporter(0, None, 4).select_frames(&index(8, 100));
+        assert_eq!(selected(frames), vec![(0, 300), (3, 300), (6, 250)]);
+    }
+
+    #[test]
+    fn empty_range() {
+        assert!(exporter(1000, None, 10)
+            .select_frames(&index(4, 100))
+            .is_empty());
+    }
+
+    #[test]
+    fn sequence_index() {
+        let dir = std::env::temp_dir().join(format!("mirror-sequence-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("index.csv"),
+            "frame,timestamp_us,width,height,file\n\
+             0,0,2,2,00000000.png\n\
+             1,16000,2,2,00000001.png\n",
+        )
+        .unwrap();
+        let index = ClipSource::Sequence(dir.clone()).index().unwrap();
+        assert_eq!(
+            index,
+            vec![
+                (Duration::ZERO, Some(dir.join("00000000.png"))),
+                (Duration::from_millis(16), Some(dir.join("00000001.png"))),
+            ]
+        );
+
+        fs::write(dir.join("index.csv"), "frame,timestamp_us\n0,zero\n").unwrap();
+        assert!(ClipSource::Sequence(dir.clone()).index().is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
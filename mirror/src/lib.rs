@@ -1,11 +1,64 @@
 mod capture;
-pub use capture::{Capture, SequentialCapture, ThreadedCapture};
+pub use capture::{
+    detect_framebuffers, AudioChunk, Capture, ClipboardData, FileCapture, Frame, FrameFileWriter,
+    FrameSink, FramebufferCandidate, FramebufferCapture, FramebufferConfig, PhysicalCapture,
+    PixelFormat, Playback, ReplayBuffer, ReplayClip, ReplayConfig, SequentialCapture,
+    ThreadedCapture, DEFAULT_PROCESS_NAME,
+};
+
+mod chain;
+pub use chain::ChainArgs;
+
+mod export;
+pub use export::{ClipExportConfig, ClipExporter, ClipFormat, ClipSource};
+
+mod pipe;
+pub use pipe::{PipeFormat, PipeOutput, PipeOutputConfig, PipeTarget, ResolutionChange};
+
+mod recorder;
+pub use recorder::{RecordFormat, Recorder, RecorderConfig};
+
+mod screenshot;
+pub use screenshot::{GuestInfo, Screenshot, ScreenshotOptions};
+
+#[cfg(feature = "preview-server")]
+mod server;
+#[cfg(feature = "preview-server")]
+pub use server::{PreviewServer, PreviewServerConfig};
+
+#[cfg(feature = "vnc-server")]
+mod vnc;
+#[cfg(feature = "vnc-server")]
+pub use vnc::{VncServer, VncServerConfig};
+
+#[cfg(all(unix, feature = "shared-memory"))]
+mod shm;
+#[cfg(all(unix, feature = "shared-memory"))]
+pub use shm::{ShmPublisher, ShmPublisherConfig, ShmReader};
 
 pub use ::mirror_dto::*;
 
 pub mod prelude {
     pub mod v1 {
-        pub use crate::capture::{Capture, SequentialCapture, ThreadedCapture};
+        pub use crate::capture::{
+            detect_framebuffers, AudioChunk, Capture, ClipboardData, FileCapture, Frame,
+            FrameFileWriter, FrameSink, FramebufferCandidate, FramebufferCapture,
+            FramebufferConfig, PhysicalCapture, PixelFormat, Playback, ReplayBuffer, ReplayClip,
+            ReplayConfig, SequentialCapture, ThreadedCapture, DEFAULT_PROCESS_NAME,
+        };
+        pub use crate::chain::ChainArgs;
+        pub use crate::export::{ClipExportConfig, ClipExporter, ClipFormat, ClipSource};
+        pub use crate::pipe::{
+            PipeFormat, PipeOutput, PipeOutputConfig, PipeTarget, ResolutionChange,
+        };
+        pub use crate::recorder::{RecordFormat, Recorder, RecorderConfig};
+        pub use crate::screenshot::{GuestInfo, Screenshot, ScreenshotOptions};
+        #[cfg(feature = "preview-server")]
+        pub use crate::server::{PreviewServer, PreviewServerConfig};
+        #[cfg(all(unix, feature = "shared-memory"))]
+        pub use crate::shm::{ShmPublisher, ShmPublisherConfig, ShmReader};
+        #[cfg(feature = "vnc-server")]
+        pub use crate::vnc::{VncServer, VncServerConfig};
         pub use ::mirror_dto::*;
     }
 }
@@ -1,23 +1,95 @@
-use ::clap::{crate_authors, crate_version, Arg, ArgAction, Command};
-use ::log::{warn, Level};
+use ::clap::{crate_authors, crate_version, value_parser, Arg, ArgAction, Command};
+use ::log::{error, warn, Level};
+use ::std::{path::PathBuf, sync::Arc, time::Duration};
 
-use ::memflow::prelude::v1::Result;
+use ::memflow::prelude::v1::{Error, ErrorKind, ErrorOrigin, Result};
 
 mod app;
-pub use app::MirrorApp;
+use app::{LogBuffer, LogSink};
+pub use app::{MirrorApp, StartupOptions};
 
 mod capture;
-pub use capture::{Capture, SequentialCapture, ThreadedCapture};
+pub use capture::{
+    detect_framebuffers, AudioChunk, Capture, ClipboardData, FileCapture, Frame, FrameFileWriter,
+    FrameSink, FramebufferCandidate, FramebufferCapture, FramebufferConfig, PhysicalCapture,
+    PixelFormat, Playback, ReplayBuffer, ReplayClip, ReplayConfig, SequentialCapture,
+    ThreadedCapture, DEFAULT_PROCESS_NAME,
+};
+
+mod chain;
+pub use chain::ChainArgs;
+
+mod cli;
 
 mod config;
 use config::MirrorConfig;
 
+mod export;
+pub use export::{ClipExportConfig, ClipExporter, ClipFormat, ClipSource};
+
+mod pipe;
+pub use pipe::{PipeFormat, PipeOutput, PipeOutputConfig, PipeTarget, ResolutionChange};
+
+mod recorder;
+pub use recorder::{RecordFormat, Recorder, RecorderConfig};
+
+mod screenshot;
+pub use screenshot::{GuestInfo, Screenshot, ScreenshotOptions};
+
+#[cfg(feature = "preview-server")]
+mod server;
+#[cfg(feature = "preview-server")]
+pub use server::{PreviewServer, PreviewServerConfig};
+
+#[cfg(feature = "vnc-server")]
+mod vnc;
+#[cfg(feature = "vnc-server")]
+pub use vnc::{VncServer, VncServerConfig};
+
+#[cfg(all(unix, feature = "shared-memory"))]
+mod shm;
+#[cfg(all(unix, feature = "shared-memory"))]
+pub use shm::{ShmPublisher, ShmPublisherConfig, ShmReader};
+
 fn main() -> Result<()> {
-    let matches = Command::new("memflow-mirror")
+    let command = Command::new("memflow-mirror")
         .version(crate_version!())
         .author(crate_authors!())
-        .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
-        .get_matches();
+        // the ui is started if no command is given
+        .args_conflicts_with_subcommands(true)
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("Uses the given config file instead of the default location"),
+        )
+        .arg(
+            Arg::new("set")
+                .long("set")
+                .value_name("FIELD=VALUE")
+                .action(ArgAction::Append)
+                .global(true)
+                .help("Overrides a config field for this session (e.g. multithreading=false)"),
+        )
+        .args(cli::chain_args())
+        .args(gui_args())
+        .subcommand(
+            Command::new("gui")
+                .about("Starts the ui (default)")
+                .args(gui_args()),
+        )
+        .subcommands(cli::commands());
+
+    let matches = command.get_matches();
+    let (name, matches) = matches.subcommand().unwrap_or(("gui", &matches));
 
     let log_level = match matches.get_count("verbose") {
         0 => Level::Error,
@@ -27,13 +99,31 @@ fn main() -> Result<()> {
         4 => Level::Trace,
         _ => Level::Trace,
     };
-    simplelog::TermLogger::init(
+    let log_buffer = Arc::new(LogBuffer::default());
+    let term_logger: Box<dyn simplelog::SharedLogger> = simplelog::TermLogger::new(
         log_level.to_level_filter(),
         simplelog::Config::default(),
-        simplelog::TerminalMode::Stdout,
+        // stdout is reserved for the output of the headless commands
+        if name == "gui" {
+            simplelog::TerminalMode::Stdout
+        } else {
+            simplelog::TerminalMode::Stderr
+        },
         simplelog::ColorChoice::Auto,
-    )
-    .unwrap();
+    );
+    if name == "gui" {
+        // the log tab always receives at least info records, independent of the verbosity
+        simplelog::CombinedLogger::init(vec![
+            term_logger,
+            LogSink::new(
+                log_level.max(Level::Info).to_level_filter(),
+                log_buffer.clone(),
+            ),
+        ])
+        .unwrap();
+    } else {
+        simplelog::CombinedLogger::init(vec![term_logger]).unwrap();
+    }
 
     if thread_priority::ThreadPriority::Max
         .set_for_current()
@@ -42,33 +132,90 @@ fn main() -> Result<()> {
         warn!("Unable to set main thread priority");
     }
 
-    // TODO: configuration via ui
-    // parse args
-    /*
-    let conn_iter = matches
-        .indices_of("connector")
-        .zip(matches.get_many::<String>("connector"))
-        .map(|(a, b)| a.zip(b.map(String::as_str)))
-        .into_iter()
-        .flatten();
+    // the config is loaded once, fields can be overridden via `MIRROR_<FIELD>` and `--set`
+    if let Some(path) = matches.get_one::<PathBuf>("config") {
+        MirrorConfig::set_path(path.clone());
+    }
+    let mut config = MirrorConfig::load_or_default();
+    for setting in matches.get_many::<String>("set").into_iter().flatten() {
+        if let Err(err) = config.apply_override(setting) {
+            error!("invalid config override {}: {}", setting, err);
+            return Err(Error(ErrorOrigin::Other, ErrorKind::Configuration));
+        }
+    }
+    for issue in config.issues.iter() {
+        warn!("config: {}", issue);
+    }
+
+    if name != "gui" {
+        return cli::run(name, matches, &config);
+    }
 
-    let os_iter = matches
-        .indices_of("os")
-        .zip(matches.get_many::<String>("os"))
-        .map(|(a, b)| a.zip(b.map(String::as_str)))
-        .into_iter()
-        .flatten();
+    let recording = matches.get_one::<String>("record").map(|path| {
+        let format = matches
+            .get_one::<String>("record-format")
+            .and_then(|format| RecordFormat::from_name(format))
+            .unwrap_or(RecordFormat::Y4m);
+        let mut config = RecorderConfig::new(path, format);
+        config.max_segment_size = matches
+            .get_one::<u64>("record-split-size")
+            .map(|size| size * 1024 * 1024);
+        config.max_segment_duration = matches
+            .get_one::<u64>("record-split-duration")
+            .map(|secs| Duration::from_secs(*secs));
+        config
+    });
 
-    let chain = OsChain::new(conn_iter, os_iter)?;
+    #[cfg(feature = "preview-server")]
+    let preview_server = matches
+        .get_one::<std::net::SocketAddr>("serve")
+        .map(|address| PreviewServerConfig {
+            address: *address,
+            jpeg_quality: *matches.get_one::<u8>("serve-quality").unwrap(),
+            scale: *matches.get_one::<f32>("serve-scale").unwrap(),
+            max_frame_rate: *matches.get_one::<u32>("serve-fps").unwrap(),
+        });
 
-    // create memflow inventory + os
-    let inventory = Inventory::scan();
-    let os = inventory.builder().os_chain(chain).build()?;
+    #[cfg(feature = "vnc-server")]
+    let vnc_server = matches
+        .get_one::<std::net::SocketAddr>("vnc")
+        .map(|address| VncServerConfig {
+            address: *address,
+            ..Default::default()
+        });
 
+    #[cfg(all(unix, feature = "shared-memory"))]
+    let shm_publisher = matches
+        .get_one::<String>("shm")
+        .map(|name| ShmPublisherConfig {
+            name: name.clone(),
+            ..Default::default()
+        });
 
-    // update capture configuration
-    capture.set_obs_capture(config.obs_capture);
-    */
+    // an explicit os chain or profile replaces the connection of the first tab
+    let capture = if matches.contains_id("os") || matches.contains_id("connector") {
+        Some(cli::connect(matches, &config)?)
+    } else {
+        None
+    };
+    let profile = match matches.get_one::<String>("profile") {
+        Some(name) => Some(cli::profile(&config, name)?.clone()),
+        None => None,
+    };
+
+    let options = StartupOptions {
+        capture,
+        profile,
+        recording,
+        #[cfg(feature = "preview-server")]
+        preview_server,
+        #[cfg(feature = "vnc-server")]
+        vnc_server,
+        #[cfg(all(unix, feature = "shared-memory"))]
+        shm_publisher,
+        log_buffer: Some(log_buffer),
+        config: Some(config),
+    };
 
     // start ui
     //tracing_subscriber::fmt::init();
@@ -77,9 +224,70 @@ fn main() -> Result<()> {
     eframe::run_native(
         "memflow mirror",
         native_options,
-        Box::new(|cc| Box::new(MirrorApp::new(cc))),
+        Box::new(|cc| Box::new(MirrorApp::new(cc, options))),
     )
     .expect("could not start gui");
 
     Ok(())
 }
+
+/// Arguments of the ui, they are accepted with and without the `gui` command
+fn gui_args() -> Vec<Arg> {
+    let mut args = vec![
+        Arg::new("record")
+            .long("record")
+            .value_name("DIR")
+            .help("Records the first connection into the given directory"),
+        Arg::new("record-format")
+            .long("record-format")
+            .value_parser(["y4m", "raw", "png", "qoi", "mirror"])
+            .default_value("y4m"),
+        Arg::new("record-split-size")
+            .long("record-split-size")
+            .value_name("MB")
+            .value_parser(value_parser!(u64))
+            .help("Starts a new file once the current one exceeds the given size"),
+        Arg::new("record-split-duration")
+            .long("record-split-duration")
+            .value_name("SECONDS")
+            .value_parser(value_parser!(u64))
+            .help("Starts a new file once the current one exceeds the given duration"),
+    ];
+
+    #[cfg(feature = "preview-server")]
+    args.extend([
+        Arg::new("serve")
+            .long("serve")
+            .value_name("ADDRESS")
+            .value_parser(value_parser!(std::net::SocketAddr))
+            .help("Serves the frames of the first connection as mjpeg over http (e.g. 127.0.0.1:8080)"),
+        Arg::new("serve-quality")
+            .long("serve-quality")
+            .value_parser(value_parser!(u8).range(1..=100))
+            .default_value("80"),
+        Arg::new("serve-scale")
+            .long("serve-scale")
+            .value_parser(value_parser!(f32))
+            .default_value("1.0"),
+        Arg::new("serve-fps")
+            .long("serve-fps")
+            .value_parser(value_parser!(u32))
+            .default_value("30"),
+    ]);
+
+    #[cfg(feature = "vnc-server")]
+    args.push(
+        Arg::new("vnc")
+            .long("vnc")
+            .value_name("ADDRESS")
+            .value_parser(value_parser!(std::net::SocketAddr))
+            .help("Serves the frames of the first connection to vnc clients (e.g. 127.0.0.1:5900)"),
+    );
+
+    #[cfg(all(unix, feature = "shared-memory"))]
+    args.push(Arg::new("shm").long("shm").value_name("NAME").help(
+        "Publishes the frames of the first connection in shared memory (e.g. /memflow-mirror)",
+    ));
+
+    args
+}
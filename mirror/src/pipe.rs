@@ -0,0 +1,546 @@
+use ::log::info;
+use ::std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::capture::{Capture, Frame};
+
+// the output skips ahead instead of writing duplicates when it falls behind by more than this
+const MAX_CATCH_UP: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PipeFormat {
+    /// Packed rgba, 4 bytes per pixel
+    Rgba,
+    /// Packed bgra, 4 bytes per pixel
+    Bgra,
+    /// Planar yuv 4:2:0 (bt.601), 1.5 bytes per pixel
+    I420,
+}
+
+impl PipeFormat {
+    pub const ALL: [PipeFormat; 3] = [PipeFormat::Rgba, PipeFormat::Bgra, PipeFormat::I420];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|format| format.name() == name.to_lowercase())
+            .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PipeFormat::Rgba => "rgba",
+            PipeFormat::Bgra => "bgra",
+            PipeFormat::I420 => "i420",
+        }
+    }
+
+    /// Returns the name of the format in ffmpeg
+    pub fn pix_fmt(&self) -> &'static str {
+        match self {
+            PipeFormat::Rgba => "rgba",
+            PipeFormat::Bgra => "bgra",
+            PipeFormat::I420 => "yuv420p",
+        }
+    }
+
+    /// Returns the size of a single frame in bytes
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        let pixels = width as usize * height as usize;
+        match self {
+            PipeFormat::Rgba | PipeFormat::Bgra => pixels * 4,
+            PipeFormat::I420 => {
+                let chroma = (width as usize).div_ceil(2) * (height as usize).div_ceil(2);
+                pixels + chroma * 2
+            }
+        }
+    }
+}
+
+/// Behavior of the output when the resolution of the guest changes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResolutionChange {
+    /// Frames are scaled to the resolution the stream was started with
+    Scale,
+    /// The stream is closed and started again with the new resolution.
+    ///
+    /// Consumers see the end of the stream before the frames of the new resolution.
+    /// Stdout can not be opened again, the stream ends instead.
+    Restart,
+}
+
+impl ResolutionChange {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "scale" => Some(ResolutionChange::Scale),
+            "restart" => Some(ResolutionChange::Restart),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PipeTarget {
+    Stdout,
+    /// A named pipe (or regular file), it is opened again when the stream restarts
+    Path(PathBuf),
+}
+
+impl fmt::Display for PipeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipeTarget::Stdout => write!(f, "-"),
+            PipeTarget::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PipeOutputConfig {
+    pub format: PipeFormat,
+    /// Constant frame rate of the stream, frames are duplicated or dropped to keep it
+    pub frame_rate: u32,
+    pub resolution_change: ResolutionChange,
+}
+
+impl Default for PipeOutputConfig {
+    fn default() -> Self {
+        Self {
+            format: PipeFormat::Rgba,
+            frame_rate: 30,
+            resolution_change: ResolutionChange::Scale,
+        }
+    }
+}
+
+/// Writes raw frames of a `Capture` at a constant frame rate, e.g. to be consumed by ffmpeg or gstreamer.
+///
+/// Unlike the `Recorder` the frames are written in the calling thread so a slow consumer
+/// applies back pressure instead of silently losing frames.
+pub struct PipeOutput {
+    target: PipeTarget,
+    config: PipeOutputConfig,
+    writer: Option<Box<dyn Write + Send>>,
+
+    // resolution of the current stream
+    width: u32,
+    height: u32,
+
+    latest: Option<Frame>,
+    latest_written: bool,
+    frame_counter: Option<u32>,
+    next_frame: Option<Instant>,
+    buffer: Vec<u8>,
+
+    frames_written: u64,
+    frames_duplicated: u64,
+    frames_dropped: u64,
+    ended: bool,
+}
+
+impl PipeOutput {
+    /// Creates a new output, the target is opened once the first frame is available
+    pub fn new(target: PipeTarget, config: PipeOutputConfig) -> Self {
+        Self {
+            target,
+            config,
+            writer: None,
+
+            width: 0,
+            height: 0,
+
+            latest: None,
+            latest_written: false,
+            frame_counter: None,
+            next_frame: None,
+            buffer: Vec::new(),
+
+            frames_written: 0,
+            frames_duplicated: 0,
+            frames_dropped: 0,
+            ended: false,
+        }
+    }
+
+    pub fn config(&self) -> &PipeOutputConfig {
+        &self.config
+    }
+
+    /// Returns the resolution of the current stream
+    pub fn resolution(&self) -> Option<(u32, u32)> {
+        self.writer.as_ref().map(|_| (self.width, self.height))
+    }
+
+    /// Returns the ffmpeg input parameters matching the current stream
+    pub fn ffmpeg_args(&self) -> Option<String> {
+        self.resolution().map(|(width, height)| {
+            format!(
+                "-f rawvideo -pix_fmt {} -video_size {}x{} -framerate {} -i {}",
+                self.config.format.pix_fmt(),
+                width,
+                height,
+                self.config.frame_rate,
+                self.target
+            )
+        })
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+
+    /// Returns the amount of frames that were written more than once to keep the frame rate
+    pub fn frames_duplicated(&self) -> u64 {
+        self.frames_duplicated
+    }
+
+    /// Returns the amount of captured frames that were replaced before they could be written
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped
+    }
+
+    /// Returns true once the stream ended because stdout can not be restarted with a new resolution
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Takes the latest frame of the capture and writes all frames that are due.
+    ///
+    /// This should be called more often than the frame rate of the stream.
+    /// Returns true if the stream was (re-)started.
+    pub fn update(&mut self, capture: &dyn Capture) -> io::Result<bool> {
+        let frame_counter = capture.frame_counter();
+        if self.frame_counter != Some(frame_counter) {
+            let frame = capture.frame();
+            if !frame.is_empty() {
+                self.frame_counter = Some(frame_counter);
+                self.push_frame(frame);
+            }
+        }
+        self.write_due()
+    }
+
+    /// Replaces the latest frame, it is written once the next frame is due
+    fn push_frame(&mut self, frame: Frame) {
+        if self.latest.is_some() && !self.latest_written {
+            self.frames_dropped += 1;
+        }
+        self.latest = Some(frame);
+        self.latest_written = false;
+    }
+
+    /// Writes all frames that are due, returns true if the stream was (re-)started
+    fn write_due(&mut self) -> io::Result<bool> {
+        let (width, height) = match &self.latest {
+            Some(_) if self.ended => return Ok(false),
+            Some(frame) => (frame.width, frame.height),
+            None => return Ok(false),
+        };
+
+        let mut started = false;
+        let restart = self.writer.is_some()
+            && self.config.resolution_change == ResolutionChange::Restart
+            && (width, height) != (self.width, self.height);
+        if restart && self.target == PipeTarget::Stdout {
+            // the frames of the new resolution could not be told apart from the previous ones
+            info!(
+                "resolution changed to {}x{}, ending the stream on stdout",
+                width, height
+            );
+            self.finish()?;
+            self.ended = true;
+            return Ok(false);
+        }
+        if self.writer.is_none() || restart {
+            self.start(width, height)?;
+            started = true;
+        }
+
+        let interval = Duration::from_secs(1) / self.config.frame_rate.max(1);
+        let now = Instant::now();
+        let mut next_frame = *self.next_frame.get_or_insert(now);
+        if now.saturating_duration_since(next_frame) > MAX_CATCH_UP {
+            next_frame = now;
+        }
+        while next_frame <= now {
+            self.write_latest()?;
+            next_frame += interval;
+        }
+        self.next_frame = Some(next_frame);
+
+        Ok(started)
+    }
+
+    /// Returns the time until the next frame is due
+    pub fn time_to_next_frame(&self) -> Duration {
+        match self.next_frame {
+            Some(next_frame) => next_frame.saturating_duration_since(Instant::now()),
+            // waiting for the first frame
+            None => Duration::from_secs(1) / self.config.frame_rate.max(1),
+        }
+    }
+
+    /// Flushes and closes the stream
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, width: u32, height: u32) -> io::Result<()> {
+        self.finish()?;
+
+        let writer: Box<dyn Write + Send> = match &self.target {
+            PipeTarget::Stdout => Box::new(io::stdout()),
+            PipeTarget::Path(path) => Box::new(BufWriter::new(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)?,
+            )),
+        };
+        self.writer = Some(writer);
+        self.width = width;
+        self.height = height;
+        self.next_frame = None;
+
+        info!(
+            "started {} pipe output with {}x{} at {} fps",
+            self.config.format.name(),
+            width,
+            height,
+            self.config.frame_rate
+        );
+        Ok(())
+    }
+
+    fn write_latest(&mut self) -> io::Result<()> {
+        let frame = match &self.latest {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        if self.latest_written {
+            // the buffer still contains the converted frame
+            self.frames_duplicated += 1;
+        } else {
+            let scaled;
+            let frame = if (frame.width, frame.height) != (self.width, self.height) {
+                scaled = scale_frame(frame, self.width, self.height);
+                &scaled
+            } else {
+                frame
+            };
+            convert_frame(frame, self.config.format, &mut self.buffer);
+            self.latest_written = true;
+        }
+
+        if let Some(writer) = &mut self.writer {
+            writer.write_all(&self.buffer)?;
+            writer.flush()?;
+        }
+        self.frames_written += 1;
+        Ok(())
+    }
+}
+
+impl Drop for PipeOutput {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+fn scale_frame(frame: &Frame, width: u32, height: u32) -> Frame {
+    let len = frame.width as usize * frame.height as usize * 4;
+    let data = image::RgbaImage::from_raw(frame.width, frame.height, frame.data[..len].to_vec())
+        .map(|image| {
+            image::imageops::resize(&image, width, height, image::imageops::FilterType::Triangle)
+                .into_raw()
+        })
+        .unwrap_or_else(|| vec![0u8; width as usize * height as usize * 4]);
+
+    Frame {
+        width,
+        height,
+        timestamp: frame.timestamp,
+        cursor: frame.cursor,
+        data,
+    }
+}
+
+/// Converts a rgba frame into the given format (bt.601 for yuv)
+fn convert_frame(frame: &Frame, format: PipeFormat, buffer: &mut Vec<u8>) {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let pixels = &frame.data[..width * height * 4];
+
+    buffer.clear();
+    match format {
+        PipeFormat::Rgba => buffer.extend_from_slice(pixels),
+        PipeFormat::Bgra => {
+            buffer.extend(
+                pixels
+                    .chunks_exact(4)
+                    .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]]),
+            );
+        }
+        PipeFormat::I420 => {
+            buffer.resize(format.frame_size(frame.width, frame.height), 0);
+            let (y, uv) = buffer.split_at_mut(width * height);
+            let chroma_width = width.div_ceil(2);
+            let (u, v) = uv.split_at_mut(chroma_width * height.div_ceil(2));
+
+            for (i, pixel) in pixels.chunks_exact(4).enumerate() {
+                let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+                y[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            }
+
+            // chroma is averaged over blocks of 2x2 pixels
+            for (i, (u, v)) in u.iter_mut().zip(v.iter_mut()).enumerate() {
+                let x = (i % chroma_width) * 2;
+                let y = (i / chroma_width) * 2;
+                let (mut r, mut g, mut b, mut count) = (0, 0, 0, 0);
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    if x + dx < width && y + dy < height {
+                        let offset = ((y + dy) * width + x + dx) * 4;
+                        r += pixels[offset] as i32;
+                        g += pixels[offset + 1] as i32;
+                        b += pixels[offset + 2] as i32;
+                        count += 1;
+                    }
+                }
+                let (r, g, b) = (r / count, g / count, b / count);
+                *u = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+                *v = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::mirror_dto::Cursor;
+    use ::std::fs;
+
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    fn frame(width: u32, height: u32, pixel: [u8; 4]) -> Frame {
+        Frame {
+            width,
+            height,
+            timestamp: None,
+            cursor: Cursor::default(),
+            data: pixel.repeat(width as usize * height as usize),
+        }
+    }
+
+    fn output(name: &str, resolution_change: ResolutionChange) -> (PipeOutput, PathBuf) {
+        let path = std::env::temp_dir().join(format!("mirror-{}-{}", name, std::process::id()));
+        let config = PipeOutputConfig {
+            format: PipeFormat::Rgba,
+            frame_rate: 1,
+            resolution_change,
+        };
+        (
+            PipeOutput::new(PipeTarget::Path(path.clone()), config),
+            path,
+        )
+    }
+
+    #[test]
+    fn i420_frame_size() {
+        assert_eq!(PipeFormat::I420.frame_size(4, 2), 8 + 2 * 2);
+        // chroma planes are rounded up for odd dimensions
+        assert_eq!(PipeFormat::I420.frame_size(3, 3), 9 + 4 * 2);
+        assert_eq!(PipeFormat::Rgba.frame_size(3, 3), 36);
+    }
+
+    #[test]
+    fn i420_conversion() {
+        // the last column is red, the others are white
+        let mut frame = frame(3, 3, WHITE);
+        for y in 0..3 {
+            frame.data[(y * 3 + 2) * 4..(y * 3 + 3) * 4].copy_from_slice(&RED);
+        }
+
+        let mut buffer = Vec::new();
+        convert_frame(&frame, PipeFormat::I420, &mut buffer);
+        assert_eq!(buffer.len(), PipeFormat::I420.frame_size(3, 3));
+
+        let (y, uv) = buffer.split_at(9);
+        let (u, v) = uv.split_at(4);
+        assert_eq!(y, [235, 235, 82, 235, 235, 82, 235, 235, 82]);
+        // the blocks of the last column only contain red pixels
+        assert_eq!(u, [128, 90, 128, 90]);
+        assert_eq!(v, [128, 240, 128, 240]);
+    }
+
+    #[test]
+    fn i420_chroma_average() {
+        // a 2x2 block of two white and two black pixels averages to gray
+        let mut frame = frame(2, 2, WHITE);
+        frame.data[4..12].copy_from_slice(&[0, 0, 0, 255, 0, 0, 0, 255]);
+
+        let mut buffer = Vec::new();
+        convert_frame(&frame, PipeFormat::I420, &mut buffer);
+        assert_eq!(buffer, [235, 16, 16, 235, 128, 128]);
+    }
+
+    #[test]
+    fn bgra_conversion() {
+        let mut buffer = Vec::new();
+        convert_frame(&frame(1, 1, [1, 2, 3, 4]), PipeFormat::Bgra, &mut buffer);
+        assert_eq!(buffer, [3, 2, 1, 4]);
+        convert_frame(&frame(1, 1, [1, 2, 3, 4]), PipeFormat::Rgba, &mut buffer);
+        assert_eq!(buffer, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn restart_truncates_the_file() {
+        let (mut output, path) = output("pipe-restart", ResolutionChange::Restart);
+
+        output.push_frame(frame(4, 4, WHITE));
+        assert!(output.write_due().unwrap());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4 * 4 * 4);
+
+        output.push_frame(frame(2, 2, RED));
+        assert!(output.write_due().unwrap());
+        assert_eq!(output.resolution(), Some((2, 2)));
+        output.finish().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), RED.repeat(4));
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn scale_to_the_initial_resolution() {
+        let (mut output, path) = output("pipe-scale", ResolutionChange::Scale);
+
+        output.push_frame(frame(2, 2, WHITE));
+        assert!(output.write_due().unwrap());
+
+        output.push_frame(frame(4, 4, RED));
+        output.next_frame = Some(Instant::now());
+        assert!(!output.write_due().unwrap());
+        output.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 2 * 2 * 4 * 2);
+        assert_eq!(data[..16], WHITE.repeat(4)[..]);
+        for pixel in data[16..].chunks_exact(4) {
+            assert!(pixel[0] >= 254 && pixel[1] <= 1 && pixel[2] <= 1);
+        }
+        assert_eq!(output.frames_written(), 2);
+
+        fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,620 @@
+use ::log::{info, warn};
+use ::parking_lot::Mutex;
+use ::serde::Serialize;
+use ::std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::capture::{Frame, FrameFileWriter, FrameSink, ReplayClip};
+
+// maximum amount of frames waiting to be written, newer frames are dropped when the writer falls behind
+const QUEUE_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordFormat {
+    /// YUV4MPEG2 stream (4:4:4), can be read by most video tools
+    Y4m,
+    /// Raw rgba frames
+    Raw,
+    /// One png file per frame
+    Png,
+    /// One qoi file per frame
+    Qoi,
+    /// Frame file including cursor state that can be replayed with the `FileCapture`
+    Mirror,
+}
+
+impl RecordFormat {
+    pub const ALL: [RecordFormat; 5] = [
+        RecordFormat::Y4m,
+        RecordFormat::Raw,
+        RecordFormat::Png,
+        RecordFormat::Qoi,
+        RecordFormat::Mirror,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|format| format.extension() == name.to_lowercase())
+            .copied()
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RecordFormat::Y4m => "Y4M",
+            RecordFormat::Raw => "Raw",
+            RecordFormat::Png => "PNG sequence",
+            RecordFormat::Qoi => "QOI sequence",
+            RecordFormat::Mirror => "Mirror replay",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Y4m => "y4m",
+            RecordFormat::Raw => "raw",
+            RecordFormat::Png => "png",
+            RecordFormat::Qoi => "qoi",
+            RecordFormat::Mirror => "mirror",
+        }
+    }
+
+    /// Returns true if all frames of a segment are written into a single file
+    fn is_stream(&self) -> bool {
+        matches!(
+            self,
+            RecordFormat::Y4m | RecordFormat::Raw | RecordFormat::Mirror
+        )
+    }
+
+    /// Returns true if all frames of a segment must have the same resolution
+    fn has_fixed_resolution(&self) -> bool {
+        matches!(self, RecordFormat::Y4m | RecordFormat::Raw)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    /// Directory the recordings are written to
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    /// Nominal frame rate stored in the y4m header, the exact timestamps are written to the index
+    pub frame_rate: u32,
+    /// Starts a new segment once the current one exceeds this size in bytes
+    pub max_segment_size: Option<u64>,
+    /// Starts a new segment once the current one exceeds this duration
+    pub max_segment_duration: Option<Duration>,
+}
+
+impl RecorderConfig {
+    pub fn new<P: Into<PathBuf>>(path: P, format: RecordFormat) -> Self {
+        Self {
+            path: path.into(),
+            format,
+            frame_rate: 60,
+            max_segment_size: None,
+            max_segment_duration: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RecorderStats {
+    frames_written: AtomicU64,
+    frames_dropped: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// Queues frames for the writer thread, shared between the `Recorder` and its sinks
+struct RecorderInput {
+    sender: Option<SyncSender<(Duration, Frame)>>,
+    stats: Arc<RecorderStats>,
+
+    start: Instant,
+    first_timestamp: Option<u64>,
+}
+
+impl RecorderInput {
+    fn push_frame(&mut self, frame: Frame) -> bool {
+        if frame.is_empty() {
+            return true;
+        }
+
+        // prefer the timestamps of the guest as they are not affected by the polling of the host
+        let timestamp = match frame.timestamp {
+            Some(timestamp) => {
+                let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+                Duration::from_micros(timestamp.saturating_sub(first_timestamp))
+            }
+            None => self.start.elapsed(),
+        };
+
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return false,
+        };
+        match sender.try_send((timestamp, frame)) {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) => {
+                self.stats.frames_dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Records frames of a `Capture` to disk.
+///
+/// Frames are encoded and written in a separate thread. If the writer can not keep up
+/// frames are dropped instead of blocking the caller.
+/// Each segment is accompanied by a csv index containing the timestamp of every frame.
+pub struct Recorder {
+    input: Arc<Mutex<RecorderInput>>,
+    thread_handle: Option<JoinHandle<io::Result<()>>>,
+    stats: Arc<RecorderStats>,
+}
+
+impl Recorder {
+    pub fn start(config: RecorderConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.path)?;
+
+        let stats = Arc::new(RecorderStats::default());
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+
+        let writer = RecorderWriter::new(config, stats.clone(), "recording");
+        let thread_handle = thread::spawn(move || writer.run(receiver));
+
+        Ok(Self {
+            input: Arc::new(Mutex::new(RecorderInput {
+                sender: Some(sender),
+                stats: stats.clone(),
+
+                start: Instant::now(),
+                first_timestamp: None,
+            })),
+            thread_handle: Some(thread_handle),
+            stats,
+        })
+    }
+
+    /// Writes all frames of an instant replay clip into a new recording.
+    ///
+    /// Returns the number of frames written. This blocks until all frames have been encoded.
+    pub fn save_clip(config: RecorderConfig, clip: &ReplayClip) -> io::Result<u64> {
+        fs::create_dir_all(&config.path)?;
+
+        let stats = Arc::new(RecorderStats::default());
+        let mut writer = RecorderWriter::new(config, stats.clone(), "replay");
+        for (timestamp, frame) in clip.frames() {
+            writer.write_frame(timestamp, &frame)?;
+        }
+        writer.close_segment()?;
+
+        Ok(stats.frames_written.load(Ordering::Relaxed))
+    }
+
+    /// Returns a sink that records every frame of a capture, see `Capture::add_frame_sink`.
+    ///
+    /// The sink is removed from the capture once the recording was stopped.
+    pub fn frame_sink(&self) -> Box<dyn FrameSink> {
+        Box::new(RecorderSink {
+            input: self.input.clone(),
+        })
+    }
+
+    /// Queues a single frame, returns false if the frame had to be dropped
+    pub fn push_frame(&mut self, frame: Frame) -> bool {
+        self.input.lock().push_frame(frame)
+    }
+
+    /// Returns false if the writer stopped due to an error
+    pub fn is_running(&self) -> bool {
+        self.thread_handle
+            .as_ref()
+            .map(|thread_handle| !thread_handle.is_finished())
+            .unwrap_or(false)
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.stats.frames_written.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_dropped(&self) -> u64 {
+        self.stats.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.stats.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Writes all pending frames and closes the recording
+    pub fn stop(mut self) -> io::Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // closing the channel stops the writer once all frames have been written
+        self.input.lock().sender.take();
+        match self.thread_handle.take() {
+            Some(thread_handle) => thread_handle
+                .join()
+                .map_err(|_| io::Error::other("recorder thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warn!("unable to finish recording: {}", err);
+        }
+    }
+}
+
+struct RecorderSink {
+    input: Arc<Mutex<RecorderInput>>,
+}
+
+impl FrameSink for RecorderSink {
+    fn push_frame(&mut self, frame: &Frame, _frame_counter: u32) -> bool {
+        let mut input = self.input.lock();
+        input.push_frame(frame.clone());
+        input.sender.is_some()
+    }
+}
+
+/// Describes the frames of a raw segment as they are written without any header
+#[derive(Serialize)]
+struct RawInfo {
+    width: u32,
+    height: u32,
+    pix_fmt: &'static str,
+    frame_size: usize,
+    frame_rate: u32,
+}
+
+struct RecorderWriter {
+    config: RecorderConfig,
+    stats: Arc<RecorderStats>,
+
+    name: String,
+    segment: Option<Segment>,
+    segment_index: u32,
+}
+
+struct Segment {
+    output: SegmentOutput,
+    index: BufWriter<File>,
+    width: u32,
+    height: u32,
+    start: Duration,
+    size: u64,
+    frames: u64,
+}
+
+enum SegmentOutput {
+    Stream(BufWriter<File>),
+    Images(PathBuf),
+    Replay(FrameFileWriter),
+}
+
+impl RecorderWriter {
+    fn new(config: RecorderConfig, stats: Arc<RecorderStats>, prefix: &str) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self {
+            config,
+            stats,
+
+            name: format!("{}-{}", prefix, now),
+            segment: None,
+            segment_index: 0,
+        }
+    }
+
+    fn run(mut self, receiver: Receiver<(Duration, Frame)>) -> io::Result<()> {
+        info!("recording to {:?}", self.config.path);
+        for (timestamp, frame) in receiver.iter() {
+            self.write_frame(timestamp, &frame)?;
+        }
+        self.close_segment()?;
+        info!("recording stopped");
+        Ok(())
+    }
+
+    fn write_frame(&mut self, timestamp: Duration, frame: &Frame) -> io::Result<()> {
+        if self.should_rotate(timestamp, frame) {
+            self.close_segment()?;
+            self.open_segment(timestamp, frame)?;
+        }
+
+        let format = self.config.format;
+        let segment = self.segment.as_mut().unwrap();
+        let (location, size) = match &mut segment.output {
+            SegmentOutput::Stream(file) => {
+                let data = match format {
+                    RecordFormat::Y4m => y4m_frame(frame),
+                    _ => frame.data[..frame.width as usize * frame.height as usize * 4].to_vec(),
+                };
+                file.write_all(&data)?;
+                (segment.size.to_string(), data.len() as u64)
+            }
+            SegmentOutput::Replay(writer) => {
+                let size = writer.write_frame(timestamp, frame)?;
+                (segment.size.to_string(), size)
+            }
+            SegmentOutput::Images(dir) => {
+                let image_format = match format {
+                    RecordFormat::Qoi => image::ImageFormat::Qoi,
+                    _ => image::ImageFormat::Png,
+                };
+                let mut data = io::Cursor::new(Vec::new());
+                image::write_buffer_with_format(
+                    &mut data,
+                    &frame.data[..frame.width as usize * frame.height as usize * 4],
+                    frame.width,
+                    frame.height,
+                    image::ColorType::Rgba8,
+                    image_format,
+                )
+                .map_err(io::Error::other)?;
+
+                let file_name = format!("{:08}.{}", segment.frames, format.extension());
+                fs::write(dir.join(&file_name), data.get_ref())?;
+                (file_name, data.get_ref().len() as u64)
+            }
+        };
+
+        writeln!(
+            segment.index,
+            "{},{},{},{},{}",
+            segment.frames,
+            timestamp.as_micros(),
+            frame.width,
+            frame.height,
+            location
+        )?;
+
+        segment.size += size;
+        segment.frames += 1;
+        self.stats.frames_written.fetch_add(1, Ordering::Relaxed);
+        self.stats.bytes_written.fetch_add(size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn should_rotate(&self, timestamp: Duration, frame: &Frame) -> bool {
+        let segment = match &self.segment {
+            Some(segment) => segment,
+            None => return true,
+        };
+
+        // some streams can not change their resolution
+        (self.config.format.has_fixed_resolution()
+            && (segment.width != frame.width || segment.height != frame.height))
+            || self
+                .config
+                .max_segment_size
+                .map(|max_size| segment.size >= max_size)
+                .unwrap_or(false)
+            || self
+                .config
+                .max_segment_duration
+                .map(|max_duration| timestamp.saturating_sub(segment.start) >= max_duration)
+                .unwrap_or(false)
+    }
+
+    fn open_segment(&mut self, timestamp: Duration, frame: &Frame) -> io::Result<()> {
+        let stem = format!("{}-{:04}", self.name, self.segment_index);
+        self.segment_index += 1;
+
+        // offsets in the index include the header of the stream
+        let format = self.config.format;
+        let mut header_len = 0;
+        let (output, index_path) = if format == RecordFormat::Mirror {
+            let writer = FrameFileWriter::create(self.config.path.join(format!(
+                "{}.{}",
+                stem,
+                format.extension()
+            )))?;
+            header_len = writer.bytes_written();
+            (
+                SegmentOutput::Replay(writer),
+                self.config.path.join(format!("{}.csv", stem)),
+            )
+        } else if format.is_stream() {
+            let mut file = BufWriter::new(File::create(self.config.path.join(format!(
+                "{}.{}",
+                stem,
+                format.extension()
+            )))?);
+            if format == RecordFormat::Y4m {
+                let header = format!(
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n",
+                    frame.width, frame.height, self.config.frame_rate
+                );
+                file.write_all(header.as_bytes())?;
+                header_len = header.len() as u64;
+            } else {
+                // the resolution of raw frames is stored next to them
+                let info = RawInfo {
+                    width: frame.width,
+                    height: frame.height,
+                    pix_fmt: "rgba",
+                    frame_size: frame.width as usize * frame.height as usize * 4,
+                    frame_rate: self.config.frame_rate,
+                };
+                let info_file = File::create(self.config.path.join(format!("{}.json", stem)))?;
+                serde_json::to_writer_pretty(info_file, &info).map_err(io::Error::other)?;
+            }
+            (
+                SegmentOutput::Stream(file),
+                self.config.path.join(format!("{}.csv", stem)),
+            )
+        } else {
+            let dir = self.config.path.join(&stem);
+            fs::create_dir_all(&dir)?;
+            let index_path = dir.join("index.csv");
+            (SegmentOutput::Images(dir), index_path)
+        };
+
+        let mut index = BufWriter::new(File::create(index_path)?);
+        writeln!(
+            index,
+            "frame,timestamp_us,width,height,{}",
+            if format.is_stream() { "offset" } else { "file" }
+        )?;
+
+        info!("starting recording segment {}", stem);
+        self.segment = Some(Segment {
+            output,
+            index,
+            width: frame.width,
+            height: frame.height,
+            start: timestamp,
+            size: header_len,
+            frames: 0,
+        });
+        Ok(())
+    }
+
+    fn close_segment(&mut self) -> io::Result<()> {
+        if let Some(mut segment) = self.segment.take() {
+            match &mut segment.output {
+                SegmentOutput::Stream(file) => file.flush()?,
+                SegmentOutput::Replay(writer) => writer.flush()?,
+                SegmentOutput::Images(_) => (),
+            }
+            segment.index.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a rgba frame into a y4m frame with full resolution chroma planes (bt.601)
+fn y4m_frame(frame: &Frame) -> Vec<u8> {
+    let pixels = frame.width as usize * frame.height as usize;
+    let header = b"FRAME\n";
+
+    let mut data = vec![0u8; header.len() + pixels * 3];
+    data[..header.len()].copy_from_slice(header);
+    let (y, uv) = data[header.len()..].split_at_mut(pixels);
+    let (u, v) = uv.split_at_mut(pixels);
+
+    for (i, pixel) in frame.data[..pixels * 4].chunks_exact(4).enumerate() {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        y[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        u[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        v[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::mirror_dto::Cursor;
+
+    fn frame(width: u32, height: u32, pixel: [u8; 4]) -> Frame {
+        Frame {
+            width,
+            height,
+            timestamp: None,
+            cursor: Cursor::default(),
+            data: pixel.repeat(width as usize * height as usize),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mirror-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn y4m_conversion() {
+        let mut frame = frame(3, 1, [255, 255, 255, 255]);
+        frame.data[4..8].copy_from_slice(&[0, 0, 0, 255]);
+        frame.data[8..12].copy_from_slice(&[255, 0, 0, 255]);
+
+        let mut expected = b"FRAME\n".to_vec();
+        expected.extend_from_slice(&[235, 16, 82]);
+        expected.extend_from_slice(&[128, 128, 90]);
+        expected.extend_from_slice(&[128, 128, 240]);
+        assert_eq!(y4m_frame(&frame), expected);
+    }
+
+    #[test]
+    fn y4m_segments() {
+        let path = temp_dir("y4m_segments");
+        let config = RecorderConfig::new(&path, RecordFormat::Y4m);
+        let mut writer = RecorderWriter::new(config, Arc::default(), "test");
+        writer
+            .write_frame(Duration::ZERO, &frame(2, 1, [0; 4]))
+            .unwrap();
+        // a new resolution starts a new segment with its own header
+        writer
+            .write_frame(Duration::from_millis(16), &frame(1, 2, [0; 4]))
+            .unwrap();
+        writer.close_segment().unwrap();
+
+        let first = fs::read(path.join(format!("{}-0000.y4m", writer.name))).unwrap();
+        let second = fs::read(path.join(format!("{}-0001.y4m", writer.name))).unwrap();
+        assert!(first.starts_with(b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444\nFRAME\n"));
+        assert!(second.starts_with(b"YUV4MPEG2 W1 H2 F60:1 Ip A1:1 C444\nFRAME\n"));
+        fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn raw_info() {
+        let path = temp_dir("raw_info");
+        let config = RecorderConfig::new(&path, RecordFormat::Raw);
+        let mut writer = RecorderWriter::new(config, Arc::default(), "test");
+        for timestamp in 0..2 {
+            writer
+                .write_frame(Duration::from_millis(timestamp), &frame(4, 2, [1; 4]))
+                .unwrap();
+        }
+        writer.close_segment().unwrap();
+
+        let raw = fs::read(path.join(format!("{}-0000.raw", writer.name))).unwrap();
+        let info: serde_json::Value = serde_json::from_slice(
+            &fs::read(path.join(format!("{}-0000.json", writer.name))).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(raw.len(), 2 * 4 * 2 * 4);
+        assert_eq!(info["width"], 4);
+        assert_eq!(info["height"], 2);
+        assert_eq!(info["pix_fmt"], "rgba");
+        assert_eq!(info["frame_size"], 4 * 2 * 4);
+        fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn frame_sink() {
+        let path = temp_dir("frame_sink");
+        let recorder = Recorder::start(RecorderConfig::new(&path, RecordFormat::Raw)).unwrap();
+        let mut sink = recorder.frame_sink();
+        assert!(sink.push_frame(&frame(2, 2, [0; 4]), 1));
+
+        recorder.stop().unwrap();
+        // the sink is removed from the capture once the recording stopped
+        assert!(!sink.push_frame(&frame(2, 2, [0; 4]), 2));
+        fs::remove_dir_all(&path).ok();
+    }
+}
@@ -0,0 +1,264 @@
+use ::std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ::mirror_dto::Cursor;
+use ::serde::Serialize;
+
+use crate::capture::Frame;
+
+/// Cursor that is drawn on top of frames, this is the same image the ui uses
+const CURSOR_IMAGE: &[u8] = include_bytes!("../resources/cursor.png");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenshotOptions {
+    /// Draws the cursor on top of the frame
+    pub cursor: bool,
+    /// Region of the frame that is saved (x, y, width, height)
+    pub crop: Option<[u32; 4]>,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        Self {
+            cursor: true,
+            crop: None,
+        }
+    }
+}
+
+/// Describes the guest a screenshot was captured from
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GuestInfo {
+    /// All stages of the os chain, e.g. `qemu > win32`
+    pub chain: String,
+    /// Os plugin of the chain
+    pub os: Option<String>,
+    /// Process of the guest agent the frame was read from
+    pub process: Option<String>,
+}
+
+/// A copy of a single frame together with the state it was captured in
+#[derive(Clone, Debug)]
+pub struct Screenshot {
+    pub frame: Frame,
+    pub frame_counter: u32,
+    pub captured_at: SystemTime,
+    /// The guest the frame was captured from
+    pub guest: Option<GuestInfo>,
+}
+
+#[derive(Serialize)]
+struct ScreenshotMetadata<'a> {
+    /// Time the screenshot was taken at in milliseconds since the unix epoch
+    timestamp: u64,
+    /// Time the frame was captured at in microseconds of the guest clock
+    frame_timestamp: Option<u64>,
+    frame_counter: u32,
+    guest: Option<&'a GuestInfo>,
+    width: u32,
+    height: u32,
+    frame_width: u32,
+    frame_height: u32,
+    crop: Option<[u32; 4]>,
+    cursor: Option<CursorMetadata>,
+}
+
+#[derive(Serialize)]
+struct CursorMetadata {
+    x: i32,
+    y: i32,
+    visible: bool,
+}
+
+impl Screenshot {
+    pub fn new(frame: Frame, frame_counter: u32) -> Self {
+        Self {
+            frame,
+            frame_counter,
+            captured_at: SystemTime::now(),
+            guest: None,
+        }
+    }
+
+    pub fn with_guest(mut self, guest: GuestInfo) -> Self {
+        self.guest = Some(guest);
+        self
+    }
+
+    /// Returns the image with the cursor drawn on top and the crop applied
+    pub fn image(&self, options: &ScreenshotOptions) -> image::RgbaImage {
+        let mut image = frame_to_image(&self.frame);
+        if options.cursor {
+            draw_cursor(&mut image, &self.frame.cursor);
+        }
+
+        match clamp_crop(options.crop, image.width(), image.height()) {
+            Some([x, y, width, height]) => {
+                image::imageops::crop_imm(&image, x, y, width, height).to_image()
+            }
+            None => image,
+        }
+    }
+
+    /// Saves the screenshot as png and writes the metadata into a json file next to it.
+    ///
+    /// Returns the path of the metadata file.
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &ScreenshotOptions,
+    ) -> io::Result<PathBuf> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let image = self.image(options);
+        image
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(io::Error::other)?;
+
+        let metadata = ScreenshotMetadata {
+            timestamp: self
+                .captured_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            frame_timestamp: self.frame.timestamp,
+            frame_counter: self.frame_counter,
+            guest: self.guest.as_ref(),
+            width: image.width(),
+            height: image.height(),
+            frame_width: self.frame.width,
+            frame_height: self.frame.height,
+            crop: clamp_crop(options.crop, self.frame.width, self.frame.height),
+            cursor: Some(CursorMetadata {
+                x: self.frame.cursor.x,
+                y: self.frame.cursor.y,
+                visible: self.frame.cursor.is_visible != 0,
+            })
+            .filter(|_| options.cursor),
+        };
+        let metadata_path = path.with_extension("json");
+        fs::write(
+            &metadata_path,
+            serde_json::to_vec_pretty(&metadata).map_err(io::Error::other)?,
+        )?;
+
+        Ok(metadata_path)
+    }
+}
+
+/// Converts a frame into an image, the alpha channel of the capture is not used
+pub(crate) fn frame_to_image(frame: &Frame) -> image::RgbaImage {
+    let len = frame.width as usize * frame.height as usize * 4;
+    let mut image = frame
+        .data
+        .get(..len)
+        .and_then(|data| image::RgbaImage::from_raw(frame.width, frame.height, data.to_vec()))
+        .unwrap_or_else(|| image::RgbaImage::new(frame.width, frame.height));
+
+    // captured frames do not always contain a valid alpha channel
+    for pixel in image.pixels_mut() {
+        pixel[3] = 0xFF;
+    }
+    image
+}
+
+/// Returns the image of the cursor, the image is only decoded once
+pub(crate) fn cursor_image() -> Option<&'static image::RgbaImage> {
+    static CURSOR: OnceLock<Option<image::RgbaImage>> = OnceLock::new();
+
+    CURSOR
+        .get_or_init(|| {
+            image::load_from_memory_with_format(CURSOR_IMAGE, image::ImageFormat::Png)
+                .ok()
+                .map(|image| image.to_rgba8())
+        })
+        .as_ref()
+}
+
+/// Draws the cursor on top of the image if it is visible
+pub(crate) fn draw_cursor(image: &mut image::RgbaImage, cursor: &Cursor) {
+    if cursor.is_visible == 0 {
+        return;
+    }
+    if let Some(cursor_image) = cursor_image() {
+        image::imageops::overlay(image, cursor_image, cursor.x as i64, cursor.y as i64);
+    }
+}
+
+/// Limits the crop region to the bounds of the image, an empty region disables cropping
+fn clamp_crop(crop: Option<[u32; 4]>, width: u32, height: u32) -> Option<[u32; 4]> {
+    let [x, y, crop_width, crop_height] = crop?;
+    let x = x.min(width);
+    let y = y.min(height);
+    let crop_width = crop_width.min(width - x);
+    let crop_height = crop_height.min(height - y);
+    if crop_width == 0 || crop_height == 0 {
+        None
+    } else {
+        Some([x, y, crop_width, crop_height])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_inside_of_the_image() {
+        assert_eq!(clamp_crop(None, 100, 50), None);
+        assert_eq!(
+            clamp_crop(Some([10, 20, 30, 20]), 100, 50),
+            Some([10, 20, 30, 20])
+        );
+    }
+
+    #[test]
+    fn crop_is_clamped() {
+        assert_eq!(
+            clamp_crop(Some([90, 40, 30, 20]), 100, 50),
+            Some([90, 40, 10, 10])
+        );
+        assert_eq!(clamp_crop(Some([200, 0, 30, 20]), 100, 50), None);
+        assert_eq!(clamp_crop(Some([10, 10, 0, 20]), 100, 50), None);
+    }
+
+    #[test]
+    fn metadata() {
+        let frame = Frame {
+            width: 4,
+            height: 2,
+            timestamp: Some(1000),
+            cursor: Cursor::default(),
+            data: vec![0xff; 4 * 2 * 4],
+        };
+        let screenshot = Screenshot::new(frame, 7).with_guest(GuestInfo {
+            chain: "qemu > win32".to_string(),
+            os: Some("win32".to_string()),
+            process: Some("mirror-guest.exe".to_string()),
+        });
+        let options = ScreenshotOptions {
+            cursor: false,
+            crop: Some([1, 0, 2, 2]),
+        };
+
+        let dir = std::env::temp_dir().join(format!("mirror-screenshot-{}", std::process::id()));
+        let metadata_path = screenshot.save(dir.join("frame.png"), &options).unwrap();
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&fs::read(&metadata_path).unwrap()).unwrap();
+        assert_eq!(metadata["frame_counter"], 7);
+        assert_eq!(metadata["width"], 2);
+        assert_eq!(metadata["frame_width"], 4);
+        assert_eq!(metadata["crop"], serde_json::json!([1, 0, 2, 2]));
+        assert_eq!(metadata["guest"]["os"], "win32");
+        assert_eq!(metadata["guest"]["process"], "mirror-guest.exe");
+        assert!(metadata["cursor"].is_null());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,481 @@
+use ::log::{info, warn};
+use ::parking_lot::{Condvar, Mutex};
+use ::serde::Serialize;
+use ::std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::capture::{Frame, FrameSink};
+use crate::screenshot::frame_to_image;
+
+const BOUNDARY: &str = "mirrorframe";
+
+// interval in which idle connections check if the server has been stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+const INDEX_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><title>memflow mirror</title></head>
+<body style=\"margin:0;background:#000\">
+<img src=\"/stream.mjpg\" style=\"width:100%;height:100vh;object-fit:contain\">
+</body>
+</html>
+";
+
+#[derive(Clone, Debug)]
+pub struct PreviewServerConfig {
+    /// Address the server listens on, this should usually be a local address
+    pub address: SocketAddr,
+    /// Quality of the jpeg frames (1-100)
+    pub jpeg_quality: u8,
+    /// Factor the frames are scaled by before they are encoded (1.0 = original resolution)
+    pub scale: f32,
+    /// Maximum number of frames per second that are sent to a single client
+    pub max_frame_rate: u32,
+}
+
+impl Default for PreviewServerConfig {
+    fn default() -> Self {
+        Self {
+            address: ([127, 0, 0, 1], 8080).into(),
+            jpeg_quality: 80,
+            scale: 1.0,
+            max_frame_rate: 30,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LatestFrame {
+    sequence: u64,
+    frame: Option<Arc<Frame>>,
+    // the jpeg is encoded once and shared between all clients
+    jpeg: Option<(u64, Arc<Vec<u8>>)>,
+}
+
+struct ServerState {
+    config: PreviewServerConfig,
+    alive: AtomicBool,
+    start: Instant,
+
+    latest: Mutex<LatestFrame>,
+    frame_available: Condvar,
+
+    clients: AtomicUsize,
+    frames_sent: AtomicU64,
+}
+
+#[derive(Serialize)]
+struct ServerStatus {
+    frames_published: u64,
+    width: u32,
+    height: u32,
+    frame_timestamp: Option<u64>,
+    clients: usize,
+    frames_sent: u64,
+    uptime_secs: u64,
+    jpeg_quality: u8,
+    scale: f32,
+    max_frame_rate: u32,
+}
+
+/// Serves the frames of a `Capture` over http.
+///
+/// Frames are usually published from the capture thread with `frame_sink`.
+/// The following endpoints are available:
+/// - `/` a page showing the stream
+/// - `/stream.mjpg` a mjpeg stream of the frames
+/// - `/snapshot.jpg` and `/snapshot.png` the current frame
+/// - `/status.json` information about the current frame and the server
+pub struct PreviewServer {
+    state: Arc<ServerState>,
+    address: SocketAddr,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl PreviewServer {
+    pub fn start(config: PreviewServerConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(config.address)?;
+        let address = listener.local_addr()?;
+        // accepting in non-blocking mode allows the server thread to be stopped
+        listener.set_nonblocking(true)?;
+
+        let state = Arc::new(ServerState {
+            config,
+            alive: AtomicBool::new(true),
+            start: Instant::now(),
+
+            latest: Mutex::new(LatestFrame::default()),
+            frame_available: Condvar::new(),
+
+            clients: AtomicUsize::new(0),
+            frames_sent: AtomicU64::new(0),
+        });
+
+        let thread_state = state.clone();
+        let thread_handle = thread::spawn(move || accept_loop(listener, thread_state));
+        info!("preview server listening on http://{}", address);
+
+        Ok(Self {
+            state,
+            address,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Returns the address the server is listening on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the number of connected clients
+    pub fn clients(&self) -> usize {
+        self.state.clients.load(Ordering::Relaxed)
+    }
+
+    /// Returns a sink that publishes the frames of a capture, see `Capture::add_frame_sink`.
+    ///
+    /// The sink is removed from the capture once the server was stopped.
+    pub fn frame_sink(&self) -> Box<dyn FrameSink> {
+        Box::new(PreviewSink {
+            state: self.state.clone(),
+            last_publish: None,
+        })
+    }
+
+    /// Publishes a single frame
+    pub fn publish_frame(&mut self, frame: Frame) {
+        self.state.publish(frame);
+    }
+}
+
+impl ServerState {
+    fn publish(&self, frame: Frame) {
+        if frame.is_empty() {
+            return;
+        }
+
+        let mut latest = self.latest.lock();
+        latest.sequence += 1;
+        latest.frame = Some(Arc::new(frame));
+        self.frame_available.notify_all();
+    }
+}
+
+struct PreviewSink {
+    state: Arc<ServerState>,
+    last_publish: Option<Instant>,
+}
+
+impl FrameSink for PreviewSink {
+    fn push_frame(&mut self, frame: &Frame, _frame_counter: u32) -> bool {
+        if !self.state.alive.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        // frames are not copied more often than they can be streamed
+        let min_interval = Duration::from_secs(1) / self.state.config.max_frame_rate.max(1);
+        if self
+            .last_publish
+            .map(|last_publish| last_publish.elapsed() < min_interval)
+            .unwrap_or(false)
+        {
+            return true;
+        }
+        self.last_publish = Some(Instant::now());
+
+        self.state.publish(frame.clone());
+        true
+    }
+}
+
+impl Drop for PreviewServer {
+    fn drop(&mut self) {
+        self.state.alive.store(false, Ordering::SeqCst);
+        self.state.frame_available.notify_all();
+        if let Some(thread_handle) = self.thread_handle.take() {
+            if thread_handle.join().is_err() {
+                warn!("preview server thread panicked");
+            }
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, state: Arc<ServerState>) {
+    while state.alive.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let state = state.clone();
+                thread::spawn(move || {
+                    state.clients.fetch_add(1, Ordering::Relaxed);
+                    if let Err(err) = handle_connection(stream, &state) {
+                        // disconnecting clients are expected
+                        if err.kind() != io::ErrorKind::BrokenPipe
+                            && err.kind() != io::ErrorKind::ConnectionReset
+                        {
+                            warn!("preview server connection failed: {}", err);
+                        }
+                    }
+                    state.clients.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) => {
+                warn!("preview server stopped: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &ServerState) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // only the request line is relevant, the headers are skipped
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts
+        .next()
+        .and_then(|target| target.split('?').next())
+        .unwrap_or("/");
+
+    let mut stream = stream;
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    match path {
+        "/" => respond(&mut stream, "200 OK", "text/html", INDEX_PAGE.as_bytes()),
+        "/stream.mjpg" => stream_mjpeg(&mut stream, state),
+        "/snapshot.jpg" => match latest_jpeg(state) {
+            Some(jpeg) => respond(&mut stream, "200 OK", "image/jpeg", &jpeg),
+            None => respond(&mut stream, "503 Service Unavailable", "text/plain", b""),
+        },
+        "/snapshot.png" => match latest_png(state)? {
+            Some(png) => respond(&mut stream, "200 OK", "image/png", &png),
+            None => respond(&mut stream, "503 Service Unavailable", "text/plain", b""),
+        },
+        "/status.json" => {
+            let status = status(state);
+            let body = serde_json::to_vec(&status).map_err(io::Error::other)?;
+            respond(&mut stream, "200 OK", "application/json", &body)
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b""),
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn stream_mjpeg(stream: &mut TcpStream, state: &ServerState) -> io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        BOUNDARY
+    );
+    stream.write_all(header.as_bytes())?;
+
+    let min_interval = Duration::from_secs(1) / state.config.max_frame_rate.max(1);
+    let mut sequence = 0;
+    let mut last_frame: Option<Instant> = None;
+    while state.alive.load(Ordering::SeqCst) {
+        // wait for a new frame
+        {
+            let mut latest = state.latest.lock();
+            if latest.sequence == sequence {
+                state.frame_available.wait_for(&mut latest, POLL_INTERVAL);
+                continue;
+            }
+            sequence = latest.sequence;
+        }
+
+        // limit the frame rate of the stream
+        if let Some(elapsed) = last_frame.map(|last_frame| last_frame.elapsed()) {
+            if elapsed < min_interval {
+                thread::sleep(min_interval - elapsed);
+            }
+        }
+        last_frame = Some(Instant::now());
+
+        if let Some(jpeg) = latest_jpeg(state) {
+            let part = format!(
+                "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                BOUNDARY,
+                jpeg.len()
+            );
+            stream.write_all(part.as_bytes())?;
+            stream.write_all(&jpeg)?;
+            stream.write_all(b"\r\n")?;
+            stream.flush()?;
+            state.frames_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the latest frame encoded as jpeg, the frame is only encoded once
+fn latest_jpeg(state: &ServerState) -> Option<Arc<Vec<u8>>> {
+    let (sequence, frame) = {
+        let latest = state.latest.lock();
+        match &latest.jpeg {
+            Some((jpeg_sequence, jpeg)) if *jpeg_sequence == latest.sequence => {
+                return Some(jpeg.clone())
+            }
+            _ => (latest.sequence, latest.frame.clone()?),
+        }
+    };
+
+    // encode without holding the lock so new frames can still be published
+    let image = scaled_image(&frame, state.config.scale);
+    let image = image::DynamicImage::ImageRgba8(image).into_rgb8();
+    let mut jpeg = Vec::new();
+    if let Err(err) =
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, state.config.jpeg_quality)
+            .encode(
+                &image,
+                image.width(),
+                image.height(),
+                image::ExtendedColorType::Rgb8,
+            )
+    {
+        warn!("unable to encode jpeg: {}", err);
+        return None;
+    }
+
+    let jpeg = Arc::new(jpeg);
+    let mut latest = state.latest.lock();
+    if latest.sequence == sequence {
+        latest.jpeg = Some((sequence, jpeg.clone()));
+    }
+    Some(jpeg)
+}
+
+fn latest_png(state: &ServerState) -> io::Result<Option<Vec<u8>>> {
+    let frame = match state.latest.lock().frame.clone() {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    let image = scaled_image(&frame, state.config.scale);
+    let mut png = io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    Ok(Some(png.into_inner()))
+}
+
+fn scaled_image(frame: &Frame, scale: f32) -> image::RgbaImage {
+    let image = frame_to_image(frame);
+    let scale = scale.clamp(0.01, 1.0);
+    if scale < 1.0 {
+        image::imageops::resize(
+            &image,
+            ((image.width() as f32 * scale) as u32).max(1),
+            ((image.height() as f32 * scale) as u32).max(1),
+            image::imageops::FilterType::Triangle,
+        )
+    } else {
+        image
+    }
+}
+
+fn status(state: &ServerState) -> ServerStatus {
+    let latest = state.latest.lock();
+    let frame = latest.frame.as_ref();
+    ServerStatus {
+        frames_published: latest.sequence,
+        width: frame.map(|frame| frame.width).unwrap_or_default(),
+        height: frame.map(|frame| frame.height).unwrap_or_default(),
+        frame_timestamp: frame.and_then(|frame| frame.timestamp),
+        clients: state.clients.load(Ordering::Relaxed),
+        frames_sent: state.frames_sent.load(Ordering::Relaxed),
+        uptime_secs: state.start.elapsed().as_secs(),
+        jpeg_quality: state.config.jpeg_quality,
+        scale: state.config.scale,
+        max_frame_rate: state.config.max_frame_rate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::mirror_dto::Cursor;
+    use ::std::io::Read;
+
+    fn frame(width: u32, height: u32) -> Frame {
+        Frame {
+            width,
+            height,
+            timestamp: None,
+            cursor: Cursor::default(),
+            data: vec![0x80; width as usize * height as usize * 4],
+        }
+    }
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn frame_sink() {
+        let server = PreviewServer::start(PreviewServerConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            max_frame_rate: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let address = server.address();
+
+        let mut sink = server.frame_sink();
+        assert!(sink.push_frame(&frame(4, 2), 1));
+        // frames above the frame rate are skipped
+        assert!(sink.push_frame(&frame(8, 4), 2));
+
+        let response = get(address, "/status.json");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let status: serde_json::Value =
+            serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(status["frames_published"], 1);
+        assert_eq!(status["width"], 4);
+        assert_eq!(status["height"], 2);
+
+        // the sink is removed once the server was stopped
+        drop(server);
+        assert!(!sink.push_frame(&frame(4, 2), 3));
+    }
+}
@@ -0,0 +1,716 @@
+use ::log::{info, warn};
+use ::mirror_dto::Cursor;
+use ::parking_lot::Mutex;
+use ::std::{
+    ffi::CString,
+    io, mem, ptr,
+    sync::{
+        atomic::{fence, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::capture::{Frame, FrameSink};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"MIRR");
+const VERSION: u32 = 1;
+
+// the frame data of each slot starts at a multiple of this
+const ALIGNMENT: usize = 64;
+
+// readers give up if the latest slot is overwritten this often while it is copied
+const MAX_READ_ATTEMPTS: usize = 64;
+
+// interval in which readers check for new frames on systems without futex support
+#[cfg(not(target_os = "linux"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Header at the start of the shared memory
+#[repr(C)]
+struct ShmHeader {
+    magic: u32,
+    version: u32,
+    slot_count: u32,
+    _reserved: u32,
+    /// Maximum size of the frame data in a slot
+    slot_data_size: u64,
+    /// Incremented for every published frame, readers wait on this value
+    sequence: AtomicU32,
+    /// Index of the slot containing the latest frame
+    latest_slot: AtomicU32,
+}
+
+/// Header in front of the frame data of every slot
+#[repr(C)]
+struct ShmSlotHeader {
+    /// Odd while the slot is being written
+    sequence: AtomicU64,
+    frame_counter: u32,
+    width: u32,
+    height: u32,
+    _reserved: u32,
+    /// Time the frame was captured at in microseconds of the guest clock, `u64::MAX` if unknown
+    timestamp: u64,
+    cursor: Cursor,
+}
+
+const HEADER_SIZE: usize = align(mem::size_of::<ShmHeader>());
+const SLOT_HEADER_SIZE: usize = align(mem::size_of::<ShmSlotHeader>());
+
+const fn align(size: usize) -> usize {
+    size.next_multiple_of(ALIGNMENT)
+}
+
+#[derive(Clone, Debug)]
+pub struct ShmPublisherConfig {
+    /// Name of the shared memory object, e.g. `/memflow-mirror`
+    pub name: String,
+    /// Number of frames kept in the ring, readers must copy a frame before it is overwritten
+    pub slots: u32,
+    /// Largest resolution that can be published, larger frames are skipped
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl Default for ShmPublisherConfig {
+    fn default() -> Self {
+        Self {
+            name: "/memflow-mirror".to_string(),
+            slots: 3,
+            max_width: 3840,
+            max_height: 2160,
+        }
+    }
+}
+
+/// A shared memory object mapped into this process
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+    /// Device and inode of the object, used to tell it apart from objects created later with the same name
+    id: (libc::dev_t, libc::ino_t),
+}
+
+// the mapping is only accessed through atomics and the seqlock of the slots
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    /// Creates a new object with the given size or opens an existing one read only.
+    ///
+    /// Creating fails if an object with the name already exists, e.g. because another instance publishes in it.
+    fn open(name: &str, len: Option<usize>) -> io::Result<Self> {
+        let c_name = CString::new(name).map_err(io::Error::other)?;
+        let writable = len.is_some();
+
+        unsafe {
+            let fd = if writable {
+                libc::shm_open(
+                    c_name.as_ptr(),
+                    libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                    0o644,
+                )
+            } else {
+                libc::shm_open(c_name.as_ptr(), libc::O_RDONLY, 0)
+            };
+            if fd < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::AlreadyExists {
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!("shared memory {} is already in use", name),
+                    ));
+                }
+                return Err(err);
+            }
+
+            let mut stat: libc::stat = mem::zeroed();
+            if libc::fstat(fd, &mut stat) != 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                if writable {
+                    libc::shm_unlink(c_name.as_ptr());
+                }
+                return Err(err);
+            }
+            let id = (stat.st_dev, stat.st_ino);
+
+            let len = match len {
+                Some(len) => {
+                    // the object was created above, so it can be removed again
+                    if libc::ftruncate(fd, len as libc::off_t) != 0 {
+                        let err = io::Error::last_os_error();
+                        libc::close(fd);
+                        libc::shm_unlink(c_name.as_ptr());
+                        return Err(err);
+                    }
+                    len
+                }
+                None => stat.st_size as usize,
+            };
+
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                if writable {
+                    libc::PROT_READ | libc::PROT_WRITE
+                } else {
+                    libc::PROT_READ
+                },
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            // the mapping stays valid after the descriptor is closed
+            libc::close(fd);
+            if ptr == libc::MAP_FAILED {
+                let err = io::Error::last_os_error();
+                if writable {
+                    libc::shm_unlink(c_name.as_ptr());
+                }
+                return Err(err);
+            }
+
+            Ok(Self {
+                ptr: ptr as *mut u8,
+                len,
+                id,
+            })
+        }
+    }
+
+    /// Removes the name of the object, unless it now refers to a different object
+    fn unlink(&self, name: &str) {
+        let c_name = match CString::new(name) {
+            Ok(c_name) => c_name,
+            Err(_) => return,
+        };
+
+        unsafe {
+            let fd = libc::shm_open(c_name.as_ptr(), libc::O_RDONLY, 0);
+            if fd < 0 {
+                // already removed
+                return;
+            }
+            let mut stat: libc::stat = mem::zeroed();
+            let result = libc::fstat(fd, &mut stat);
+            libc::close(fd);
+
+            if result != 0 || (stat.st_dev, stat.st_ino) != self.id {
+                warn!(
+                    "shared memory {} was replaced by another process, it is not removed",
+                    name
+                );
+                return;
+            }
+            libc::shm_unlink(c_name.as_ptr());
+        }
+    }
+
+    fn header(&self) -> &ShmHeader {
+        unsafe { &*(self.ptr as *const ShmHeader) }
+    }
+
+    fn slot_size(&self) -> usize {
+        SLOT_HEADER_SIZE + align(self.header().slot_data_size as usize)
+    }
+
+    fn slot(&self, index: u32) -> *mut ShmSlotHeader {
+        unsafe {
+            self.ptr
+                .add(HEADER_SIZE + index as usize * self.slot_size()) as *mut _
+        }
+    }
+
+    fn slot_data(&self, index: u32) -> *mut u8 {
+        unsafe { (self.slot(index) as *mut u8).add(SLOT_HEADER_SIZE) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut _, self.len);
+        }
+    }
+}
+
+/// Publishes the frames of a `Capture` in POSIX shared memory.
+///
+/// The frames are written into a ring of slots which are protected by a seqlock,
+/// so any number of host processes can read them with a `ShmReader` without blocking the publisher.
+pub struct ShmPublisher {
+    config: ShmPublisherConfig,
+    writer: Arc<Mutex<ShmWriter>>,
+}
+
+/// The mapping shared between the publisher and its frame sinks
+struct ShmWriter {
+    /// Unmapped once the publisher was dropped
+    mapping: Option<Mapping>,
+    frames_published: u64,
+    frames_skipped: u64,
+}
+
+impl ShmPublisher {
+    pub fn create(config: ShmPublisherConfig) -> io::Result<Self> {
+        if config.slots == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one slot is required",
+            ));
+        }
+
+        let slot_data_size = config.max_width as usize * config.max_height as usize * 4;
+        let len = HEADER_SIZE + config.slots as usize * (SLOT_HEADER_SIZE + align(slot_data_size));
+        let mapping = Mapping::open(&config.name, Some(len))?;
+
+        unsafe {
+            // the memory is zeroed by ftruncate, the magic is written last so readers never see a partial header
+            let header = mapping.ptr as *mut ShmHeader;
+            (*header).version = VERSION;
+            (*header).slot_count = config.slots;
+            (*header).slot_data_size = slot_data_size as u64;
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*header).magic, MAGIC);
+        }
+
+        info!(
+            "publishing frames in shared memory {} ({} slots, {} bytes)",
+            config.name, config.slots, len
+        );
+        Ok(Self {
+            config,
+            writer: Arc::new(Mutex::new(ShmWriter {
+                mapping: Some(mapping),
+                frames_published: 0,
+                frames_skipped: 0,
+            })),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn frames_published(&self) -> u64 {
+        self.writer.lock().frames_published
+    }
+
+    /// Returns the amount of frames that were too large for the slots
+    pub fn frames_skipped(&self) -> u64 {
+        self.writer.lock().frames_skipped
+    }
+
+    /// Returns a sink that publishes the frames of a capture, see `Capture::add_frame_sink`.
+    ///
+    /// The sink is removed from the capture once the publisher was dropped.
+    pub fn frame_sink(&self) -> Box<dyn FrameSink> {
+        Box::new(ShmSink {
+            writer: self.writer.clone(),
+        })
+    }
+
+    /// Publishes a single frame
+    pub fn publish_frame(&self, frame: &Frame, frame_counter: u32) -> bool {
+        self.writer.lock().publish_frame(frame, frame_counter)
+    }
+}
+
+impl ShmWriter {
+    fn publish_frame(&mut self, frame: &Frame, frame_counter: u32) -> bool {
+        let mapping = match &self.mapping {
+            Some(mapping) => mapping,
+            None => return false,
+        };
+        if frame.is_empty() {
+            return false;
+        }
+
+        let len = frame.width as usize * frame.height as usize * 4;
+        let header = mapping.header();
+        if len as u64 > header.slot_data_size {
+            if self.frames_skipped == 0 {
+                warn!(
+                    "frame with {}x{} is too large for the shared memory",
+                    frame.width, frame.height
+                );
+            }
+            self.frames_skipped += 1;
+            return false;
+        }
+
+        let index = (header.latest_slot.load(Ordering::Relaxed) + 1) % header.slot_count;
+        unsafe {
+            let slot = mapping.slot(index);
+            let sequence = (*slot).sequence.load(Ordering::Relaxed);
+            (*slot).sequence.store(sequence + 1, Ordering::Relaxed);
+            fence(Ordering::Release);
+
+            ptr::write_volatile(&mut (*slot).frame_counter, frame_counter);
+            ptr::write_volatile(&mut (*slot).width, frame.width);
+            ptr::write_volatile(&mut (*slot).height, frame.height);
+            ptr::write_volatile(&mut (*slot).timestamp, frame.timestamp.unwrap_or(u64::MAX));
+            ptr::write_volatile(&mut (*slot).cursor, frame.cursor);
+            ptr::copy_nonoverlapping(frame.data.as_ptr(), mapping.slot_data(index), len);
+
+            (*slot).sequence.store(sequence + 2, Ordering::Release);
+        }
+
+        header.latest_slot.store(index, Ordering::Release);
+        header.sequence.fetch_add(1, Ordering::AcqRel);
+        futex_wake(&header.sequence);
+
+        self.frames_published += 1;
+        true
+    }
+}
+
+struct ShmSink {
+    writer: Arc<Mutex<ShmWriter>>,
+}
+
+impl FrameSink for ShmSink {
+    fn push_frame(&mut self, frame: &Frame, frame_counter: u32) -> bool {
+        let mut writer = self.writer.lock();
+        if writer.mapping.is_none() {
+            return false;
+        }
+        writer.publish_frame(frame, frame_counter);
+        true
+    }
+}
+
+impl Drop for ShmPublisher {
+    fn drop(&mut self) {
+        // readers keep their mapping, the name is removed so new readers can not attach anymore
+        if let Some(mapping) = self.writer.lock().mapping.take() {
+            mapping.unlink(&self.config.name);
+        }
+    }
+}
+
+/// Reads frames published by a `ShmPublisher` in another process
+pub struct ShmReader {
+    mapping: Mapping,
+    sequence: Option<u32>,
+}
+
+impl ShmReader {
+    pub fn open(name: &str) -> io::Result<Self> {
+        let mapping = Mapping::open(name, None)?;
+        if mapping.len < HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory is too small",
+            ));
+        }
+
+        let header = mapping.header();
+        let magic = unsafe { ptr::read_volatile(&header.magic) };
+        fence(Ordering::Acquire);
+        if magic != MAGIC || header.version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory does not contain mirror frames",
+            ));
+        }
+        if mapping.len < HEADER_SIZE + header.slot_count as usize * mapping.slot_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "shared memory is too small",
+            ));
+        }
+
+        Ok(Self {
+            mapping,
+            sequence: None,
+        })
+    }
+
+    /// Returns the number of frames published so far
+    pub fn sequence(&self) -> u32 {
+        self.mapping.header().sequence.load(Ordering::Acquire)
+    }
+
+    /// Waits until a frame is published that has not been read yet, returns false on timeout
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            let sequence = self.sequence();
+            if self.sequence != Some(sequence) {
+                return true;
+            }
+
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return false,
+            };
+            futex_wait(&self.mapping.header().sequence, sequence, remaining);
+        }
+    }
+
+    /// Returns the latest frame together with its frame counter if it has not been read yet
+    pub fn read(&mut self) -> Option<(Frame, u32)> {
+        let sequence = self.sequence();
+        if self.sequence == Some(sequence) {
+            return None;
+        }
+
+        let frame = self.read_latest()?;
+        self.sequence = Some(sequence);
+        Some(frame)
+    }
+
+    /// Waits for and returns the next frame
+    pub fn next_frame(&mut self, timeout: Duration) -> Option<(Frame, u32)> {
+        if self.wait(timeout) {
+            self.read()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the latest frame together with its frame counter
+    pub fn read_latest(&self) -> Option<(Frame, u32)> {
+        let header = self.mapping.header();
+        if header.sequence.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+
+        // the slot is read again if it was overwritten in the meantime
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let index = header.latest_slot.load(Ordering::Acquire);
+            if index >= header.slot_count {
+                return None;
+            }
+
+            unsafe {
+                let slot = self.mapping.slot(index);
+                let sequence = (*slot).sequence.load(Ordering::Acquire);
+                if sequence % 2 == 1 {
+                    std::hint::spin_loop();
+                    continue;
+                }
+
+                let frame_counter = ptr::read_volatile(&(*slot).frame_counter);
+                let width = ptr::read_volatile(&(*slot).width);
+                let height = ptr::read_volatile(&(*slot).height);
+                let timestamp = ptr::read_volatile(&(*slot).timestamp);
+                let cursor = ptr::read_volatile(&(*slot).cursor);
+
+                let len = width as usize * height as usize * 4;
+                if len as u64 > header.slot_data_size {
+                    continue;
+                }
+                let mut data = vec![0u8; len];
+                ptr::copy_nonoverlapping(self.mapping.slot_data(index), data.as_mut_ptr(), len);
+
+                fence(Ordering::Acquire);
+                if (*slot).sequence.load(Ordering::Relaxed) != sequence {
+                    continue;
+                }
+
+                return Some((
+                    Frame {
+                        width,
+                        height,
+                        timestamp: Some(timestamp).filter(|&timestamp| timestamp != u64::MAX),
+                        cursor,
+                        data,
+                    },
+                    frame_counter,
+                ));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wait(value: &AtomicU32, expected: u32, timeout: Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    };
+    // the futex is shared between processes so the private flag can not be used
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            value.as_ptr(),
+            libc::FUTEX_WAIT,
+            expected,
+            &timeout as *const libc::timespec,
+            ptr::null::<u32>(),
+            0u32,
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wake(value: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            value.as_ptr(),
+            libc::FUTEX_WAKE,
+            i32::MAX,
+            ptr::null::<libc::timespec>(),
+            ptr::null::<u32>(),
+            0u32,
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wait(value: &AtomicU32, expected: u32, timeout: Duration) {
+    if value.load(Ordering::Acquire) == expected {
+        std::thread::sleep(timeout.min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wake(_value: &AtomicU32) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::thread;
+
+    fn config(name: &str, slots: u32) -> ShmPublisherConfig {
+        ShmPublisherConfig {
+            name: format!("/mirror-test-{}-{}", name, std::process::id()),
+            slots,
+            max_width: 16,
+            max_height: 16,
+        }
+    }
+
+    fn frame(width: u32, height: u32, value: u8) -> Frame {
+        Frame {
+            width,
+            height,
+            timestamp: Some(value as u64),
+            cursor: Cursor::default(),
+            data: vec![value; width as usize * height as usize * 4],
+        }
+    }
+
+    #[test]
+    fn publish_and_read() {
+        let publisher = ShmPublisher::create(config("read", 2)).unwrap();
+        let mut reader = ShmReader::open(publisher.name()).unwrap();
+        assert!(reader.read().is_none());
+        assert!(!reader.wait(Duration::from_millis(1)));
+
+        assert!(publisher.publish_frame(&frame(2, 2, 1), 10));
+        let (read, frame_counter) = reader.read().unwrap();
+        assert_eq!(frame_counter, 10);
+        assert_eq!((read.width, read.height, read.timestamp), (2, 2, Some(1)));
+        assert_eq!(read.data, vec![1; 16]);
+        // every frame is only returned once
+        assert!(reader.read().is_none());
+
+        // the ring wraps around and the latest frame is returned
+        for value in 2..=5 {
+            publisher.publish_frame(&frame(3, 1, value), value as u32);
+        }
+        let (read, frame_counter) = reader.next_frame(Duration::from_secs(1)).unwrap();
+        assert_eq!((frame_counter, read.width), (5, 3));
+        assert_eq!(read.data, vec![5; 12]);
+        assert_eq!(publisher.frames_published(), 5);
+    }
+
+    #[test]
+    fn skip_large_frames() {
+        let publisher = ShmPublisher::create(config("large", 1)).unwrap();
+        assert!(!publisher.publish_frame(&frame(32, 32, 1), 1));
+        assert!(!publisher.publish_frame(&frame(0, 0, 1), 2));
+        assert_eq!(publisher.frames_skipped(), 1);
+        assert_eq!(publisher.frames_published(), 0);
+    }
+
+    #[test]
+    fn slot_being_written_is_not_read() {
+        let publisher = ShmPublisher::create(config("seqlock", 1)).unwrap();
+        let reader = ShmReader::open(publisher.name()).unwrap();
+        publisher.publish_frame(&frame(2, 2, 1), 1);
+
+        // an odd sequence marks a slot the publisher is writing to
+        let slot = reader.mapping.slot(0);
+        let sequence = unsafe { &(*slot).sequence };
+        let even = sequence.load(Ordering::Acquire);
+        assert_eq!(even % 2, 0);
+
+        let writer = publisher.writer.lock();
+        let slot = writer.mapping.as_ref().unwrap().slot(0);
+        unsafe { (*slot).sequence.store(even + 1, Ordering::Release) };
+        assert!(reader.read_latest().is_none());
+
+        unsafe { (*slot).sequence.store(even + 2, Ordering::Release) };
+        assert_eq!(reader.read_latest().unwrap().1, 1);
+    }
+
+    #[test]
+    fn frames_are_not_torn() {
+        // a single slot is overwritten by every frame, so readers race with the publisher
+        let publisher = ShmPublisher::create(config("torn", 1)).unwrap();
+        let reader = ShmReader::open(publisher.name()).unwrap();
+        publisher.publish_frame(&frame(16, 16, 0), 0);
+
+        let handle = thread::spawn(move || {
+            for value in 1..=2000u32 {
+                publisher.publish_frame(&frame(16, 16, value as u8), value);
+            }
+        });
+        while !handle.is_finished() {
+            if let Some((frame, frame_counter)) = reader.read_latest() {
+                let value = frame_counter as u8;
+                assert!(frame.data.iter().all(|&byte| byte == value));
+                assert_eq!(frame.timestamp, Some(value as u64));
+            }
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn names_are_exclusive() {
+        let config = config("exclusive", 1);
+        let publisher = ShmPublisher::create(config.clone()).unwrap();
+        let err = ShmPublisher::create(config.clone()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        drop(publisher);
+        assert!(ShmReader::open(&config.name).is_err());
+        drop(ShmPublisher::create(config).unwrap());
+    }
+
+    #[test]
+    fn only_remove_own_object() {
+        let config = config("replaced", 1);
+        let publisher = ShmPublisher::create(config.clone()).unwrap();
+
+        // another process removes the object and publishes under the same name
+        let name = CString::new(config.name.as_str()).unwrap();
+        unsafe { libc::shm_unlink(name.as_ptr()) };
+        let replacement = ShmPublisher::create(config.clone()).unwrap();
+
+        drop(publisher);
+        assert!(ShmReader::open(&config.name).is_ok());
+        drop(replacement);
+        assert!(ShmReader::open(&config.name).is_err());
+    }
+
+    #[test]
+    fn sink_is_removed_with_the_publisher() {
+        let publisher = ShmPublisher::create(config("sink", 1)).unwrap();
+        let mut reader = ShmReader::open(publisher.name()).unwrap();
+        let mut sink = publisher.frame_sink();
+
+        assert!(sink.push_frame(&frame(2, 2, 7), 7));
+        assert_eq!(reader.read().unwrap().1, 7);
+
+        drop(publisher);
+        assert!(!sink.push_frame(&frame(2, 2, 8), 8));
+    }
+}
@@ -0,0 +1,1052 @@
+use ::flate2::{Compress, Compression, FlushCompress};
+use ::log::{info, warn};
+use ::mirror_dto::Cursor;
+use ::parking_lot::Mutex;
+use ::std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    convert::TryInto,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::capture::{Frame, FrameSink};
+use crate::screenshot::cursor_image;
+
+const ENCODING_RAW: i32 = 0;
+const ENCODING_COPY_RECT: i32 = 1;
+const ENCODING_ZLIB: i32 = 6;
+const ENCODING_TIGHT: i32 = 7;
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+const ENCODING_POINTER_POS: i32 = -232;
+const ENCODING_CURSOR: i32 = -239;
+
+// frames are compared and sent in tiles of this size
+const TILE_SIZE: u32 = 64;
+
+// interval in which connections check for new frames and update requests
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// interval in which the listener checks if the server has been stopped
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(250);
+
+// upper bound for the clipboard text sent by clients, larger messages are treated as an error
+const MAX_CUT_TEXT_LEN: u32 = 1024 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct VncServerConfig {
+    /// Address the server listens on, this should usually be a local address
+    pub address: SocketAddr,
+    /// Name of the desktop shown by the clients
+    pub name: String,
+}
+
+impl Default for VncServerConfig {
+    fn default() -> Self {
+        Self {
+            address: ([127, 0, 0, 1], 5900).into(),
+            name: "memflow mirror".to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LatestFrame {
+    sequence: u64,
+    frame: Option<Arc<Frame>>,
+}
+
+struct ServerState {
+    config: VncServerConfig,
+    alive: AtomicBool,
+    latest: Mutex<LatestFrame>,
+    clients: AtomicUsize,
+}
+
+/// Serves the frames of a `Capture` to vnc clients (rfb 3.3 - 3.8).
+///
+/// Frames are usually published from the capture thread with `frame_sink`.
+///
+/// Supported encodings are raw, copy-rect, zlib and tight (basic compression),
+/// as well as the desktop-size, cursor and pointer-position pseudo encodings.
+/// Clients can only view the guest, input events are ignored.
+pub struct VncServer {
+    state: Arc<ServerState>,
+    address: SocketAddr,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl VncServer {
+    pub fn start(config: VncServerConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(config.address)?;
+        let address = listener.local_addr()?;
+        // accepting in non-blocking mode allows the server thread to be stopped
+        listener.set_nonblocking(true)?;
+
+        let state = Arc::new(ServerState {
+            config,
+            alive: AtomicBool::new(true),
+            latest: Mutex::new(LatestFrame::default()),
+            clients: AtomicUsize::new(0),
+        });
+
+        let thread_state = state.clone();
+        let thread_handle = thread::spawn(move || accept_loop(listener, thread_state));
+        info!("vnc server listening on {}", address);
+
+        Ok(Self {
+            state,
+            address,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Returns the address the server is listening on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the number of connected clients
+    pub fn clients(&self) -> usize {
+        self.state.clients.load(Ordering::Relaxed)
+    }
+
+    /// Returns a sink that publishes the frames of a capture, see `Capture::add_frame_sink`.
+    ///
+    /// The sink is removed from the capture once the server was stopped.
+    pub fn frame_sink(&self) -> Box<dyn FrameSink> {
+        Box::new(VncSink {
+            state: self.state.clone(),
+        })
+    }
+
+    /// Publishes a single frame
+    pub fn publish_frame(&mut self, frame: Frame) {
+        self.state.publish(frame);
+    }
+}
+
+impl ServerState {
+    fn publish(&self, frame: Frame) {
+        if frame.is_empty() {
+            return;
+        }
+
+        let mut latest = self.latest.lock();
+        latest.sequence += 1;
+        latest.frame = Some(Arc::new(frame));
+    }
+}
+
+struct VncSink {
+    state: Arc<ServerState>,
+}
+
+impl FrameSink for VncSink {
+    fn push_frame(&mut self, frame: &Frame, _frame_counter: u32) -> bool {
+        if !self.state.alive.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        // frames are only copied while someone is watching
+        if self.state.clients.load(Ordering::Relaxed) == 0
+            && self.state.latest.lock().frame.is_some()
+        {
+            return true;
+        }
+
+        self.state.publish(frame.clone());
+        true
+    }
+}
+
+impl Drop for VncServer {
+    fn drop(&mut self) {
+        self.state.alive.store(false, Ordering::SeqCst);
+        if let Some(thread_handle) = self.thread_handle.take() {
+            if thread_handle.join().is_err() {
+                warn!("vnc server thread panicked");
+            }
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, state: Arc<ServerState>) {
+    while state.alive.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, address)) => {
+                let state = state.clone();
+                thread::spawn(move || {
+                    info!("vnc client connected: {}", address);
+                    state.clients.fetch_add(1, Ordering::Relaxed);
+                    if let Err(err) = handle_client(stream, &state) {
+                        if err.kind() != io::ErrorKind::BrokenPipe
+                            && err.kind() != io::ErrorKind::ConnectionReset
+                            && err.kind() != io::ErrorKind::UnexpectedEof
+                        {
+                            warn!("vnc connection failed: {}", err);
+                        }
+                    }
+                    state.clients.fetch_sub(1, Ordering::Relaxed);
+                    info!("vnc client disconnected: {}", address);
+                });
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(err) => {
+                warn!("vnc server stopped: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Pixel format as described in the rfb specification
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RfbPixelFormat {
+    bits_per_pixel: u8,
+    depth: u8,
+    big_endian: bool,
+    true_colour: bool,
+    red_max: u16,
+    green_max: u16,
+    blue_max: u16,
+    red_shift: u8,
+    green_shift: u8,
+    blue_shift: u8,
+}
+
+impl RfbPixelFormat {
+    /// 32 bit little endian bgrx, this is sent to clients in the server init
+    const DEFAULT: RfbPixelFormat = RfbPixelFormat {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        red_max: 255,
+        green_max: 255,
+        blue_max: 255,
+        red_shift: 16,
+        green_shift: 8,
+        blue_shift: 0,
+    };
+
+    fn read(buf: &[u8; 16]) -> Self {
+        Self {
+            bits_per_pixel: buf[0],
+            depth: buf[1],
+            big_endian: buf[2] != 0,
+            true_colour: buf[3] != 0,
+            red_max: u16::from_be_bytes([buf[4], buf[5]]),
+            green_max: u16::from_be_bytes([buf[6], buf[7]]),
+            blue_max: u16::from_be_bytes([buf[8], buf[9]]),
+            red_shift: buf[10],
+            green_shift: buf[11],
+            blue_shift: buf[12],
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[
+            self.bits_per_pixel,
+            self.depth,
+            self.big_endian as u8,
+            self.true_colour as u8,
+        ]);
+        out.extend_from_slice(&self.red_max.to_be_bytes());
+        out.extend_from_slice(&self.green_max.to_be_bytes());
+        out.extend_from_slice(&self.blue_max.to_be_bytes());
+        out.extend_from_slice(&[self.red_shift, self.green_shift, self.blue_shift, 0, 0, 0]);
+    }
+
+    fn is_supported(&self) -> bool {
+        self.true_colour && matches!(self.bits_per_pixel, 8 | 16 | 32)
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel as usize / 8
+    }
+
+    /// Returns true if tight encoded pixels are sent as 3 byte rgb values
+    fn is_tight_rgb(&self) -> bool {
+        self.bits_per_pixel == 32
+            && self.depth == 24
+            && self.red_max == 255
+            && self.green_max == 255
+            && self.blue_max == 255
+    }
+
+    fn push_pixel(&self, rgba: &[u8], out: &mut Vec<u8>) {
+        let scale = |value: u8, max: u16| value as u32 * max as u32 / 255;
+        let pixel = scale(rgba[0], self.red_max) << self.red_shift
+            | scale(rgba[1], self.green_max) << self.green_shift
+            | scale(rgba[2], self.blue_max) << self.blue_shift;
+
+        match (self.bits_per_pixel, self.big_endian) {
+            (8, _) => out.push(pixel as u8),
+            (16, false) => out.extend_from_slice(&(pixel as u16).to_le_bytes()),
+            (16, true) => out.extend_from_slice(&(pixel as u16).to_be_bytes()),
+            (_, false) => out.extend_from_slice(&pixel.to_le_bytes()),
+            (_, true) => out.extend_from_slice(&pixel.to_be_bytes()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct UpdateRequest {
+    incremental: bool,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Messages of the client, they are read in a separate thread
+#[derive(Default)]
+struct ClientRequests {
+    pixel_format: Option<RfbPixelFormat>,
+    encodings: Option<Vec<i32>>,
+    update: Option<UpdateRequest>,
+    closed: bool,
+}
+
+fn handle_client(mut stream: TcpStream, state: &ServerState) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+
+    // protocol version
+    stream.write_all(b"RFB 003.008\n")?;
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    let minor = std::str::from_utf8(&version[8..11])
+        .ok()
+        .and_then(|minor| minor.parse::<u32>().ok())
+        .unwrap_or(3);
+
+    // security, authentication is not supported
+    if minor >= 7 {
+        stream.write_all(&[1, 1])?;
+        let mut security_type = [0u8; 1];
+        stream.read_exact(&mut security_type)?;
+        if security_type[0] != 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported security type",
+            ));
+        }
+        if minor >= 8 {
+            stream.write_all(&0u32.to_be_bytes())?;
+        }
+    } else {
+        stream.write_all(&1u32.to_be_bytes())?;
+    }
+
+    // client init (the shared flag is ignored as all clients are view-only)
+    let mut shared = [0u8; 1];
+    stream.read_exact(&mut shared)?;
+
+    // the resolution is only known once the first frame has been captured
+    let (mut sequence, frame) = loop {
+        if !state.alive.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let latest = state.latest.lock();
+        if let Some(frame) = &latest.frame {
+            break (latest.sequence, frame.clone());
+        }
+        drop(latest);
+        thread::sleep(ACCEPT_INTERVAL);
+    };
+
+    let mut server_init = Vec::new();
+    server_init.extend_from_slice(&(frame.width as u16).to_be_bytes());
+    server_init.extend_from_slice(&(frame.height as u16).to_be_bytes());
+    RfbPixelFormat::DEFAULT.write(&mut server_init);
+    server_init.extend_from_slice(&(state.config.name.len() as u32).to_be_bytes());
+    server_init.extend_from_slice(state.config.name.as_bytes());
+    stream.write_all(&server_init)?;
+
+    let requests = Arc::new(Mutex::new(ClientRequests::default()));
+    let reader_stream = stream.try_clone()?;
+    let reader_requests = requests.clone();
+    let reader = thread::spawn(move || {
+        if let Err(err) = read_messages(reader_stream, &reader_requests) {
+            if err.kind() != io::ErrorKind::UnexpectedEof
+                && err.kind() != io::ErrorKind::ConnectionReset
+            {
+                warn!("unable to read vnc client message: {}", err);
+            }
+        }
+        reader_requests.lock().closed = true;
+    });
+
+    let mut session = Session::new(stream, frame.width, frame.height);
+    let result = (|| {
+        // the first update always contains the entire frame
+        sequence = sequence.wrapping_sub(1);
+        while state.alive.load(Ordering::SeqCst) {
+            let (request, frame) = {
+                let mut requests = requests.lock();
+                if requests.closed {
+                    break;
+                }
+                if let Some(pixel_format) = requests.pixel_format.take() {
+                    session.pixel_format = pixel_format;
+                    session.previous = None;
+                }
+                if let Some(encodings) = requests.encodings.take() {
+                    session.encodings = encodings;
+                }
+
+                let latest = state.latest.lock();
+                match (requests.update, &latest.frame) {
+                    (Some(request), Some(frame))
+                        if !request.incremental
+                            || latest.sequence != sequence
+                            || session.cursor_changed(&frame.cursor) =>
+                    {
+                        sequence = latest.sequence;
+                        requests.update = None;
+                        (request, frame.clone())
+                    }
+                    _ => {
+                        drop(latest);
+                        drop(requests);
+                        thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                }
+            };
+
+            session.send_update(request, frame)?;
+        }
+        Ok(())
+    })();
+
+    // stops the reader thread
+    session.stream.shutdown(Shutdown::Both).ok();
+    reader.join().ok();
+    result
+}
+
+fn read_messages(mut stream: TcpStream, requests: &Mutex<ClientRequests>) -> io::Result<()> {
+    loop {
+        let mut message_type = [0u8; 1];
+        stream.read_exact(&mut message_type)?;
+        match message_type[0] {
+            // SetPixelFormat
+            0 => {
+                let mut buf = [0u8; 19];
+                stream.read_exact(&mut buf)?;
+                let pixel_format = RfbPixelFormat::read(buf[3..].try_into().unwrap());
+                if pixel_format.is_supported() {
+                    requests.lock().pixel_format = Some(pixel_format);
+                } else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unsupported pixel format",
+                    ));
+                }
+            }
+            // SetEncodings
+            2 => {
+                let mut buf = [0u8; 3];
+                stream.read_exact(&mut buf)?;
+                let count = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+                let mut buf = vec![0u8; count * 4];
+                stream.read_exact(&mut buf)?;
+                requests.lock().encodings = Some(
+                    buf.chunks_exact(4)
+                        .map(|encoding| i32::from_be_bytes(encoding.try_into().unwrap()))
+                        .collect(),
+                );
+            }
+            // FramebufferUpdateRequest
+            3 => {
+                let mut buf = [0u8; 9];
+                stream.read_exact(&mut buf)?;
+                let u16_at = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]) as u32;
+                let request = UpdateRequest {
+                    incremental: buf[0] != 0,
+                    x: u16_at(1),
+                    y: u16_at(3),
+                    width: u16_at(5),
+                    height: u16_at(7),
+                };
+
+                // a pending full update is not replaced by an incremental one
+                let mut requests = requests.lock();
+                if !request.incremental || requests.update.is_none() {
+                    requests.update = Some(request);
+                }
+            }
+            // KeyEvent
+            4 => stream.read_exact(&mut [0u8; 7])?,
+            // PointerEvent
+            5 => stream.read_exact(&mut [0u8; 5])?,
+            // ClientCutText
+            6 => {
+                let mut buf = [0u8; 7];
+                stream.read_exact(&mut buf)?;
+                let len = u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]);
+                if len > MAX_CUT_TEXT_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "clipboard text is too large",
+                    ));
+                }
+                io::copy(&mut (&mut stream).take(len as u64), &mut io::sink())?;
+            }
+            message_type => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown message type {}", message_type),
+                ))
+            }
+        }
+    }
+}
+
+/// State of a single client connection
+struct Session {
+    stream: TcpStream,
+    pixel_format: RfbPixelFormat,
+    encodings: Vec<i32>,
+
+    width: u32,
+    height: u32,
+    /// Contents of the framebuffer of the client, if they are known
+    previous: Option<Arc<Frame>>,
+
+    cursor_visible: Option<bool>,
+    cursor_position: Option<(i32, i32)>,
+
+    // zlib and tight use a single compression stream for the entire connection
+    zlib: Compress,
+    tight: Compress,
+}
+
+impl Session {
+    fn new(stream: TcpStream, width: u32, height: u32) -> Self {
+        Self {
+            stream,
+            pixel_format: RfbPixelFormat::DEFAULT,
+            encodings: vec![ENCODING_RAW],
+
+            width,
+            height,
+            previous: None,
+
+            cursor_visible: None,
+            cursor_position: None,
+
+            zlib: Compress::new(Compression::fast(), true),
+            tight: Compress::new(Compression::fast(), true),
+        }
+    }
+
+    fn supports(&self, encoding: i32) -> bool {
+        self.encodings.contains(&encoding)
+    }
+
+    /// Returns the encoding for pixel data, clients list their encodings in order of preference
+    fn preferred_encoding(&self) -> i32 {
+        self.encodings
+            .iter()
+            .copied()
+            .find(|encoding| matches!(*encoding, ENCODING_RAW | ENCODING_ZLIB | ENCODING_TIGHT))
+            .unwrap_or(ENCODING_RAW)
+    }
+
+    fn cursor_changed(&self, cursor: &Cursor) -> bool {
+        (self.supports(ENCODING_CURSOR) && self.cursor_visible != Some(cursor.is_visible != 0))
+            || (self.supports(ENCODING_POINTER_POS)
+                && cursor.is_visible != 0
+                && self.cursor_position != Some((cursor.x, cursor.y)))
+    }
+
+    fn send_update(&mut self, request: UpdateRequest, frame: Arc<Frame>) -> io::Result<()> {
+        let mut body = Vec::new();
+        let mut rects = 0u16;
+
+        // resolution changes require the client to resize its framebuffer
+        if (frame.width, frame.height) != (self.width, self.height)
+            && self.supports(ENCODING_DESKTOP_SIZE)
+        {
+            self.width = frame.width;
+            self.height = frame.height;
+            self.previous = None;
+            push_rect_header(
+                &mut body,
+                0,
+                0,
+                self.width,
+                self.height,
+                ENCODING_DESKTOP_SIZE,
+            );
+            rects += 1;
+        }
+
+        rects += self.push_cursor(&mut body, &frame.cursor);
+
+        // only frames of the same resolution can be compared
+        let previous = self
+            .previous
+            .take()
+            .filter(|previous| (previous.width, previous.height) == (frame.width, frame.height));
+        let full_update = !request.incremental || previous.is_none();
+
+        // the update is limited to the requested region and the size of the framebuffer of the client
+        let right = (request.x + request.width).min(self.width).min(frame.width);
+        let bottom = (request.y + request.height)
+            .min(self.height)
+            .min(frame.height);
+
+        let mut dirty = Vec::new();
+        let mut tile_y = request.y - request.y % TILE_SIZE;
+        while request.x < right && tile_y < bottom {
+            let mut tile_x = request.x - request.x % TILE_SIZE;
+            while tile_x < right {
+                let x = tile_x.max(request.x);
+                let y = tile_y.max(request.y);
+                let width = (tile_x + TILE_SIZE).min(right) - x;
+                let height = (tile_y + TILE_SIZE).min(bottom) - y;
+
+                let changed = match &previous {
+                    Some(previous) if !full_update => {
+                        !region_equals(&frame, previous, (x, y), (x, y), width, height)
+                    }
+                    _ => true,
+                };
+                if changed {
+                    dirty.push((x, y, width, height));
+                }
+                tile_x += TILE_SIZE;
+            }
+            tile_y += TILE_SIZE;
+        }
+
+        // moved content is copied from unchanged tiles of the previous frame on the client,
+        // copies are sent first so their sources are not overwritten by other rectangles
+        let mut copies = Vec::new();
+        if let Some(previous) = previous.as_ref().filter(|_| !full_update) {
+            if self.supports(ENCODING_COPY_RECT) && !dirty.is_empty() {
+                let sources = unchanged_tiles(&frame, previous, &dirty);
+                dirty.retain(|&(x, y, width, height)| {
+                    if width != TILE_SIZE || height != TILE_SIZE {
+                        return true;
+                    }
+                    let source = sources
+                        .get(&hash_region(&frame, x, y, width, height))
+                        .filter(|&&(src_x, src_y)| {
+                            region_equals(&frame, previous, (x, y), (src_x, src_y), width, height)
+                        });
+                    match source {
+                        Some(&(src_x, src_y)) => {
+                            copies.push((x, y, src_x, src_y));
+                            false
+                        }
+                        None => true,
+                    }
+                });
+            }
+        }
+        for (x, y, src_x, src_y) in copies {
+            push_rect_header(&mut body, x, y, TILE_SIZE, TILE_SIZE, ENCODING_COPY_RECT);
+            body.extend_from_slice(&(src_x as u16).to_be_bytes());
+            body.extend_from_slice(&(src_y as u16).to_be_bytes());
+            rects += 1;
+        }
+
+        let encoding = self.preferred_encoding();
+        for (x, y, width, height) in dirty {
+            push_rect_header(&mut body, x, y, width, height, encoding);
+            self.push_pixels(&mut body, &frame, (x, y, width, height), encoding)?;
+            rects += 1;
+        }
+
+        let mut message = Vec::with_capacity(body.len() + 4);
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&rects.to_be_bytes());
+        message.extend_from_slice(&body);
+        self.stream.write_all(&message)?;
+
+        // the client only received the requested region, the rest of its framebuffer is unchanged
+        let covers_client = request.x == 0
+            && request.y == 0
+            && right == self.width.min(frame.width)
+            && bottom == self.height.min(frame.height);
+        self.previous = if covers_client {
+            Some(frame)
+        } else {
+            previous.map(|mut previous| {
+                if request.x < right && request.y < bottom {
+                    copy_region(
+                        Arc::make_mut(&mut previous),
+                        &frame,
+                        (request.x, request.y),
+                        right - request.x,
+                        bottom - request.y,
+                    );
+                }
+                previous
+            })
+        };
+        Ok(())
+    }
+
+    /// Adds the cursor shape and position if they changed, returns the number of rectangles
+    fn push_cursor(&mut self, body: &mut Vec<u8>, cursor: &Cursor) -> u16 {
+        let mut rects = 0;
+        let visible = cursor.is_visible != 0;
+
+        if self.supports(ENCODING_CURSOR) && self.cursor_visible != Some(visible) {
+            self.cursor_visible = Some(visible);
+            match cursor_image().filter(|_| visible) {
+                Some(image) => {
+                    // the hotspot of the arrow is in the top left corner
+                    push_rect_header(body, 0, 0, image.width(), image.height(), ENCODING_CURSOR);
+                    for pixel in image.pixels() {
+                        self.pixel_format.push_pixel(&pixel.0, body);
+                    }
+                    let stride = (image.width() as usize).div_ceil(8);
+                    for row in image.rows() {
+                        let mut mask = vec![0u8; stride];
+                        for (x, pixel) in row.enumerate() {
+                            if pixel[3] > 127 {
+                                mask[x / 8] |= 0x80 >> (x % 8);
+                            }
+                        }
+                        body.extend_from_slice(&mask);
+                    }
+                }
+                // an empty cursor hides it on the client
+                None => push_rect_header(body, 0, 0, 0, 0, ENCODING_CURSOR),
+            }
+            rects += 1;
+        }
+
+        if self.supports(ENCODING_POINTER_POS)
+            && visible
+            && self.cursor_position != Some((cursor.x, cursor.y))
+        {
+            self.cursor_position = Some((cursor.x, cursor.y));
+            push_rect_header(
+                body,
+                cursor.x.max(0) as u32,
+                cursor.y.max(0) as u32,
+                0,
+                0,
+                ENCODING_POINTER_POS,
+            );
+            rects += 1;
+        }
+
+        rects
+    }
+
+    fn push_pixels(
+        &mut self,
+        body: &mut Vec<u8>,
+        frame: &Frame,
+        (x, y, width, height): (u32, u32, u32, u32),
+        encoding: i32,
+    ) -> io::Result<()> {
+        let tight_rgb = encoding == ENCODING_TIGHT && self.pixel_format.is_tight_rgb();
+        let bytes_per_pixel = if tight_rgb {
+            3
+        } else {
+            self.pixel_format.bytes_per_pixel()
+        };
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * bytes_per_pixel);
+        for row in y..y + height {
+            let offset = (row as usize * frame.width as usize + x as usize) * 4;
+            let row = &frame.data[offset..offset + width as usize * 4];
+            for pixel in row.chunks_exact(4) {
+                if tight_rgb {
+                    pixels.extend_from_slice(&pixel[..3]);
+                } else {
+                    self.pixel_format.push_pixel(pixel, &mut pixels);
+                }
+            }
+        }
+
+        match encoding {
+            ENCODING_ZLIB => {
+                let data = deflate(&mut self.zlib, &pixels)?;
+                body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                body.extend_from_slice(&data);
+            }
+            ENCODING_TIGHT => {
+                // basic compression with stream 0 and without a filter
+                body.push(0x00);
+                if pixels.len() < 12 {
+                    body.extend_from_slice(&pixels);
+                } else {
+                    let data = deflate(&mut self.tight, &pixels)?;
+                    push_compact_len(body, data.len());
+                    body.extend_from_slice(&data);
+                }
+            }
+            _ => body.extend_from_slice(&pixels),
+        }
+        Ok(())
+    }
+}
+
+fn push_rect_header(body: &mut Vec<u8>, x: u32, y: u32, width: u32, height: u32, encoding: i32) {
+    body.extend_from_slice(&(x as u16).to_be_bytes());
+    body.extend_from_slice(&(y as u16).to_be_bytes());
+    body.extend_from_slice(&(width as u16).to_be_bytes());
+    body.extend_from_slice(&(height as u16).to_be_bytes());
+    body.extend_from_slice(&encoding.to_be_bytes());
+}
+
+/// Writes a length in the compact representation used by the tight encoding
+fn push_compact_len(body: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        body.push(len as u8);
+    } else if len < 0x4000 {
+        body.push((len & 0x7f) as u8 | 0x80);
+        body.push((len >> 7) as u8);
+    } else {
+        body.push((len & 0x7f) as u8 | 0x80);
+        body.push(((len >> 7) & 0x7f) as u8 | 0x80);
+        body.push((len >> 14) as u8);
+    }
+}
+
+/// Compresses the data with a sync flush so the client can decode it right away
+fn deflate(compress: &mut Compress, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len() / 2 + 64);
+    let start = compress.total_in();
+    loop {
+        if output.len() == output.capacity() {
+            output.reserve(output.capacity());
+        }
+        let consumed = (compress.total_in() - start) as usize;
+        compress
+            .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+            .map_err(io::Error::other)?;
+
+        // the flush is complete once all data is consumed and the output was not filled up
+        let consumed = (compress.total_in() - start) as usize;
+        if consumed == data.len() && output.len() < output.capacity() {
+            return Ok(output);
+        }
+    }
+}
+
+/// Compares a region of two frames which have the same resolution
+fn region_equals(
+    frame: &Frame,
+    other: &Frame,
+    (x, y): (u32, u32),
+    (other_x, other_y): (u32, u32),
+    width: u32,
+    height: u32,
+) -> bool {
+    let stride = frame.width as usize * 4;
+    let len = width as usize * 4;
+    (0..height as usize).all(|row| {
+        let offset = (y as usize + row) * stride + x as usize * 4;
+        let other_offset = (other_y as usize + row) * stride + other_x as usize * 4;
+        frame.data[offset..offset + len] == other.data[other_offset..other_offset + len]
+    })
+}
+
+/// Copies a region between two frames which have the same resolution
+fn copy_region(frame: &mut Frame, source: &Frame, (x, y): (u32, u32), width: u32, height: u32) {
+    let stride = frame.width as usize * 4;
+    let len = width as usize * 4;
+    for row in y as usize..(y + height) as usize {
+        let offset = row * stride + x as usize * 4;
+        frame.data[offset..offset + len].copy_from_slice(&source.data[offset..offset + len]);
+    }
+}
+
+fn hash_region(frame: &Frame, x: u32, y: u32, width: u32, height: u32) -> u64 {
+    let stride = frame.width as usize * 4;
+    let mut hasher = DefaultHasher::new();
+    for row in y as usize..(y + height) as usize {
+        let offset = row * stride + x as usize * 4;
+        frame.data[offset..offset + width as usize * 4].hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Returns the hashes of all full tiles that did not change between the frames
+fn unchanged_tiles(
+    frame: &Frame,
+    previous: &Frame,
+    dirty: &[(u32, u32, u32, u32)],
+) -> HashMap<u64, (u32, u32)> {
+    let mut tiles = HashMap::new();
+    for tile_y in (0..frame.height / TILE_SIZE).map(|y| y * TILE_SIZE) {
+        for tile_x in (0..frame.width / TILE_SIZE).map(|x| x * TILE_SIZE) {
+            let is_dirty = dirty.iter().any(|&(x, y, width, height)| {
+                x < tile_x + TILE_SIZE
+                    && tile_x < x + width
+                    && y < tile_y + TILE_SIZE
+                    && tile_y < y + height
+            });
+            if !is_dirty {
+                tiles
+                    .entry(hash_region(previous, tile_x, tile_y, TILE_SIZE, TILE_SIZE))
+                    .or_insert((tile_x, tile_y));
+            }
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::flate2::{Decompress, FlushDecompress};
+
+    /// Returns a single row of two tiles filled with the given values
+    fn frame(left: u8, right: u8) -> Arc<Frame> {
+        let width = TILE_SIZE * 2;
+        let data = (0..width)
+            .flat_map(|i| {
+                let value = if i % width < TILE_SIZE { left } else { right };
+                [value, value, value, 255]
+            })
+            .collect();
+        Arc::new(Frame {
+            width,
+            height: 1,
+            timestamp: None,
+            cursor: Cursor::default(),
+            data,
+        })
+    }
+
+    fn request(incremental: bool, width: u32) -> UpdateRequest {
+        UpdateRequest {
+            incremental,
+            x: 0,
+            y: 0,
+            width,
+            height: TILE_SIZE,
+        }
+    }
+
+    /// Returns a session and the client end of its connection
+    fn session() -> (Session, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (Session::new(stream, TILE_SIZE * 2, 1), client)
+    }
+
+    /// Reads a framebuffer update of raw rectangles and returns their position
+    fn read_update(client: &mut TcpStream) -> Vec<(u16, u16)> {
+        let mut header = [0u8; 4];
+        client.read_exact(&mut header).unwrap();
+        let rects = u16::from_be_bytes([header[2], header[3]]);
+        (0..rects)
+            .map(|_| {
+                let mut rect = [0u8; 12];
+                client.read_exact(&mut rect).unwrap();
+                let u16_at = |i: usize| u16::from_be_bytes([rect[i], rect[i + 1]]);
+                assert_eq!(
+                    i32::from_be_bytes(rect[8..].try_into().unwrap()),
+                    ENCODING_RAW
+                );
+                let len = u16_at(4) as usize * u16_at(6) as usize * 4;
+                client.read_exact(&mut vec![0u8; len]).unwrap();
+                (u16_at(0), u16_at(2))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let (mut session, mut client) = session();
+        session
+            .send_update(request(false, TILE_SIZE * 2), frame(1, 1))
+            .unwrap();
+        assert_eq!(read_update(&mut client), vec![(0, 0), (64, 0)]);
+
+        // only the requested region is sent
+        session
+            .send_update(request(true, TILE_SIZE), frame(2, 2))
+            .unwrap();
+        assert_eq!(read_update(&mut client), vec![(0, 0)]);
+
+        // the rest of the frame is still outdated on the client
+        session
+            .send_update(request(true, TILE_SIZE * 2), frame(2, 2))
+            .unwrap();
+        assert_eq!(read_update(&mut client), vec![(64, 0)]);
+
+        session
+            .send_update(request(true, TILE_SIZE * 2), frame(2, 2))
+            .unwrap();
+        assert!(read_update(&mut client).is_empty());
+    }
+
+    #[test]
+    fn pixel_formats() {
+        let mut out = Vec::new();
+        RfbPixelFormat::DEFAULT.push_pixel(&[1, 2, 3, 255], &mut out);
+        assert_eq!(out, vec![3, 2, 1, 0]);
+
+        let rgb565 = RfbPixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+            ..RfbPixelFormat::DEFAULT
+        };
+        let mut out = Vec::new();
+        rgb565.push_pixel(&[255, 0, 0, 255], &mut out);
+        rgb565.push_pixel(&[0, 0, 255, 255], &mut out);
+        assert_eq!(out, vec![0x00, 0xf8, 0x1f, 0x00]);
+
+        let mut buf = Vec::new();
+        rgb565.write(&mut buf);
+        assert_eq!(RfbPixelFormat::read(buf[..16].try_into().unwrap()), rgb565);
+    }
+
+    #[test]
+    fn compact_len() {
+        for (len, expected) in [
+            (0x7f, vec![0x7f]),
+            (0x80, vec![0x80, 0x01]),
+            (0x3fff, vec![0xff, 0x7f]),
+            (0x4000, vec![0x80, 0x80, 0x01]),
+        ] {
+            let mut body = Vec::new();
+            push_compact_len(&mut body, len);
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[test]
+    fn deflate_stream() {
+        // all messages share one stream and can be decoded as soon as they are received
+        let mut compress = Compress::new(Compression::fast(), true);
+        let mut decompress = Decompress::new(true);
+        for message in [vec![1u8; 1000], (0..=255).collect::<Vec<u8>>()] {
+            let data = deflate(&mut compress, &message).unwrap();
+            let mut output = Vec::with_capacity(message.len());
+            decompress
+                .decompress_vec(&data, &mut output, FlushDecompress::Sync)
+                .unwrap();
+            assert_eq!(output, message);
+        }
+    }
+}